@@ -0,0 +1,3 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX dags_name_trgm_idx ON dags USING gin (name gin_trgm_ops);
@@ -0,0 +1,5 @@
+ALTER TABLE dags
+    ADD COLUMN lifecycle TEXT NOT NULL DEFAULT 'active'
+        CHECK (lifecycle IN ('active', 'deprecated', 'archived')),
+    ADD COLUMN deprecation_reason TEXT,
+    ADD COLUMN replaced_by UUID REFERENCES dags(id);
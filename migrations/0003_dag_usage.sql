@@ -0,0 +1,8 @@
+CREATE TABLE dag_usage (
+                           dag_id UUID REFERENCES dags(id),
+                           day DATE NOT NULL,
+                           reads BIGINT NOT NULL DEFAULT 0,
+                           runs BIGINT NOT NULL DEFAULT 0,
+                           edits BIGINT NOT NULL DEFAULT 0,
+                           PRIMARY KEY (dag_id, day)
+);
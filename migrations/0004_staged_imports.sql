@@ -0,0 +1,32 @@
+CREATE TABLE imports (
+                         id UUID PRIMARY KEY,
+                         name TEXT NOT NULL,
+                         target_dag_id UUID REFERENCES dags(id),
+                         status TEXT NOT NULL DEFAULT 'uploading'
+                             CHECK (status IN ('uploading', 'validating', 'valid', 'invalid', 'promoted')),
+                         node_count INTEGER NOT NULL DEFAULT 0,
+                         edge_count INTEGER NOT NULL DEFAULT 0,
+                         validated_items INTEGER NOT NULL DEFAULT 0,
+                         errors JSONB NOT NULL DEFAULT '[]',
+                         dag_id UUID REFERENCES dags(id),
+                         created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE import_nodes (
+                              seq BIGSERIAL PRIMARY KEY,
+                              import_id UUID NOT NULL REFERENCES imports(id) ON DELETE CASCADE,
+                              client_id TEXT NOT NULL,
+                              label TEXT NOT NULL,
+                              node_id UUID
+);
+
+CREATE TABLE import_edges (
+                              seq BIGSERIAL PRIMARY KEY,
+                              import_id UUID NOT NULL REFERENCES imports(id) ON DELETE CASCADE,
+                              source_client_id TEXT NOT NULL,
+                              target_client_id TEXT NOT NULL,
+                              edge_id UUID
+);
+
+CREATE INDEX import_nodes_import_id_idx ON import_nodes (import_id);
+CREATE INDEX import_edges_import_id_idx ON import_edges (import_id);
@@ -0,0 +1,15 @@
+CREATE TABLE jobs (
+                      id UUID PRIMARY KEY,
+                      kind TEXT NOT NULL,
+                      status TEXT NOT NULL DEFAULT 'queued'
+                          CHECK (status IN ('queued', 'running', 'succeeded', 'failed')),
+                      payload JSONB NOT NULL,
+                      result JSONB,
+                      error TEXT,
+                      attempts INT NOT NULL DEFAULT 0,
+                      created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                      started_at TIMESTAMPTZ,
+                      finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX jobs_queued_idx ON jobs (created_at) WHERE status = 'queued';
@@ -0,0 +1,7 @@
+-- Every migration from here on ends by recording its number
+CREATE TABLE schema_migrations (
+                                   version INT PRIMARY KEY,
+                                   applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO schema_migrations (version) VALUES (1), (2), (3), (4), (5), (6);
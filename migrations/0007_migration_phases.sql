@@ -0,0 +1,7 @@
+-- expand: additive, safe to run while the previous build still serves
+-- contract: removes or changes what the previous build relies on
+ALTER TABLE schema_migrations
+    ADD COLUMN phase TEXT NOT NULL DEFAULT 'expand'
+        CHECK (phase IN ('expand', 'contract'));
+
+INSERT INTO schema_migrations (version, phase) VALUES (7, 'expand');
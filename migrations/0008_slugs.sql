@@ -0,0 +1,32 @@
+-- Nullable so builds from before slugs can keep inserting (expand)
+ALTER TABLE dags ADD COLUMN slug TEXT;
+ALTER TABLE nodes ADD COLUMN slug TEXT;
+ALTER TABLE import_nodes ADD COLUMN slug TEXT;
+
+-- Same rules as slugs::slugify; duplicates get the start of their id appended
+UPDATE dags d SET slug = s.slug
+FROM (
+    SELECT id, base || CASE WHEN row_number() OVER (PARTITION BY base ORDER BY id) > 1
+                            THEN '-' || left(id::text, 8) ELSE '' END AS slug
+    FROM (
+        SELECT id, coalesce(nullif(trim(both '-' from left(regexp_replace(lower(name), '[^a-z0-9]+', '-', 'g'), 60)), ''), 'dag') AS base
+        FROM dags
+    ) b
+) s
+WHERE d.id = s.id;
+
+UPDATE nodes n SET slug = s.slug
+FROM (
+    SELECT id, base || CASE WHEN row_number() OVER (PARTITION BY dag_id, base ORDER BY id) > 1
+                            THEN '-' || left(id::text, 8) ELSE '' END AS slug
+    FROM (
+        SELECT id, dag_id, coalesce(nullif(trim(both '-' from left(regexp_replace(lower(label), '[^a-z0-9]+', '-', 'g'), 60)), ''), 'node') AS base
+        FROM nodes
+    ) b
+) s
+WHERE n.id = s.id;
+
+CREATE UNIQUE INDEX dags_slug_idx ON dags (slug);
+CREATE UNIQUE INDEX nodes_dag_slug_idx ON nodes (dag_id, slug);
+
+INSERT INTO schema_migrations (version, phase) VALUES (8, 'expand');
@@ -0,0 +1,6 @@
+ALTER TABLE nodes ADD COLUMN external_ids JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE import_nodes ADD COLUMN external_ids JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX nodes_external_ids_idx ON nodes USING GIN (external_ids jsonb_path_ops);
+
+INSERT INTO schema_migrations (version, phase) VALUES (9, 'expand');
@@ -0,0 +1,9 @@
+ALTER TABLE imports ADD COLUMN namespace TEXT;
+ALTER TABLE imports ADD COLUMN source_system TEXT;
+
+-- Where a node came from: {import_id, source_system, namespace, imported_at}.
+-- NULL for nodes created through the API.
+ALTER TABLE nodes ADD COLUMN provenance JSONB;
+CREATE INDEX nodes_provenance_idx ON nodes USING GIN (provenance jsonb_path_ops);
+
+INSERT INTO schema_migrations (version, phase) VALUES (10, 'expand');
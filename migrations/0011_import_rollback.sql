@@ -0,0 +1,5 @@
+ALTER TABLE imports DROP CONSTRAINT imports_status_check;
+ALTER TABLE imports ADD CONSTRAINT imports_status_check
+    CHECK (status IN ('uploading', 'validating', 'valid', 'invalid', 'promoted', 'rolled_back'));
+
+INSERT INTO schema_migrations (version, phase) VALUES (11, 'expand');
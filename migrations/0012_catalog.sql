@@ -0,0 +1,32 @@
+-- DAGs published as reusable templates. Every publish adds an immutable
+-- version holding a snapshot of the DAG's nodes and edges.
+CREATE TABLE catalog_entries (
+                                 id UUID PRIMARY KEY,
+                                 name TEXT NOT NULL,
+                                 source_dag_id UUID UNIQUE REFERENCES dags(id),
+                                 created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE catalog_versions (
+                                  entry_id UUID NOT NULL REFERENCES catalog_entries(id),
+                                  version INTEGER NOT NULL,
+                                  node_count INTEGER NOT NULL,
+                                  edge_count INTEGER NOT NULL,
+                                  snapshot JSONB NOT NULL,
+                                  published_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                                  PRIMARY KEY (entry_id, version)
+);
+
+-- DAGs made from a catalog version; subscribed copies can be brought up to
+-- the latest version
+CREATE TABLE catalog_copies (
+                                dag_id UUID PRIMARY KEY REFERENCES dags(id),
+                                entry_id UUID NOT NULL REFERENCES catalog_entries(id),
+                                version INTEGER NOT NULL,
+                                subscribed BOOLEAN NOT NULL DEFAULT false,
+                                copied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX catalog_copies_entry_id_idx ON catalog_copies (entry_id);
+
+INSERT INTO schema_migrations (version, phase) VALUES (12, 'expand');
@@ -0,0 +1,16 @@
+ALTER TABLE catalog_entries ADD COLUMN description TEXT;
+ALTER TABLE catalog_entries ADD COLUMN categories TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE catalog_reviews (
+                                 id UUID PRIMARY KEY,
+                                 entry_id UUID NOT NULL REFERENCES catalog_entries(id),
+                                 rating SMALLINT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+                                 comment TEXT,
+                                 author TEXT,
+                                 created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX catalog_reviews_entry_id_idx ON catalog_reviews (entry_id);
+CREATE INDEX catalog_entries_categories_idx ON catalog_entries USING GIN (categories);
+
+INSERT INTO schema_migrations (version, phase) VALUES (13, 'expand');
@@ -0,0 +1,6 @@
+ALTER TABLE nodes ADD COLUMN owner TEXT;
+ALTER TABLE nodes ADD COLUMN source_url TEXT;
+
+CREATE INDEX nodes_owner_idx ON nodes (owner);
+
+INSERT INTO schema_migrations (version, phase) VALUES (14, 'expand');
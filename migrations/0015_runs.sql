@@ -0,0 +1,34 @@
+-- What running a node does, e.g. {"type": "shell", "command": "..."}
+ALTER TABLE nodes ADD COLUMN task JSONB;
+
+CREATE TABLE runs (
+                      id UUID PRIMARY KEY,
+                      dag_id UUID NOT NULL REFERENCES dags(id),
+                      status TEXT NOT NULL DEFAULT 'queued',
+                      job_id UUID,
+                      created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                      started_at TIMESTAMPTZ,
+                      finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX runs_dag_id_idx ON runs (dag_id, created_at);
+
+-- One row per node of the DAG as it was when the run started. Labels, tasks
+-- and dependencies are copied so later edits don't change a run under way.
+CREATE TABLE task_runs (
+                           run_id UUID NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+                           node_id UUID NOT NULL,
+                           label TEXT NOT NULL,
+                           task JSONB,
+                           depends_on UUID[] NOT NULL DEFAULT '{}',
+                           state TEXT NOT NULL DEFAULT 'pending',
+                           exit_code INTEGER,
+                           http_status INTEGER,
+                           output TEXT,
+                           error TEXT,
+                           started_at TIMESTAMPTZ,
+                           finished_at TIMESTAMPTZ,
+                           PRIMARY KEY (run_id, node_id)
+);
+
+INSERT INTO schema_migrations (version, phase) VALUES (15, 'expand');
@@ -0,0 +1,16 @@
+-- Issues in external trackers (Jira, GitHub, ...) filed about a run or one of
+-- its failed tasks
+CREATE TABLE run_tickets (
+                             id UUID PRIMARY KEY,
+                             run_id UUID NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+                             node_id UUID,
+                             system TEXT NOT NULL,
+                             key TEXT NOT NULL,
+                             url TEXT,
+                             created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                             FOREIGN KEY (run_id, node_id) REFERENCES task_runs(run_id, node_id) ON DELETE CASCADE
+);
+
+CREATE INDEX run_tickets_run_id_idx ON run_tickets (run_id);
+
+INSERT INTO schema_migrations (version, phase) VALUES (16, 'expand');
@@ -0,0 +1,9 @@
+-- Free-form data for clients, a type to tell kinds of nodes apart and where
+-- a UI last placed the node ({x, y})
+ALTER TABLE nodes ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE nodes ADD COLUMN node_type TEXT;
+ALTER TABLE nodes ADD COLUMN position JSONB;
+
+CREATE INDEX nodes_node_type_idx ON nodes (node_type);
+
+INSERT INTO schema_migrations (version, phase) VALUES (17, 'expand');
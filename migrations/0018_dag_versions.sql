@@ -0,0 +1,41 @@
+-- Saved revisions of a DAG. A version copies the DAG's nodes and edges as
+-- they were, ids included, and never changes afterwards.
+CREATE TABLE dag_versions (
+                              dag_id UUID NOT NULL REFERENCES dags(id),
+                              version INTEGER NOT NULL,
+                              message TEXT,
+                              node_count INTEGER NOT NULL,
+                              edge_count INTEGER NOT NULL,
+                              created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                              PRIMARY KEY (dag_id, version)
+);
+
+CREATE TABLE dag_version_nodes (
+                                   dag_id UUID NOT NULL,
+                                   version INTEGER NOT NULL,
+                                   id UUID NOT NULL,
+                                   label TEXT NOT NULL,
+                                   slug TEXT,
+                                   external_ids JSONB NOT NULL,
+                                   provenance JSONB,
+                                   owner TEXT,
+                                   source_url TEXT,
+                                   task JSONB,
+                                   metadata JSONB NOT NULL,
+                                   node_type TEXT,
+                                   position JSONB,
+                                   PRIMARY KEY (dag_id, version, id),
+                                   FOREIGN KEY (dag_id, version) REFERENCES dag_versions(dag_id, version) ON DELETE CASCADE
+);
+
+CREATE TABLE dag_version_edges (
+                                   dag_id UUID NOT NULL,
+                                   version INTEGER NOT NULL,
+                                   id UUID NOT NULL,
+                                   source UUID NOT NULL,
+                                   target UUID NOT NULL,
+                                   PRIMARY KEY (dag_id, version, id),
+                                   FOREIGN KEY (dag_id, version) REFERENCES dag_versions(dag_id, version) ON DELETE CASCADE
+);
+
+INSERT INTO schema_migrations (version, phase) VALUES (18, 'expand');
@@ -0,0 +1,11 @@
+-- Who created a node or edge and why, as the caller reported it
+ALTER TABLE nodes ADD COLUMN created_by TEXT;
+ALTER TABLE nodes ADD COLUMN reason TEXT;
+ALTER TABLE edges ADD COLUMN created_by TEXT;
+ALTER TABLE edges ADD COLUMN reason TEXT;
+ALTER TABLE dag_version_nodes ADD COLUMN created_by TEXT;
+ALTER TABLE dag_version_nodes ADD COLUMN reason TEXT;
+ALTER TABLE dag_version_edges ADD COLUMN created_by TEXT;
+ALTER TABLE dag_version_edges ADD COLUMN reason TEXT;
+
+INSERT INTO schema_migrations (version, phase) VALUES (19, 'expand');
@@ -0,0 +1,9 @@
+-- The controlled vocabulary for node labels: once any prefix is listed, new
+-- and changed labels must start with one of them
+CREATE TABLE label_prefixes (
+                                prefix TEXT PRIMARY KEY CHECK (prefix <> ''),
+                                description TEXT,
+                                created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO schema_migrations (version, phase) VALUES (20, 'expand');
@@ -0,0 +1,8 @@
+-- Draft nodes are staged edits that runs leave out until they are made
+-- active; edges into deprecated nodes are answered with a warning
+ALTER TABLE nodes
+    ADD COLUMN state TEXT NOT NULL DEFAULT 'active'
+        CHECK (state IN ('draft', 'active', 'deprecated'));
+ALTER TABLE dag_version_nodes ADD COLUMN state TEXT NOT NULL DEFAULT 'active';
+
+INSERT INTO schema_migrations (version, phase) VALUES (21, 'expand');
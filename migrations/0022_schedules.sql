@@ -0,0 +1,22 @@
+-- Cron schedules that start runs of a DAG. next_run_at is the next due
+-- time; the scheduler claims due rows with FOR UPDATE SKIP LOCKED, so
+-- several instances can share the table.
+CREATE TABLE schedules (
+                           id UUID PRIMARY KEY,
+                           dag_id UUID NOT NULL REFERENCES dags(id),
+                           cron TEXT NOT NULL,
+                           catch_up TEXT NOT NULL DEFAULT 'skip'
+                               CHECK (catch_up IN ('skip', 'latest', 'all')),
+                           paused BOOLEAN NOT NULL DEFAULT false,
+                           next_run_at TIMESTAMPTZ NOT NULL,
+                           last_run_id UUID,
+                           last_run_at TIMESTAMPTZ,
+                           created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX schedules_due ON schedules (next_run_at) WHERE NOT paused;
+CREATE INDEX schedules_dag_id ON schedules (dag_id);
+
+ALTER TABLE runs ADD COLUMN schedule_id UUID REFERENCES schedules(id) ON DELETE SET NULL;
+ALTER TABLE runs ADD COLUMN scheduled_for TIMESTAMPTZ;
+
+INSERT INTO schema_migrations (version, phase) VALUES (22, 'expand');
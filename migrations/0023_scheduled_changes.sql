@@ -0,0 +1,22 @@
+-- Structural changes to a DAG that take effect at effective_at. operations
+-- holds the nodes and edges to add and remove, with the ids of the new ones
+-- assigned on submission; the applier claims due rows with FOR UPDATE SKIP
+-- LOCKED and records applied or failed (with the error) once it has tried.
+CREATE TABLE scheduled_changes (
+                                   id UUID PRIMARY KEY,
+                                   dag_id UUID NOT NULL REFERENCES dags(id),
+                                   effective_at TIMESTAMPTZ NOT NULL,
+                                   status TEXT NOT NULL DEFAULT 'pending'
+                                       CHECK (status IN ('pending', 'applied', 'failed')),
+                                   operations JSONB NOT NULL,
+                                   error JSONB,
+                                   language TEXT NOT NULL DEFAULT 'en',
+                                   created_by TEXT,
+                                   reason TEXT,
+                                   created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                                   applied_at TIMESTAMPTZ
+);
+CREATE INDEX scheduled_changes_due ON scheduled_changes (effective_at) WHERE status = 'pending';
+CREATE INDEX scheduled_changes_dag_id ON scheduled_changes (dag_id);
+
+INSERT INTO schema_migrations (version, phase) VALUES (23, 'expand');
@@ -0,0 +1,18 @@
+-- API keys. Only a hash of each key is kept; the key itself is shown once,
+-- when it is issued. DAGs and imports record the key that created them,
+-- which is the only key besides admins that may see them. Rows from before
+-- keys existed have no owner and are left to admins.
+CREATE TABLE api_keys (
+                          id UUID PRIMARY KEY,
+                          name TEXT NOT NULL CHECK (name <> ''),
+                          key_hash TEXT NOT NULL UNIQUE,
+                          role TEXT NOT NULL CHECK (role IN ('read', 'write', 'admin')),
+                          created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                          revoked_at TIMESTAMPTZ
+);
+
+ALTER TABLE dags ADD COLUMN owner_key_id UUID REFERENCES api_keys(id);
+CREATE INDEX dags_owner_key_id ON dags (owner_key_id);
+ALTER TABLE imports ADD COLUMN owner_key_id UUID REFERENCES api_keys(id);
+
+INSERT INTO schema_migrations (version, phase) VALUES (24, 'expand');
@@ -0,0 +1,42 @@
+-- Environments DAGs are promoted through, in order of position. refs holds
+-- the names the environment knows its datasets, connections and the like
+-- by; promotion rewrites one environment's names to the next one's. A DAG
+-- promoted into an environment points back at the DAG it came from, and
+-- each promotion is kept, with who asked for it, who approved it and what
+-- was rewritten.
+CREATE TABLE environments (
+                              name TEXT PRIMARY KEY CHECK (name <> ''),
+                              position INTEGER NOT NULL UNIQUE,
+                              requires_approval BOOLEAN NOT NULL DEFAULT false,
+                              refs JSONB NOT NULL DEFAULT '{}',
+                              created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+INSERT INTO environments (name, position, requires_approval) VALUES
+    ('dev', 1, false),
+    ('staging', 2, false),
+    ('prod', 3, true);
+
+ALTER TABLE dags ADD COLUMN environment TEXT REFERENCES environments(name);
+ALTER TABLE dags ADD COLUMN promoted_from UUID REFERENCES dags(id);
+CREATE UNIQUE INDEX dags_promoted_from ON dags (promoted_from, environment);
+
+CREATE TABLE promotions (
+                            id UUID PRIMARY KEY,
+                            dag_id UUID NOT NULL REFERENCES dags(id),
+                            version INTEGER NOT NULL,
+                            from_environment TEXT NOT NULL REFERENCES environments(name),
+                            to_environment TEXT NOT NULL REFERENCES environments(name),
+                            status TEXT NOT NULL DEFAULT 'pending'
+                                CHECK (status IN ('pending', 'applied', 'rejected')),
+                            target_dag_id UUID REFERENCES dags(id),
+                            rewrites JSONB NOT NULL DEFAULT '[]',
+                            requested_by TEXT,
+                            reason TEXT,
+                            decided_by TEXT,
+                            decided_at TIMESTAMPTZ,
+                            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                            FOREIGN KEY (dag_id, version) REFERENCES dag_versions (dag_id, version)
+);
+CREATE INDEX promotions_dag_id ON promotions (dag_id);
+
+INSERT INTO schema_migrations (version, phase) VALUES (25, 'expand');
@@ -0,0 +1,21 @@
+-- Named connections to the systems tasks talk to. fields holds the settings
+-- that may be shown; secrets holds the others, each sealed with AES-256-GCM,
+-- by field name. A row with an environment overrides the one without for DAGs in
+-- that environment. task_runs keeps the names a node referenced when its run
+-- started; they are looked up when the task is dispatched.
+CREATE TABLE connections (
+                             id UUID PRIMARY KEY,
+                             name TEXT NOT NULL CHECK (name <> ''),
+                             kind TEXT NOT NULL CHECK (kind IN ('postgres', 'http', 's3')),
+                             environment TEXT REFERENCES environments(name),
+                             fields JSONB NOT NULL DEFAULT '{}',
+                             secrets JSONB NOT NULL DEFAULT '{}',
+                             created_by TEXT,
+                             created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                             updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX connections_name ON connections (name, COALESCE(environment, ''));
+
+ALTER TABLE task_runs ADD COLUMN connections TEXT[] NOT NULL DEFAULT '{}';
+
+INSERT INTO schema_migrations (version, phase) VALUES (26, 'expand');
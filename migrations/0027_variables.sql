@@ -0,0 +1,32 @@
+-- Key/value variables that node tasks use as {{ var.NAME }}. A row with an
+-- environment overrides the one without for DAGs in that environment. Secret
+-- values are sealed like connection secrets. variable_changes records every
+-- create, update and delete, with the values unless the variable is secret;
+-- it outlives the variables it describes.
+CREATE TABLE variables (
+                           id UUID PRIMARY KEY,
+                           name TEXT NOT NULL CHECK (name <> ''),
+                           environment TEXT REFERENCES environments(name),
+                           value TEXT NOT NULL,
+                           secret BOOLEAN NOT NULL DEFAULT false,
+                           created_by TEXT,
+                           created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                           updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX variables_name ON variables (name, COALESCE(environment, ''));
+
+CREATE TABLE variable_changes (
+                                  id UUID PRIMARY KEY,
+                                  variable_id UUID NOT NULL,
+                                  name TEXT NOT NULL,
+                                  environment TEXT,
+                                  action TEXT NOT NULL CHECK (action IN ('created', 'updated', 'deleted')),
+                                  old_value TEXT,
+                                  new_value TEXT,
+                                  changed_by TEXT,
+                                  reason TEXT,
+                                  changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX variable_changes_variable_id ON variable_changes (variable_id, changed_at);
+
+INSERT INTO schema_migrations (version, phase) VALUES (27, 'expand');
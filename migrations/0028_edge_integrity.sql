@@ -0,0 +1,15 @@
+-- Edges stay within their DAG: both ends must be nodes of the edge's dag_id,
+-- and an edge can't lead from a node to itself. The constraints are added
+-- NOT VALID so edges made before this (which could cross DAGs or loop) stay
+-- where they are; new and changed edges are checked. 0039 validates them
+-- once those are gone. Builds older than this may still write such edges,
+-- so it is a contract migration.
+ALTER TABLE nodes ADD CONSTRAINT nodes_id_dag_id_key UNIQUE (id, dag_id);
+
+ALTER TABLE edges ADD CONSTRAINT edges_source_in_dag
+    FOREIGN KEY (source, dag_id) REFERENCES nodes (id, dag_id) NOT VALID;
+ALTER TABLE edges ADD CONSTRAINT edges_target_in_dag
+    FOREIGN KEY (target, dag_id) REFERENCES nodes (id, dag_id) NOT VALID;
+ALTER TABLE edges ADD CONSTRAINT edges_not_self_loop CHECK (source <> target) NOT VALID;
+
+INSERT INTO schema_migrations (version, phase) VALUES (28, 'contract');
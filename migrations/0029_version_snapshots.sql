@@ -0,0 +1,6 @@
+-- Versions keep their nodes and edges as one compressed binary snapshot
+-- instead of a row apiece. Versions saved before this keep their rows in
+-- dag_version_nodes and dag_version_edges and are read from there.
+ALTER TABLE dag_versions ADD COLUMN snapshot BYTEA;
+
+INSERT INTO schema_migrations (version, phase) VALUES (29, 'expand');
@@ -0,0 +1,71 @@
+-- Append-only history of every change to DAGs, nodes and edges, written by
+-- triggers so no path that changes them can skip it. A created row keeps the
+-- whole new row in new, a deleted one the whole old row in old, and an
+-- update only the columns that changed, before in old and after in new.
+-- Who made the change comes from settings the service puts on the
+-- transaction (dag.actor, dag.key_id, dag.reason). Rows outlive what they
+-- describe. What already existed is recorded as a baseline, so replaying a
+-- DAG's events from its first one gives it as it was at any point since.
+CREATE TABLE events (
+                        id BIGSERIAL PRIMARY KEY,
+                        dag_id UUID NOT NULL,
+                        entity_type TEXT NOT NULL CHECK (entity_type IN ('dag', 'node', 'edge')),
+                        entity_id UUID NOT NULL,
+                        action TEXT NOT NULL CHECK (action IN ('baseline', 'created', 'updated', 'deleted')),
+                        actor TEXT,
+                        key_id UUID,
+                        reason TEXT,
+                        old JSONB,
+                        new JSONB,
+                        occurred_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX events_dag_id ON events (dag_id, occurred_at);
+CREATE INDEX events_entity_id ON events (entity_id, occurred_at);
+
+CREATE FUNCTION record_event() RETURNS trigger AS $$
+DECLARE
+    old_row JSONB := CASE WHEN TG_OP <> 'INSERT' THEN to_jsonb(OLD) END;
+    new_row JSONB := CASE WHEN TG_OP <> 'DELETE' THEN to_jsonb(NEW) END;
+    image JSONB := COALESCE(new_row, old_row);
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        SELECT jsonb_object_agg(n.key, o.value), jsonb_object_agg(n.key, n.value)
+          INTO old_row, new_row
+          FROM jsonb_each(new_row) n JOIN jsonb_each(old_row) o ON o.key = n.key
+         WHERE n.value IS DISTINCT FROM o.value;
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+    END IF;
+
+    INSERT INTO events (dag_id, entity_type, entity_id, action, actor, key_id, reason, old, new)
+    VALUES (
+        (CASE WHEN TG_ARGV[0] = 'dag' THEN image->>'id' ELSE image->>'dag_id' END)::uuid,
+        TG_ARGV[0],
+        (image->>'id')::uuid,
+        CASE TG_OP WHEN 'INSERT' THEN 'created' WHEN 'UPDATE' THEN 'updated' ELSE 'deleted' END,
+        NULLIF(current_setting('dag.actor', true), ''),
+        NULLIF(current_setting('dag.key_id', true), '')::uuid,
+        NULLIF(current_setting('dag.reason', true), ''),
+        old_row,
+        new_row
+    );
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER dags_events AFTER INSERT OR UPDATE OR DELETE ON dags
+    FOR EACH ROW EXECUTE FUNCTION record_event('dag');
+CREATE TRIGGER nodes_events AFTER INSERT OR UPDATE OR DELETE ON nodes
+    FOR EACH ROW EXECUTE FUNCTION record_event('node');
+CREATE TRIGGER edges_events AFTER INSERT OR UPDATE OR DELETE ON edges
+    FOR EACH ROW EXECUTE FUNCTION record_event('edge');
+
+INSERT INTO events (dag_id, entity_type, entity_id, action, new)
+SELECT id, 'dag', id, 'baseline', to_jsonb(d) FROM dags d;
+INSERT INTO events (dag_id, entity_type, entity_id, action, new)
+SELECT dag_id, 'node', id, 'baseline', to_jsonb(n) FROM nodes n;
+INSERT INTO events (dag_id, entity_type, entity_id, action, new)
+SELECT dag_id, 'edge', id, 'baseline', to_jsonb(e) FROM edges e;
+
+INSERT INTO schema_migrations (version, phase) VALUES (30, 'expand');
@@ -0,0 +1,5 @@
+-- Set to stop a running validation; the validation job checks it between
+-- batches and marks the import invalid
+ALTER TABLE imports ADD COLUMN abort_requested BOOLEAN NOT NULL DEFAULT false;
+
+INSERT INTO schema_migrations (version, phase) VALUES (31, 'expand');
@@ -0,0 +1,11 @@
+-- What an import into a DAG does with nodes whose label the DAG already has.
+-- Imports without a policy replace the DAG's contents as before; with one
+-- they add to it. Staged nodes may carry metadata to merge into the nodes
+-- they match, and promoted_label keeps the label a node was created with
+-- when it differs from the staged one, so rollback can tell it apart.
+ALTER TABLE imports ADD COLUMN on_duplicate TEXT
+    CHECK (on_duplicate IN ('error', 'skip', 'merge_metadata', 'suffix'));
+ALTER TABLE import_nodes ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE import_nodes ADD COLUMN promoted_label TEXT;
+
+INSERT INTO schema_migrations (version, phase) VALUES (32, 'expand');
@@ -0,0 +1,27 @@
+-- A version on every DAG, node and edge for optimistic concurrency. Versions
+-- come from one sequence, so a row deleted and created again with the same
+-- id never gets a version it had before. A trigger moves the version on with
+-- every update that changes something, whichever statement makes it.
+CREATE SEQUENCE entity_versions;
+
+ALTER TABLE dags ADD COLUMN version BIGINT NOT NULL DEFAULT nextval('entity_versions');
+ALTER TABLE nodes ADD COLUMN version BIGINT NOT NULL DEFAULT nextval('entity_versions');
+ALTER TABLE edges ADD COLUMN version BIGINT NOT NULL DEFAULT nextval('entity_versions');
+
+CREATE FUNCTION bump_version() RETURNS trigger AS $$
+BEGIN
+    IF NEW IS DISTINCT FROM OLD THEN
+        NEW.version := nextval('entity_versions');
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER dags_version BEFORE UPDATE ON dags
+    FOR EACH ROW EXECUTE FUNCTION bump_version();
+CREATE TRIGGER nodes_version BEFORE UPDATE ON nodes
+    FOR EACH ROW EXECUTE FUNCTION bump_version();
+CREATE TRIGGER edges_version BEFORE UPDATE ON edges
+    FOR EACH ROW EXECUTE FUNCTION bump_version();
+
+INSERT INTO schema_migrations (version, phase) VALUES (33, 'expand');
@@ -0,0 +1,5 @@
+-- Why a schedule last declined to start a run: the due times it let pass and
+-- what was wrong with the DAG's structure. Cleared once it starts one again.
+ALTER TABLE schedules ADD COLUMN last_refusal JSONB;
+
+INSERT INTO schema_migrations (version, phase) VALUES (34, 'expand');
@@ -0,0 +1,6 @@
+-- When the watchdog last found the run without a state change for too long
+-- and set its status to stalled. Kept after the run moves on, as a record.
+ALTER TABLE runs ADD COLUMN stalled_at TIMESTAMPTZ;
+CREATE INDEX runs_unfinished_idx ON runs (status, created_at) WHERE status IN ('queued', 'running', 'stalled');
+
+INSERT INTO schema_migrations (version, phase) VALUES (35, 'expand');
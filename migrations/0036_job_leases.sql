@@ -0,0 +1,11 @@
+-- A worker holds a running job on a lease until lease_expires_at, renewing
+-- it while it works; a job whose lease ran out is claimed again. Every claim
+-- takes a new fence from lease_fences, and writes a worker makes for its job
+-- only apply while the job still carries that fence, so a worker whose lease
+-- was given to another can no longer change the job's run.
+CREATE SEQUENCE lease_fences;
+ALTER TABLE jobs ADD COLUMN fence BIGINT, ADD COLUMN lease_expires_at TIMESTAMPTZ;
+CREATE UNIQUE INDEX jobs_fence_idx ON jobs (fence);
+CREATE INDEX jobs_leased_idx ON jobs (lease_expires_at) WHERE status = 'running';
+
+INSERT INTO schema_migrations (version, phase) VALUES (36, 'expand');
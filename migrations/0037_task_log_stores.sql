@@ -0,0 +1,9 @@
+-- Which log store holds a task's log (NULL while it has none), and the fence
+-- of the job lease it was written under, which is part of its key in stores
+-- outside the database. Logs kept in the database stay in output.
+ALTER TABLE task_runs ADD COLUMN log_store TEXT CHECK (log_store IN ('postgres', 'filesystem', 's3', 'loki')),
+                      ADD COLUMN log_fence BIGINT;
+UPDATE task_runs SET log_store = 'postgres' WHERE output IS NOT NULL;
+CREATE INDEX task_runs_log_store_idx ON task_runs (log_store) WHERE log_store IS NOT NULL;
+
+INSERT INTO schema_migrations (version, phase) VALUES (37, 'expand');
@@ -0,0 +1,22 @@
+-- What the nodes of a run produced for the nodes after them: JSON values,
+-- or blobs with their content type. A node writes its own while it runs,
+-- proving who it is with the token it was started with, whose hash is kept
+-- on its task run; a node that starts over loses what it wrote before.
+CREATE TABLE run_artifacts (
+                               run_id UUID NOT NULL,
+                               node_id UUID NOT NULL,
+                               key TEXT NOT NULL,
+                               kind TEXT NOT NULL CHECK (kind IN ('value', 'blob')),
+                               value JSONB,
+                               content BYTEA,
+                               content_type TEXT,
+                               size BIGINT NOT NULL,
+                               created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                               PRIMARY KEY (run_id, node_id, key),
+                               FOREIGN KEY (run_id, node_id) REFERENCES task_runs (run_id, node_id) ON DELETE CASCADE,
+                               CHECK ((kind = 'value') = (value IS NOT NULL) AND (kind = 'blob') = (content IS NOT NULL))
+);
+
+ALTER TABLE task_runs ADD COLUMN outputs_token_hash TEXT;
+
+INSERT INTO schema_migrations (version, phase) VALUES (38, 'expand');
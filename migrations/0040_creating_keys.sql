@@ -0,0 +1,25 @@
+-- The API key each node and edge was created with, next to the created_by
+-- its caller reported in X-Actor. It comes from the setting the service puts
+-- on the transaction (dag.key_id, see 0030) rather than from the statement,
+-- and updates keep it, so callers can't choose it. ADMIN_API_KEY itself, and
+-- authentication being off, leave it NULL.
+ALTER TABLE nodes ADD COLUMN created_by_key_id UUID REFERENCES api_keys(id);
+ALTER TABLE edges ADD COLUMN created_by_key_id UUID REFERENCES api_keys(id);
+
+CREATE FUNCTION record_creating_key() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        NEW.created_by_key_id := NULLIF(current_setting('dag.key_id', true), '')::uuid;
+    ELSE
+        NEW.created_by_key_id := OLD.created_by_key_id;
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER nodes_creating_key BEFORE INSERT OR UPDATE ON nodes
+    FOR EACH ROW EXECUTE FUNCTION record_creating_key();
+CREATE TRIGGER edges_creating_key BEFORE INSERT OR UPDATE ON edges
+    FOR EACH ROW EXECUTE FUNCTION record_creating_key();
+
+INSERT INTO schema_migrations (version, phase) VALUES (40, 'expand');
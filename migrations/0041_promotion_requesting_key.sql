@@ -0,0 +1,5 @@
+-- The API key a promotion was requested with, so it can't be decided with
+-- the same key whatever X-Actor says
+ALTER TABLE promotions ADD COLUMN requested_by_key_id UUID REFERENCES api_keys(id);
+
+INSERT INTO schema_migrations (version, phase) VALUES (41, 'expand');
@@ -0,0 +1,7 @@
+-- The API keys whose DAGs may use a connection. DAGs without an owner, made
+-- with ADMIN_API_KEY or with authentication off, may use any; others only
+-- those that list their owner, so one key's tasks can't be handed another's
+-- credentials.
+ALTER TABLE connections ADD COLUMN owners UUID[] NOT NULL DEFAULT '{}';
+
+INSERT INTO schema_migrations (version, phase) VALUES (42, 'expand');
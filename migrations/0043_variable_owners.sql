@@ -0,0 +1,5 @@
+-- The API keys whose DAGs may use a secret variable, as connections have
+-- (see 0042). Other variables serve every DAG.
+ALTER TABLE variables ADD COLUMN owners UUID[] NOT NULL DEFAULT '{}';
+
+INSERT INTO schema_migrations (version, phase) VALUES (43, 'expand');
@@ -0,0 +1,550 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::graph::{self, Graph};
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::usage::{self, Access};
+use crate::{loaded_dag, Edge, Node};
+
+#[derive(Deserialize)]
+pub struct PathParams {
+    from: Uuid,
+    to: Uuid,
+    weight: Option<String>,
+}
+
+// Loads a DAG's nodes and edges and counts the read
+async fn load_graph(pool: &PgPool, locale: &Locale, dag_id: Uuid) -> Result<(Vec<Node>, Vec<Edge>), AppError> {
+    let (_, nodes, edges) = loaded_dag(pool, locale, dag_id, "fetch_dag_failed").await?;
+    usage::record(pool, dag_id, Access::Read);
+    Ok((nodes, edges))
+}
+
+fn cycle_detected(locale: &Locale, dag_id: Uuid) -> AppError {
+    locale.error(StatusCode::UNPROCESSABLE_ENTITY, "dag_has_cycle", &[("id", &dag_id)])
+}
+
+fn node_not_found(locale: &Locale, node_id: Uuid, dag_id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "node_not_found_in_dag", &[("node", &node_id), ("dag", &dag_id)])
+}
+
+async fn best_path(pool: PgPool, locale: Locale, dag_id: Uuid, params: PathParams, longest: bool) -> Result<Response, AppError> {
+    // Only hop counts are available until nodes/edges carry weights of their own
+    match params.weight.as_deref() {
+        None | Some("hops") => {}
+        Some(other) => {
+            return Err(locale.error(StatusCode::BAD_REQUEST, "unsupported_weight", &[("weight", &other)]))
+        }
+    }
+
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let graph = Graph::new(&nodes, &edges);
+    let Some(from) = graph.position(&params.from) else {
+        return Err(node_not_found(&locale, params.from, dag_id));
+    };
+    let Some(to) = graph.position(&params.to) else {
+        return Err(node_not_found(&locale, params.to, dag_id));
+    };
+
+    let (order, blocked) = graph.topological_order();
+    if !blocked.is_empty() {
+        return Err(cycle_detected(&locale, dag_id));
+    }
+
+    match graph.best_path(&order, from, to, longest, |_, _| 1.0) {
+        Some((path, cost)) => Ok(Json(serde_json::json!({
+            "from": params.from,
+            "to": params.to,
+            "path": path.iter().map(|&n| graph.ids[n]).collect::<Vec<_>>(),
+            "cost": cost,
+        }))
+        .into_response()),
+        None => Err(locale.error(StatusCode::NOT_FOUND, "no_path", &[("from", &params.from), ("to", &params.to)])),
+    }
+}
+
+pub async fn shortest_path(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<PathParams>,
+) -> Result<Response, AppError> {
+    best_path(pool, locale, dag_id, params, false).await
+}
+
+pub async fn longest_path(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<PathParams>,
+) -> Result<Response, AppError> {
+    best_path(pool, locale, dag_id, params, true).await
+}
+
+#[derive(Deserialize)]
+pub struct TraversalParams {
+    // Steps out from the node; unlimited when left out
+    depth: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Reached<'a> {
+    #[serde(flatten)]
+    node: &'a Node,
+    distance: usize,
+}
+
+// The nodes of a node's DAG upstream (backwards) or downstream of it,
+// nearest first
+async fn traverse(pool: PgPool, locale: Locale, node_id: Uuid, params: TraversalParams, backwards: bool) -> Result<Response, AppError> {
+    let dag_id: Uuid = sqlx::query_scalar(&format!("SELECT dag_id FROM nodes WHERE id = $1 AND {}", auth::visible("dag_id", 2)))
+        .bind(node_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "node_not_found", &[("id", &node_id)]))?;
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let graph = Graph::new(&nodes, &edges);
+    let Some(start) = graph.position(&node_id) else {
+        return Err(node_not_found(&locale, node_id, dag_id));
+    };
+    let mut reached: Vec<Reached> = graph
+        .reach(start, backwards, params.depth)
+        .into_iter()
+        .zip(&nodes)
+        .filter_map(|(reached, node)| Some(Reached { node, distance: reached?.0 }))
+        .filter(|r| r.distance > 0)
+        .collect();
+    reached.sort_by(|a, b| a.distance.cmp(&b.distance).then_with(|| a.node.label.cmp(&b.node.label)));
+
+    Ok(Json(serde_json::json!({
+        "node_id": node_id,
+        "dag_id": dag_id,
+        "depth": params.depth,
+        "count": reached.len(),
+        "nodes": reached,
+    }))
+    .into_response())
+}
+
+pub async fn ancestors(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(node_id): Path<Uuid>,
+    Query(params): Query<TraversalParams>,
+) -> Result<Response, AppError> {
+    traverse(pool, locale, node_id, params, true).await
+}
+
+pub async fn descendants(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(node_id): Path<Uuid>,
+    Query(params): Query<TraversalParams>,
+) -> Result<Response, AppError> {
+    traverse(pool, locale, node_id, params, false).await
+}
+
+#[derive(Deserialize)]
+pub struct ReachabilityParams {
+    from: Uuid,
+    to: Uuid,
+    depth: Option<usize>,
+}
+
+// Whether `to` is downstream of `from`, upstream of it or neither, with the
+// fewest-hops path between them in edge order. Unlike the shortest-path
+// endpoint this works on graphs with cycles.
+pub async fn reachability(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<ReachabilityParams>,
+) -> Result<Response, AppError> {
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let graph = Graph::new(&nodes, &edges);
+    let Some(from) = graph.position(&params.from) else {
+        return Err(node_not_found(&locale, params.from, dag_id));
+    };
+    let Some(to) = graph.position(&params.to) else {
+        return Err(node_not_found(&locale, params.to, dag_id));
+    };
+
+    // Walking back from `to` towards `from` visits the path downstream of
+    // `from` in edge order; walking forward from `to` gives it for upstream
+    let found = [("downstream", true), ("upstream", false)].into_iter().find_map(|(direction, backwards)| {
+        let reached = graph.reach(to, backwards, params.depth);
+        reached[from]?;
+        let mut path = vec![from];
+        while let Some(&n) = path.last().filter(|&&n| n != to) {
+            path.push(reached[n]?.1);
+        }
+        if !backwards {
+            path.reverse();
+        }
+        Some((direction, path))
+    });
+
+    let (direction, path) = match found {
+        Some((direction, path)) => (Some(direction), path.iter().map(|&n| graph.ids[n]).collect()),
+        None => (None, Vec::new()),
+    };
+    Ok(Json(serde_json::json!({
+        "from": params.from,
+        "to": params.to,
+        "connected": direction.is_some(),
+        "direction": direction,
+        "hops": path.len().saturating_sub(1),
+        "path": path,
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FlowPayload {
+    source: Uuid,
+    sink: Uuid,
+    capacity: Option<String>,
+}
+
+pub async fn max_flow(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<FlowPayload>,
+) -> Result<Response, AppError> {
+    // Every edge carries one unit until edges can store capacities of their own
+    match payload.capacity.as_deref() {
+        None | Some("unit") => {}
+        Some(other) => {
+            return Err(locale.error(StatusCode::BAD_REQUEST, "unsupported_capacity", &[("capacity", &other)]))
+        }
+    }
+    if payload.source == payload.sink {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "source_equals_sink", &[]));
+    }
+
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let graph = Graph::new(&nodes, &edges);
+    let Some(source) = graph.position(&payload.source) else {
+        return Err(node_not_found(&locale, payload.source, dag_id));
+    };
+    let Some(sink) = graph.position(&payload.sink) else {
+        return Err(node_not_found(&locale, payload.sink, dag_id));
+    };
+
+    let (edge_ids, arcs): (Vec<Uuid>, Vec<(usize, usize, f64)>) = edges
+        .iter()
+        .filter_map(|e| Some((e.id, (graph.position(&e.source)?, graph.position(&e.target)?, 1.0))))
+        .unzip();
+    let (flow, cut) = graph::max_flow(graph.node_count(), &arcs, source, sink);
+
+    Ok(Json(serde_json::json!({
+        "source": payload.source,
+        "sink": payload.sink,
+        "max_flow": flow,
+        "min_cut": cut.iter().map(|&i| edge_ids[i]).collect::<Vec<_>>(),
+    }))
+    .into_response())
+}
+
+pub async fn levels(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let graph = Graph::new(&nodes, &edges);
+    if !graph.topological_order().1.is_empty() {
+        return Err(cycle_detected(&locale, dag_id));
+    }
+
+    let layers = graph.layers();
+    let levels: Vec<_> = layers
+        .iter()
+        .enumerate()
+        .map(|(level, members)| {
+            serde_json::json!({
+                "level": level,
+                "count": members.len(),
+                "nodes": members.iter().map(|&n| graph.ids[n]).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "level_count": layers.len(),
+        "max_parallelism": layers.iter().map(|l| l.len()).max().unwrap_or(0),
+        "levels": levels,
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct SimulatePayload {
+    workers: usize,
+    #[serde(default)]
+    durations: HashMap<Uuid, f64>,
+    default_duration: Option<f64>,
+}
+
+pub async fn simulate(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<SimulatePayload>,
+) -> Result<Response, AppError> {
+    if payload.workers == 0 {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "workers_required", &[]));
+    }
+
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let graph = Graph::new(&nodes, &edges);
+    let (order, blocked) = graph.topological_order();
+    if !blocked.is_empty() {
+        return Err(cycle_detected(&locale, dag_id));
+    }
+
+    if let Some(unknown) = payload.durations.keys().find(|id| graph.position(id).is_none()) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "node_not_found_in_dag", &[("node", unknown), ("dag", &dag_id)]));
+    }
+    let mut durations = Vec::with_capacity(graph.node_count());
+    for id in &graph.ids {
+        match payload.durations.get(id).copied().or(payload.default_duration) {
+            Some(d) if d >= 0.0 => durations.push(d),
+            Some(_) => return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "negative_duration", &[("node", id)])),
+            None => return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "missing_duration", &[("node", id)])),
+        }
+    }
+
+    let schedule = graph.simulate(&order, &durations, payload.workers);
+    let makespan = schedule.iter().map(|&(_, end, _)| end).fold(0.0, f64::max);
+    let mut entries: Vec<_> = schedule
+        .iter()
+        .enumerate()
+        .map(|(n, &(start, end, worker))| (start, n, end, worker))
+        .collect();
+    entries.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.3.cmp(&b.3)));
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "workers": payload.workers,
+        "makespan": makespan,
+        "schedule": entries
+            .iter()
+            .map(|&(start, n, end, worker)| serde_json::json!({
+                "node_id": graph.ids[n],
+                "worker": worker,
+                "start": start,
+                "end": end,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ImpactPayload {
+    nodes: Vec<Uuid>,
+}
+
+pub async fn impact(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<ImpactPayload>,
+) -> Result<Response, AppError> {
+    let members = sqlx::query_as::<_, Node>(
+        "SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes WHERE dag_id = $1 AND id = ANY($2)",
+    )
+    .bind(dag_id)
+    .bind(&payload.nodes)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?;
+    if let Some(missing) = payload.nodes.iter().find(|id| !members.iter().any(|n| n.id == **id)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "node_not_found_in_dag", &[("node", missing), ("dag", &dag_id)]));
+    }
+
+    // Follows edges regardless of which DAG they are recorded in, so
+    // dependencies reaching into other DAGs show up as well
+    let affected = sqlx::query_as::<_, Node>(
+        "WITH RECURSIVE downstream(id) AS (
+             SELECT target FROM edges WHERE source = ANY($1)
+             UNION
+             SELECT e.target FROM edges e JOIN downstream d ON e.source = d.id
+         )
+         SELECT n.id, n.dag_id, n.label, n.slug, n.external_ids, n.provenance, n.owner, n.source_url, n.task, n.metadata, n.node_type, n.position, n.created_by, n.reason, n.state FROM nodes n
+         JOIN downstream d ON n.id = d.id
+         WHERE n.id <> ALL($1)
+         ORDER BY n.dag_id, n.label",
+    )
+    .bind(&payload.nodes)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| AppError::database(e, &locale, "impact_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+
+    let mut per_dag: Vec<(Uuid, usize)> = Vec::new();
+    for node in &affected {
+        match per_dag.last_mut() {
+            Some((id, count)) if *id == node.dag_id => *count += 1,
+            _ => per_dag.push((node.dag_id, 1)),
+        }
+    }
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "removed": payload.nodes,
+        "affected_nodes": affected,
+        "affected_dags": per_dag
+            .iter()
+            .map(|(id, count)| serde_json::json!({ "dag_id": id, "node_count": count }))
+            .collect::<Vec<_>>(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct PartitionPayload {
+    k: usize,
+}
+
+pub async fn partition(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<PartitionPayload>,
+) -> Result<Response, AppError> {
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+    if payload.k == 0 || payload.k > nodes.len() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_partition_count", &[("count", &nodes.len())]));
+    }
+
+    let graph = Graph::new(&nodes, &edges);
+    let part = graph.partition(payload.k);
+    let cut_edges: Vec<Uuid> = edges
+        .iter()
+        .filter(|e| match (graph.position(&e.source), graph.position(&e.target)) {
+            (Some(s), Some(t)) => part[s] != part[t],
+            _ => false,
+        })
+        .map(|e| e.id)
+        .collect();
+
+    let partitions: Vec<_> = (0..payload.k)
+        .map(|p| {
+            let members: Vec<Uuid> = (0..graph.node_count())
+                .filter(|&n| part[n] == p)
+                .map(|n| graph.ids[n])
+                .collect();
+            serde_json::json!({ "index": p, "size": members.len(), "nodes": members })
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "k": payload.k,
+        "partitions": partitions,
+        "cut_edge_count": cut_edges.len(),
+        "cut_edges": cut_edges,
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct AnalyticsParams {
+    // A numeric metadata field giving each node's weight or duration for the
+    // critical path; without it every node weighs 1
+    weight: Option<String>,
+    // For nodes that don't have the weight field
+    #[serde(default)]
+    default_weight: f64,
+}
+
+// Structural properties of a DAG in one read: counts, roots and leaves,
+// depth, the critical path and how many separate pieces it falls into.
+// Depth and the critical path are null while the graph has a cycle.
+pub async fn analytics(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<AnalyticsParams>,
+) -> Result<Response, AppError> {
+    if params.default_weight < 0.0 {
+        return Err(locale.error(StatusCode::BAD_REQUEST, "negative_default_weight", &[]));
+    }
+    let (nodes, edges) = load_graph(&pool, &locale, dag_id).await?;
+
+    let mut weights = Vec::with_capacity(nodes.len());
+    for node in &nodes {
+        let weight = match &params.weight {
+            None => 1.0,
+            Some(field) => match node.metadata.get(field) {
+                None | Some(serde_json::Value::Null) => params.default_weight,
+                Some(value) => match value.as_f64() {
+                    Some(w) if w >= 0.0 => w,
+                    _ => {
+                        return Err(locale.error(
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            "invalid_node_weight",
+                            &[("node", &node.id), ("field", field)],
+                        ))
+                    }
+                },
+            },
+        };
+        weights.push(weight);
+    }
+
+    let graph = Graph::new(&nodes, &edges);
+    let (order, blocked) = graph.topological_order();
+    let acyclic = blocked.is_empty();
+    let roots: Vec<Uuid> = (0..graph.node_count()).filter(|&n| graph.incoming[n].is_empty()).map(|n| graph.ids[n]).collect();
+    let leaves: Vec<Uuid> = (0..graph.node_count()).filter(|&n| graph.outgoing[n].is_empty()).map(|n| graph.ids[n]).collect();
+    let (max_depth, critical_path) = if acyclic {
+        let (path, length) = graph.critical_path(&order, &weights);
+        let depth = graph.layers().len().saturating_sub(1);
+        let path = serde_json::json!({
+            "nodes": path.iter().map(|&n| graph.ids[n]).collect::<Vec<_>>(),
+            "length": length,
+            "weight": params.weight,
+        });
+        (Some(depth), Some(path))
+    } else {
+        (None, None)
+    };
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "node_count": graph.node_count(),
+        "edge_count": graph.outgoing.iter().map(Vec::len).sum::<usize>(),
+        "acyclic": acyclic,
+        "root_count": roots.len(),
+        "roots": roots,
+        "leaf_count": leaves.len(),
+        "leaves": leaves,
+        "max_depth": max_depth,
+        "critical_path": critical_path,
+        "component_count": graph.component_count(),
+    }))
+    .into_response())
+}
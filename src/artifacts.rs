@@ -0,0 +1,391 @@
+// What nodes of a run hand on to the nodes after them. A running task writes
+// outputs, JSON values or blobs, to POST /runs/:id/nodes/:node_id/outputs with
+// the token it was started with (DAG_OUTPUTS_URL and DAG_OUTPUTS_TOKEN for
+// shell tasks, outputs_url and outputs_token in an http task's default body).
+// Tasks downstream of it refer to them as {{ outputs.LABEL.KEY }}, filled in
+// when they start like variables are; a blob comes in as the URL to fetch it
+// from, with the same token. Outputs stay with the run to be looked at afterwards.
+use axum::{
+    body::Bytes,
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json as JsonColumn, FromRow, PgPool};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::connections;
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::maintenance::Writable;
+
+pub const TOKEN_HEADER: &str = "x-task-token";
+
+const ARTIFACT_COLUMNS: &str = "a.node_id, t.label, a.key, a.kind, a.value, a.content_type, a.size, a.created_at";
+
+static PUBLIC_URL: OnceLock<String> = OnceLock::new();
+
+// Takes PUBLIC_URL from the config at startup
+pub fn configure(public_url: &str) {
+    PUBLIC_URL.get_or_init(|| public_url.to_string());
+}
+
+pub fn outputs_url(run_id: Uuid, node_id: Uuid) -> String {
+    format!("{}/runs/{}/nodes/{}/outputs", PUBLIC_URL.get().map_or("", String::as_str), run_id, node_id)
+}
+
+// Whether a task's token lets it make this request without an API key: a
+// POST writing the outputs of the node it was started for, or a GET of
+// outputs in its run while it is running, such as the blobs it is handed
+pub async fn allowed_with_token(pool: &PgPool, method: &Method, path: &str, token: &str) -> Result<bool, sqlx::Error> {
+    let segments = path.split('/').collect::<Vec<_>>();
+    let (run_id, node_id) = match (method, &segments[..]) {
+        (&Method::POST, ["", "runs", run_id, "nodes", node_id, "outputs"]) => (*run_id, Some(*node_id)),
+        (&Method::GET, ["", "runs", run_id, "outputs"] | ["", "runs", run_id, "nodes", _, "outputs", ..]) => (*run_id, None),
+        _ => return Ok(false),
+    };
+    let Ok(run_id) = run_id.parse::<Uuid>() else {
+        return Ok(false);
+    };
+    let node_id = match node_id.map(str::parse::<Uuid>) {
+        Some(Ok(node_id)) => Some(node_id),
+        Some(Err(_)) => return Ok(false),
+        None => None,
+    };
+    sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM task_runs WHERE run_id = $1 AND outputs_token_hash = $3
+             AND CASE WHEN $2::uuid IS NULL THEN state = 'running' ELSE node_id = $2 END)",
+    )
+        .bind(run_id)
+        .bind(node_id)
+        .bind(digest(token))
+        .fetch_one(pool)
+        .await
+}
+
+// A token for a task starting now, and the hash to keep of it
+pub fn new_token() -> Result<(String, String), String> {
+    let mut bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut bytes).map_err(|e| e.to_string())?;
+    let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let hash = digest(&token);
+    Ok((token, hash))
+}
+
+fn digest(token: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum Kind {
+    Value,
+    Blob,
+}
+
+#[derive(Serialize, FromRow)]
+struct Artifact {
+    node_id: Uuid,
+    label: String,
+    key: String,
+    kind: Kind,
+    // Blobs are fetched on their own from GET .../outputs/:key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<JsonColumn<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    size: i64,
+    created_at: DateTime<Utc>,
+}
+
+// An output as written; blobs have content, values a value
+struct Output {
+    key: String,
+    kind: Kind,
+    value: Option<serde_json::Value>,
+    content: Option<Vec<u8>>,
+}
+
+#[derive(FromRow)]
+struct Stored {
+    value: Option<JsonColumn<serde_json::Value>>,
+    content: Option<Vec<u8>>,
+    content_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BlobParams {
+    key: Option<String>,
+}
+
+// A JSON object sets the values of its keys; any other body is a blob
+// stored under ?key=. Only the node's running task may write, with its
+// token in X-Task-Token, and what it writes replaces what it wrote before
+// under the same keys.
+pub async fn write_outputs(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path((run_id, node_id)): Path<(Uuid, Uuid)>,
+    Query(params): Query<BlobParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let Some(token) = headers.get(TOKEN_HEADER).and_then(|v| v.to_str().ok()) else {
+        return Err(locale.error(StatusCode::UNAUTHORIZED, "task_token_missing", &[]));
+    };
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("application/octet-stream");
+    let outputs: Vec<Output> = if content_type.starts_with("application/json") {
+        if params.key.is_some() {
+            return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "output_key_with_values", &[]));
+        }
+        let Ok(serde_json::Value::Object(values)) = serde_json::from_slice(&body) else {
+            return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_outputs", &[]));
+        };
+        values
+            .into_iter()
+            .map(|(key, value)| Output { key, kind: Kind::Value, value: Some(value), content: None })
+            .collect()
+    } else {
+        let Some(key) = params.key else {
+            return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "output_key_required", &[]));
+        };
+        vec![Output { key, kind: Kind::Blob, value: None, content: Some(body.to_vec()) }]
+    };
+    if let Some(output) = outputs.iter().find(|o| !connections::valid_name(&o.key)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_output_key", &[("key", &output.key)]));
+    }
+
+    let (outputs, locale) = (&outputs, &locale);
+    let hash = digest(token);
+    let (hash, content_type) = (&hash, content_type);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // Shared, so the task's state can't move on until the outputs are in
+        let task: Option<(String, Option<String>)> = sqlx::query_as(
+            "SELECT state, outputs_token_hash FROM task_runs WHERE run_id = $1 AND node_id = $2 FOR SHARE",
+        )
+            .bind(run_id)
+            .bind(node_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some((state, token_hash)) = task else {
+            return Err(TxError::Rejected(
+                locale.error(StatusCode::NOT_FOUND, "task_run_not_found", &[("run", &run_id), ("node", &node_id)]),
+            ));
+        };
+        if token_hash.as_ref() != Some(hash) {
+            return Err(TxError::Rejected(locale.error(StatusCode::FORBIDDEN, "task_token_invalid", &[])));
+        }
+        if state != "running" {
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "task_not_running", &[("node", &node_id)])));
+        }
+
+        let mut written = Vec::with_capacity(outputs.len());
+        for Output { key, kind, value, content } in outputs {
+            let size = value.as_ref().map_or_else(|| content.as_ref().map_or(0, Vec::len), |v| v.to_string().len());
+            let artifact = sqlx::query_as::<_, Artifact>(&format!(
+                "WITH a AS (
+                     INSERT INTO run_artifacts (run_id, node_id, key, kind, value, content, content_type, size)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                     ON CONFLICT (run_id, node_id, key) DO UPDATE SET kind = EXCLUDED.kind, value = EXCLUDED.value,
+                         content = EXCLUDED.content, content_type = EXCLUDED.content_type, size = EXCLUDED.size, created_at = now()
+                     RETURNING *
+                 )
+                 SELECT {} FROM a JOIN task_runs t ON t.run_id = a.run_id AND t.node_id = a.node_id",
+                ARTIFACT_COLUMNS
+            ))
+                .bind(run_id)
+                .bind(node_id)
+                .bind(key)
+                .bind(kind)
+                .bind(value.as_ref().map(JsonColumn))
+                .bind(content)
+                .bind((*kind == Kind::Blob).then_some(content_type))
+                .bind(size as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+            written.push(artifact);
+        }
+        Ok(written)
+    }))
+    .await;
+
+    let written = result.map_err(|e| e.respond(locale, "write_outputs_failed"))?;
+    Ok((StatusCode::CREATED, Json(written)).into_response())
+}
+
+async fn run_visible(pool: &PgPool, locale: &Locale, run_id: Uuid) -> Result<(), AppError> {
+    let exists: bool = sqlx::query_scalar(&format!("SELECT EXISTS (SELECT 1 FROM runs WHERE id = $1 AND {})", auth::visible("dag_id", 2)))
+        .bind(run_id)
+        .bind(auth::owner())
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::database(e, locale, "fetch_outputs_failed"))?;
+    if !exists {
+        return Err(locale.error(StatusCode::NOT_FOUND, "run_not_found", &[("id", &run_id)]));
+    }
+    Ok(())
+}
+
+async fn list(pool: &PgPool, locale: &Locale, run_id: Uuid, node_id: Option<Uuid>) -> Result<Response, AppError> {
+    run_visible(pool, locale, run_id).await?;
+    let artifacts = sqlx::query_as::<_, Artifact>(&format!(
+        "SELECT {} FROM run_artifacts a JOIN task_runs t ON t.run_id = a.run_id AND t.node_id = a.node_id
+         WHERE a.run_id = $1 AND ($2::uuid IS NULL OR a.node_id = $2) ORDER BY t.label, a.key",
+        ARTIFACT_COLUMNS
+    ))
+        .bind(run_id)
+        .bind(node_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::database(e, locale, "fetch_outputs_failed"))?;
+    Ok(Json(artifacts).into_response())
+}
+
+// Every output of the run, by node label and key
+pub async fn run_outputs(State(pool): State<PgPool>, locale: Locale, Path(run_id): Path<Uuid>) -> Result<Response, AppError> {
+    list(&pool, &locale, run_id, None).await
+}
+
+pub async fn node_outputs(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path((run_id, node_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, AppError> {
+    list(&pool, &locale, run_id, Some(node_id)).await
+}
+
+// One output: a value as JSON, a blob as it was written
+pub async fn get_output(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path((run_id, node_id, key)): Path<(Uuid, Uuid, String)>,
+) -> Result<Response, AppError> {
+    run_visible(&pool, &locale, run_id).await?;
+    let output = sqlx::query_as::<_, Stored>(
+        "SELECT value, content, content_type FROM run_artifacts WHERE run_id = $1 AND node_id = $2 AND key = $3",
+    )
+        .bind(run_id)
+        .bind(node_id)
+        .bind(&key)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_outputs_failed"))?;
+    match output {
+        Some(Stored { value: Some(JsonColumn(value)), .. }) => Ok(Json(value).into_response()),
+        Some(Stored { value: None, content: Some(content), content_type }) => Ok((
+            [(header::CONTENT_TYPE, content_type.unwrap_or_else(|| "application/octet-stream".to_string()))],
+            content,
+        )
+            .into_response()),
+        _ => Err(locale.error(StatusCode::NOT_FOUND, "output_not_found", &[("node", &node_id), ("key", &key)])),
+    }
+}
+
+// Where the text has {{ outputs.LABEL.KEY }} placeholders, with the label
+// (or node id) and key. The key runs from the last dot, so labels may have
+// dots in them.
+fn placeholders(text: &str) -> Vec<(Range<usize>, &str, &str)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(start) = text[from..].find("{{").map(|i| from + i) {
+        let Some(end) = text[start..].find("}}").map(|i| start + i + 2) else { break };
+        let reference = text[start + 2..end - 2].trim().strip_prefix("outputs.").and_then(|r| r.rsplit_once('.'));
+        match reference {
+            Some((node, key)) if !node.is_empty() && connections::valid_name(key) => {
+                found.push((start..end, node, key));
+                from = end;
+            }
+            _ => from = start + 2,
+        }
+    }
+    found
+}
+
+// Whether the text has any placeholders to fill in
+pub fn is_templated(text: &str) -> bool {
+    !placeholders(text).is_empty()
+}
+
+fn visit_strings(value: &mut serde_json::Value, f: &mut impl FnMut(&mut String)) {
+    match value {
+        serde_json::Value::String(text) => f(text),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| visit_strings(item, f)),
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(|item| visit_strings(item, f)),
+        _ => {}
+    }
+}
+
+// Fills in the output placeholders in the strings of `task` from the nodes
+// in `upstream`, by label and by id: those the task waits on, directly or
+// not, so they are done by the time it starts. Values that are strings go in
+// as they are, other JSON as its text and blobs as their URL. The inner
+// error says why the task can't be given them.
+pub async fn render<T: Serialize + DeserializeOwned>(
+    pool: &PgPool,
+    run_id: Uuid,
+    upstream: &HashMap<String, Uuid>,
+    task: &T,
+) -> Result<Result<T, String>, sqlx::Error> {
+    let mut value = match serde_json::to_value(task) {
+        Ok(value) => value,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+    let mut references = BTreeSet::new();
+    visit_strings(&mut value, &mut |text| {
+        references.extend(placeholders(text).into_iter().map(|(_, node, key)| (node.to_string(), key.to_string())));
+    });
+    if references.is_empty() {
+        return Ok(serde_json::from_value(value).map_err(|e| e.to_string()));
+    }
+
+    let mut nodes = Vec::new();
+    for (node, _) in &references {
+        match upstream.get(node) {
+            Some(id) => nodes.push(*id),
+            None => return Ok(Err(format!("Outputs of '{}' are used but it is not upstream of this node", node))),
+        }
+    }
+    let rows: Vec<(Uuid, String, Option<JsonColumn<serde_json::Value>>)> =
+        sqlx::query_as("SELECT node_id, key, value FROM run_artifacts WHERE run_id = $1 AND node_id = ANY($2)")
+            .bind(run_id)
+            .bind(&nodes)
+            .fetch_all(pool)
+            .await?;
+    let mut values = HashMap::new();
+    for (node, key) in &references {
+        let id = upstream[node];
+        let Some((_, _, value)) = rows.iter().find(|(n, k, _)| *n == id && k == key) else {
+            return Ok(Err(format!("Node '{}' has no output '{}'", node, key)));
+        };
+        let text = match value {
+            Some(JsonColumn(serde_json::Value::String(text))) => text.clone(),
+            Some(JsonColumn(value)) => value.to_string(),
+            None => format!("{}/{}", outputs_url(run_id, id), key),
+        };
+        values.insert((node.as_str(), key.as_str()), text);
+    }
+
+    visit_strings(&mut value, &mut |text| {
+        let mut filled = String::with_capacity(text.len());
+        let mut last = 0;
+        for (range, node, key) in placeholders(text) {
+            filled.push_str(&text[last..range.start]);
+            filled.push_str(&values[&(node, key)]);
+            last = range.end;
+        }
+        filled.push_str(&text[last..]);
+        *text = filled;
+    });
+    Ok(serde_json::from_value(value).map_err(|e| e.to_string()))
+}
@@ -0,0 +1,219 @@
+// API keys. With ADMIN_API_KEY set, every request except for the API docs
+// needs `Authorization: Bearer <key>`, either that key or one issued through
+// /admin/api-keys. A key sees only the DAGs (and imports) it created, admin
+// keys see everything, and read keys may not change anything. Without
+// ADMIN_API_KEY the service stays open to anyone who can reach it.
+//
+// The middleware keeps the caller in a task-local for the rest of the
+// request, so the queries that look DAGs up can narrow themselves to the
+// caller's. Background work runs outside any request and sees everything.
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::artifacts;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::Writable;
+
+const KEY_PREFIX: &str = "dagk_";
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    Read,
+    Write,
+    Admin,
+}
+
+#[derive(Clone, Copy)]
+struct Caller {
+    // None for ADMIN_API_KEY itself, which has no row
+    key_id: Option<Uuid>,
+    role: Role,
+}
+
+// The key whose DAGs the current request is limited to. None for admins, with
+// authentication off and outside requests.
+pub fn owner() -> Option<Uuid> {
+    CALLER.try_with(|c| if c.role == Role::Admin { None } else { c.key_id }).ok().flatten()
+}
+
+// The key to record as the owner of a DAG or import the request creates
+pub fn creator() -> Option<Uuid> {
+    CALLER.try_with(|c| c.key_id).ok().flatten()
+}
+
+// Whether the caller may do what only admins may, which everyone may with
+// authentication off
+pub fn admin() -> bool {
+    CALLER.try_with(|c| c.role == Role::Admin).unwrap_or(true)
+}
+
+// Whether the request came with an API key, which authentication being on
+// means for every request but the few it lets through
+pub fn authenticated() -> bool {
+    CALLER.try_with(|_| ()).is_ok()
+}
+
+pub fn read_only() -> bool {
+    CALLER.try_with(|c| c.role == Role::Read).unwrap_or(false)
+}
+
+// A condition that holds when `column` is the id of a DAG the caller may
+// see, for queries that bind owner() as $`param`
+pub fn visible(column: &str, param: usize) -> String {
+    format!("(${p}::uuid IS NULL OR {c} IN (SELECT id FROM dags WHERE owner_key_id = ${p}))", p = param, c = column)
+}
+
+fn digest(key: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(key.as_bytes()))
+}
+
+fn unauthorized(locale: &Locale, key: &str) -> Response {
+    ([(header::WWW_AUTHENTICATE, "Bearer")], locale.error(StatusCode::UNAUTHORIZED, key, &[])).into_response()
+}
+
+pub async fn authenticate<B>(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    locale: Locale,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(admin_key) = &config.admin_api_key else {
+        return next.run(request).await;
+    };
+    if request.uri().path().starts_with("/api-docs") || request.uri().path() == "/metrics" {
+        return next.run(request).await;
+    }
+    // Tasks write and read outputs with their own token instead of a key
+    if let Some(token) = request.headers().get(artifacts::TOKEN_HEADER).and_then(|v| v.to_str().ok()) {
+        match artifacts::allowed_with_token(&pool, request.method(), request.uri().path(), token).await {
+            Ok(true) => return next.run(request).await,
+            Ok(false) => {}
+            Err(e) => return AppError::database(e, &locale, "authenticate_failed").into_response(),
+        }
+    }
+
+    let Some(key) = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+    else {
+        return unauthorized(&locale, "api_key_missing");
+    };
+    let hash = digest(key);
+    let caller = if hash == digest(admin_key) {
+        Caller { key_id: None, role: Role::Admin }
+    } else {
+        match sqlx::query_as::<_, (Uuid, Role)>("SELECT id, role FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL")
+            .bind(&hash)
+            .fetch_optional(&pool)
+            .await
+        {
+            Ok(Some((id, role))) => Caller { key_id: Some(id), role },
+            Ok(None) => return unauthorized(&locale, "api_key_invalid"),
+            Err(e) => return AppError::database(e, &locale, "authenticate_failed").into_response(),
+        }
+    };
+    if request.uri().path().starts_with("/admin/") && caller.role != Role::Admin {
+        return locale.error(StatusCode::FORBIDDEN, "api_key_not_admin", &[]).into_response();
+    }
+    CALLER.scope(caller, next.run(request)).await
+}
+
+#[derive(Serialize, FromRow)]
+struct ApiKey {
+    id: Uuid,
+    name: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyPayload {
+    name: String,
+    role: Role,
+}
+
+// Answers with the key itself, which is not stored and can't be shown again
+pub async fn create_api_key(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Json(payload): Json<CreateApiKeyPayload>,
+) -> Result<Response, AppError> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_api_key_name", &[]));
+    }
+
+    let random: Vec<u8> = [Uuid::new_v4(), Uuid::new_v4()].iter().flat_map(|u| u.into_bytes()).collect();
+    let key = format!("{}{}", KEY_PREFIX, base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(random));
+    let api_key = sqlx::query_as::<_, ApiKey>(
+        "INSERT INTO api_keys (id, name, key_hash, role) VALUES ($1, $2, $3, $4)
+         RETURNING id, name, role, created_at, revoked_at",
+    )
+        .bind(ids::new_id())
+        .bind(name)
+        .bind(digest(&key))
+        .bind(payload.role)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "create_api_key_failed"))?;
+
+    let mut body = serde_json::json!(api_key);
+    body["key"] = serde_json::json!(key);
+    Ok((StatusCode::CREATED, [(header::LOCATION, format!("/admin/api-keys/{}", api_key.id))], Json(body)).into_response())
+}
+
+pub async fn list_api_keys(
+    State(pool): State<PgPool>,
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let keys = sqlx::query_as::<_, ApiKey>("SELECT id, name, role, created_at, revoked_at FROM api_keys ORDER BY created_at")
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_api_keys_failed"))?;
+    Ok(Json(keys).into_response())
+}
+
+// Revoked keys stop working at once; the DAGs they created stay, visible to
+// admins
+pub async fn revoke_api_key(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(key_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let revoked = sqlx::query("UPDATE api_keys SET revoked_at = COALESCE(revoked_at, now()) WHERE id = $1")
+        .bind(key_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "revoke_api_key_failed"))?;
+    if revoked.rows_affected() == 0 {
+        return Err(locale.error(StatusCode::NOT_FOUND, "api_key_not_found", &[("id", &key_id)]));
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
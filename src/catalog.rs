@@ -0,0 +1,569 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as JsonColumn, FromRow, PgPool};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::events;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::Writable;
+use crate::slugs::{self, DagId};
+use crate::usage::{self, Access};
+use crate::{like_pattern, loaded_dag, lock_dag, taken_or, with_warning, writable, Lifecycle, DAG};
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotNode {
+    id: Uuid,
+    label: String,
+    external_ids: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEdge {
+    source: Uuid,
+    target: Uuid,
+}
+
+// A published DAG as it was at publish time. Node ids are those of the
+// source DAG; copies get fresh ones.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    nodes: Vec<SnapshotNode>,
+    edges: Vec<SnapshotEdge>,
+}
+
+#[derive(Serialize, FromRow)]
+struct CatalogEntry {
+    id: Uuid,
+    name: String,
+    description: Option<String>,
+    categories: Vec<String>,
+    source_dag_id: Option<Uuid>,
+    latest_version: i32,
+    published_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+    // DAGs copied from any version of the entry
+    copies: i64,
+    rating: Option<f64>,
+    review_count: i64,
+}
+
+#[derive(Serialize, FromRow)]
+struct CatalogVersion {
+    version: i32,
+    node_count: i32,
+    edge_count: i32,
+    published_at: DateTime<Utc>,
+}
+
+fn entry_not_found(locale: &Locale, entry_id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "catalog_entry_not_found", &[("id", &entry_id)])
+}
+
+fn version_not_found(locale: &Locale, entry_id: Uuid, version: i32) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "catalog_version_not_found", &[("id", &entry_id), ("version", &version)])
+}
+
+// The given version of an entry, or its latest one
+async fn fetch_snapshot<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    entry_id: Uuid,
+    version: Option<i32>,
+) -> Result<Option<(i32, JsonColumn<Snapshot>)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT version, snapshot FROM catalog_versions
+         WHERE entry_id = $1 AND ($2::integer IS NULL OR version = $2)
+         ORDER BY version DESC LIMIT 1",
+    )
+        .bind(entry_id)
+        .bind(version)
+        .fetch_optional(executor)
+        .await
+}
+
+// Fills an empty DAG with a copy of a snapshot
+async fn insert_snapshot(tx: &mut sqlx::PgConnection, dag_id: Uuid, snapshot: &Snapshot) -> Result<(), sqlx::Error> {
+    let new_ids: HashMap<Uuid, Uuid> = snapshot.nodes.iter().map(|n| (n.id, ids::new_id())).collect();
+    let mut taken = HashSet::new();
+    let mut node_ids = Vec::with_capacity(snapshot.nodes.len());
+    let mut labels = Vec::with_capacity(snapshot.nodes.len());
+    let mut node_slugs = Vec::with_capacity(snapshot.nodes.len());
+    let mut external_ids = Vec::with_capacity(snapshot.nodes.len());
+    for node in &snapshot.nodes {
+        let id = new_ids[&node.id];
+        let slug = slugs::slugify(&node.label, "node");
+        let slug = if taken.contains(&slug) { slugs::disambiguate(&slug, id) } else { slug };
+        taken.insert(slug.clone());
+        node_ids.push(id);
+        labels.push(node.label.as_str());
+        node_slugs.push(slug);
+        external_ids.push(serde_json::json!(node.external_ids).to_string());
+    }
+    sqlx::query(
+        "INSERT INTO nodes (id, dag_id, label, slug, external_ids)
+         SELECT u.id, $1, u.label, u.slug, u.external_ids::jsonb
+         FROM UNNEST($2::uuid[], $3::text[], $4::text[], $5::text[]) AS u(id, label, slug, external_ids)",
+    )
+        .bind(dag_id)
+        .bind(&node_ids)
+        .bind(&labels)
+        .bind(&node_slugs)
+        .bind(&external_ids)
+        .execute(&mut *tx)
+        .await?;
+
+    let edge_ids: Vec<Uuid> = snapshot.edges.iter().map(|_| ids::new_id()).collect();
+    sqlx::query(
+        "INSERT INTO edges (id, source, target, dag_id)
+         SELECT u.id, u.source, u.target, $1 FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[]) AS u(id, source, target)",
+    )
+        .bind(dag_id)
+        .bind(&edge_ids)
+        .bind(snapshot.edges.iter().map(|e| new_ids[&e.source]).collect::<Vec<_>>())
+        .bind(snapshot.edges.iter().map(|e| new_ids[&e.target]).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct PublishPayload {
+    // Defaults to the DAG's name on first publish
+    name: Option<String>,
+}
+
+// Publishes the current state of a DAG to the catalog as its next version.
+// The first publish creates the catalog entry.
+pub async fn publish_dag(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<PublishPayload>,
+) -> Result<Response, AppError> {
+    let (dag, nodes, edges) = loaded_dag(&pool, &locale, dag_id, "fetch_dag_failed").await?;
+
+    // Edges into other DAGs can't be part of a template
+    let node_set: HashSet<Uuid> = nodes.iter().map(|n| n.id).collect();
+    let snapshot = JsonColumn(Snapshot {
+        nodes: nodes
+            .into_iter()
+            .map(|n| SnapshotNode { id: n.id, label: n.label, external_ids: n.external_ids.0 })
+            .collect(),
+        edges: edges
+            .into_iter()
+            .filter(|e| node_set.contains(&e.source) && node_set.contains(&e.target))
+            .map(|e| SnapshotEdge { source: e.source, target: e.target })
+            .collect(),
+    });
+
+    let name = payload.name.as_deref().unwrap_or(&dag.name);
+    let (payload, snapshot) = (&payload, &snapshot);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // Also locks the entry, which serializes version numbers
+        let entry_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO catalog_entries (id, name, source_dag_id) VALUES ($1, $2, $3)
+             ON CONFLICT (source_dag_id) DO UPDATE SET name = COALESCE($4, catalog_entries.name)
+             RETURNING id",
+        )
+            .bind(ids::new_id())
+            .bind(name)
+            .bind(dag_id)
+            .bind(&payload.name)
+            .fetch_one(&mut *tx)
+            .await?;
+        let version = sqlx::query_as::<_, CatalogVersion>(
+            "INSERT INTO catalog_versions (entry_id, version, node_count, edge_count, snapshot)
+             SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4 FROM catalog_versions WHERE entry_id = $1
+             RETURNING version, node_count, edge_count, published_at",
+        )
+            .bind(entry_id)
+            .bind(snapshot.nodes.len() as i32)
+            .bind(snapshot.edges.len() as i32)
+            .bind(snapshot)
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok((entry_id, version))
+    }))
+    .await;
+
+    let (entry_id, version) = result.map_err(|e| e.respond(&locale, "publish_dag_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(Json(serde_json::json!({
+        "entry_id": entry_id,
+        "source_dag_id": dag_id,
+        "version": version.version,
+        "node_count": version.node_count,
+        "edge_count": version.edge_count,
+        "published_at": version.published_at,
+    }))
+        .into_response())
+}
+
+const ENTRY_COLUMNS: &str = "SELECT e.id, e.name, e.description, e.categories, e.source_dag_id,
+            v.version AS latest_version, v.published_at, e.created_at,
+            (SELECT COUNT(*) FROM catalog_copies c WHERE c.entry_id = e.id) AS copies,
+            r.rating, r.review_count
+     FROM catalog_entries e
+     JOIN LATERAL (
+         SELECT version, published_at FROM catalog_versions WHERE entry_id = e.id ORDER BY version DESC LIMIT 1
+     ) v ON true
+     JOIN LATERAL (
+         SELECT AVG(rating)::float8 AS rating, COUNT(*) AS review_count FROM catalog_reviews WHERE entry_id = e.id
+     ) r ON true";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CatalogOrder {
+    #[default]
+    Name,
+    Rating,
+    Copies,
+    Recent,
+}
+
+#[derive(Deserialize)]
+pub struct CatalogSearch {
+    // Matched case-insensitively against names and descriptions
+    q: Option<String>,
+    category: Option<String>,
+    min_rating: Option<f64>,
+    #[serde(default)]
+    sort: CatalogOrder,
+}
+
+pub async fn list_catalog(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(search): Query<CatalogSearch>,
+) -> Result<Response, AppError> {
+    let order = match search.sort {
+        CatalogOrder::Name => "e.name, e.id",
+        CatalogOrder::Rating => "r.rating DESC NULLS LAST, r.review_count DESC, e.name",
+        CatalogOrder::Copies => "copies DESC, e.name",
+        CatalogOrder::Recent => "v.published_at DESC",
+    };
+    let query = format!(
+        "{} WHERE ($1::text IS NULL OR e.name ILIKE $1 OR e.description ILIKE $1)
+              AND ($2::text IS NULL OR $2 = ANY(e.categories))
+              AND ($3::float8 IS NULL OR r.rating >= $3)
+         ORDER BY {}",
+        ENTRY_COLUMNS, order
+    );
+    let entries = sqlx::query_as::<_, CatalogEntry>(&query)
+        .bind(search.q.as_deref().map(like_pattern))
+        .bind(search.category.map(|c| c.trim().to_lowercase()))
+        .bind(search.min_rating)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_catalog_failed"))?;
+    Ok(Json(entries).into_response())
+}
+
+pub async fn get_catalog_entry(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(entry_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let failed = |e| AppError::database(e, &locale, "fetch_catalog_failed");
+    let entry = sqlx::query_as::<_, CatalogEntry>(&format!("{} WHERE e.id = $1", ENTRY_COLUMNS))
+        .bind(entry_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(failed)?
+        .ok_or_else(|| entry_not_found(&locale, entry_id))?;
+    let versions = sqlx::query_as::<_, CatalogVersion>(
+        "SELECT version, node_count, edge_count, published_at FROM catalog_versions
+         WHERE entry_id = $1 ORDER BY version DESC",
+    )
+        .bind(entry_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(failed)?;
+    Ok(Json(serde_json::json!({
+        "entry": entry,
+        "versions": versions,
+    }))
+        .into_response())
+}
+
+pub async fn get_catalog_version(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path((entry_id, version)): Path<(Uuid, i32)>,
+) -> Result<Response, AppError> {
+    let (version, snapshot) = fetch_snapshot(&pool, entry_id, Some(version))
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_catalog_failed"))?
+        .ok_or_else(|| version_not_found(&locale, entry_id, version))?;
+    Ok(Json(serde_json::json!({
+        "entry_id": entry_id,
+        "version": version,
+        "nodes": snapshot.0.nodes,
+        "edges": snapshot.0.edges,
+    }))
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UpdateEntryPayload {
+    name: Option<String>,
+    description: Option<String>,
+    categories: Option<Vec<String>>,
+}
+
+// Changes the listing of an entry; fields left out stay as they are.
+// Categories are stored trimmed and lowercased.
+pub async fn update_catalog_entry(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(entry_id): Path<Uuid>,
+    Json(payload): Json<UpdateEntryPayload>,
+) -> Result<Response, AppError> {
+    let categories = payload.categories.map(|categories| {
+        let mut categories: Vec<String> = categories
+            .iter()
+            .map(|c| c.trim().to_lowercase())
+            .filter(|c| !c.is_empty())
+            .collect();
+        categories.sort();
+        categories.dedup();
+        categories
+    });
+    // Entries are shared by every key, so only admins may edit them
+    if !auth::admin() {
+        return Err(locale.error(StatusCode::FORBIDDEN, "catalog_entry_needs_admin", &[]));
+    }
+    let updated = sqlx::query(
+        "UPDATE catalog_entries SET name = COALESCE($2, name), description = COALESCE($3, description),
+                categories = COALESCE($4, categories)
+         WHERE id = $1",
+    )
+        .bind(entry_id)
+        .bind(payload.name)
+        .bind(payload.description)
+        .bind(categories)
+        .execute(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "update_catalog_entry_failed"))?;
+    if updated.rows_affected() == 0 {
+        return Err(entry_not_found(&locale, entry_id));
+    }
+
+    let entry = sqlx::query_as::<_, CatalogEntry>(&format!("{} WHERE e.id = $1", ENTRY_COLUMNS))
+        .bind(entry_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_catalog_failed"))?;
+    Ok(Json(entry).into_response())
+}
+
+#[derive(Serialize, FromRow)]
+struct Review {
+    id: Uuid,
+    rating: i16,
+    comment: Option<String>,
+    author: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct ReviewPayload {
+    rating: i16,
+    comment: Option<String>,
+    author: Option<String>,
+}
+
+pub async fn add_review(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(entry_id): Path<Uuid>,
+    Json(payload): Json<ReviewPayload>,
+) -> Result<Response, AppError> {
+    if !(1..=5).contains(&payload.rating) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_rating", &[]));
+    }
+
+    let review = sqlx::query_as::<_, Review>(
+        "INSERT INTO catalog_reviews (id, entry_id, rating, comment, author)
+         SELECT $1, id, $3, $4, $5 FROM catalog_entries WHERE id = $2
+         RETURNING id, rating, comment, author, created_at",
+    )
+        .bind(ids::new_id())
+        .bind(entry_id)
+        .bind(payload.rating)
+        .bind(payload.comment)
+        .bind(payload.author)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "add_review_failed"))?
+        .ok_or_else(|| entry_not_found(&locale, entry_id))?;
+    Ok((StatusCode::CREATED, Json(review)).into_response())
+}
+
+pub async fn list_reviews(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(entry_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let reviews = sqlx::query_as::<_, Review>(
+        "SELECT id, rating, comment, author, created_at FROM catalog_reviews
+         WHERE entry_id = $1 ORDER BY created_at DESC",
+    )
+        .bind(entry_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_catalog_failed"))?;
+    Ok(Json(reviews).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct CopyPayload {
+    id: Option<Uuid>,
+    // Defaults to the entry's name
+    name: Option<String>,
+    // Defaults to the latest version
+    version: Option<i32>,
+    // Subscribed copies can later be brought up to the latest version
+    #[serde(default)]
+    subscribe: bool,
+}
+
+// Makes a new DAG from a catalog version
+pub async fn copy_catalog_entry(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(entry_id): Path<Uuid>,
+    Json(payload): Json<CopyPayload>,
+) -> Result<Response, AppError> {
+    let failed = |e| AppError::database(e, &locale, "fetch_catalog_failed");
+    let entry_name: String = sqlx::query_scalar("SELECT name FROM catalog_entries WHERE id = $1")
+        .bind(entry_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(failed)?
+        .ok_or_else(|| entry_not_found(&locale, entry_id))?;
+    let (version, snapshot) = fetch_snapshot(&pool, entry_id, payload.version)
+        .await
+        .map_err(failed)?
+        .ok_or_else(|| version_not_found(&locale, entry_id, payload.version.unwrap_or_default()))?;
+
+    let dag_id = payload.id.unwrap_or_else(ids::new_id);
+    let name = payload.name.unwrap_or(entry_name);
+    let (name, snapshot, subscribe, locale) = (&name, &snapshot.0, payload.subscribe, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let slug = slugs::dag_slug(&mut *tx, name, dag_id).await?;
+        sqlx::query("INSERT INTO dags (id, name, slug, owner_key_id) VALUES ($1, $2, $3, $4)")
+            .bind(dag_id)
+            .bind(name)
+            .bind(&slug)
+            .bind(auth::creator())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| taken_or(e, locale, dag_id))?;
+        insert_snapshot(&mut *tx, dag_id, snapshot).await?;
+        sqlx::query("INSERT INTO catalog_copies (dag_id, entry_id, version, subscribed) VALUES ($1, $2, $3, $4)")
+            .bind(dag_id)
+            .bind(entry_id)
+            .bind(version)
+            .bind(subscribe)
+            .execute(&mut *tx)
+            .await?;
+        Ok(slug)
+    }))
+    .await;
+
+    let slug = result.map_err(|e| e.respond(locale, "copy_catalog_entry_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    let dag = DAG {
+        id: dag_id,
+        name: name.clone(),
+        lifecycle: Lifecycle::Active,
+        deprecation_reason: None,
+        replaced_by: None,
+        slug: Some(slug),
+    };
+    Ok(Json(serde_json::json!({
+        "dag": dag,
+        "entry_id": entry_id,
+        "version": version,
+        "subscribed": subscribe,
+    }))
+        .into_response())
+}
+
+// Replaces the contents of a subscribed copy with the latest version of its
+// catalog entry. Local changes to the copy are lost.
+pub async fn sync_catalog_copy(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    let locale = &locale;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let copy: Option<(Uuid, i32, bool)> = sqlx::query_as(
+            "SELECT entry_id, version, subscribed FROM catalog_copies WHERE dag_id = $1",
+        )
+            .bind(dag_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some((entry_id, current, true)) = copy else {
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "dag_not_subscribed", &[("id", &dag_id)])));
+        };
+        let Some((latest, snapshot)) = fetch_snapshot(&mut *tx, entry_id, None).await? else {
+            return Err(TxError::Database(sqlx::Error::RowNotFound));
+        };
+        if latest == current {
+            return Ok((entry_id, latest, false, warning));
+        }
+
+        sqlx::query(
+            "DELETE FROM edges WHERE dag_id = $1
+                OR source IN (SELECT id FROM nodes WHERE dag_id = $1)
+                OR target IN (SELECT id FROM nodes WHERE dag_id = $1)",
+        )
+            .bind(dag_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE dag_id = $1")
+            .bind(dag_id)
+            .execute(&mut *tx)
+            .await?;
+        insert_snapshot(&mut *tx, dag_id, &snapshot.0).await?;
+        sqlx::query("UPDATE catalog_copies SET version = $2 WHERE dag_id = $1")
+            .bind(dag_id)
+            .bind(latest)
+            .execute(&mut *tx)
+            .await?;
+        Ok((entry_id, latest, true, warning))
+    }))
+    .await;
+
+    let (entry_id, version, updated, warning) = result.map_err(|e| e.respond(locale, "sync_catalog_copy_failed"))?;
+    if updated {
+        usage::record(&pool, dag_id, Access::Edit);
+        events::publish(dag_id, "graph_changed", serde_json::json!({ "cause": "catalog_sync", "version": version }));
+    }
+    let body = Json(serde_json::json!({
+        "dag_id": dag_id,
+        "entry_id": entry_id,
+        "version": version,
+        "updated": updated,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
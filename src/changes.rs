@@ -0,0 +1,60 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+
+// Headers callers use to say who is making a change and why, recorded as
+// sent. With authentication on, the database also records the API key the
+// change came with (created_by_key_id on nodes and edges, key_id in their
+// history), which callers can't choose.
+const ACTOR_HEADER: &str = "x-actor";
+const REASON_HEADER: &str = "x-change-reason";
+
+// Longest actor or reason kept; the rest is cut off
+const MAX_CHARS: usize = 500;
+
+#[derive(Clone)]
+pub struct Change {
+    pub created_by: Option<String>,
+    pub reason: Option<String>,
+}
+
+fn header_text(headers: &HeaderMap, name: &str) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?.trim();
+    (!value.is_empty()).then(|| value.chars().take(MAX_CHARS).collect())
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Change {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Change {
+            created_by: header_text(&parts.headers, ACTOR_HEADER),
+            reason: header_text(&parts.headers, REASON_HEADER),
+        })
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: Change;
+}
+
+// Keeps the request's actor and reason at hand for db::unit_of_work, which
+// puts them on the transaction for the history the database records
+pub async fn scope<B>(request: Request<B>, next: Next<B>) -> Response {
+    let change = Change {
+        created_by: header_text(request.headers(), ACTOR_HEADER),
+        reason: header_text(request.headers(), REASON_HEADER),
+    };
+    CURRENT.scope(change, next.run(request)).await
+}
+
+// The actor and reason of the request being handled, if any
+pub fn current() -> Option<Change> {
+    CURRENT.try_with(Change::clone).ok()
+}
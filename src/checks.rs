@@ -0,0 +1,185 @@
+// A report on the structure of a DAG, for CI jobs that check graphs brought
+// in from other tools. Each check names the nodes or edges it objects to and
+// has a severity; the DAG passes while no check at an error level, or at
+// whatever level the caller sets with fail_on, finds anything.
+use axum::{
+    extract::{Json, Query, State},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet, VecDeque};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::graph::Graph;
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::usage::{self, Access};
+use crate::versions::{self, VersionParam};
+use crate::{Edge, Node};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    // Nodes on or behind a cycle
+    Cycles,
+    // Nodes without any edge
+    OrphanNodes,
+    // Edges with the same source and target as an earlier one
+    DuplicateEdges,
+    SelfLoops,
+    // Nodes from which no path leads to a node without successors
+    NoPathToSink,
+    // Edges whose source or target is not a node of the DAG
+    DanglingEndpoints,
+}
+
+impl Check {
+    const ALL: [Check; 6] = [
+        Check::Cycles,
+        Check::OrphanNodes,
+        Check::DuplicateEdges,
+        Check::SelfLoops,
+        Check::NoPathToSink,
+        Check::DanglingEndpoints,
+    ];
+
+    fn default_severity(self) -> Severity {
+        match self {
+            Check::Cycles | Check::SelfLoops | Check::DanglingEndpoints => Severity::Error,
+            Check::DuplicateEdges | Check::NoPathToSink => Severity::Warning,
+            Check::OrphanNodes => Severity::Info,
+        }
+    }
+
+    // Whether the ids it reports are of nodes or of edges
+    fn element(self) -> &'static str {
+        match self {
+            Check::Cycles | Check::OrphanNodes | Check::NoPathToSink => "node",
+            Check::DuplicateEdges | Check::SelfLoops | Check::DanglingEndpoints => "edge",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+fn error_level() -> Severity {
+    Severity::Error
+}
+
+#[derive(Deserialize)]
+pub struct ValidatePayload {
+    // Which checks to run; all of them by default
+    checks: Option<Vec<Check>>,
+    // Severities to use instead of the defaults, by check
+    #[serde(default)]
+    severities: HashMap<Check, Severity>,
+    // The lowest severity that makes the DAG fail
+    #[serde(default = "error_level")]
+    fail_on: Severity,
+}
+
+#[derive(Serialize)]
+struct Finding {
+    check: Check,
+    severity: Severity,
+    passed: bool,
+    element: &'static str,
+    count: usize,
+    ids: Vec<Uuid>,
+}
+
+// What `check` objects to in the graph
+fn run(check: Check, graph: &Graph, nodes: &[Node], edges: &[Edge]) -> Vec<Uuid> {
+    let ids = |positions: Vec<usize>| positions.into_iter().map(|n| graph.ids[n]).collect();
+    match check {
+        Check::Cycles => ids(graph.topological_order().1),
+        Check::OrphanNodes => ids(
+            (0..graph.node_count())
+                .filter(|&n| graph.incoming[n].is_empty() && graph.outgoing[n].is_empty())
+                .collect(),
+        ),
+        Check::DuplicateEdges => {
+            let mut seen = HashSet::new();
+            edges.iter().filter(|e| !seen.insert((e.source, e.target))).map(|e| e.id).collect()
+        }
+        Check::SelfLoops => edges.iter().filter(|e| e.source == e.target).map(|e| e.id).collect(),
+        Check::NoPathToSink => {
+            // Walks back from the sinks; whatever isn't reached can't get to one
+            let mut reached = vec![false; graph.node_count()];
+            let mut queue: VecDeque<usize> = (0..graph.node_count()).filter(|&n| graph.outgoing[n].is_empty()).collect();
+            for &n in &queue {
+                reached[n] = true;
+            }
+            while let Some(n) = queue.pop_front() {
+                for &m in &graph.incoming[n] {
+                    if !reached[m] {
+                        reached[m] = true;
+                        queue.push_back(m);
+                    }
+                }
+            }
+            ids((0..graph.node_count()).filter(|&n| !reached[n]).collect())
+        }
+        Check::DanglingEndpoints => {
+            let in_dag: HashSet<Uuid> = nodes.iter().map(|n| n.id).collect();
+            edges
+                .iter()
+                .filter(|e| !in_dag.contains(&e.source) || !in_dag.contains(&e.target))
+                .map(|e| e.id)
+                .collect()
+        }
+    }
+}
+
+// Runs the checks on the DAG, or on one of its saved versions with
+// ?version=, and answers with a finding per check
+pub async fn validate_dag(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(at): Query<VersionParam>,
+    Json(payload): Json<ValidatePayload>,
+) -> Result<Response, AppError> {
+    let (_, nodes, edges) = versions::loaded_at(&pool, &locale, dag_id, &at, "fetch_dag_failed").await?;
+    usage::record(&pool, dag_id, Access::Read);
+
+    let graph = Graph::new(&nodes, &edges);
+    let mut checks = payload.checks.unwrap_or_else(|| Check::ALL.to_vec());
+    let mut seen = HashSet::new();
+    checks.retain(|&check| seen.insert(check));
+    let findings: Vec<Finding> = checks
+        .into_iter()
+        .map(|check| {
+            let ids = run(check, &graph, &nodes, &edges);
+            Finding {
+                check,
+                severity: payload.severities.get(&check).copied().unwrap_or_else(|| check.default_severity()),
+                passed: ids.is_empty(),
+                element: check.element(),
+                count: ids.len(),
+                ids,
+            }
+        })
+        .collect();
+    let failed = |severity: Severity| findings.iter().filter(|f| !f.passed && f.severity == severity).count();
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "valid": findings.iter().all(|f| f.passed || f.severity < payload.fail_on),
+        "summary": {
+            "errors": failed(Severity::Error),
+            "warnings": failed(Severity::Warning),
+            "info": failed(Severity::Info),
+        },
+        "checks": findings,
+    }))
+    .into_response())
+}
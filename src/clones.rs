@@ -0,0 +1,193 @@
+// Copies of DAGs, whole or in part, as new DAGs the caller owns. Nodes keep
+// everything but their id: labels, slugs, tasks, metadata and provenance come
+// along, and edges are copied between the copied nodes only.
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sqlx::types::Json as JsonColumn;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet, VecDeque};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::db;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::Writable;
+use crate::slugs::{self, DagId};
+use crate::usage::{self, Access};
+use crate::versions::{self, VersionParam, NODE_COLUMNS};
+use crate::{taken_or, Edge, Lifecycle, Node, DAG};
+
+// Makes a DAG with copies of `nodes` and of the edges between them. Answers
+// with the new DAG and the new id of each copied node by its old one.
+async fn copied(
+    pool: &PgPool,
+    locale: &Locale,
+    dag_id: Uuid,
+    name: &str,
+    nodes: Vec<Node>,
+    edges: &[Edge],
+) -> Result<Response, AppError> {
+    let new_ids: HashMap<Uuid, Uuid> = nodes.iter().map(|n| (n.id, ids::new_id())).collect();
+    let edges: Vec<&Edge> = edges
+        .iter()
+        .filter(|e| new_ids.contains_key(&e.source) && new_ids.contains_key(&e.target))
+        .collect();
+    let nodes: Vec<Node> = nodes
+        .into_iter()
+        .map(|node| Node { id: new_ids[&node.id], dag_id, ..node })
+        .collect();
+
+    let (nodes, edges, new_ids) = (&nodes, &edges, &new_ids);
+    let result = db::unit_of_work(pool, |tx| Box::pin(async move {
+        let slug = slugs::dag_slug(&mut *tx, name, dag_id).await?;
+        sqlx::query("INSERT INTO dags (id, name, slug, owner_key_id) VALUES ($1, $2, $3, $4)")
+            .bind(dag_id)
+            .bind(name)
+            .bind(&slug)
+            .bind(auth::creator())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| taken_or(e, locale, dag_id))?;
+        sqlx::query(&format!(
+            "INSERT INTO nodes ({columns}) SELECT {columns} FROM jsonb_populate_recordset(NULL::nodes, $1)",
+            columns = NODE_COLUMNS
+        ))
+            .bind(JsonColumn(nodes))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO edges (id, source, target, dag_id, created_by, reason)
+             SELECT u.id, u.source, u.target, $1, u.created_by, u.reason
+             FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[], $5::text[], $6::text[]) AS u(id, source, target, created_by, reason)",
+        )
+            .bind(dag_id)
+            .bind(edges.iter().map(|_| ids::new_id()).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| new_ids[&e.source]).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| new_ids[&e.target]).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.created_by.clone()).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.reason.clone()).collect::<Vec<_>>())
+            .execute(&mut *tx)
+            .await?;
+        Ok(slug)
+    }))
+    .await;
+
+    let slug = result.map_err(|e| e.respond(locale, "copy_dag_failed"))?;
+    usage::record(pool, dag_id, Access::Edit);
+    let dag = DAG {
+        id: dag_id,
+        name: name.to_string(),
+        lifecycle: Lifecycle::Active,
+        deprecation_reason: None,
+        replaced_by: None,
+        slug: Some(slug),
+    };
+    Ok(Json(serde_json::json!({
+        "dag": dag,
+        "node_ids": new_ids,
+        "edge_count": edges.len(),
+    }))
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ClonePayload {
+    // Callers may bring their own id for the copy; it must not be in use yet
+    id: Option<Uuid>,
+    // Defaults to the source DAG's name
+    name: Option<String>,
+}
+
+// Copies the DAG, or one of its saved versions with ?version=
+pub async fn clone_dag(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(source_id): DagId,
+    Query(at): Query<VersionParam>,
+    Json(payload): Json<ClonePayload>,
+) -> Result<Response, AppError> {
+    let (source, nodes, edges) = versions::loaded_at(&pool, &locale, source_id, &at, "copy_dag_failed").await?;
+    usage::record(&pool, source_id, Access::Read);
+    let name = payload.name.unwrap_or(source.name);
+    copied(&pool, &locale, payload.id.unwrap_or_else(ids::new_id), &name, nodes, &edges).await
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Closure {
+    // Just the given nodes
+    #[default]
+    None,
+    // The given nodes and everything upstream of them, downstream of them,
+    // or both
+    Ancestors,
+    Descendants,
+    Both,
+}
+
+#[derive(Deserialize)]
+pub struct SubgraphPayload {
+    node_ids: Vec<Uuid>,
+    #[serde(default)]
+    closure: Closure,
+    id: Option<Uuid>,
+    name: Option<String>,
+}
+
+// The nodes reachable from `start` following edges forwards, or backwards
+fn reachable(start: &[Uuid], edges: &[Edge], forwards: bool) -> HashSet<Uuid> {
+    let mut next: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for edge in edges {
+        let (from, to) = if forwards { (edge.source, edge.target) } else { (edge.target, edge.source) };
+        next.entry(from).or_default().push(to);
+    }
+    let mut seen: HashSet<Uuid> = start.iter().copied().collect();
+    let mut queue: VecDeque<Uuid> = start.iter().copied().collect();
+    while let Some(id) = queue.pop_front() {
+        for &to in next.get(&id).into_iter().flatten() {
+            if seen.insert(to) {
+                queue.push_back(to);
+            }
+        }
+    }
+    seen
+}
+
+// Copies the given nodes, and with a closure their ancestors, descendants or
+// both, into a new DAG with the edges among them
+pub async fn extract_subgraph(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(source_id): DagId,
+    Query(at): Query<VersionParam>,
+    Json(payload): Json<SubgraphPayload>,
+) -> Result<Response, AppError> {
+    if payload.node_ids.is_empty() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "subgraph_empty", &[]));
+    }
+    let (source, nodes, edges) = versions::loaded_at(&pool, &locale, source_id, &at, "copy_dag_failed").await?;
+    usage::record(&pool, source_id, Access::Read);
+    let present: HashSet<Uuid> = nodes.iter().map(|n| n.id).collect();
+    if let Some(missing) = payload.node_ids.iter().find(|id| !present.contains(id)) {
+        return Err(locale.error(StatusCode::NOT_FOUND, "node_not_found_in_dag", &[("node", missing), ("dag", &source_id)]));
+    }
+
+    let mut selected: HashSet<Uuid> = payload.node_ids.iter().copied().collect();
+    if matches!(payload.closure, Closure::Ancestors | Closure::Both) {
+        selected.extend(reachable(&payload.node_ids, &edges, false));
+    }
+    if matches!(payload.closure, Closure::Descendants | Closure::Both) {
+        selected.extend(reachable(&payload.node_ids, &edges, true));
+    }
+    let nodes: Vec<Node> = nodes.into_iter().filter(|n| selected.contains(&n.id)).collect();
+    let name = payload.name.unwrap_or(source.name);
+    copied(&pool, &locale, payload.id.unwrap_or_else(ids::new_id), &name, nodes, &edges).await
+}
@@ -0,0 +1,240 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+
+const DEFAULT_BIND_ADDR: &str = "127.0.0.1:3000";
+const DEFAULT_CONFIG_FILE: &str = ".env";
+// sqlx's own default
+const DEFAULT_POOL_SIZE: u32 = 10;
+const DEFAULT_EXPORT_CONCURRENCY: usize = 2;
+// 8 MiB/s
+const DEFAULT_EXPORT_BYTES_PER_SEC: u64 = 8 * 1024 * 1024;
+// 64 MiB
+const DEFAULT_IMPORT_MAX_BYTES: usize = 64 * 1024 * 1024;
+const DEFAULT_IMPORT_MAX_NODES: usize = 100_000;
+const DEFAULT_IMPORT_MAX_EDGES: usize = 500_000;
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+// How new rows get their ids
+#[derive(Clone, Copy)]
+pub enum IdStrategy {
+    Uuid4,
+    Uuid7,
+    Ulid,
+}
+
+// Where task logs go, and the settings of every store they can be kept in
+// or moved between. A store is configured when its settings are there.
+pub struct TaskLogSettings {
+    // postgres, filesystem, s3 or loki
+    pub store: String,
+    pub dir: Option<PathBuf>,
+    pub s3: Option<S3Settings>,
+    pub loki: Option<LokiSettings>,
+}
+
+#[derive(Clone)]
+pub struct S3Settings {
+    pub bucket: String,
+    pub region: String,
+    // Any S3-compatible service; AWS's endpoint for the region by default.
+    // Buckets are addressed by path.
+    pub endpoint: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub prefix: String,
+}
+
+#[derive(Clone)]
+pub struct LokiSettings {
+    pub url: String,
+    // Sent as X-Scope-OrgID to a multi-tenant Loki
+    pub tenant: Option<String>,
+}
+
+// Startup settings. Values come from the environment, which is first filled
+// in from CONFIG_FILE (.env unless set) without overriding what is already
+// there.
+pub struct Config {
+    pub bind_addr: SocketAddr,
+    // The service's address as tasks reach it, e.g. to write their outputs
+    pub public_url: String,
+    pub database_url: String,
+    pub pool_size: u32,
+    // info adds startup and shutdown lines, debug one line per request
+    pub log_level: LogLevel,
+    pub id_strategy: IdStrategy,
+    // Turns authentication on; requests carrying it act as an admin
+    pub admin_api_key: Option<String>,
+    // Seals connection secrets; changing it makes the stored ones unreadable
+    pub connections_key: Option<String>,
+    // Exports each API key may have in progress at once, and how fast they
+    // are sent to it together (0 for no limit)
+    pub export_concurrency: usize,
+    pub export_bytes_per_sec: u64,
+    // The largest import payload, and the most nodes and edges an import may
+    // hold, whether sent at once or in chunks
+    pub import_max_bytes: usize,
+    pub import_max_nodes: usize,
+    pub import_max_edges: usize,
+    pub task_logs: TaskLogSettings,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, String> {
+        match env::var("CONFIG_FILE") {
+            Ok(file) => {
+                dotenvy::from_filename(&file).map_err(|e| format!("Failed to read CONFIG_FILE {}: {}", file, e))?;
+            }
+            Err(_) => {
+                dotenvy::from_filename(DEFAULT_CONFIG_FILE).ok();
+            }
+        }
+
+        let bind_addr = setting("BIND_ADDR", DEFAULT_BIND_ADDR);
+        let bind_addr = bind_addr
+            .parse()
+            .map_err(|_| format!("BIND_ADDR must be an address like {}, got '{}'", DEFAULT_BIND_ADDR, bind_addr))?;
+        let public_url = match env::var("PUBLIC_URL").ok().filter(|v| !v.trim().is_empty()) {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}", reachable(bind_addr)),
+        };
+        let database_url = env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set".to_string())?;
+        let pool_size = setting("DB_POOL_SIZE", &DEFAULT_POOL_SIZE.to_string());
+        let pool_size = pool_size
+            .parse()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or_else(|| format!("DB_POOL_SIZE must be a positive number, got '{}'", pool_size))?;
+        let log_level = match setting("LOG_LEVEL", "info").to_lowercase().as_str() {
+            "error" => LogLevel::Error,
+            "warn" => LogLevel::Warn,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            other => return Err(format!("LOG_LEVEL must be error, warn, info or debug, got '{}'", other)),
+        };
+        let id_strategy = match setting("ID_STRATEGY", "uuid4").to_lowercase().as_str() {
+            "uuid4" => IdStrategy::Uuid4,
+            "uuid7" => IdStrategy::Uuid7,
+            "ulid" => IdStrategy::Ulid,
+            other => return Err(format!("ID_STRATEGY must be uuid4, uuid7 or ulid, got '{}'", other)),
+        };
+
+        let admin_api_key = env::var("ADMIN_API_KEY").ok().filter(|key| !key.trim().is_empty());
+        let connections_key = env::var("CONNECTIONS_KEY").ok().filter(|key| !key.trim().is_empty());
+
+        let export_concurrency = setting("EXPORT_CONCURRENCY", &DEFAULT_EXPORT_CONCURRENCY.to_string());
+        let export_concurrency = export_concurrency
+            .parse()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| format!("EXPORT_CONCURRENCY must be a positive number, got '{}'", export_concurrency))?;
+        let export_bytes_per_sec = setting("EXPORT_BYTES_PER_SEC", &DEFAULT_EXPORT_BYTES_PER_SEC.to_string());
+        let export_bytes_per_sec = export_bytes_per_sec
+            .parse()
+            .map_err(|_| format!("EXPORT_BYTES_PER_SEC must be a number of bytes, got '{}'", export_bytes_per_sec))?;
+
+        let [import_max_bytes, import_max_nodes, import_max_edges] = [
+            ("IMPORT_MAX_BYTES", DEFAULT_IMPORT_MAX_BYTES),
+            ("IMPORT_MAX_NODES", DEFAULT_IMPORT_MAX_NODES),
+            ("IMPORT_MAX_EDGES", DEFAULT_IMPORT_MAX_EDGES),
+        ]
+        .map(|(name, default)| {
+            let value = setting(name, &default.to_string());
+            value
+                .parse()
+                .ok()
+                .filter(|limit| *limit > 0)
+                .ok_or_else(|| format!("{} must be a positive number, got '{}'", name, value))
+        });
+
+        let task_logs = task_log_settings()?;
+
+        Ok(Config {
+            bind_addr,
+            public_url,
+            database_url,
+            pool_size,
+            log_level,
+            id_strategy,
+            admin_api_key,
+            connections_key,
+            export_concurrency,
+            export_bytes_per_sec,
+            import_max_bytes: import_max_bytes?,
+            import_max_nodes: import_max_nodes?,
+            import_max_edges: import_max_edges?,
+            task_logs,
+        })
+    }
+}
+
+// The bind address as a local client can reach it: loopback in place of
+// "any address"
+fn reachable(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port()),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port()),
+        _ => addr,
+    }
+}
+
+fn task_log_settings() -> Result<TaskLogSettings, String> {
+    let optional = |name: &str| env::var(name).ok().filter(|v| !v.trim().is_empty());
+    let store = setting("TASK_LOG_STORE", "postgres").to_lowercase();
+    let s3 = match optional("TASK_LOG_S3_BUCKET") {
+        Some(bucket) => {
+            let region = setting("TASK_LOG_S3_REGION", DEFAULT_S3_REGION);
+            let [access_key_id, secret_access_key] = ["TASK_LOG_S3_ACCESS_KEY_ID", "TASK_LOG_S3_SECRET_ACCESS_KEY"]
+                .map(|name| optional(name).ok_or_else(|| format!("{} must be set along with TASK_LOG_S3_BUCKET", name)));
+            Some(S3Settings {
+                bucket,
+                endpoint: setting("TASK_LOG_S3_ENDPOINT", &format!("https://s3.{}.amazonaws.com", region))
+                    .trim_end_matches('/')
+                    .to_string(),
+                region,
+                access_key_id: access_key_id?,
+                secret_access_key: secret_access_key?,
+                prefix: setting("TASK_LOG_S3_PREFIX", ""),
+            })
+        }
+        None => None,
+    };
+    let settings = TaskLogSettings {
+        dir: optional("TASK_LOG_DIR").map(PathBuf::from),
+        s3,
+        loki: optional("TASK_LOG_LOKI_URL").map(|url| LokiSettings {
+            url: url.trim_end_matches('/').to_string(),
+            tenant: optional("TASK_LOG_LOKI_TENANT"),
+        }),
+        store,
+    };
+    let configured = match settings.store.as_str() {
+        "postgres" => true,
+        "filesystem" => settings.dir.is_some(),
+        "s3" => settings.s3.is_some(),
+        "loki" => settings.loki.is_some(),
+        other => return Err(format!("TASK_LOG_STORE must be postgres, filesystem, s3 or loki, got '{}'", other)),
+    };
+    if !configured {
+        let needs = match settings.store.as_str() {
+            "filesystem" => "TASK_LOG_DIR",
+            "s3" => "TASK_LOG_S3_BUCKET",
+            _ => "TASK_LOG_LOKI_URL",
+        };
+        return Err(format!("TASK_LOG_STORE={} needs {}", settings.store, needs));
+    }
+    Ok(settings)
+}
+
+fn setting(name: &str, default: &str) -> String {
+    env::var(name).ok().filter(|v| !v.trim().is_empty()).unwrap_or_else(|| default.to_string())
+}
@@ -0,0 +1,489 @@
+// Connections: named settings and credentials for the systems tasks talk to
+// (postgres, http, s3), managed under /admin/connections. A node lists the
+// ones its task needs in its metadata, as "connections": ["warehouse"], and
+// each is looked up when a run dispatches the task: the row for the DAG's
+// environment if there is one, the row without an environment otherwise.
+// A DAG owned by an API key only gets the connections that list the key in
+// their owners; DAGs without an owner get any.
+//
+// Secret fields (passwords, tokens) are sealed with AES-256-GCM under a key
+// derived from CONNECTIONS_KEY and are never answered back; without the key
+// connections can't hold secrets.
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use openssl::symm::{self, Cipher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json as JsonColumn;
+use sqlx::{FromRow, PgPool};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::changes::Change;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::Writable;
+
+const CONNECTION_COLUMNS: &str = "id, name, kind, environment, fields, secrets, owners, created_by, created_at, updated_at";
+// Stands in for secret values in answers
+pub const MASK: &str = "********";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+type Fields = BTreeMap<String, String>;
+
+static KEY: OnceLock<Option<[u8; 32]>> = OnceLock::new();
+
+// Takes CONNECTIONS_KEY from the config at startup
+pub fn configure(key: Option<&str>) {
+    KEY.get_or_init(|| key.map(|key| Sha256::digest(key.as_bytes()).into()));
+}
+
+pub fn key() -> Option<&'static [u8; 32]> {
+    KEY.get().and_then(Option::as_ref)
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Kind {
+    Postgres,
+    Http,
+    S3,
+}
+
+struct FieldSpec {
+    name: &'static str,
+    required: bool,
+    secret: bool,
+}
+
+const fn setting(name: &'static str, required: bool) -> FieldSpec {
+    FieldSpec { name, required, secret: false }
+}
+
+const fn secret(name: &'static str) -> FieldSpec {
+    FieldSpec { name, required: false, secret: true }
+}
+
+const POSTGRES_FIELDS: &[FieldSpec] = &[
+    setting("host", true),
+    setting("port", false),
+    setting("database", true),
+    setting("user", true),
+    secret("password"),
+];
+const HTTP_FIELDS: &[FieldSpec] = &[setting("url", true), setting("username", false), secret("password"), secret("token")];
+const S3_FIELDS: &[FieldSpec] = &[
+    setting("bucket", true),
+    setting("region", false),
+    setting("endpoint", false),
+    setting("access_key_id", false),
+    secret("secret_access_key"),
+];
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Postgres => "postgres",
+            Kind::Http => "http",
+            Kind::S3 => "s3",
+        }
+    }
+
+    fn fields(self) -> &'static [FieldSpec] {
+        match self {
+            Kind::Postgres => POSTGRES_FIELDS,
+            Kind::Http => HTTP_FIELDS,
+            Kind::S3 => S3_FIELDS,
+        }
+    }
+
+    fn is_secret(self, field: &str) -> bool {
+        self.fields().iter().any(|f| f.name == field && f.secret)
+    }
+}
+
+#[derive(FromRow)]
+struct Row {
+    id: Uuid,
+    name: String,
+    kind: Kind,
+    environment: Option<String>,
+    fields: JsonColumn<BTreeMap<String, String>>,
+    // Sealed values by field name
+    secrets: JsonColumn<BTreeMap<String, String>>,
+    owners: Vec<Uuid>,
+    created_by: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+// A connection as answered: secret fields that are set show as MASK
+#[derive(Serialize)]
+struct Connection {
+    id: Uuid,
+    name: String,
+    kind: Kind,
+    environment: Option<String>,
+    fields: BTreeMap<String, String>,
+    owners: Vec<Uuid>,
+    created_by: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<Row> for Connection {
+    fn from(row: Row) -> Self {
+        let mut fields = row.fields.0;
+        fields.extend(row.secrets.0.into_keys().map(|field| (field, MASK.to_string())));
+        Connection {
+            id: row.id,
+            name: row.name,
+            kind: row.kind,
+            environment: row.environment,
+            fields,
+            owners: row.owners,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+// Nonce, ciphertext and tag, base64-encoded. The row's id and the field
+// name go in as associated data so a sealed value can't be moved to another
+// field or row. Variables seal their secret values the same way.
+pub fn seal(key: &[u8; 32], id: Uuid, field: &str, value: &str) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce).map_err(|e| e.to_string())?;
+    let mut tag = [0u8; TAG_LEN];
+    let aad = [id.as_bytes(), field.as_bytes()].concat();
+    let ciphertext = symm::encrypt_aead(Cipher::aes_256_gcm(), key, Some(&nonce), &aad, value.as_bytes(), &mut tag)
+        .map_err(|e| e.to_string())?;
+    let sealed = [&nonce[..], &ciphertext, &tag].concat();
+    Ok(base64::engine::general_purpose::STANDARD.encode(sealed))
+}
+
+pub fn unseal(key: &[u8; 32], id: Uuid, field: &str, sealed: &str) -> Result<String, String> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(sealed).map_err(|e| e.to_string())?;
+    if bytes.len() < NONCE_LEN + TAG_LEN {
+        return Err(format!("sealed {} is too short", field));
+    }
+    let (nonce, rest) = bytes.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let aad = [id.as_bytes(), field.as_bytes()].concat();
+    let plaintext = symm::decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), &aad, ciphertext, tag)
+        .map_err(|_| format!("{} could not be decrypted; was CONNECTIONS_KEY changed?", field))?;
+    String::from_utf8(plaintext).map_err(|e| e.to_string())
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn connection_not_found(locale: &Locale, id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "connection_not_found", &[("id", &id)])
+}
+
+fn failed(locale: &Locale, error: String) -> AppError {
+    locale.error(StatusCode::INTERNAL_SERVER_ERROR, "seal_secrets_failed", &[("error", &error)])
+}
+
+// Splits the fields into settings and secrets, refusing unknown fields and
+// missing required ones. Empty values count as absent.
+fn split_fields(
+    locale: &Locale,
+    kind: Kind,
+    fields: Fields,
+) -> Result<(Fields, Fields), AppError> {
+    let (mut settings, mut secrets) = (BTreeMap::new(), BTreeMap::new());
+    for (field, value) in fields {
+        if !kind.fields().iter().any(|f| f.name == field) {
+            let known: Vec<&str> = kind.fields().iter().map(|f| f.name).collect();
+            return Err(locale
+                .error(StatusCode::UNPROCESSABLE_ENTITY, "connection_unknown_field", &[("field", &field), ("kind", &kind.as_str())])
+                .with_details(serde_json::json!({ "fields": known })));
+        }
+        if value.is_empty() {
+            continue;
+        }
+        if kind.is_secret(&field) {
+            secrets.insert(field, value);
+        } else {
+            settings.insert(field, value);
+        }
+    }
+    if let Some(missing) = kind.fields().iter().find(|f| f.required && !settings.contains_key(f.name)) {
+        return Err(locale.error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "connection_missing_field",
+            &[("field", &missing.name), ("kind", &kind.as_str())],
+        ));
+    }
+    Ok((settings, secrets))
+}
+
+fn sealed(locale: &Locale, id: Uuid, secrets: BTreeMap<String, String>) -> Result<BTreeMap<String, String>, AppError> {
+    if secrets.is_empty() {
+        return Ok(secrets);
+    }
+    let key = key().ok_or_else(|| locale.error(StatusCode::CONFLICT, "connections_key_missing", &[]))?;
+    secrets
+        .into_iter()
+        .map(|(field, value)| {
+            let sealed = seal(key, id, &field, &value).map_err(|e| failed(locale, e))?;
+            Ok((field, sealed))
+        })
+        .collect()
+}
+
+fn saved_or(e: sqlx::Error, locale: &Locale, name: &str, key: &str) -> AppError {
+    match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+            locale.error(StatusCode::CONFLICT, "connection_exists", &[("name", &name)])
+        }
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23503") => {
+            locale.error(StatusCode::NOT_FOUND, "environment_not_found", &[("name", &name)])
+        }
+        _ => AppError::database(e, locale, key),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateConnectionPayload {
+    name: String,
+    kind: Kind,
+    // Makes the connection an override for DAGs in that environment
+    environment: Option<String>,
+    #[serde(default)]
+    fields: BTreeMap<String, String>,
+    // API keys whose DAGs may use it, besides DAGs without an owner
+    #[serde(default)]
+    owners: Vec<Uuid>,
+}
+
+pub async fn create_connection(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
+    Json(payload): Json<CreateConnectionPayload>,
+) -> Result<Response, AppError> {
+    let name = payload.name.trim();
+    if !valid_name(name) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_connection_name", &[("name", &name)]));
+    }
+    let (settings, secrets) = split_fields(&locale, payload.kind, payload.fields)?;
+    let id = ids::new_id();
+    let secrets = sealed(&locale, id, secrets)?;
+    let row = sqlx::query_as::<_, Row>(&format!(
+        "INSERT INTO connections (id, name, kind, environment, fields, secrets, owners, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING {}",
+        CONNECTION_COLUMNS
+    ))
+        .bind(id)
+        .bind(name)
+        .bind(payload.kind)
+        .bind(payload.environment.as_deref().map(str::trim))
+        .bind(JsonColumn(&settings))
+        .bind(JsonColumn(&secrets))
+        .bind(&payload.owners)
+        .bind(&change.created_by)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| saved_or(e, &locale, name, "create_connection_failed"))?;
+    let location = format!("/admin/connections/{}", row.id);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(Connection::from(row))).into_response())
+}
+
+pub async fn list_connections(
+    State(pool): State<PgPool>,
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let rows = sqlx::query_as::<_, Row>(&format!(
+        "SELECT {} FROM connections ORDER BY name, environment NULLS FIRST",
+        CONNECTION_COLUMNS
+    ))
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_connections_failed"))?;
+    Ok(Json(rows.into_iter().map(Connection::from).collect::<Vec<_>>()).into_response())
+}
+
+async fn fetch_row(pool: &PgPool, locale: &Locale, id: Uuid) -> Result<Row, AppError> {
+    sqlx::query_as::<_, Row>(&format!("SELECT {} FROM connections WHERE id = $1", CONNECTION_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::database(e, locale, "fetch_connections_failed"))?
+        .ok_or_else(|| connection_not_found(locale, id))
+}
+
+pub async fn get_connection(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let row = fetch_row(&pool, &locale, id).await?;
+    Ok(Json(Connection::from(row)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UpdateConnectionPayload {
+    fields: BTreeMap<String, String>,
+    owners: Option<Vec<Uuid>>,
+}
+
+// Replaces the settings, and the owners if given. Secret fields left out keep
+// their value and an empty one removes it, so clients never need to send
+// secrets back.
+pub async fn update_connection(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdateConnectionPayload>,
+) -> Result<Response, AppError> {
+    let row = fetch_row(&pool, &locale, id).await?;
+    // A masked value sent back as it was answered is left out too
+    let fields: BTreeMap<String, String> = payload.fields.into_iter().filter(|(_, value)| value != MASK).collect();
+    let mut kept = row.secrets.0;
+    kept.retain(|field, _| !fields.contains_key(field));
+    let (settings, secrets) = split_fields(&locale, row.kind, fields)?;
+    let mut secrets = sealed(&locale, id, secrets)?;
+    secrets.extend(kept);
+    let row = sqlx::query_as::<_, Row>(&format!(
+        "UPDATE connections SET fields = $2, secrets = $3, owners = COALESCE($4, owners), updated_at = now()
+         WHERE id = $1 RETURNING {}",
+        CONNECTION_COLUMNS
+    ))
+        .bind(id)
+        .bind(JsonColumn(&settings))
+        .bind(JsonColumn(&secrets))
+        .bind(&payload.owners)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "update_connection_failed"))?
+        .ok_or_else(|| connection_not_found(&locale, id))?;
+    Ok(Json(Connection::from(row)).into_response())
+}
+
+// Tasks that still reference the connection fail when next dispatched
+pub async fn delete_connection(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let deleted = sqlx::query("DELETE FROM connections WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "delete_connection_failed"))?;
+    if deleted.rows_affected() == 0 {
+        return Err(connection_not_found(&locale, id));
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// A connection as a task gets it, secrets included
+pub struct Resolved {
+    pub name: String,
+    pub kind: Kind,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Resolved {
+    // The connection as one URI, like Airflow's AIRFLOW_CONN_* variables
+    pub fn uri(&self) -> String {
+        let field = |name: &str| self.fields.get(name).map(String::as_str).unwrap_or_default();
+        match self.kind {
+            Kind::Postgres => {
+                let password = self.fields.get("password").map(|p| format!(":{}", escape(p))).unwrap_or_default();
+                let port = self.fields.get("port").map(|p| format!(":{}", p)).unwrap_or_default();
+                format!("postgres://{}{}@{}{}/{}", escape(field("user")), password, field("host"), port, escape(field("database")))
+            }
+            Kind::Http => field("url").to_string(),
+            Kind::S3 => format!("s3://{}", field("bucket")),
+        }
+    }
+
+    // The Authorization header an http connection adds to requests: its
+    // token as a bearer token, or else its username and password
+    pub fn authorization(&self) -> Option<String> {
+        if let Some(token) = self.fields.get("token") {
+            return Some(format!("Bearer {}", token));
+        }
+        let username = self.fields.get("username")?;
+        let password = self.fields.get("password").map(String::as_str).unwrap_or_default();
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        Some(format!("Basic {}", credentials))
+    }
+}
+
+// Percent-encodes what can't appear in the user, password or path of a URI
+fn escape(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// A condition that holds when the row may be handed to tasks of the DAG whose
+// id is parameter `dag`: the DAG has no owner, or the row lists it in owners.
+// Variables share it.
+pub fn usable_by(dag: usize) -> String {
+    format!(
+        "(SELECT owner_key_id FROM dags WHERE id = ${d}) IS NULL OR (SELECT owner_key_id FROM dags WHERE id = ${d}) = ANY(owners)",
+        d = dag
+    )
+}
+
+// Looks up the connections a task of the DAG references, preferring the
+// DAG's environment's overrides, among those its owner may use. The inner
+// error says why the task can't be given them.
+pub async fn resolve(pool: &PgPool, dag_id: Uuid, names: &[String]) -> Result<Result<Vec<Resolved>, String>, sqlx::Error> {
+    if names.is_empty() {
+        return Ok(Ok(Vec::new()));
+    }
+    let rows = sqlx::query_as::<_, Row>(&format!(
+        "SELECT DISTINCT ON (name) {} FROM connections
+         WHERE name = ANY($2) AND (environment IS NULL OR environment = (SELECT environment FROM dags WHERE id = $1))
+             AND ({})
+         ORDER BY name, environment NULLS LAST",
+        CONNECTION_COLUMNS,
+        usable_by(1)
+    ))
+        .bind(dag_id)
+        .bind(names)
+        .fetch_all(pool)
+        .await?;
+    let mut resolved = Vec::with_capacity(names.len());
+    for name in names {
+        let Some(row) = rows.iter().find(|r| &r.name == name) else {
+            return Ok(Err(format!("Connection '{}' not found, or not shared with the DAG's owner", name)));
+        };
+        let mut fields = row.fields.0.clone();
+        for (field, sealed) in &row.secrets.0 {
+            let Some(key) = key() else {
+                return Ok(Err(format!("Connection '{}' has secrets but CONNECTIONS_KEY is not set", name)));
+            };
+            match unseal(key, row.id, field, sealed) {
+                Ok(value) => fields.insert(field.clone(), value),
+                Err(e) => return Ok(Err(format!("Connection '{}': {}", name, e))),
+            };
+        }
+        resolved.push(Resolved { name: row.name.clone(), kind: row.kind, fields });
+    }
+    Ok(Ok(resolved))
+}
@@ -0,0 +1,137 @@
+// Five-field cron expressions (minute hour day-of-month month day-of-week),
+// evaluated in UTC. Fields take *, numbers, names (jan, mon), ranges, lists
+// and steps; @hourly, @daily, @weekly, @monthly and @yearly are shorthands.
+// As in classic cron, when both day fields are restricted a day matching
+// either one fires.
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+
+// Long enough for 29 February to come around
+const SEARCH_YEARS: i32 = 8;
+
+const MONTHS: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+pub struct Cron {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+        Ok(Cron {
+            minutes: field(minute, 0, 59, &[])?,
+            hours: field(hour, 0, 23, &[])? as u32,
+            days: field(day, 1, 31, &[])? as u32,
+            months: field(month, 1, 12, MONTHS)? as u16,
+            // 7 is Sunday too
+            weekdays: {
+                let bits = field(weekday, 0, 7, WEEKDAYS)?;
+                ((bits | bits >> 7) & 0x7f) as u8
+            },
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        if self.months & 1 << date.month() == 0 {
+            return false;
+        }
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    // The first time strictly after `after` that the expression fires, or
+    // None if it never does (e.g. 30 February)
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.naive_utc() + Duration::minutes(1);
+        let mut date = start.date();
+        let mut from = (start.hour(), start.minute());
+        while date.year() <= after.year() + SEARCH_YEARS {
+            if self.day_matches(date) {
+                for hour in from.0..24 {
+                    if self.hours & 1 << hour == 0 {
+                        continue;
+                    }
+                    let first_minute = if hour == from.0 { from.1 } else { 0 };
+                    if let Some(minute) = (first_minute..60).find(|m| self.minutes & 1 << m != 0) {
+                        let time = NaiveTime::from_hms_opt(hour, minute, 0)?;
+                        return Some(Utc.from_utc_datetime(&date.and_time(time)));
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+            from = (0, 0);
+        }
+        None
+    }
+}
+
+// The values a field allows, as bits
+fn field(text: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err("step must not be 0".to_string());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (low, high) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((low, high)) => (value(low, min, max, names)?, value(high, min, max, names)?),
+                // n/step runs from n to the end of the field
+                None if step > 1 => (value(range, min, max, names)?, max),
+                None => {
+                    let v = value(range, min, max, names)?;
+                    (v, v)
+                }
+            },
+        };
+        if low > high {
+            return Err(format!("range '{}' runs backwards", range));
+        }
+        for v in (low..=high).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+fn value(text: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, String> {
+    let lower = text.to_lowercase();
+    // Month names count from 1, weekday names from 0
+    if let Some(i) = names.iter().position(|name| *name == lower) {
+        return Ok(i as u32 + min);
+    }
+    match text.parse() {
+        Ok(v) if (min..=max).contains(&v) => Ok(v),
+        _ => Err(format!("'{}' is not between {} and {}", text, min, max)),
+    }
+}
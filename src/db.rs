@@ -0,0 +1,123 @@
+use axum::http::StatusCode;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::changes;
+use crate::error::AppError;
+use crate::i18n::Locale;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_BACKOFF: Duration = Duration::from_millis(20);
+
+pub type Work<'c, T> = Pin<Box<dyn Future<Output = Result<T, TxError>> + Send + 'c>>;
+
+// Why a unit of work didn't commit: the database failed, concurrent writers
+// kept conflicting with it, or the work itself refused to go on and already
+// knows what to answer
+pub enum TxError {
+    Database(sqlx::Error),
+    Contended(sqlx::Error),
+    Rejected(AppError),
+}
+
+impl From<sqlx::Error> for TxError {
+    fn from(e: sqlx::Error) -> Self {
+        TxError::Database(e)
+    }
+}
+
+impl TxError {
+    // `key` names the message for plain database failures
+    pub fn respond(self, locale: &Locale, key: &str) -> AppError {
+        match self {
+            TxError::Rejected(error) => error,
+            TxError::Contended(e) => locale.error(StatusCode::CONFLICT, "write_conflict", &[("error", &e)]),
+            TxError::Database(e) => AppError::database(e, locale, key),
+        }
+    }
+}
+
+// Serialization failures and deadlocks say nothing about the work itself;
+// running it again usually succeeds
+fn is_transient(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(db) => matches!(db.code().as_deref(), Some("40001") | Some("40P01")),
+        _ => false,
+    }
+}
+
+// A unique violation on a primary key, i.e. the id is already taken
+pub fn is_duplicate_id(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(db) => {
+            db.code().as_deref() == Some("23505") && db.constraint().is_some_and(|c| c.ends_with("_pkey"))
+        }
+        _ => false,
+    }
+}
+
+// Exponential backoff with full jitter, so writers that collided once don't
+// collide again on the retry
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_BACKOFF.as_micros() as u64 * (1 << attempt);
+    Duration::from_micros((Uuid::new_v4().as_u128() % u128::from(ceiling)) as u64)
+}
+
+// Says who is making the transaction's changes, from the request's API key,
+// X-Actor and X-Change-Reason, for the triggers that record them in events.
+// Transactions outside a request, like scheduled work, go unattributed.
+async fn attribute(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    let change = changes::current();
+    let key_id = auth::creator();
+    if change.is_none() && key_id.is_none() {
+        return Ok(());
+    }
+    let change = change.unwrap_or(changes::Change { created_by: None, reason: None });
+    sqlx::query(
+        "SELECT set_config('dag.actor', COALESCE($1, ''), true), set_config('dag.key_id', COALESCE($2::text, ''), true),
+                set_config('dag.reason', COALESCE($3, ''), true)",
+    )
+        .bind(change.created_by)
+        .bind(key_id)
+        .bind(change.reason)
+        .execute(&mut *tx)
+        .await
+        .map(|_| ())
+}
+
+// Runs `work` in a single transaction. It is committed when `work` returns
+// Ok and rolled back on any error, so handlers that touch several tables
+// either apply completely or not at all. `work` may borrow from the caller
+// for 'a. It is run again, up to MAX_ATTEMPTS times in total, when Postgres
+// aborts the transaction over a serialization failure or deadlock.
+pub async fn unit_of_work<'a, T, F>(pool: &PgPool, work: F) -> Result<T, TxError>
+where
+    F: for<'c> Fn(&'c mut Transaction<'a, Postgres>) -> Work<'c, T>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let mut tx: Transaction<'a, Postgres> = pool.begin().await?;
+            attribute(&mut tx).await?;
+            let value = work(&mut tx).await?;
+            tx.commit().await?;
+            Ok(value)
+        }
+        .await;
+
+        match result {
+            Err(TxError::Database(e)) if is_transient(&e) => {
+                if attempt == MAX_ATTEMPTS {
+                    return Err(TxError::Contended(e));
+                }
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
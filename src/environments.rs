@@ -0,0 +1,747 @@
+// Environments (dev, staging, prod, ...) and promotion of DAG versions from
+// one environment to the next. Promoting a saved version copies its nodes and
+// edges into the DAG that stands for it in the next environment, making that
+// DAG on the first promotion. Names the environments list under the same
+// reference in `refs` (a dataset, a connection) are rewritten from the one
+// environment's to the next one's in node tasks and metadata on the way.
+//
+// Promotions into an environment that requires approval wait until someone
+// other than the requester approves them; with authentication on, only admin
+// keys may decide.
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json as JsonColumn;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::changes::Change;
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::events;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::Writable;
+use crate::slugs::{self, DagId};
+use crate::usage::{self, Access};
+use crate::versions::{self, NODE_COLUMNS};
+use crate::{lock_dag, writable, Node};
+
+const ENVIRONMENT_COLUMNS: &str = "name, position, requires_approval, refs, created_at";
+const PROMOTION_COLUMNS: &str = "id, dag_id, version, from_environment, to_environment, status, target_dag_id, rewrites,
+     requested_by, requested_by_key_id, reason, decided_by, decided_at, created_at";
+
+#[derive(Serialize, FromRow)]
+struct Environment {
+    name: String,
+    position: i32,
+    requires_approval: bool,
+    refs: JsonColumn<BTreeMap<String, String>>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum PromotionStatus {
+    Pending,
+    Applied,
+    Rejected,
+}
+
+impl PromotionStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            PromotionStatus::Pending => "pending",
+            PromotionStatus::Applied => "applied",
+            PromotionStatus::Rejected => "rejected",
+        }
+    }
+}
+
+// One reference rewritten in one field of a node. node_id is the node's id in
+// the promoted version.
+#[derive(Serialize, Deserialize)]
+struct Rewrite {
+    node_id: Uuid,
+    field: String,
+    reference: String,
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, FromRow)]
+struct Promotion {
+    id: Uuid,
+    dag_id: Uuid,
+    version: i32,
+    from_environment: String,
+    to_environment: String,
+    status: PromotionStatus,
+    // Set once the promotion is applied
+    target_dag_id: Option<Uuid>,
+    rewrites: JsonColumn<Vec<Rewrite>>,
+    requested_by: Option<String>,
+    // None for ADMIN_API_KEY and with authentication off
+    requested_by_key_id: Option<Uuid>,
+    reason: Option<String>,
+    decided_by: Option<String>,
+    decided_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+fn environment_not_found(locale: &Locale, name: &str) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "environment_not_found", &[("name", &name)])
+}
+
+fn promotion_not_found(locale: &Locale, id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "promotion_not_found", &[("id", &id)])
+}
+
+async fn fetch_environment<'e, E: PgExecutor<'e>>(executor: E, name: &str) -> Result<Option<Environment>, sqlx::Error> {
+    sqlx::query_as::<_, Environment>(&format!("SELECT {} FROM environments WHERE name = $1", ENVIRONMENT_COLUMNS))
+        .bind(name)
+        .fetch_optional(executor)
+        .await
+}
+
+// The environment that comes after `name`, if any
+async fn next_environment<'e, E: PgExecutor<'e>>(executor: E, name: &str) -> Result<Option<Environment>, sqlx::Error> {
+    sqlx::query_as::<_, Environment>(&format!(
+        "SELECT {} FROM environments WHERE position > (SELECT position FROM environments WHERE name = $1)
+         ORDER BY position LIMIT 1",
+        ENVIRONMENT_COLUMNS
+    ))
+        .bind(name)
+        .fetch_optional(executor)
+        .await
+}
+
+pub async fn list_environments(
+    State(pool): State<PgPool>,
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let environments = sqlx::query_as::<_, Environment>(&format!(
+        "SELECT {} FROM environments ORDER BY position",
+        ENVIRONMENT_COLUMNS
+    ))
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_environments_failed"))?;
+    Ok(Json(environments).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct EnvironmentPayload {
+    position: i32,
+    #[serde(default)]
+    requires_approval: bool,
+    #[serde(default)]
+    refs: BTreeMap<String, String>,
+}
+
+// Creates the environment or replaces its settings
+pub async fn put_environment(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(name): Path<String>,
+    Json(payload): Json<EnvironmentPayload>,
+) -> Result<Response, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_environment_name", &[]));
+    }
+    let environment = sqlx::query_as::<_, Environment>(&format!(
+        "INSERT INTO environments (name, position, requires_approval, refs) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (name) DO UPDATE SET position = $2, requires_approval = $3, refs = $4
+         RETURNING {}",
+        ENVIRONMENT_COLUMNS
+    ))
+        .bind(name)
+        .bind(payload.position)
+        .bind(payload.requires_approval)
+        .bind(JsonColumn(&payload.refs))
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => locale.error(
+                StatusCode::CONFLICT,
+                "environment_position_taken",
+                &[("position", &payload.position)],
+            ),
+            _ => AppError::database(e, &locale, "update_environment_failed"),
+        })?;
+    Ok(Json(environment).into_response())
+}
+
+async fn dag_environment(pool: &PgPool, locale: &Locale, dag_id: Uuid) -> Result<Response, AppError> {
+    let environment = async {
+        let (environment, promoted_from): (Option<String>, Option<Uuid>) =
+            sqlx::query_as("SELECT environment, promoted_from FROM dags WHERE id = $1")
+                .bind(dag_id)
+                .fetch_one(pool)
+                .await?;
+        let promoted_to: Vec<(Uuid, Option<String>)> =
+            sqlx::query_as("SELECT id, environment FROM dags WHERE promoted_from = $1 ORDER BY environment")
+                .bind(dag_id)
+                .fetch_all(pool)
+                .await?;
+        Ok::<_, sqlx::Error>((environment, promoted_from, promoted_to))
+    };
+    let (environment, promoted_from, promoted_to) = environment
+        .await
+        .map_err(|e| AppError::database(e, locale, "fetch_dag_failed"))?;
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "environment": environment,
+        "promoted_from": promoted_from,
+        "promoted_to": promoted_to
+            .into_iter()
+            .map(|(id, environment)| serde_json::json!({ "dag_id": id, "environment": environment }))
+            .collect::<Vec<_>>(),
+    }))
+        .into_response())
+}
+
+pub async fn get_dag_environment(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    let response = dag_environment(&pool, &locale, dag_id).await?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct DagEnvironmentPayload {
+    // null takes the DAG out of the promotion pipeline
+    environment: Option<String>,
+}
+
+pub async fn set_dag_environment(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<DagEnvironmentPayload>,
+) -> Result<Response, AppError> {
+    let (environment, locale) = (payload.environment.as_deref().map(str::trim), &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id).map_err(TxError::Rejected)?;
+        if let Some(name) = environment {
+            if fetch_environment(&mut *tx, name).await?.is_none() {
+                return Err(TxError::Rejected(environment_not_found(locale, name)));
+            }
+        }
+        sqlx::query("UPDATE dags SET environment = $2 WHERE id = $1")
+            .bind(dag_id)
+            .bind(environment)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| match &e {
+                // Another DAG promoted from the same one is already there
+                sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => TxError::Rejected(locale.error(
+                    StatusCode::CONFLICT,
+                    "environment_taken_by_promotion",
+                    &[("id", &dag_id), ("environment", &environment.unwrap_or_default())],
+                )),
+                _ => TxError::Database(e),
+            })?;
+        Ok(())
+    }))
+    .await;
+
+    result.map_err(|e| e.respond(locale, "update_dag_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    dag_environment(&pool, locale, dag_id).await
+}
+
+struct Pair {
+    reference: String,
+    from: String,
+    to: String,
+}
+
+// The names to rewrite when promoting from `from` to `to`: references both
+// environments list under different names, longest name first so that a name
+// containing another one is rewritten as a whole
+fn rewrite_pairs(from: &Environment, to: &Environment) -> Vec<Pair> {
+    let mut pairs: Vec<Pair> = from
+        .refs
+        .iter()
+        .filter_map(|(reference, old)| {
+            let new = to.refs.get(reference)?;
+            (!old.is_empty() && old != new).then(|| Pair { reference: reference.clone(), from: old.clone(), to: new.clone() })
+        })
+        .collect();
+    pairs.sort_by(|a, b| b.from.len().cmp(&a.from.len()).then_with(|| a.reference.cmp(&b.reference)));
+    pairs
+}
+
+// Replaces the names in one pass, so a rewritten name is never rewritten again
+fn rewrite_text(text: &str, pairs: &[Pair], used: &mut BTreeSet<usize>) -> Option<String> {
+    let mut rewritten = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut changed = false;
+    while let Some(c) = rest.chars().next() {
+        match pairs.iter().position(|p| rest.starts_with(p.from.as_str())) {
+            Some(i) => {
+                rewritten.push_str(&pairs[i].to);
+                rest = &rest[pairs[i].from.len()..];
+                used.insert(i);
+                changed = true;
+            }
+            None => {
+                rewritten.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    changed.then_some(rewritten)
+}
+
+// Rewrites every string in the value; object keys are left alone
+fn rewrite_value(value: &mut serde_json::Value, pairs: &[Pair], used: &mut BTreeSet<usize>) {
+    match value {
+        serde_json::Value::String(text) => {
+            if let Some(rewritten) = rewrite_text(text, pairs, used) {
+                *text = rewritten;
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| rewrite_value(item, pairs, used)),
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(|field| rewrite_value(field, pairs, used)),
+        _ => {}
+    }
+}
+
+// Rewrites the references in the nodes' tasks and metadata and reports what
+// changed
+fn rewrite_nodes(nodes: &mut [Node], pairs: &[Pair]) -> Vec<Rewrite> {
+    let mut rewrites = Vec::new();
+    let mut report = |node_id: Uuid, field: &str, used: BTreeSet<usize>| {
+        rewrites.extend(used.into_iter().map(|i| Rewrite {
+            node_id,
+            field: field.to_string(),
+            reference: pairs[i].reference.clone(),
+            from: pairs[i].from.clone(),
+            to: pairs[i].to.clone(),
+        }));
+    };
+    for node in nodes.iter_mut() {
+        if let Some(task) = &mut node.task {
+            let mut used = BTreeSet::new();
+            let mut value = serde_json::json!(task.0);
+            rewrite_value(&mut value, pairs, &mut used);
+            // A rewrite that leaves no valid task behind keeps the old one
+            if let Ok(rewritten) = serde_json::from_value(value) {
+                task.0 = rewritten;
+                report(node.id, "task", used);
+            }
+        }
+        let mut used = BTreeSet::new();
+        rewrite_value(&mut node.metadata.0, pairs, &mut used);
+        report(node.id, "metadata", used);
+    }
+    rewrites
+}
+
+// The version's nodes and edges with references rewritten for the promotion
+async fn promoted_contents(
+    tx: &mut sqlx::PgConnection,
+    locale: &Locale,
+    promotion: &Promotion,
+) -> Result<(Vec<Node>, Vec<crate::Edge>, Vec<Rewrite>), TxError> {
+    let Some((mut nodes, edges)) = versions::saved_contents(&mut *tx, promotion.dag_id, promotion.version).await? else {
+        return Err(TxError::Rejected(versions::version_not_found(locale, promotion.dag_id, promotion.version)));
+    };
+    let from = fetch_environment(&mut *tx, &promotion.from_environment).await?;
+    let to = fetch_environment(&mut *tx, &promotion.to_environment).await?;
+    let pairs = match (from, to) {
+        (Some(from), Some(to)) => rewrite_pairs(&from, &to),
+        _ => Vec::new(),
+    };
+    let rewrites = rewrite_nodes(&mut nodes, &pairs);
+    Ok((nodes, edges, rewrites))
+}
+
+// Replaces the contents of the DAG standing for the source in the target
+// environment with the promoted version, making that DAG if there is none
+// yet. Its working copy is saved as a version first. Nodes keep their ids
+// across promotions by slug; edges other DAGs had to the replaced nodes go.
+async fn apply(
+    tx: &mut sqlx::PgConnection,
+    locale: &Locale,
+    promotion: &Promotion,
+    decided_by: Option<&str>,
+) -> Result<Promotion, TxError> {
+    let (mut nodes, edges, rewrites) = promoted_contents(&mut *tx, locale, promotion).await?;
+
+    let existing: Option<Uuid> = sqlx::query_scalar("SELECT id FROM dags WHERE promoted_from = $1 AND environment = $2")
+        .bind(promotion.dag_id)
+        .bind(&promotion.to_environment)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let mut kept_ids = HashMap::new();
+    let target_id = match existing {
+        Some(target_id) => {
+            writable(lock_dag(&mut *tx, target_id).await?, locale, target_id).map_err(TxError::Rejected)?;
+            let message = locale.t(
+                "version_saved_before_promotion",
+                &[("version", &promotion.version), ("environment", &promotion.from_environment)],
+            );
+            versions::save_version(&mut *tx, target_id, Some(&message)).await?;
+            kept_ids = sqlx::query_as::<_, (String, Uuid)>("SELECT slug, id FROM nodes WHERE dag_id = $1 AND slug IS NOT NULL")
+                .bind(target_id)
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .collect();
+            sqlx::query(
+                "DELETE FROM edges WHERE dag_id = $1
+                    OR source IN (SELECT id FROM nodes WHERE dag_id = $1)
+                    OR target IN (SELECT id FROM nodes WHERE dag_id = $1)",
+            )
+                .bind(target_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM nodes WHERE dag_id = $1")
+                .bind(target_id)
+                .execute(&mut *tx)
+                .await?;
+            target_id
+        }
+        None => {
+            let target_id = ids::new_id();
+            let (name, owner): (String, Option<Uuid>) = sqlx::query_as("SELECT name, owner_key_id FROM dags WHERE id = $1")
+                .bind(promotion.dag_id)
+                .fetch_one(&mut *tx)
+                .await?;
+            let slug = slugs::dag_slug(&mut *tx, &name, target_id).await?;
+            sqlx::query(
+                "INSERT INTO dags (id, name, slug, owner_key_id, environment, promoted_from) VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+                .bind(target_id)
+                .bind(&name)
+                .bind(&slug)
+                .bind(owner)
+                .bind(&promotion.to_environment)
+                .bind(promotion.dag_id)
+                .execute(&mut *tx)
+                .await?;
+            target_id
+        }
+    };
+
+    let new_ids: HashMap<Uuid, Uuid> = nodes
+        .iter()
+        .map(|n| (n.id, n.slug.as_ref().and_then(|slug| kept_ids.get(slug).copied()).unwrap_or_else(ids::new_id)))
+        .collect();
+    for node in &mut nodes {
+        node.id = new_ids[&node.id];
+        node.dag_id = target_id;
+    }
+    sqlx::query(&format!(
+        "INSERT INTO nodes ({columns}) SELECT {columns} FROM jsonb_populate_recordset(NULL::nodes, $1)",
+        columns = NODE_COLUMNS
+    ))
+        .bind(JsonColumn(&nodes))
+        .execute(&mut *tx)
+        .await?;
+
+    // Edges into other DAGs stay behind
+    let edges: Vec<_> = edges
+        .iter()
+        .filter(|e| new_ids.contains_key(&e.source) && new_ids.contains_key(&e.target))
+        .collect();
+    sqlx::query(
+        "INSERT INTO edges (id, source, target, dag_id, created_by, reason)
+         SELECT u.id, u.source, u.target, $1, u.created_by, u.reason
+         FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[], $5::text[], $6::text[]) AS u(id, source, target, created_by, reason)",
+    )
+        .bind(target_id)
+        .bind(edges.iter().map(|_| ids::new_id()).collect::<Vec<_>>())
+        .bind(edges.iter().map(|e| new_ids[&e.source]).collect::<Vec<_>>())
+        .bind(edges.iter().map(|e| new_ids[&e.target]).collect::<Vec<_>>())
+        .bind(edges.iter().map(|e| e.created_by.clone()).collect::<Vec<_>>())
+        .bind(edges.iter().map(|e| e.reason.clone()).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+
+    let promotion = sqlx::query_as::<_, Promotion>(&format!(
+        "UPDATE promotions SET status = 'applied', target_dag_id = $2, rewrites = $3, decided_by = $4, decided_at = now()
+         WHERE id = $1 RETURNING {}",
+        PROMOTION_COLUMNS
+    ))
+        .bind(promotion.id)
+        .bind(target_id)
+        .bind(JsonColumn(&rewrites))
+        .bind(decided_by)
+        .fetch_one(&mut *tx)
+        .await?;
+    Ok(promotion)
+}
+
+fn announce(pool: &PgPool, promotion: &Promotion) {
+    if let Some(target_id) = promotion.target_dag_id {
+        usage::record(pool, target_id, Access::Edit);
+        events::publish(target_id, "graph_changed", serde_json::json!({ "cause": "promotion", "promotion_id": promotion.id }));
+    }
+}
+
+fn promotion_response(status: StatusCode, promotion: Promotion) -> Response {
+    let location = format!("/promotions/{}", promotion.id);
+    (status, [(header::LOCATION, location)], Json(promotion)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CreatePromotionPayload {
+    version: i32,
+}
+
+// Asks for a saved version of the DAG to be promoted to the next environment.
+// Unless that environment requires approval the promotion is applied at once.
+pub async fn create_promotion(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
+    DagId(dag_id): DagId,
+    Json(payload): Json<CreatePromotionPayload>,
+) -> Result<Response, AppError> {
+    let (version, change, locale) = (payload.version, &change, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let environment: Option<String> = sqlx::query_scalar("SELECT environment FROM dags WHERE id = $1")
+            .bind(dag_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let Some(environment) = environment else {
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "dag_not_in_environment", &[("id", &dag_id)])));
+        };
+        let Some(next) = next_environment(&mut *tx, &environment).await? else {
+            return Err(TxError::Rejected(locale.error(
+                StatusCode::CONFLICT,
+                "no_next_environment",
+                &[("id", &dag_id), ("environment", &environment)],
+            )));
+        };
+        let exists: Option<i32> = sqlx::query_scalar("SELECT version FROM dag_versions WHERE dag_id = $1 AND version = $2")
+            .bind(dag_id)
+            .bind(version)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            return Err(TxError::Rejected(versions::version_not_found(locale, dag_id, version)));
+        }
+
+        let promotion = sqlx::query_as::<_, Promotion>(&format!(
+            "INSERT INTO promotions (id, dag_id, version, from_environment, to_environment, requested_by, requested_by_key_id, reason)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING {}",
+            PROMOTION_COLUMNS
+        ))
+            .bind(ids::new_id())
+            .bind(dag_id)
+            .bind(version)
+            .bind(&environment)
+            .bind(&next.name)
+            .bind(&change.created_by)
+            .bind(auth::creator())
+            .bind(&change.reason)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !next.requires_approval {
+            return apply(&mut *tx, locale, &promotion, change.created_by.as_deref()).await;
+        }
+        // Shows the approver what will be rewritten
+        let (_, _, rewrites) = promoted_contents(&mut *tx, locale, &promotion).await?;
+        Ok(sqlx::query_as::<_, Promotion>(&format!(
+            "UPDATE promotions SET rewrites = $2 WHERE id = $1 RETURNING {}",
+            PROMOTION_COLUMNS
+        ))
+            .bind(promotion.id)
+            .bind(JsonColumn(&rewrites))
+            .fetch_one(&mut *tx)
+            .await?)
+    }))
+    .await;
+
+    let promotion = result.map_err(|e| e.respond(locale, "create_promotion_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    announce(&pool, &promotion);
+    Ok(promotion_response(StatusCode::CREATED, promotion))
+}
+
+// Promotions of the DAG and into it, newest first
+pub async fn list_promotions(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    let promotions = sqlx::query_as::<_, Promotion>(&format!(
+        "SELECT {} FROM promotions WHERE dag_id = $1 OR target_dag_id = $1 ORDER BY created_at DESC",
+        PROMOTION_COLUMNS
+    ))
+        .bind(dag_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_promotions_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(Json(promotions).into_response())
+}
+
+pub async fn get_promotion(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(promotion_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let promotion = sqlx::query_as::<_, Promotion>(&format!(
+        "SELECT {} FROM promotions WHERE id = $1 AND {}",
+        PROMOTION_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(promotion_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_promotions_failed"))?
+        .ok_or_else(|| promotion_not_found(&locale, promotion_id))?;
+    Ok(Json(promotion).into_response())
+}
+
+// Whether a caller would be deciding on their own promotion: with
+// authentication on (`key` set to the caller's key id) whoever used the same
+// API key, whatever X-Actor they send; with it off, whoever sends the same
+// X-Actor
+fn same_requester(promotion: &Promotion, key: Option<Option<Uuid>>, actor: Option<&str>) -> bool {
+    match key {
+        Some(key) => promotion.requested_by_key_id == key,
+        None => promotion.requested_by.is_some() && promotion.requested_by.as_deref() == actor,
+    }
+}
+
+// Locks a pending promotion the caller may decide on
+async fn decidable(
+    tx: &mut sqlx::PgConnection,
+    locale: &Locale,
+    change: &Change,
+    promotion_id: Uuid,
+) -> Result<Promotion, TxError> {
+    if !auth::admin() {
+        return Err(TxError::Rejected(locale.error(StatusCode::FORBIDDEN, "promotion_needs_admin", &[])));
+    }
+    let promotion = sqlx::query_as::<_, Promotion>(&format!(
+        "SELECT {} FROM promotions WHERE id = $1 AND {} FOR UPDATE",
+        PROMOTION_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(promotion_id)
+        .bind(auth::owner())
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| TxError::Rejected(promotion_not_found(locale, promotion_id)))?;
+    if promotion.status != PromotionStatus::Pending {
+        return Err(TxError::Rejected(locale.error(
+            StatusCode::CONFLICT,
+            "promotion_not_pending",
+            &[("id", &promotion_id), ("status", &promotion.status.as_str())],
+        )));
+    }
+    let deciding = auth::authenticated().then(auth::creator);
+    if same_requester(&promotion, deciding, change.created_by.as_deref()) {
+        return Err(TxError::Rejected(locale.error(StatusCode::FORBIDDEN, "promotion_self_approval", &[("id", &promotion_id)])));
+    }
+    Ok(promotion)
+}
+
+pub async fn approve_promotion(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
+    Path(promotion_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let (change, locale) = (&change, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let promotion = decidable(&mut *tx, locale, change, promotion_id).await?;
+        apply(&mut *tx, locale, &promotion, change.created_by.as_deref()).await
+    }))
+    .await;
+
+    let promotion = result.map_err(|e| e.respond(locale, "approve_promotion_failed"))?;
+    announce(&pool, &promotion);
+    Ok(Json(promotion).into_response())
+}
+
+pub async fn reject_promotion(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
+    Path(promotion_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let (change, locale) = (&change, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        decidable(&mut *tx, locale, change, promotion_id).await?;
+        Ok(sqlx::query_as::<_, Promotion>(&format!(
+            "UPDATE promotions SET status = 'rejected', decided_by = $2, decided_at = now() WHERE id = $1 RETURNING {}",
+            PROMOTION_COLUMNS
+        ))
+            .bind(promotion_id)
+            .bind(&change.created_by)
+            .fetch_one(&mut *tx)
+            .await?)
+    }))
+    .await;
+
+    let promotion = result.map_err(|e| e.respond(locale, "reject_promotion_failed"))?;
+    Ok(Json(promotion).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requested(key: Option<Uuid>, actor: Option<&str>) -> Promotion {
+        Promotion {
+            id: Uuid::nil(),
+            dag_id: Uuid::nil(),
+            version: 1,
+            from_environment: "dev".to_string(),
+            to_environment: "prod".to_string(),
+            status: PromotionStatus::Pending,
+            target_dag_id: None,
+            rewrites: JsonColumn(Vec::new()),
+            requested_by: actor.map(str::to_string),
+            requested_by_key_id: key,
+            reason: None,
+            decided_by: None,
+            decided_at: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn requesting_key_may_not_decide_whatever_its_actor() {
+        let key = Uuid::new_v4();
+        let promotion = requested(Some(key), Some("alice"));
+        assert!(same_requester(&promotion, Some(Some(key)), Some("bob")));
+        assert!(same_requester(&promotion, Some(Some(key)), None));
+        assert!(!same_requester(&promotion, Some(Some(Uuid::new_v4())), Some("alice")));
+        assert!(!same_requester(&promotion, Some(None), Some("alice")));
+    }
+
+    #[test]
+    fn actor_decides_without_authentication() {
+        let promotion = requested(None, Some("alice"));
+        assert!(same_requester(&promotion, None, Some("alice")));
+        assert!(!same_requester(&promotion, None, Some("bob")));
+        assert!(!same_requester(&requested(None, None), None, None));
+    }
+}
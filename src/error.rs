@@ -0,0 +1,91 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sqlx::postgres::PgDatabaseError;
+
+use crate::i18n::Locale;
+
+// What a handler answers when it can't do what was asked, rendered as
+// {code, message, details}. The code is the message's key, so clients can
+// tell errors apart without parsing messages in whatever language they asked
+// for.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    code: String,
+    message: String,
+    details: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a serde_json::Value>,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, code: &str, message: String) -> Self {
+        AppError { status, code: code.to_string(), message, details: None }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    // Adds one field to the details, keeping the ones already there
+    pub fn with_detail(mut self, key: &str, value: serde_json::Value) -> Self {
+        match &mut self.details {
+            Some(serde_json::Value::Object(details)) => {
+                details.insert(key.to_string(), value);
+            }
+            _ => self.details = Some(serde_json::json!({ key: value })),
+        }
+        self
+    }
+
+    // Constraint violations and invalid values are the caller's doing and
+    // answer 404, 409 or 422 with the constraint in the details. Anything
+    // else is ours: a 500 with the `key` message.
+    pub fn database(e: sqlx::Error, locale: &Locale, key: &str) -> Self {
+        if let sqlx::Error::RowNotFound = e {
+            return locale.error(StatusCode::NOT_FOUND, "row_not_found", &[]);
+        }
+        let Some(db) = e.as_database_error().and_then(|db| db.try_downcast_ref::<PgDatabaseError>()) else {
+            return locale.error(StatusCode::INTERNAL_SERVER_ERROR, key, &[("error", &e)]);
+        };
+        let (status, code) = match db.code() {
+            "23505" => (StatusCode::CONFLICT, "constraint_unique"),
+            "23503" if db.message().starts_with("update or delete") => (StatusCode::CONFLICT, "constraint_still_referenced"),
+            "23503" => (StatusCode::UNPROCESSABLE_ENTITY, "constraint_foreign_key"),
+            "23502" | "23514" | "23P01" => (StatusCode::UNPROCESSABLE_ENTITY, "constraint_check"),
+            // Data exceptions: malformed, out of range or too long values
+            code if code.starts_with("22") => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_value"),
+            _ => return locale.error(StatusCode::INTERNAL_SERVER_ERROR, key, &[("error", &e)]),
+        };
+        let constraint = db.constraint().unwrap_or_default();
+        locale
+            .error(status, code, &[("constraint", &constraint), ("error", &db.message())])
+            .with_details(serde_json::json!({ "constraint": db.constraint(), "detail": db.detail() }))
+    }
+
+    // The body this error answers with, for errors kept to report later
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self.body())
+    }
+
+    fn body(&self) -> Body<'_> {
+        Body { code: &self.code, message: &self.message, details: self.details.as_ref() }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
@@ -0,0 +1,229 @@
+// Live notifications of changes to DAGs. Handlers publish an event once
+// their change is committed and GET /dags/:id/ws streams a DAG's events to
+// WebSocket clients as JSON text frames. Events are not stored and only
+// reach clients of the same process, so a client that connects late or
+// falls behind reloads the DAG.
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hyper::{upgrade::Upgraded, Body, Request};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use sqlx::PgPool;
+use std::io;
+use std::sync::OnceLock;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadHalf};
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::find_dag;
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::telemetry;
+use crate::usage::{self, Access};
+
+// How many events a slow client may be behind before it misses some
+const CAPACITY: usize = 1024;
+
+// Clients only ever send control frames, so anything bigger is not one of ours
+const MAX_CLIENT_FRAME: u64 = 64 * 1024;
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+static CHANNEL: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+
+#[derive(Serialize, Clone)]
+pub struct Event {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    dag_id: Uuid,
+    at: DateTime<Utc>,
+    data: serde_json::Value,
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    pub fn data(&self) -> &serde_json::Value {
+        &self.data
+    }
+}
+
+fn channel() -> &'static broadcast::Sender<Event> {
+    CHANNEL.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+// The channel publish sends to, for the app state
+pub fn sender() -> broadcast::Sender<Event> {
+    channel().clone()
+}
+
+pub fn publish(dag_id: Uuid, kind: &'static str, data: serde_json::Value) {
+    telemetry::count_change(kind);
+    // Sending only fails when nobody is listening
+    let _ = channel().send(Event { kind, dag_id, at: Utc::now(), data });
+}
+
+fn has_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+// Streams the DAG's events until the client goes away or the DAG is deleted
+pub async fn dag_socket(
+    State(pool): State<PgPool>,
+    State(events): State<broadcast::Sender<Event>>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    mut request: Request<Body>,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+
+    let headers = request.headers();
+    let key = headers.get(header::SEC_WEBSOCKET_KEY).map(|key| key.as_bytes().trim_ascii());
+    let handshake = has_token(headers, header::UPGRADE, "websocket")
+        && has_token(headers, header::CONNECTION, "upgrade")
+        && has_token(headers, header::SEC_WEBSOCKET_VERSION, "13");
+    let Some(key) = key.filter(|_| handshake) else {
+        let mut response = locale.error(StatusCode::UPGRADE_REQUIRED, "websocket_required", &[]).into_response();
+        response.headers_mut().insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        response.headers_mut().insert(header::SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+        return Ok(response);
+    };
+    let accept = base64::engine::general_purpose::STANDARD
+        .encode(Sha1::new().chain_update(key).chain_update(HANDSHAKE_GUID).finalize());
+
+    // Subscribing before answering means nothing published after the
+    // handshake is missed
+    let events = events.subscribe();
+    let upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(socket) => stream(socket, dag_id, events).await,
+            Err(e) => tracing::warn!("WebSocket upgrade for DAG {} failed: {}", dag_id, e),
+        }
+    });
+    usage::record(&pool, dag_id, Access::Read);
+    Ok((
+        StatusCode::SWITCHING_PROTOCOLS,
+        [(header::UPGRADE, "websocket".to_string()), (header::CONNECTION, "upgrade".to_string()), (header::SEC_WEBSOCKET_ACCEPT, accept)],
+    )
+        .into_response())
+}
+
+enum Reply {
+    Pong(Vec<u8>),
+    Close(Vec<u8>),
+}
+
+async fn stream(socket: Upgraded, dag_id: Uuid, mut events: broadcast::Receiver<Event>) {
+    let (reader, mut writer) = tokio::io::split(socket);
+    let (replies, mut pending) = mpsc::channel(8);
+    let reading = tokio::spawn(read_frames(reader, replies));
+    loop {
+        let (bytes, last) = tokio::select! {
+            reply = pending.recv() => match reply {
+                Some(Reply::Pong(payload)) => (frame(OP_PONG, &payload), false),
+                // Echo the client's close code back
+                Some(Reply::Close(payload)) => (frame(OP_CLOSE, &payload[..payload.len().min(2)]), true),
+                None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) if event.dag_id == dag_id => {
+                    let text = serde_json::to_string(&event).unwrap_or_default();
+                    let mut bytes = frame(OP_TEXT, text.as_bytes());
+                    // Normal closure: there is nothing left to watch
+                    let deleted = event.kind == "dag_deleted";
+                    if deleted {
+                        bytes.extend(frame(OP_CLOSE, &1000u16.to_be_bytes()));
+                    }
+                    (bytes, deleted)
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    let text = serde_json::json!({ "type": "lagged", "dag_id": dag_id, "missed": missed }).to_string();
+                    (frame(OP_TEXT, text.as_bytes()), false)
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if writer.write_all(&bytes).await.is_err() || last {
+            break;
+        }
+    }
+    reading.abort();
+}
+
+// Clients have nothing to tell us beyond pings and goodbyes; other frames
+// are read and dropped
+async fn read_frames(mut reader: ReadHalf<Upgraded>, replies: mpsc::Sender<Reply>) {
+    loop {
+        let reply = match read_frame(&mut reader).await {
+            Ok((OP_PING, payload)) => Reply::Pong(payload),
+            Ok((OP_CLOSE, payload)) => Reply::Close(payload),
+            Ok(_) => continue,
+            Err(_) => Reply::Close(Vec::new()),
+        };
+        let closing = matches!(reply, Reply::Close(_));
+        if replies.send(reply).await.is_err() || closing {
+            return;
+        }
+    }
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let len = match head[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((head[0] & 0x0f, payload))
+}
+
+// A whole, unmasked frame as servers send them
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
@@ -0,0 +1,1029 @@
+// Runs DAGs. A run copies the DAG's nodes, tasks and dependencies when it
+// starts and is then driven by a background job, which starts every node
+// whose dependencies have succeeded on its own tokio task. Nodes behind a
+// failure are skipped; nodes without a task succeed straight away.
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
+use hyper::{Body, Method, Request, Uri};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgQueryResult, types::Json as JsonColumn, FromRow, PgPool};
+use std::collections::HashMap;
+use std::env;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::broadcast;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::artifacts;
+use crate::auth;
+use crate::connections::{self, Kind, Resolved};
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::events::{self, Event};
+use crate::i18n::Locale;
+use crate::ids;
+use crate::jobs::{self, JobKind};
+use crate::logs::{self, Backend, TaskLog};
+use crate::maintenance::Writable;
+use crate::slugs::DagId;
+use crate::usage::{self, Access};
+use crate::variables;
+use crate::{find_dag, lifecycle_warning, lock_dag, source_url_valid, with_warning};
+
+// Tasks of one run that may be running at the same time
+const DEFAULT_CONCURRENCY: usize = 4;
+// Output kept per task; anything after it is cut off
+const MAX_OUTPUT: usize = 64 * 1024;
+// The service's own environment variables shell tasks get; the rest, such as
+// DATABASE_URL and the keys, stay with the service
+const SHELL_ENV: [&str; 4] = ["PATH", "HOME", "LANG", "TZ"];
+// Longest window the run heatmap covers
+const MAX_HEATMAP_DAYS: i32 = 366;
+// How long GET /runs/:id/wait holds on to a request, unless told otherwise and at most
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+const MAX_WAIT: Duration = Duration::from_secs(120);
+
+const RUN_COLUMNS: &str = "id, dag_id, status, job_id, schedule_id, scheduled_for, created_at, started_at, finished_at, stalled_at";
+const TASK_RUN_COLUMNS: &str =
+    "node_id, label, task, depends_on, connections, state, exit_code, http_status, output, log_store, error, started_at, finished_at";
+
+// What running a node does
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Task {
+    // Runs under `sh -c` and succeeds on exit status 0
+    Shell {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+    },
+    // Sends a request and succeeds on a 2xx answer. Without a body the
+    // callback gets the run, DAG and node ids as JSON.
+    Http {
+        url: String,
+        #[serde(default = "default_method")]
+        method: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+    },
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+impl Task {
+    // A command to run, or an http(s) URL and a method to send
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Task::Shell { command, .. } => !command.trim().is_empty(),
+            // A path is sent to the node's http connection. A URL with
+            // variables or outputs in it can only be checked once they are
+            // filled in.
+            Task::Http { url, method, .. } => {
+                Method::from_bytes(method.as_bytes()).is_ok()
+                    && (variables::is_templated(url) || artifacts::is_templated(url) || url.parse::<Uri>().is_ok_and(|uri| {
+                        (matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some())
+                            || (uri.scheme().is_none() && url.starts_with('/'))
+                    }))
+            }
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        match self {
+            Task::Shell { timeout_secs, .. } | Task::Http { timeout_secs, .. } => timeout_secs.map(Duration::from_secs),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum RunStatus {
+    Queued,
+    Running,
+    // Queued or running without a state change for longer than the watchdog
+    // allows; back to running if it moves again
+    Stalled,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum TaskState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, FromRow)]
+struct Run {
+    id: Uuid,
+    dag_id: Uuid,
+    status: RunStatus,
+    job_id: Option<Uuid>,
+    // Set on runs a schedule started, with the time it was due
+    schedule_id: Option<Uuid>,
+    scheduled_for: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    stalled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, FromRow)]
+struct TaskRun {
+    node_id: Uuid,
+    label: String,
+    task: Option<JsonColumn<Task>>,
+    depends_on: Vec<Uuid>,
+    // Names of the connections the node's metadata listed
+    connections: Vec<String>,
+    state: TaskState,
+    exit_code: Option<i32>,
+    http_status: Option<i32>,
+    // Kept here while the log is in postgres; GET /runs/:id/tasks/:node_id/log
+    // reads it from whichever store has it
+    output: Option<String>,
+    log_store: Option<Backend>,
+    error: Option<String>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+// An issue filed about a run, or about one of its failed tasks when node_id
+// is set
+#[derive(Serialize, FromRow)]
+struct Ticket {
+    id: Uuid,
+    node_id: Option<Uuid>,
+    system: String,
+    key: String,
+    url: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+// Starts a run of the DAG as it is now. Answers 202 with the run; follow it
+// at GET /runs/:id.
+pub async fn start_run(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    let locale = &locale;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let Some(dag) = lock_dag(&mut *tx, dag_id).await? else {
+            return Err(TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", &dag_id)])));
+        };
+        let run = create_run(&mut *tx, dag_id, None).await?;
+        Ok((run, lifecycle_warning(&dag, locale)))
+    }))
+    .await;
+
+    let (run, warning) = result.map_err(|e| e.respond(locale, "start_run_failed"))?;
+    jobs::wake();
+    usage::record(&pool, dag_id, Access::Run);
+    run_changed(dag_id, run.id, run.status);
+    let location = format!("/runs/{}", run.id);
+    Ok(with_warning(
+        (StatusCode::ACCEPTED, [(header::LOCATION, location)], Json(run)).into_response(),
+        warning,
+    ))
+}
+
+// A WITH clause giving, as (node, source) pairs, what the nodes of the DAG
+// whose id is in parameter `dag` wait for in a run. Edges from nodes of other
+// DAGs are not dependencies. Draft nodes stay out of runs; a node behind one
+// waits for what the draft would have waited for.
+fn upstream(dag: &str) -> String {
+    format!(
+        "WITH RECURSIVE upstream (node, source) AS (
+             SELECT e.target, e.source FROM edges e JOIN nodes s ON s.id = e.source WHERE s.dag_id = {dag}
+             UNION
+             SELECT u.node, e.source FROM upstream u
+             JOIN nodes d ON d.id = u.source AND d.state = 'draft'
+             JOIN edges e ON e.target = d.id JOIN nodes s ON s.id = e.source AND s.dag_id = {dag}
+         )",
+        dag = dag
+    )
+}
+
+// The nodes a run of the DAG would have and what each waits for, as
+// (node, dependency) pairs
+pub async fn run_plan(tx: &mut sqlx::PgConnection, dag_id: Uuid) -> Result<(Vec<Uuid>, Vec<(Uuid, Uuid)>), sqlx::Error> {
+    let nodes = sqlx::query_scalar("SELECT id FROM nodes WHERE dag_id = $1 AND state <> 'draft' ORDER BY id")
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let dependencies = sqlx::query_as(&format!(
+        "{}
+         SELECT DISTINCT u.node, u.source FROM upstream u
+         JOIN nodes n ON n.id = u.node AND n.dag_id = $1 AND n.state <> 'draft'
+         JOIN nodes s ON s.id = u.source AND s.state <> 'draft'",
+        upstream("$1")
+    ))
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    Ok((nodes, dependencies))
+}
+
+// Copies the DAG as it is now into a queued run. Callers hold the DAG lock,
+// which keeps its structure still while it is copied.
+async fn create_run(
+    tx: &mut sqlx::PgConnection,
+    dag_id: Uuid,
+    schedule: Option<(Uuid, DateTime<Utc>)>,
+) -> Result<Run, sqlx::Error> {
+    let run_id = ids::new_id();
+    let job_id = jobs::enqueue(&mut *tx, JobKind::DagRun, serde_json::json!({ "run_id": run_id })).await?;
+    let run = sqlx::query_as::<_, Run>(&format!(
+        "INSERT INTO runs (id, dag_id, job_id, schedule_id, scheduled_for) VALUES ($1, $2, $3, $4, $5) RETURNING {}",
+        RUN_COLUMNS
+    ))
+        .bind(run_id)
+        .bind(dag_id)
+        .bind(job_id)
+        .bind(schedule.map(|(id, _)| id))
+        .bind(schedule.map(|(_, at)| at))
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query(&format!(
+        "{}
+         INSERT INTO task_runs (run_id, node_id, label, task, depends_on, connections)
+         SELECT $1, n.id, n.label, n.task, ARRAY(
+             SELECT DISTINCT u.source FROM upstream u JOIN nodes s ON s.id = u.source
+             WHERE u.node = n.id AND s.state <> 'draft'),
+             ARRAY(SELECT jsonb_array_elements_text(CASE jsonb_typeof(n.metadata->'connections')
+                 WHEN 'array' THEN n.metadata->'connections' ELSE '[]' END))
+         FROM nodes n WHERE n.dag_id = $2 AND n.state <> 'draft'",
+        upstream("$2")
+    ))
+        .bind(run_id)
+        .bind(dag_id)
+        .execute(&mut *tx)
+        .await?;
+    Ok(run)
+}
+
+// For runs started by a schedule; announce_run once the transaction commits
+pub async fn queue_run(tx: &mut sqlx::PgConnection, dag_id: Uuid, schedule: (Uuid, DateTime<Utc>)) -> Result<Uuid, sqlx::Error> {
+    Ok(create_run(tx, dag_id, Some(schedule)).await?.id)
+}
+
+pub fn announce_run(dag_id: Uuid, run_id: Uuid) {
+    jobs::wake();
+    run_changed(dag_id, run_id, RunStatus::Queued);
+}
+
+// A run with the state of every node in it
+pub async fn get_run(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(run_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let (run, body) = run_details(&pool, &locale, run_id).await?;
+    usage::record(&pool, run.dag_id, Access::Read);
+    Ok(Json(body).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct WaitParams {
+    timeout: Option<String>,
+}
+
+// "30", "30s" or "2m"
+fn wait_timeout(text: Option<&str>) -> Option<Duration> {
+    let Some(text) = text else {
+        return Some(DEFAULT_WAIT);
+    };
+    let (number, unit) = match text.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (text.strip_suffix('s').unwrap_or(text), 1),
+    };
+    let secs: u64 = number.parse().ok()?;
+    Some(Duration::from_secs(secs.checked_mul(unit)?).min(MAX_WAIT))
+}
+
+// Long poll: answers with the run as soon as it or one of its nodes changes
+// state, or as it is once the timeout passes. A finished run answers at once.
+pub async fn wait_for_run(
+    State(pool): State<PgPool>,
+    State(events): State<broadcast::Sender<Event>>,
+    locale: Locale,
+    Path(run_id): Path<Uuid>,
+    Query(params): Query<WaitParams>,
+) -> Result<Response, AppError> {
+    let Some(timeout) = wait_timeout(params.timeout.as_deref()) else {
+        return Err(locale.error(StatusCode::BAD_REQUEST, "invalid_wait_timeout", &[("max", &MAX_WAIT.as_secs())]));
+    };
+    // Subscribed before the run is read so no change in between is missed
+    let mut changes = events.subscribe();
+    let (run, body) = run_details(&pool, &locale, run_id).await?;
+    usage::record(&pool, run.dag_id, Access::Read);
+
+    let finished = matches!(run.status, RunStatus::Succeeded | RunStatus::Failed);
+    let changed = !finished
+        && tokio::time::timeout(timeout, async {
+            loop {
+                match changes.recv().await {
+                    Ok(event) => {
+                        let about_run = event.data()["run_id"] == serde_json::json!(run_id);
+                        if about_run && matches!(event.kind(), "run_status_changed" | "task_status_changed") {
+                            return;
+                        }
+                    }
+                    // Too far behind to tell, so look again
+                    Err(_) => return,
+                }
+            }
+        })
+        .await
+        .is_ok();
+
+    let mut body = if changed { run_details(&pool, &locale, run_id).await?.1 } else { body };
+    body["changed"] = serde_json::json!(changed);
+    Ok(Json(body).into_response())
+}
+
+async fn run_details(pool: &PgPool, locale: &Locale, run_id: Uuid) -> Result<(Run, serde_json::Value), AppError> {
+    let failed = |e| AppError::database(e, locale, "fetch_runs_failed");
+    let run = sqlx::query_as::<_, Run>(&format!(
+        "SELECT {} FROM runs WHERE id = $1 AND {}",
+        RUN_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(run_id)
+        .bind(auth::owner())
+        .fetch_optional(pool)
+        .await
+        .map_err(failed)?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "run_not_found", &[("id", &run_id)]))?;
+    let tasks = sqlx::query_as::<_, TaskRun>(&format!(
+        "SELECT {} FROM task_runs WHERE run_id = $1 ORDER BY started_at NULLS LAST, label",
+        TASK_RUN_COLUMNS
+    ))
+        .bind(run_id)
+        .fetch_all(pool)
+        .await
+        .map_err(failed)?;
+    let tickets = sqlx::query_as::<_, Ticket>(
+        "SELECT id, node_id, system, key, url, created_at FROM run_tickets WHERE run_id = $1 ORDER BY created_at",
+    )
+        .bind(run_id)
+        .fetch_all(pool)
+        .await
+        .map_err(failed)?;
+
+    let mut body = serde_json::json!(run);
+    body["tasks"] = serde_json::json!(tasks);
+    body["tickets"] = serde_json::json!(tickets);
+    Ok((run, body))
+}
+
+#[derive(Deserialize)]
+pub struct TicketPayload {
+    // Only failed tasks take tickets; without a node the ticket is about the
+    // whole run
+    node_id: Option<Uuid>,
+    system: String,
+    key: String,
+    url: Option<String>,
+}
+
+pub async fn add_ticket(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(run_id): Path<Uuid>,
+    Json(payload): Json<TicketPayload>,
+) -> Result<Response, AppError> {
+    if payload.system.trim().is_empty() || payload.key.trim().is_empty() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_ticket", &[]));
+    }
+    if payload.url.as_deref().is_some_and(|url| !source_url_valid(url)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_ticket_url", &[]));
+    }
+
+    let failed = |e| AppError::database(e, &locale, "add_ticket_failed");
+    let exists: bool = sqlx::query_scalar(&format!("SELECT EXISTS (SELECT 1 FROM runs WHERE id = $1 AND {})", auth::visible("dag_id", 2)))
+        .bind(run_id)
+        .bind(auth::owner())
+        .fetch_one(&pool)
+        .await
+        .map_err(failed)?;
+    if !exists {
+        return Err(locale.error(StatusCode::NOT_FOUND, "run_not_found", &[("id", &run_id)]));
+    }
+    if let Some(node_id) = payload.node_id {
+        let state = sqlx::query_scalar::<_, TaskState>("SELECT state FROM task_runs WHERE run_id = $1 AND node_id = $2")
+            .bind(run_id)
+            .bind(node_id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(failed)?
+            .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "task_run_not_found", &[("run", &run_id), ("node", &node_id)]))?;
+        if state != TaskState::Failed {
+            return Err(locale.error(StatusCode::CONFLICT, "ticket_task_not_failed", &[("node", &node_id)]));
+        }
+    }
+
+    let ticket = sqlx::query_as::<_, Ticket>(
+        "INSERT INTO run_tickets (id, run_id, node_id, system, key, url) VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, node_id, system, key, url, created_at",
+    )
+        .bind(ids::new_id())
+        .bind(run_id)
+        .bind(payload.node_id)
+        .bind(payload.system.trim())
+        .bind(payload.key.trim())
+        .bind(payload.url)
+        .fetch_one(&pool)
+        .await
+        .map_err(failed)?;
+    Ok((StatusCode::CREATED, Json(ticket)).into_response())
+}
+
+pub async fn delete_ticket(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path((run_id, ticket_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, AppError> {
+    let deleted = sqlx::query(&format!(
+        "DELETE FROM run_tickets WHERE id = $1 AND run_id IN (SELECT id FROM runs WHERE id = $2 AND {})",
+        auth::visible("dag_id", 3)
+    ))
+        .bind(ticket_id)
+        .bind(run_id)
+        .bind(auth::owner())
+        .execute(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "delete_ticket_failed"))?;
+    if deleted.rows_affected() == 0 {
+        return Err(locale.error(StatusCode::NOT_FOUND, "ticket_not_found", &[("id", &ticket_id)]));
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ListRunsParams {
+    limit: Option<i64>,
+}
+
+// The DAG's most recent runs, newest first
+pub async fn list_runs(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<ListRunsParams>,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+    let runs = sqlx::query_as::<_, Run>(&format!(
+        "SELECT {} FROM runs WHERE dag_id = $1 ORDER BY created_at DESC LIMIT $2",
+        RUN_COLUMNS
+    ))
+        .bind(dag_id)
+        .bind(params.limit.unwrap_or(20).max(1))
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_runs_failed"))?;
+    Ok(Json(runs).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct AllRunsParams {
+    status: Option<RunStatus>,
+    limit: Option<i64>,
+}
+
+// Recent runs of every DAG the caller can see, newest first; ?status=stalled
+// gives the runs the watchdog is waiting on someone to look at
+pub async fn list_all_runs(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(params): Query<AllRunsParams>,
+) -> Result<Response, AppError> {
+    let runs = sqlx::query_as::<_, Run>(&format!(
+        "SELECT {} FROM runs WHERE ($1::text IS NULL OR status = $1) AND {} ORDER BY created_at DESC LIMIT $3",
+        RUN_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(params.status)
+        .bind(auth::owner())
+        .bind(params.limit.unwrap_or(20).max(1))
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_runs_failed"))?;
+    Ok(Json(runs).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct HeatmapParams {
+    days: Option<i32>,
+}
+
+#[derive(FromRow)]
+struct HeatmapDay {
+    day: NaiveDate,
+    succeeded: i64,
+    failed: i64,
+    mean_secs: Option<f64>,
+    max_secs: Option<f64>,
+}
+
+// Finished runs per day for a calendar view, by the day they started (or were
+// queued, for runs that failed before starting). Every day of the window is
+// there, oldest first, as [succeeded, failed, mean_secs, max_secs]; durations
+// are null on days without finished runs.
+pub async fn run_heatmap(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<HeatmapParams>,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+
+    let days = params.days.unwrap_or(90).clamp(1, MAX_HEATMAP_DAYS);
+    let data = sqlx::query_as::<_, HeatmapDay>(
+        "SELECT d.day::date AS day,
+                COUNT(r.id) FILTER (WHERE r.status = 'succeeded') AS succeeded,
+                COUNT(r.id) FILTER (WHERE r.status = 'failed') AS failed,
+                round(AVG(EXTRACT(EPOCH FROM r.finished_at - r.started_at)), 3)::float8 AS mean_secs,
+                round(MAX(EXTRACT(EPOCH FROM r.finished_at - r.started_at)), 3)::float8 AS max_secs
+         FROM generate_series(CURRENT_DATE - ($2 - 1), CURRENT_DATE, interval '1 day') AS d(day)
+         LEFT JOIN runs r ON r.dag_id = $1 AND r.status IN ('succeeded', 'failed')
+                         AND COALESCE(r.started_at, r.created_at) >= d.day
+                         AND COALESCE(r.started_at, r.created_at) < d.day + interval '1 day'
+         GROUP BY d.day
+         ORDER BY d.day",
+    )
+        .bind(dag_id)
+        .bind(days)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_runs_failed"))?;
+
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "from": data.first().map(|d| d.day),
+        "days": days,
+        "columns": ["succeeded", "failed", "mean_secs", "max_secs"],
+        "data": data
+            .iter()
+            .map(|d| serde_json::json!([d.succeeded, d.failed, d.mean_secs, d.max_secs]))
+            .collect::<Vec<_>>(),
+    }))
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct RunJob {
+    run_id: Uuid,
+}
+
+// Why a run's job stopped before the run finished
+enum Failure {
+    Database(sqlx::Error),
+    // The job was claimed again, by a worker that now drives the run; this
+    // one may no longer change it
+    LeaseLost,
+}
+
+impl From<sqlx::Error> for Failure {
+    fn from(e: sqlx::Error) -> Self {
+        Failure::Database(e)
+    }
+}
+
+// For writes fenced with jobs::holds_lease, which change nothing once the
+// lease has gone to another worker
+fn fenced(done: PgQueryResult) -> Result<(), Failure> {
+    if done.rows_affected() == 0 {
+        return Err(Failure::LeaseLost);
+    }
+    Ok(())
+}
+
+// Drives the run under the lease with fencing token `fence`. Every change
+// to the run and its nodes carries the token, so a worker whose lease ran
+// out and was given to another can't overwrite what the new one records.
+pub async fn run_job(pool: &PgPool, fence: i64, payload: serde_json::Value) -> Result<serde_json::Value, String> {
+    let job: RunJob = serde_json::from_value(payload).map_err(|e| e.to_string())?;
+    match execute(pool, fence, job.run_id).await {
+        Ok(status) => Ok(serde_json::json!({ "run_id": job.run_id, "status": status })),
+        Err(Failure::LeaseLost) => Err("Lost the job's lease".to_string()),
+        Err(Failure::Database(e)) => {
+            let dag_id = sqlx::query_scalar(&format!(
+                "UPDATE runs SET status = 'failed', finished_at = now() WHERE id = $1 AND {} RETURNING dag_id",
+                jobs::holds_lease(2)
+            ))
+                .bind(job.run_id)
+                .bind(fence)
+                .fetch_optional(pool)
+                .await;
+            if let Ok(Some(dag_id)) = dag_id {
+                run_changed(dag_id, job.run_id, RunStatus::Failed);
+            }
+            Err(e.to_string())
+        }
+    }
+}
+
+// What a node sees of the run it is part of
+#[derive(Serialize, Clone)]
+struct Context {
+    run_id: Uuid,
+    dag_id: Uuid,
+    node_id: Uuid,
+    label: String,
+    // Where the task writes its outputs, and the token that lets it
+    outputs_url: String,
+    outputs_token: String,
+}
+
+#[derive(Default)]
+struct Outcome {
+    succeeded: bool,
+    exit_code: Option<i32>,
+    http_status: Option<i32>,
+    output: Option<String>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn failed(error: String) -> Self {
+        Outcome { error: Some(error), ..Outcome::default() }
+    }
+}
+
+// A run picked up again after a restart resumes: finished nodes keep their
+// state and interrupted ones start over
+async fn execute(pool: &PgPool, fence: i64, run_id: Uuid) -> Result<RunStatus, Failure> {
+    let dag_id = sqlx::query_scalar::<_, Uuid>(&format!(
+        "UPDATE runs SET status = 'running', started_at = COALESCE(started_at, now()) WHERE id = $1 AND {} RETURNING dag_id",
+        jobs::holds_lease(2)
+    ))
+        .bind(run_id)
+        .bind(fence)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Failure::LeaseLost)?;
+    run_changed(dag_id, run_id, RunStatus::Running);
+    sqlx::query(&format!(
+        "UPDATE task_runs SET state = 'pending', started_at = NULL WHERE run_id = $1 AND state = 'running' AND {}",
+        jobs::holds_lease(2)
+    ))
+        .bind(run_id)
+        .bind(fence)
+        .execute(pool)
+        .await?;
+    let tasks = sqlx::query_as::<_, TaskRun>(&format!("SELECT {} FROM task_runs WHERE run_id = $1", TASK_RUN_COLUMNS))
+        .bind(run_id)
+        .fetch_all(pool)
+        .await?;
+
+    let concurrency = env::var("RUN_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .max(1);
+    let mut states: HashMap<Uuid, TaskState> = tasks.iter().map(|t| (t.node_id, t.state)).collect();
+    let graph: HashMap<Uuid, (&str, &[Uuid])> =
+        tasks.iter().map(|t| (t.node_id, (t.label.as_str(), t.depends_on.as_slice()))).collect();
+    let upstream: HashMap<Uuid, HashMap<String, Uuid>> = tasks.iter().map(|t| (t.node_id, upstream_of(&graph, t.node_id))).collect();
+    let mut pending: Vec<TaskRun> = tasks.into_iter().filter(|t| t.state == TaskState::Pending).collect();
+    let mut running = JoinSet::new();
+    let mut running_nodes = HashMap::new();
+    loop {
+        // Skips spread to everything downstream of a failure
+        while let Some(i) = pending.iter().position(|t| {
+            t.depends_on
+                .iter()
+                .any(|d| matches!(states.get(d), Some(TaskState::Failed | TaskState::Skipped)))
+        }) {
+            let task = pending.swap_remove(i);
+            skip(pool, fence, dag_id, run_id, task.node_id, None).await?;
+            states.insert(task.node_id, TaskState::Skipped);
+        }
+
+        while running.len() < concurrency {
+            let Some(i) = pending
+                .iter()
+                .position(|t| t.depends_on.iter().all(|d| states.get(d) == Some(&TaskState::Succeeded)))
+            else {
+                break;
+            };
+            let task = pending.swap_remove(i);
+            let token = artifacts::new_token();
+            let hash = token.as_ref().ok().map(|(_, hash)| hash.as_str());
+            fenced(
+                sqlx::query(&format!(
+                    "UPDATE task_runs SET state = 'running', started_at = now(), outputs_token_hash = $3
+                     WHERE run_id = $1 AND node_id = $2 AND {}",
+                    jobs::holds_lease(4)
+                ))
+                    .bind(run_id)
+                    .bind(task.node_id)
+                    .bind(hash)
+                    .bind(fence)
+                    .execute(pool)
+                    .await?,
+            )?;
+            // Whatever an earlier attempt at the node wrote is gone
+            sqlx::query(&format!("DELETE FROM run_artifacts WHERE run_id = $1 AND node_id = $2 AND {}", jobs::holds_lease(3)))
+                .bind(run_id)
+                .bind(task.node_id)
+                .bind(fence)
+                .execute(pool)
+                .await?;
+            task_changed(dag_id, run_id, task.node_id, TaskState::Running);
+            states.insert(task.node_id, TaskState::Running);
+            let context = Context {
+                run_id,
+                dag_id,
+                node_id: task.node_id,
+                label: task.label,
+                outputs_url: artifacts::outputs_url(run_id, task.node_id),
+                outputs_token: token.as_ref().map(|(token, _)| token.clone()).unwrap_or_default(),
+            };
+            // A task sees connections and variables as they were when it
+            // started
+            let connections = connections::resolve(pool, dag_id, &task.connections).await?;
+            // Outputs go in after variables, so nothing a task wrote is
+            // taken for a variable placeholder
+            let work = match task.task {
+                Some(JsonColumn(work)) => match variables::render(pool, dag_id, &work).await? {
+                    Ok(work) => artifacts::render(pool, run_id, &upstream[&task.node_id], &work).await?.map(Some),
+                    Err(error) => Err(error),
+                },
+                None => Ok(None),
+            };
+            let work = token.map_err(|e| format!("Failed to make the task's outputs token: {}", e)).and(work);
+            let handle = running.spawn(async move {
+                match (connections, work) {
+                    (Ok(connections), Ok(work)) => perform(work, context, connections).await,
+                    (Err(error), _) | (_, Err(error)) => Outcome::failed(error),
+                }
+            });
+            running_nodes.insert(handle.id(), task.node_id);
+        }
+
+        let Some(joined) = running.join_next_with_id().await else { break };
+        let (node_id, outcome) = match joined {
+            Ok((id, outcome)) => (running_nodes.remove(&id), outcome),
+            Err(e) => (running_nodes.remove(&e.id()), Outcome::failed(format!("Task stopped unexpectedly: {}", e))),
+        };
+        let Some(node_id) = node_id else { continue };
+        let state = if outcome.succeeded { TaskState::Succeeded } else { TaskState::Failed };
+        // To the microsecond, as Postgres keeps finished_at, for stores that
+        // look the log up by time
+        let log = TaskLog { dag_id, run_id, node_id, fence, at: Utc::now().trunc_subsecs(6) };
+        let (log_store, output) = logs::keep(&log, outcome.output).await;
+        fenced(
+            sqlx::query(&format!(
+                "UPDATE task_runs SET state = $3, exit_code = $4, http_status = $5, output = $6, error = $7, finished_at = $8,
+                     log_store = $9, log_fence = $10
+                 WHERE run_id = $1 AND node_id = $2 AND {}",
+                jobs::holds_lease(10)
+            ))
+                .bind(run_id)
+                .bind(node_id)
+                .bind(state)
+                .bind(outcome.exit_code)
+                .bind(outcome.http_status)
+                .bind(output)
+                .bind(outcome.error)
+                .bind(log.at)
+                .bind(log_store)
+                .bind(fence)
+                .execute(pool)
+                .await?,
+        )?;
+        task_changed(dag_id, run_id, node_id, state);
+        states.insert(node_id, state);
+    }
+
+    // Whatever is left waits on itself through a cycle and can never start
+    for task in pending {
+        skip(pool, fence, dag_id, run_id, task.node_id, Some("Waits on a dependency cycle")).await?;
+        states.insert(task.node_id, TaskState::Skipped);
+    }
+
+    let status = if states.values().all(|s| *s == TaskState::Succeeded) {
+        RunStatus::Succeeded
+    } else {
+        RunStatus::Failed
+    };
+    fenced(
+        sqlx::query(&format!("UPDATE runs SET status = $2, finished_at = now() WHERE id = $1 AND {}", jobs::holds_lease(3)))
+            .bind(run_id)
+            .bind(status)
+            .bind(fence)
+            .execute(pool)
+            .await?,
+    )?;
+    run_changed(dag_id, run_id, status);
+    Ok(status)
+}
+
+// The nodes the node waits on, directly or not, by label and by id
+fn upstream_of(graph: &HashMap<Uuid, (&str, &[Uuid])>, node_id: Uuid) -> HashMap<String, Uuid> {
+    let mut found = HashMap::new();
+    let mut queue: Vec<Uuid> = graph.get(&node_id).map_or_else(Vec::new, |(_, deps)| deps.to_vec());
+    while let Some(id) = queue.pop() {
+        let Some((label, deps)) = graph.get(&id) else { continue };
+        if found.insert(id.to_string(), id).is_none() {
+            found.entry(label.to_string()).or_insert(id);
+            queue.extend_from_slice(deps);
+        }
+    }
+    found
+}
+
+async fn skip(pool: &PgPool, fence: i64, dag_id: Uuid, run_id: Uuid, node_id: Uuid, error: Option<&str>) -> Result<(), Failure> {
+    fenced(
+        sqlx::query(&format!(
+            "UPDATE task_runs SET state = 'skipped', error = $3, finished_at = now() WHERE run_id = $1 AND node_id = $2 AND {}",
+            jobs::holds_lease(4)
+        ))
+            .bind(run_id)
+            .bind(node_id)
+            .bind(error)
+            .bind(fence)
+            .execute(pool)
+            .await?,
+    )?;
+    task_changed(dag_id, run_id, node_id, TaskState::Skipped);
+    Ok(())
+}
+
+pub fn run_changed(dag_id: Uuid, run_id: Uuid, status: RunStatus) {
+    events::publish(dag_id, "run_status_changed", serde_json::json!({ "run_id": run_id, "status": status }));
+}
+
+fn task_changed(dag_id: Uuid, run_id: Uuid, node_id: Uuid, state: TaskState) {
+    events::publish(dag_id, "task_status_changed", serde_json::json!({ "run_id": run_id, "node_id": node_id, "state": state }));
+}
+
+async fn perform(task: Option<Task>, context: Context, connections: Vec<Resolved>) -> Outcome {
+    let Some(task) = task else {
+        return Outcome { succeeded: true, ..Outcome::default() };
+    };
+    let timeout = task.timeout();
+    let work = async move {
+        match task {
+            Task::Shell { command, .. } => shell(&command, &context, &connections).await,
+            Task::Http { url, method, body, .. } => {
+                let body = body.unwrap_or_else(|| serde_json::json!(context));
+                // A path goes to the node's first http connection, with its
+                // credentials
+                let (url, authorization) = match url.starts_with('/') {
+                    false => (url, None),
+                    true => match connections.iter().find(|c| c.kind == Kind::Http) {
+                        Some(connection) => {
+                            let base = connection.fields.get("url").map(String::as_str).unwrap_or_default();
+                            (format!("{}{}", base.trim_end_matches('/'), url), connection.authorization())
+                        }
+                        None => return Outcome::failed(format!("{} needs an http connection to go to", url)),
+                    },
+                };
+                http(&url, &method, authorization.as_deref(), body.to_string()).await
+            }
+        }
+    };
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, work)
+            .await
+            .unwrap_or_else(|_| Outcome::failed(format!("Timed out after {}s", timeout.as_secs()))),
+        None => work.await,
+    }
+}
+
+fn truncated(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_OUTPUT)]).into_owned()
+}
+
+// The process is killed if the task times out. Each connection comes in as
+// CONN_<NAME> (a URI) and CONN_<NAME>_<FIELD>, names upper-cased with - as _.
+async fn shell(command: &str, context: &Context, connections: &[Resolved]) -> Outcome {
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .env_clear()
+        .envs(SHELL_ENV.iter().filter_map(|name| Some((name, env::var_os(name)?))))
+        .env("DAG_RUN_ID", context.run_id.to_string())
+        .env("DAG_ID", context.dag_id.to_string())
+        .env("DAG_NODE_ID", context.node_id.to_string())
+        .env("DAG_NODE_LABEL", &context.label)
+        .env("DAG_OUTPUTS_URL", &context.outputs_url)
+        .env("DAG_OUTPUTS_TOKEN", &context.outputs_token);
+    for connection in connections {
+        let prefix = format!("CONN_{}", connection.name.to_uppercase().replace('-', "_"));
+        process.env(&prefix, connection.uri());
+        for (field, value) in &connection.fields {
+            process.env(format!("{}_{}", prefix, field.to_uppercase()), value);
+        }
+    }
+    let result = process
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await;
+    match result {
+        Ok(output) => {
+            let mut text = output.stdout;
+            text.extend_from_slice(&output.stderr);
+            Outcome {
+                succeeded: output.status.success(),
+                exit_code: output.status.code(),
+                output: Some(truncated(&text)),
+                error: match output.status.code() {
+                    _ if output.status.success() => None,
+                    Some(code) => Some(format!("Command exited with status {}", code)),
+                    None => Some("Command was killed by a signal".to_string()),
+                },
+                ..Outcome::default()
+            }
+        }
+        Err(e) => Outcome::failed(format!("Failed to start command: {}", e)),
+    }
+}
+
+async fn http(url: &str, method: &str, authorization: Option<&str>, body: String) -> Outcome {
+    let mut headers = vec![(header::CONTENT_TYPE.as_str(), "application/json")];
+    headers.extend(authorization.map(|authorization| (header::AUTHORIZATION.as_str(), authorization)));
+    match send(url, method, &headers, body.into_bytes()).await {
+        Ok((status, body)) => Outcome {
+            succeeded: status.is_success(),
+            http_status: Some(status.as_u16().into()),
+            output: Some(truncated(&body)),
+            error: (!status.is_success()).then(|| format!("Callback answered {}", status)),
+            ..Outcome::default()
+        },
+        Err(e) => Outcome::failed(format!("Callback failed: {}", e)),
+    }
+}
+
+// One request over a connection of its own, plain or TLS by the URL's scheme.
+// Also used by the log stores.
+pub async fn send(url: &str, method: &str, headers: &[(&str, &str)], body: Vec<u8>) -> Result<(StatusCode, Vec<u8>), String> {
+    let uri: Uri = url.parse().map_err(|e: hyper::http::uri::InvalidUri| e.to_string())?;
+    let host = uri.host().ok_or("URL has no host")?.to_string();
+    let https = uri.scheme_str() == Some("https");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let authority = uri.authority().map_or(host.clone(), |a| a.to_string());
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(header::HOST, authority);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request
+        .body(Body::from(body))
+        .map_err(|e| e.to_string())?;
+
+    let stream = TcpStream::connect((host.as_str(), port)).await.map_err(|e| e.to_string())?;
+    if https {
+        let connector = tokio_native_tls::native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+        let stream = tokio_native_tls::TlsConnector::from(connector)
+            .connect(&host, stream)
+            .await
+            .map_err(|e| e.to_string())?;
+        exchange(stream, request).await
+    } else {
+        exchange(stream, request).await
+    }
+}
+
+async fn exchange<S>(stream: S, request: Request<Body>) -> Result<(StatusCode, Vec<u8>), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await.map_err(|e| e.to_string())?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let response = sender.send_request(request).await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+    Ok((status, body.to_vec()))
+}
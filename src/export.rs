@@ -0,0 +1,324 @@
+// Exports are rendered in full, then sent in chunks. Each API key may have
+// EXPORT_CONCURRENCY exports in progress at once, and the chunks of all of
+// them together go out at no more than EXPORT_BYTES_PER_SEC. An export
+// answers with an ETag and honours Range, so an interrupted download picks up
+// where it stopped as long as the DAG hasn't changed in between.
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum::body::{self, Body, Bytes};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::ops::Range;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::snapshots;
+use crate::usage::{self, Access};
+use crate::versions::{self, VersionParam};
+use crate::{Edge, Node, DAG};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// Seconds a caller turned away for having too many exports should wait
+const RETRY_AFTER: &str = "5";
+
+struct Limits {
+    concurrency: usize,
+    bytes_per_sec: u64,
+}
+
+// Per API key, or None for the admin key and with authentication off
+#[derive(Default)]
+struct Tenant {
+    exports: usize,
+    // When the key's share of bandwidth is next free
+    free_at: Option<Instant>,
+}
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+static TENANTS: OnceLock<Mutex<HashMap<Option<Uuid>, Tenant>>> = OnceLock::new();
+
+// Takes EXPORT_CONCURRENCY and EXPORT_BYTES_PER_SEC from the config at startup
+pub fn configure(concurrency: usize, bytes_per_sec: u64) {
+    LIMITS.get_or_init(|| Limits { concurrency, bytes_per_sec });
+}
+
+fn limits() -> &'static Limits {
+    LIMITS.get_or_init(|| Limits { concurrency: usize::MAX, bytes_per_sec: 0 })
+}
+
+fn tenants() -> std::sync::MutexGuard<'static, HashMap<Option<Uuid>, Tenant>> {
+    TENANTS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// One of the key's exports in progress, given back when dropped
+struct Slot(Option<Uuid>);
+
+impl Slot {
+    fn take(tenant: Option<Uuid>) -> Option<Slot> {
+        let mut tenants = tenants();
+        let entry = tenants.entry(tenant).or_default();
+        if entry.exports >= limits().concurrency {
+            return None;
+        }
+        entry.exports += 1;
+        Some(Slot(tenant))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut tenants = tenants();
+        if let Some(entry) = tenants.get_mut(&self.0) {
+            entry.exports -= 1;
+            if entry.exports == 0 && entry.free_at.is_none_or(|at| at <= Instant::now()) {
+                tenants.remove(&self.0);
+            }
+        }
+    }
+}
+
+// Waits until the key may send `len` more bytes
+async fn throttle(tenant: Option<Uuid>, len: usize) {
+    let rate = limits().bytes_per_sec;
+    if rate == 0 {
+        return;
+    }
+    let start = {
+        let mut tenants = tenants();
+        let entry = tenants.entry(tenant).or_default();
+        let start = entry.free_at.map_or_else(Instant::now, |at| at.max(Instant::now()));
+        entry.free_at = Some(start + Duration::from_secs_f64(len as f64 / rate as f64));
+        start
+    };
+    tokio::time::sleep_until(start).await;
+}
+
+// Sends `bytes` in chunks at the key's pace. The slot is held until the last
+// one is sent or the client goes away.
+fn streamed(slot: Slot, bytes: Bytes) -> Response {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        // The whole slot, not just the key in it, so it is dropped at the end
+        let slot = slot;
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let end = (offset + CHUNK_SIZE).min(bytes.len());
+            throttle(slot.0, end - offset).await;
+            if sender.send_data(bytes.slice(offset..end)).await.is_err() {
+                break;
+            }
+            offset = end;
+        }
+    });
+    Response::new(body::boxed(body))
+}
+
+enum Wanted {
+    All,
+    Part(Range<usize>),
+    Unsatisfiable,
+}
+
+// The part of a `len` byte export the Range header asks for. A range that
+// came with an If-Range naming another version, and anything other than a
+// single byte range, gets the whole export.
+fn wanted(headers: &HeaderMap, etag: &str, len: usize) -> Wanted {
+    let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return Wanted::All;
+    };
+    if headers.get(header::IF_RANGE).is_some_and(|v| v.as_bytes() != etag.as_bytes()) {
+        return Wanted::All;
+    }
+    let Some((start, end)) = range.trim().strip_prefix("bytes=").and_then(|r| r.split_once('-')) else {
+        return Wanted::All;
+    };
+    if end.contains(',') {
+        return Wanted::All;
+    }
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.parse::<usize>(), end.parse::<usize>()) {
+        // The last `end` bytes
+        _ if start.is_empty() => match end.parse::<usize>() {
+            Ok(suffix) if suffix > 0 => len.saturating_sub(suffix)..len,
+            _ => return Wanted::Unsatisfiable,
+        },
+        (Ok(start), _) if end.is_empty() => start..len,
+        (Ok(start), Ok(end)) if start <= end => start..len.min(end + 1),
+        _ => return Wanted::All,
+    };
+    if range.start >= len {
+        return Wanted::Unsatisfiable;
+    }
+    Wanted::Part(range)
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Dot,
+    Mermaid,
+    #[default]
+    Json,
+    // The compact binary form versions are stored in, with every field of
+    // every node
+    Snapshot,
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: Format,
+}
+
+// Nodes ordered by label, then id, and the edges between them in the same
+// order, so the same DAG always exports to the same text. Edges into other
+// DAGs are left out.
+fn canonical<'a>(nodes: &'a [Node], edges: &[Edge]) -> (Vec<&'a Node>, Vec<(usize, usize)>) {
+    let mut sorted: Vec<&Node> = nodes.iter().collect();
+    sorted.sort_by(|a, b| a.label.cmp(&b.label).then(a.id.cmp(&b.id)));
+    let position: HashMap<Uuid, usize> = sorted.iter().enumerate().map(|(i, n)| (n.id, i)).collect();
+    let mut arcs: Vec<(usize, usize)> = edges
+        .iter()
+        .filter_map(|e| Some((*position.get(&e.source)?, *position.get(&e.target)?)))
+        .collect();
+    arcs.sort();
+    arcs.dedup();
+    (sorted, arcs)
+}
+
+fn quote_dot(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+fn to_dot(dag: &DAG, nodes: &[&Node], arcs: &[(usize, usize)]) -> String {
+    let mut dot = String::new();
+    let _ = writeln!(dot, "digraph {} {{", quote_dot(&dag.name));
+    dot.push_str("  rankdir=TB;\n  node [shape=box, style=rounded];\n");
+    for node in nodes {
+        let _ = writeln!(dot, "  {} [label={}];", quote_dot(&node.id.to_string()), quote_dot(&node.label));
+    }
+    for &(source, target) in arcs {
+        let _ = writeln!(
+            dot,
+            "  {} -> {};",
+            quote_dot(&nodes[source].id.to_string()),
+            quote_dot(&nodes[target].id.to_string())
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+// Mermaid ids can't be uuids (hyphens read as links), so nodes are n0, n1, ...
+// in canonical order and labels carry the text
+fn quote_mermaid(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "#quot;").replace('\n', " "))
+}
+
+fn to_mermaid(nodes: &[&Node], arcs: &[(usize, usize)]) -> String {
+    let mut mermaid = String::from("flowchart TD\n");
+    for (i, node) in nodes.iter().enumerate() {
+        let _ = writeln!(mermaid, "  n{}[{}]", i, quote_mermaid(&node.label));
+    }
+    for &(source, target) in arcs {
+        let _ = writeln!(mermaid, "  n{} --> n{}", source, target);
+    }
+    mermaid
+}
+
+// The DAG with each node listing the nodes it depends on
+fn to_json(dag: &DAG, nodes: &[&Node], arcs: &[(usize, usize)]) -> serde_json::Value {
+    let mut depends_on: BTreeMap<usize, Vec<Uuid>> = BTreeMap::new();
+    for &(source, target) in arcs {
+        depends_on.entry(target).or_default().push(nodes[source].id);
+    }
+    serde_json::json!({
+        "id": dag.id,
+        "name": dag.name,
+        "slug": dag.slug,
+        "lifecycle": dag.lifecycle,
+        "nodes": nodes.iter().enumerate().map(|(i, node)| serde_json::json!({
+            "id": node.id,
+            "label": node.label,
+            "slug": node.slug,
+            "external_ids": node.external_ids,
+            "owner": node.owner,
+            "source_url": node.source_url,
+            "task": node.task,
+            "node_type": node.node_type,
+            "metadata": node.metadata,
+            "position": node.position,
+            "state": node.state,
+            "created_by": node.created_by,
+            "reason": node.reason,
+            "depends_on": depends_on.remove(&i).unwrap_or_default(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+pub async fn export_dag(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<ExportParams>,
+    Query(at): Query<VersionParam>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    // Taken before loading, so the DAGs a key has in memory are bounded too
+    let tenant = auth::creator();
+    let Some(slot) = Slot::take(tenant) else {
+        let error = locale.error(StatusCode::TOO_MANY_REQUESTS, "export_limit_reached", &[("count", &limits().concurrency)]);
+        return Ok(([(header::RETRY_AFTER, RETRY_AFTER)], error).into_response());
+    };
+    let (dag, nodes, edges) = versions::loaded_at(&pool, &locale, dag_id, &at, "export_failed").await?;
+    usage::record(&pool, dag_id, Access::Read);
+    let (content_type, bytes) = match params.format {
+        Format::Snapshot => (snapshots::CONTENT_TYPE, snapshots::encode(dag.id, &nodes, &edges)),
+        format => {
+            let (nodes, arcs) = canonical(&nodes, &edges);
+            match format {
+                Format::Dot => ("text/vnd.graphviz; charset=utf-8", to_dot(&dag, &nodes, &arcs).into_bytes()),
+                Format::Mermaid => ("text/plain; charset=utf-8", to_mermaid(&nodes, &arcs).into_bytes()),
+                _ => ("application/json", to_json(&dag, &nodes, &arcs).to_string().into_bytes()),
+            }
+        }
+    };
+    drop((nodes, edges));
+
+    let bytes = Bytes::from(bytes);
+    let etag = format!("\"{:.32x}\"", Sha256::digest(&bytes));
+    let len = bytes.len();
+    let (mut response, sent) = match wanted(&headers, &etag, len) {
+        Wanted::All => (streamed(slot, bytes), len),
+        Wanted::Part(range) => {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+            let sent = range.len();
+            let body = streamed(slot, bytes.slice(range));
+            ((StatusCode::PARTIAL_CONTENT, [(header::CONTENT_RANGE, content_range)], body).into_response(), sent)
+        }
+        Wanted::Unsatisfiable => {
+            let error = locale.error(StatusCode::RANGE_NOT_SATISFIABLE, "export_range_invalid", &[("length", &len)]);
+            return Ok(([(header::CONTENT_RANGE, format!("bytes */{}", len))], error).into_response());
+        }
+    };
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(sent));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    Ok(response)
+}
@@ -0,0 +1,213 @@
+// A small filter language for selecting rows in bulk requests, e.g.
+// `label ~ 'tmp_%' and not namespace = 'prod'`. Fields map to fixed SQL
+// expressions and every value becomes a bind parameter, so a filter can never
+// inject SQL.
+//
+//   filter     := and_filter ("or" and_filter)*
+//   and_filter := unary ("and" unary)*
+//   unary      := "not" unary | "(" filter ")" | field op 'value'
+//   op         := "=" | "!=" | "~" (LIKE) | "!~" (NOT LIKE)
+
+use axum::http::StatusCode;
+
+use crate::error::AppError;
+use crate::i18n::Locale;
+
+enum Token {
+    Word(String),
+    Text(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+pub enum FilterError {
+    UnknownField(String),
+    UnterminatedString(usize),
+    Unexpected(usize),
+    UnexpectedEnd,
+}
+
+impl FilterError {
+    pub fn error(&self, locale: &Locale) -> AppError {
+        let status = StatusCode::BAD_REQUEST;
+        match self {
+            FilterError::UnknownField(field) => locale.error(status, "filter_unknown_field", &[("field", field)]),
+            FilterError::UnterminatedString(at) => locale.error(status, "filter_unterminated_string", &[("position", at)]),
+            FilterError::Unexpected(at) => locale.error(status, "filter_unexpected", &[("position", at)]),
+            FilterError::UnexpectedEnd => locale.error(status, "filter_unexpected_end", &[]),
+        }
+    }
+}
+
+// A parsed filter as an SQL condition over `values`, which are numbered from
+// the `first_param` given to `parse`
+pub struct Filter {
+    pub sql: String,
+    pub values: Vec<String>,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::Open
+            }
+            ')' => {
+                i += 1;
+                Token::Close
+            }
+            '=' => {
+                i += 1;
+                Token::Op("=")
+            }
+            '~' => {
+                i += 1;
+                Token::Op("LIKE")
+            }
+            '!' => match chars.get(i + 1) {
+                Some('=') => {
+                    i += 2;
+                    Token::Op("<>")
+                }
+                Some('~') => {
+                    i += 2;
+                    Token::Op("NOT LIKE")
+                }
+                _ => return Err(FilterError::Unexpected(start)),
+            },
+            // Quotes inside a value are doubled, as in SQL
+            '\'' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match (chars.get(i), chars.get(i + 1)) {
+                        (Some('\''), Some('\'')) => {
+                            text.push('\'');
+                            i += 2;
+                        }
+                        (Some('\''), _) => {
+                            i += 1;
+                            break;
+                        }
+                        (Some(&c), _) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                        (None, _) => return Err(FilterError::UnterminatedString(start)),
+                    }
+                }
+                Token::Text(text)
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                Token::Word(chars[start..i].iter().collect())
+            }
+            _ => return Err(FilterError::Unexpected(start)),
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    at: usize,
+    fields: &'a [(&'a str, &'a str)],
+    first_param: usize,
+    values: Vec<String>,
+}
+
+impl Parser<'_> {
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.at) {
+            Some((_, Token::Word(word))) if word.eq_ignore_ascii_case(keyword) => {
+                self.at += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn next(&mut self) -> Result<&(usize, Token), FilterError> {
+        let token = self.tokens.get(self.at).ok_or(FilterError::UnexpectedEnd)?;
+        self.at += 1;
+        Ok(token)
+    }
+
+    fn filter(&mut self) -> Result<String, FilterError> {
+        let mut sql = self.and_filter()?;
+        while self.keyword("or") {
+            sql = format!("{} OR {}", sql, self.and_filter()?);
+        }
+        Ok(sql)
+    }
+
+    fn and_filter(&mut self) -> Result<String, FilterError> {
+        let mut sql = self.unary()?;
+        while self.keyword("and") {
+            sql = format!("{} AND {}", sql, self.unary()?);
+        }
+        Ok(sql)
+    }
+
+    fn unary(&mut self) -> Result<String, FilterError> {
+        if self.keyword("not") {
+            return Ok(format!("NOT {}", self.unary()?));
+        }
+        let fields = self.fields;
+        let column = match self.next()? {
+            (_, Token::Open) => {
+                let sql = self.filter()?;
+                return match self.next()? {
+                    (_, Token::Close) => Ok(format!("({})", sql)),
+                    (at, _) => Err(FilterError::Unexpected(*at)),
+                };
+            }
+            (_, Token::Word(word)) => fields
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(word))
+                .map(|(_, column)| *column)
+                .ok_or_else(|| FilterError::UnknownField(word.clone()))?,
+            (at, _) => return Err(FilterError::Unexpected(*at)),
+        };
+        let op = match self.next()? {
+            (_, Token::Op(op)) => *op,
+            (at, _) => return Err(FilterError::Unexpected(*at)),
+        };
+        let value = match self.next()? {
+            (_, Token::Text(text)) => text.clone(),
+            (at, _) => return Err(FilterError::Unexpected(*at)),
+        };
+        self.values.push(value);
+        Ok(format!("({} {} ${})", column, op, self.first_param + self.values.len() - 1))
+    }
+}
+
+// `fields` maps the names a filter may use to the SQL expressions they stand for
+pub fn parse(input: &str, fields: &[(&str, &str)], first_param: usize) -> Result<Filter, FilterError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        at: 0,
+        fields,
+        first_param,
+        values: Vec::new(),
+    };
+    let sql = parser.filter()?;
+    if let Some((at, _)) = parser.tokens.get(parser.at) {
+        return Err(FilterError::Unexpected(*at));
+    }
+    Ok(Filter { sql, values: parser.values })
+}
@@ -0,0 +1,367 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use uuid::Uuid;
+
+use crate::{Edge, Node};
+
+// In-memory adjacency view of a DAG. Nodes are addressed by their position
+// in `ids`; edges pointing outside the node set are ignored.
+pub struct Graph {
+    pub ids: Vec<Uuid>,
+    index: HashMap<Uuid, usize>,
+    pub outgoing: Vec<Vec<usize>>,
+    pub incoming: Vec<Vec<usize>>,
+}
+
+impl Graph {
+    pub fn new(nodes: &[Node], edges: &[Edge]) -> Self {
+        let ids: Vec<Uuid> = nodes.iter().map(|n| n.id).collect();
+        let index: HashMap<Uuid, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let arcs = edges
+            .iter()
+            .filter_map(|e| Some((*index.get(&e.source)?, *index.get(&e.target)?)));
+        Graph::from_arcs(ids, arcs)
+    }
+
+    // Builds a graph from node ids and arcs given as positions into `ids`
+    pub fn from_arcs(ids: Vec<Uuid>, arcs: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let index: HashMap<Uuid, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let mut outgoing = vec![Vec::new(); ids.len()];
+        let mut incoming = vec![Vec::new(); ids.len()];
+        for (s, t) in arcs {
+            outgoing[s].push(t);
+            incoming[t].push(s);
+        }
+
+        Graph {
+            ids,
+            index,
+            outgoing,
+            incoming,
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn position(&self, id: &Uuid) -> Option<usize> {
+        self.index.get(id).copied()
+    }
+
+    // Kahn's algorithm. Returns the sorted prefix and the nodes that could not
+    // be ordered because they sit on (or behind) a cycle.
+    pub fn topological_order(&self) -> (Vec<usize>, Vec<usize>) {
+        let mut in_degree: Vec<usize> = self.incoming.iter().map(|i| i.len()).collect();
+        let mut queue: VecDeque<usize> = (0..self.node_count()).filter(|&i| in_degree[i] == 0).collect();
+        let mut order = Vec::with_capacity(self.node_count());
+
+        while let Some(n) = queue.pop_front() {
+            order.push(n);
+            for &m in &self.outgoing[n] {
+                in_degree[m] -= 1;
+                if in_degree[m] == 0 {
+                    queue.push_back(m);
+                }
+            }
+        }
+
+        let blocked = (0..self.node_count()).filter(|&i| in_degree[i] > 0).collect();
+        (order, blocked)
+    }
+
+    // Longest-path layering: every node sits one layer below its deepest
+    // dependency. Nodes blocked by a cycle are collected in a trailing layer.
+    pub fn layers(&self) -> Vec<Vec<usize>> {
+        let (order, blocked) = self.topological_order();
+        let mut depth = vec![0usize; self.node_count()];
+        for &n in &order {
+            for &m in &self.outgoing[n] {
+                depth[m] = depth[m].max(depth[n] + 1);
+            }
+        }
+
+        let mut layers: Vec<Vec<usize>> = Vec::new();
+        for &n in &order {
+            if layers.len() <= depth[n] {
+                layers.resize(depth[n] + 1, Vec::new());
+            }
+            layers[depth[n]].push(n);
+        }
+        if !blocked.is_empty() {
+            layers.push(blocked);
+        }
+        layers
+    }
+
+    // Breadth-first search from `from` along edges (or against them when
+    // `backwards`), at most `depth` steps out. Gives the distance of every
+    // node reached and the node it was first reached from. Cycles are fine.
+    pub fn reach(&self, from: usize, backwards: bool, depth: Option<usize>) -> Vec<Option<(usize, usize)>> {
+        let neighbours = if backwards { &self.incoming } else { &self.outgoing };
+        let mut reached = vec![None; self.node_count()];
+        reached[from] = Some((0, from));
+        let mut queue = VecDeque::from([from]);
+        while let Some(n) = queue.pop_front() {
+            let Some((distance, _)) = reached[n] else { continue };
+            if depth.is_some_and(|depth| distance >= depth) {
+                continue;
+            }
+            for &m in &neighbours[n] {
+                if reached[m].is_none() {
+                    reached[m] = Some((distance + 1, n));
+                    queue.push_back(m);
+                }
+            }
+        }
+        reached
+    }
+
+    // Cheapest (or most expensive) path between two nodes, by dynamic
+    // programming over a topological order of an acyclic graph. `cost` prices
+    // the step from one node to the next.
+    pub fn best_path(
+        &self,
+        order: &[usize],
+        from: usize,
+        to: usize,
+        longest: bool,
+        cost: impl Fn(usize, usize) -> f64,
+    ) -> Option<(Vec<usize>, f64)> {
+        let mut best: Vec<Option<f64>> = vec![None; self.node_count()];
+        let mut previous: Vec<Option<usize>> = vec![None; self.node_count()];
+        best[from] = Some(0.0);
+
+        for &n in order {
+            let Some(reached) = best[n] else { continue };
+            for &m in &self.outgoing[n] {
+                let candidate = reached + cost(n, m);
+                let better = match best[m] {
+                    None => true,
+                    Some(current) if longest => candidate > current,
+                    Some(current) => candidate < current,
+                };
+                if better {
+                    best[m] = Some(candidate);
+                    previous[m] = Some(n);
+                }
+            }
+        }
+
+        let total = best[to]?;
+        let mut path = vec![to];
+        while let Some(p) = previous[*path.last().unwrap()] {
+            path.push(p);
+        }
+        path.reverse();
+        Some((path, total))
+    }
+
+    // The heaviest chain of nodes, by the sum of their weights, over a
+    // topological order of an acyclic graph. Returns the chain and its total.
+    pub fn critical_path(&self, order: &[usize], weights: &[f64]) -> (Vec<usize>, f64) {
+        let mut finish = vec![0.0f64; self.node_count()];
+        let mut previous: Vec<Option<usize>> = vec![None; self.node_count()];
+        for &n in order {
+            let before = self.incoming[n].iter().copied().max_by(|&a, &b| finish[a].total_cmp(&finish[b]));
+            finish[n] = weights[n] + before.map_or(0.0, |p| finish[p]);
+            previous[n] = before;
+        }
+
+        let Some(end) = order.iter().copied().max_by(|&a, &b| finish[a].total_cmp(&finish[b])) else {
+            return (Vec::new(), 0.0);
+        };
+        let mut path = vec![end];
+        while let Some(p) = previous[*path.last().unwrap()] {
+            path.push(p);
+        }
+        path.reverse();
+        (path, finish[end])
+    }
+
+    // Groups of nodes linked by edges in either direction
+    pub fn component_count(&self) -> usize {
+        let mut seen = vec![false; self.node_count()];
+        let mut count = 0;
+        for start in 0..self.node_count() {
+            if seen[start] {
+                continue;
+            }
+            count += 1;
+            seen[start] = true;
+            let mut stack = vec![start];
+            while let Some(n) = stack.pop() {
+                for &m in self.outgoing[n].iter().chain(&self.incoming[n]) {
+                    if !seen[m] {
+                        seen[m] = true;
+                        stack.push(m);
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    // List-scheduling simulation on `workers` identical workers. Ready nodes are
+    // started longest-remaining-path first; returns (start, end, worker) per
+    // node. The graph must be acyclic.
+    pub fn simulate(&self, order: &[usize], durations: &[f64], workers: usize) -> Vec<(f64, f64, usize)> {
+        let mut priority = vec![0.0f64; self.node_count()];
+        for &n in order.iter().rev() {
+            let tail = self.outgoing[n].iter().map(|&m| priority[m]).fold(0.0, f64::max);
+            priority[n] = durations[n] + tail;
+        }
+
+        let mut waiting: Vec<usize> = self.incoming.iter().map(|i| i.len()).collect();
+        let mut ready: Vec<usize> = (0..self.node_count()).filter(|&n| waiting[n] == 0).collect();
+        let mut free: BTreeSet<usize> = (0..workers).collect();
+        let mut running: Vec<usize> = Vec::new();
+        let mut schedule = vec![(0.0, 0.0, 0); self.node_count()];
+        let mut now = 0.0;
+
+        while !ready.is_empty() || !running.is_empty() {
+            ready.sort_by(|&a, &b| priority[a].total_cmp(&priority[b]));
+            while !ready.is_empty() {
+                let Some(worker) = free.pop_first() else { break };
+                let n = ready.pop().unwrap();
+                schedule[n] = (now, now + durations[n], worker);
+                running.push(n);
+            }
+
+            now = running.iter().map(|&n| schedule[n].1).fold(f64::INFINITY, f64::min);
+            let (done, still_running): (Vec<usize>, Vec<usize>) =
+                running.iter().partition(|&&n| schedule[n].1 <= now);
+            running = still_running;
+            for n in done {
+                free.insert(schedule[n].2);
+                for &m in &self.outgoing[n] {
+                    waiting[m] -= 1;
+                    if waiting[m] == 0 {
+                        ready.push(m);
+                    }
+                }
+            }
+        }
+        schedule
+    }
+
+    // Balanced k-way partition heuristic. Seeds the parts with contiguous runs
+    // of a depth-first topological order (chains stay together), then greedily
+    // moves nodes to the part holding most of their neighbours while part
+    // sizes stay within 10% of the ideal. Returns the part of every node.
+    pub fn partition(&self, k: usize) -> Vec<usize> {
+        let count = self.node_count();
+        let mut in_degree: Vec<usize> = self.incoming.iter().map(|i| i.len()).collect();
+        let mut stack: Vec<usize> = (0..count).rev().filter(|&n| in_degree[n] == 0).collect();
+        let mut order = Vec::with_capacity(count);
+        while let Some(n) = stack.pop() {
+            order.push(n);
+            for &m in self.outgoing[n].iter().rev() {
+                in_degree[m] -= 1;
+                if in_degree[m] == 0 {
+                    stack.push(m);
+                }
+            }
+        }
+        order.extend((0..count).filter(|&n| in_degree[n] > 0));
+
+        let mut part = vec![0; count];
+        let mut sizes = vec![0; k];
+        for (i, &n) in order.iter().enumerate() {
+            part[n] = i * k / count;
+            sizes[part[n]] += 1;
+        }
+
+        let ideal = count as f64 / k as f64;
+        let max_size = (ideal * 1.1).ceil() as usize;
+        let min_size = (ideal * 0.9).floor() as usize;
+        for _ in 0..10 {
+            let mut moved = false;
+            for n in 0..count {
+                let mut links = vec![0i64; k];
+                for &m in self.outgoing[n].iter().chain(self.incoming[n].iter()) {
+                    links[part[m]] += 1;
+                }
+                let own = part[n];
+                let best = (0..k)
+                    .filter(|&p| p != own && sizes[p] < max_size)
+                    .max_by_key(|&p| links[p]);
+                if let Some(p) = best {
+                    if links[p] > links[own] && sizes[own] > min_size.max(1) {
+                        sizes[own] -= 1;
+                        sizes[p] += 1;
+                        part[n] = p;
+                        moved = true;
+                    }
+                }
+            }
+            if !moved {
+                break;
+            }
+        }
+        part
+    }
+}
+
+// Edmonds-Karp max flow over arcs `(from, to, capacity)` between nodes
+// `0..node_count`. Returns the flow value and the indices of the arcs that
+// make up a minimum cut.
+pub fn max_flow(
+    node_count: usize,
+    arcs: &[(usize, usize, f64)],
+    source: usize,
+    sink: usize,
+) -> (f64, Vec<usize>) {
+    // Residual arc 2i is arc i itself, 2i + 1 is its reverse
+    let mut residual: Vec<f64> = arcs.iter().flat_map(|&(_, _, c)| [c, 0.0]).collect();
+    let head = |r: usize| if r & 1 == 0 { arcs[r / 2].1 } else { arcs[r / 2].0 };
+    let mut adjacency = vec![Vec::new(); node_count];
+    for (i, &(from, to, _)) in arcs.iter().enumerate() {
+        adjacency[from].push(2 * i);
+        adjacency[to].push(2 * i + 1);
+    }
+
+    let reachable = |residual: &[f64]| {
+        let mut via: Vec<Option<usize>> = vec![None; node_count];
+        let mut seen = vec![false; node_count];
+        let mut queue = VecDeque::from([source]);
+        seen[source] = true;
+        while let Some(n) = queue.pop_front() {
+            for &r in &adjacency[n] {
+                let m = head(r);
+                if !seen[m] && residual[r] > f64::EPSILON {
+                    seen[m] = true;
+                    via[m] = Some(r);
+                    queue.push_back(m);
+                }
+            }
+        }
+        (seen, via)
+    };
+
+    let mut total = 0.0;
+    loop {
+        let (seen, via) = reachable(&residual);
+        if !seen[sink] {
+            let cut = arcs
+                .iter()
+                .enumerate()
+                .filter(|(_, &(from, to, _))| seen[from] && !seen[to])
+                .map(|(i, _)| i)
+                .collect();
+            return (total, cut);
+        }
+
+        let mut augmenting = Vec::new();
+        let mut n = sink;
+        while let Some(r) = via[n] {
+            augmenting.push(r);
+            n = head(r ^ 1);
+        }
+        let bottleneck = augmenting.iter().map(|&r| residual[r]).fold(f64::INFINITY, f64::min);
+        for &r in &augmenting {
+            residual[r] -= bottleneck;
+            residual[r ^ 1] += bottleneck;
+        }
+        total += bottleneck;
+    }
+}
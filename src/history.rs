@@ -0,0 +1,172 @@
+// Who changed a DAG and when. The database records every change to a DAG,
+// its nodes and its edges in the events table (see migration 0030); this
+// lists them and replays them to show the DAG as it was at a given time.
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json as JsonColumn;
+use sqlx::{FromRow, PgPool};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::usage::{self, Access};
+use crate::{page_response, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+
+const ENTRY_COLUMNS: &str = "id, entity_type, entity_id, action, actor, key_id, reason, old, new, occurred_at";
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum EntityType {
+    Dag,
+    Node,
+    Edge,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Action {
+    // What already existed when history started being kept
+    Baseline,
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize, FromRow)]
+struct Entry {
+    id: i64,
+    entity_type: EntityType,
+    entity_id: Uuid,
+    action: Action,
+    // Who made the change as they said in X-Actor, and with which API key
+    actor: Option<String>,
+    key_id: Option<Uuid>,
+    reason: Option<String>,
+    // The whole row for creates and deletes; for updates, just the columns
+    // that changed
+    old: Option<JsonColumn<serde_json::Value>>,
+    new: Option<JsonColumn<serde_json::Value>>,
+    occurred_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    // Changes at or after `from` and before `to`
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    entity_type: Option<EntityType>,
+    entity_id: Option<Uuid>,
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+// Pages of a DAG's changes, newest first, with the number of matching
+// changes in X-Total-Count
+pub async fn dag_history(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<HistoryParams>,
+) -> Result<Response, AppError> {
+    let matching = "FROM events WHERE dag_id = $1
+           AND ($2::timestamptz IS NULL OR occurred_at >= $2) AND ($3::timestamptz IS NULL OR occurred_at < $3)
+           AND ($4::text IS NULL OR entity_type = $4) AND ($5::uuid IS NULL OR entity_id = $5)";
+    let page = async {
+        let total = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) {}", matching))
+            .bind(dag_id)
+            .bind(params.from)
+            .bind(params.to)
+            .bind(params.entity_type)
+            .bind(params.entity_id)
+            .fetch_one(&pool)
+            .await?;
+        let entries = sqlx::query_as::<_, Entry>(&format!(
+            "SELECT {} {} ORDER BY id DESC LIMIT $6 OFFSET $7",
+            ENTRY_COLUMNS, matching
+        ))
+            .bind(dag_id)
+            .bind(params.from)
+            .bind(params.to)
+            .bind(params.entity_type)
+            .bind(params.entity_id)
+            .bind(params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE))
+            .bind(params.offset.max(0))
+            .fetch_all(&pool)
+            .await?;
+        Ok::<_, sqlx::Error>((entries, total))
+    };
+
+    let (entries, total) = page.await.map_err(|e| AppError::database(e, &locale, "fetch_history_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(page_response(entries, total, None))
+}
+
+#[derive(Deserialize)]
+pub struct StateParams {
+    // Defaults to now
+    at: Option<DateTime<Utc>>,
+}
+
+// The DAG, its nodes and its edges as they were at `at`, rebuilt by
+// replaying its changes up to then. Rows are given as stored, every column
+// included.
+pub async fn dag_state_at(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<StateParams>,
+) -> Result<Response, AppError> {
+    let at = params.at.unwrap_or_else(Utc::now);
+    let entries = sqlx::query_as::<_, Entry>(&format!(
+        "SELECT {} FROM events WHERE dag_id = $1 AND occurred_at <= $2 ORDER BY id",
+        ENTRY_COLUMNS
+    ))
+        .bind(dag_id)
+        .bind(at)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_history_failed"))?;
+
+    let mut rows: [BTreeMap<Uuid, serde_json::Value>; 3] = Default::default();
+    for entry in &entries {
+        let rows = &mut rows[entry.entity_type as usize];
+        match (entry.action, &entry.new) {
+            (Action::Baseline | Action::Created, Some(JsonColumn(new))) => {
+                rows.insert(entry.entity_id, new.clone());
+            }
+            (Action::Updated, Some(JsonColumn(serde_json::Value::Object(changed)))) => {
+                if let Some(serde_json::Value::Object(row)) = rows.get_mut(&entry.entity_id) {
+                    row.extend(changed.clone());
+                }
+            }
+            (Action::Deleted, _) => {
+                rows.remove(&entry.entity_id);
+            }
+            _ => {}
+        }
+    }
+    let [dags, nodes, edges] = rows;
+    let Some(dag) = dags.into_values().next() else {
+        return Err(locale.error(StatusCode::NOT_FOUND, "dag_history_not_found", &[("id", &dag_id), ("at", &at)]));
+    };
+
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(Json(serde_json::json!({
+        "at": at,
+        "dag": dag,
+        "nodes": nodes.into_values().collect::<Vec<_>>(),
+        "edges": edges.into_values().collect::<Vec<_>>(),
+        "event_count": entries.len(),
+    }))
+    .into_response())
+}
@@ -0,0 +1,377 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts, StatusCode},
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::{env, fs};
+
+use crate::error::AppError;
+
+const DEFAULT_LANGUAGE: &str = "en";
+
+// Built-in English messages. Placeholders are written as {name}.
+const ENGLISH: &[(&str, &str)] = &[
+    ("dag_not_found", "DAG with id {id} not found"),
+    ("node_not_found_in_dag", "Node with id {node} not found in DAG {dag}"),
+    ("dag_has_cycle", "DAG {id} contains a cycle"),
+    ("no_path", "No path from {from} to {to}"),
+    ("unsupported_weight", "Unsupported weight '{weight}', expected 'hops'"),
+    ("unsupported_capacity", "Unsupported capacity '{capacity}', expected 'unit'"),
+    ("source_equals_sink", "Source and sink must be different nodes"),
+    ("workers_required", "At least one worker is required"),
+    ("negative_duration", "Duration of node {node} must not be negative"),
+    ("missing_duration", "No duration given for node {node}"),
+    ("invalid_partition_count", "k must be between 1 and the number of nodes ({count})"),
+    ("read_only", "The service is in read-only mode for maintenance, try again later"),
+    ("read_only_with_message", "The service is in read-only mode for maintenance: {message}"),
+    ("write_conflict", "The change kept conflicting with concurrent writes, try again: {error}"),
+    ("row_not_found", "Not found"),
+    ("constraint_unique", "This conflicts with an existing row ({constraint})"),
+    ("constraint_still_referenced", "This is still referenced elsewhere ({constraint})"),
+    ("constraint_foreign_key", "This refers to something that does not exist ({constraint})"),
+    ("constraint_check", "A value breaks the rules for it: {error}"),
+    ("invalid_value", "Invalid value: {error}"),
+    ("id_taken", "Id {id} is already in use"),
+    ("create_dag_failed", "Failed to create DAG: {error}"),
+    ("fetch_dags_failed", "Failed to fetch DAGs: {error}"),
+    ("fetch_dag_failed", "Failed to fetch DAG details: {error}"),
+    ("update_dag_failed", "Failed to update DAG: {error}"),
+    ("dag_has_pending_imports", "DAG {id} cannot be deleted while imports into it are pending: {ids}"),
+    ("dag_has_active_runs", "DAG {id} cannot be deleted while it is running: {ids}"),
+    ("delete_dag_failed", "Failed to delete DAG: {error}"),
+    ("node_label_not_found", "No node labelled {label} in this DAG"),
+    ("node_label_ambiguous", "Several nodes are labelled {label}: {ids}"),
+    ("filter_unknown_field", "Unknown filter field {field}"),
+    ("filter_unterminated_string", "Unterminated string in filter at position {position}"),
+    ("filter_unexpected", "Unexpected input in filter at position {position}"),
+    ("filter_unexpected_end", "Filter ends unexpectedly"),
+    ("delete_nodes_failed", "Failed to delete Nodes: {error}"),
+    ("merge_same_node", "A node cannot be merged into itself"),
+    ("merge_creates_cycle", "Merging {remove} into {keep} would create a cycle"),
+    ("merge_nodes_failed", "Failed to merge Nodes: {error}"),
+    ("split_node_failed", "Failed to split Node: {error}"),
+    ("relabel_empty_find", "The text to find must not be empty"),
+    ("relabel_invalid_regex", "Invalid regular expression: {error}"),
+    ("relabel_blank_label", "Relabeling would leave node {label} with an empty label"),
+    ("relabel_nodes_failed", "Failed to relabel Nodes: {error}"),
+    ("normalize_failed", "Failed to normalize DAG: {error}"),
+    ("node_not_found", "Node with id {id} not found"),
+    ("blank_external_id", "External system names and ids must not be blank"),
+    ("invalid_source_url", "Source links must be http:// or https:// URLs"),
+    ("invalid_task", "A task needs a command, or an http:// or https:// URL and a valid method"),
+    ("upsert_node_failed", "Failed to create or update Node: {error}"),
+    ("update_node_failed", "Failed to update Node: {error}"),
+    ("delete_node_failed", "Failed to delete Node: {error}"),
+    ("create_node_failed", "Failed to create Node: {error}"),
+    ("fetch_nodes_failed", "Failed to fetch Nodes: {error}"),
+    ("edge_creates_cycle", "An edge from {source} to {target} would create a cycle"),
+    ("edge_not_found", "Edge with id {id} not found"),
+    ("insert_node_failed", "Failed to insert Node on Edge: {error}"),
+    ("create_edge_failed", "Failed to create Edge: {error}"),
+    ("fetch_edges_failed", "Failed to fetch Edges: {error}"),
+    ("update_edge_failed", "Failed to update Edge: {error}"),
+    ("delete_edge_failed", "Failed to delete Edge: {error}"),
+    ("render_failed", "Failed to render DAG: {error}"),
+    ("rasterize_failed", "Failed to rasterize DAG: {error}"),
+    ("export_failed", "Failed to export DAG: {error}"),
+    ("version_not_found", "DAG {dag} has no version {version}"),
+    ("version_saved_before_restore", "Saved before restoring version {version}"),
+    ("fetch_versions_failed", "Failed to fetch DAG versions: {error}"),
+    ("create_version_failed", "Failed to save DAG version: {error}"),
+    ("restore_version_failed", "Failed to restore DAG version: {error}"),
+    ("node_deprecated_target", "Node {id} ({label}) is deprecated; new edges should not depend on it"),
+    ("label_prefix_not_allowed", "Label '{label}' does not start with an allowed prefix"),
+    ("blank_label_prefix", "Label prefixes must not be blank"),
+    ("fetch_label_prefixes_failed", "Failed to fetch label prefixes: {error}"),
+    ("set_label_prefixes_failed", "Failed to save label prefixes: {error}"),
+    ("websocket_required", "This endpoint only speaks WebSocket (version 13); send an Upgrade request"),
+    ("impact_failed", "Failed to compute impact: {error}"),
+    ("dag_deprecated", "DAG {id} is deprecated"),
+    ("dag_deprecated_with_reason", "DAG {id} is deprecated: {reason}"),
+    ("dag_archived", "DAG {id} is archived and read-only"),
+    ("replacement_is_self", "A DAG cannot replace itself"),
+    ("replacement_not_found", "Replacement DAG {id} not found"),
+    ("update_lifecycle_failed", "Failed to update DAG lifecycle: {error}"),
+    ("fetch_usage_failed", "Failed to fetch usage: {error}"),
+    ("import_not_found", "Import with id {id} not found"),
+    ("import_not_uploading", "Import {id} is {status} and no longer accepts uploads"),
+    ("import_not_valid", "Import {id} is {status}; only valid imports can be promoted"),
+    ("import_validating", "Import {id} is being validated"),
+    ("import_blank_label", "Node {ids} has an empty label"),
+    ("import_duplicate_client_id", "Client id {ids} is used by more than one node"),
+    ("import_unknown_endpoint", "Edge {ids} references a node that is not part of the import"),
+    ("import_self_loop", "Node {ids} has an edge to itself"),
+    ("import_label_prefix", "Label of node {ids} does not start with an allowed prefix"),
+    ("import_cycle", "Nodes {ids} are on or behind a cycle"),
+    ("import_internal_error", "Validation stopped because of an internal error"),
+    ("import_invalid", "Nothing was imported; problems found in the graph: {count}"),
+    ("import_dag_failed", "Failed to import DAG: {error}"),
+    ("blank_import_namespace", "Import namespace must not be blank"),
+    ("import_not_promoted", "Import {id} cannot be rolled back while it is {status}"),
+    ("import_rollback_conflicts", "Import {id} cannot be rolled back because what it created has changed since"),
+    ("import_rollback_node_modified", "Imported nodes were modified: {ids}"),
+    ("import_rollback_foreign_edge", "Edges added after the import touch imported nodes: {ids}"),
+    ("import_rollback_foreign_node", "Nodes added after the import live in its DAG: {ids}"),
+    ("import_rollback_dag_referenced", "The imported DAG is referenced by: {ids}"),
+    ("rollback_import_failed", "Failed to roll back import: {error}"),
+    ("create_import_failed", "Failed to create import: {error}"),
+    ("fetch_import_failed", "Failed to fetch import: {error}"),
+    ("validate_import_failed", "Failed to start import validation: {error}"),
+    ("upload_chunk_failed", "Failed to upload import chunk: {error}"),
+    ("promote_import_failed", "Failed to promote import: {error}"),
+    ("delete_import_failed", "Failed to delete import: {error}"),
+    ("catalog_entry_not_found", "Catalog entry with id {id} not found"),
+    ("catalog_version_not_found", "Catalog entry {id} has no version {version}"),
+    ("dag_not_subscribed", "DAG {id} is not a subscribed copy of a catalog entry"),
+    ("publish_dag_failed", "Failed to publish DAG: {error}"),
+    ("fetch_catalog_failed", "Failed to fetch catalog: {error}"),
+    ("copy_catalog_entry_failed", "Failed to copy catalog entry: {error}"),
+    ("sync_catalog_copy_failed", "Failed to update DAG from the catalog: {error}"),
+    ("invalid_rating", "Ratings go from 1 to 5"),
+    ("update_catalog_entry_failed", "Failed to update catalog entry: {error}"),
+    ("add_review_failed", "Failed to add review: {error}"),
+    ("run_not_found", "Run with id {id} not found"),
+    ("start_run_failed", "Failed to start run: {error}"),
+    ("fetch_runs_failed", "Failed to fetch runs: {error}"),
+    ("task_run_not_found", "Run {run} has no task for node {node}"),
+    ("blank_ticket", "Ticket systems and keys must not be blank"),
+    ("invalid_ticket_url", "Ticket links must be http:// or https:// URLs"),
+    ("invalid_wait_timeout", "timeout must be a number of seconds like 30 or 30s, or minutes like 2m (at most {max}s)"),
+    ("ticket_task_not_failed", "Only failed tasks take tickets, and node {node} has not failed"),
+    ("ticket_not_found", "Ticket with id {id} not found"),
+    ("add_ticket_failed", "Failed to add ticket: {error}"),
+    ("delete_ticket_failed", "Failed to delete ticket: {error}"),
+    ("job_not_found", "Job with id {id} not found"),
+    ("fetch_job_failed", "Failed to fetch job: {error}"),
+    ("invalid_cron", "'{cron}' is not a valid cron expression: {error}"),
+    ("cron_never_fires", "Cron expression '{cron}' never fires"),
+    ("schedule_not_found", "Schedule with id {id} not found"),
+    ("fetch_schedules_failed", "Failed to fetch schedules: {error}"),
+    ("create_schedule_failed", "Failed to create schedule: {error}"),
+    ("update_schedule_failed", "Failed to update schedule: {error}"),
+    ("delete_schedule_failed", "Failed to delete schedule: {error}"),
+    ("effective_at_not_future", "effective_at must be in the future, not {effective_at}"),
+    ("scheduled_change_empty", "A scheduled change must add or remove at least one node or edge"),
+    ("scheduled_change_blank_label", "New node {client_id} has an empty label"),
+    ("scheduled_change_unknown_endpoint", "Edge endpoint '{endpoint}' is neither a new node's client_id nor a node id"),
+    ("scheduled_change_not_found", "Scheduled change with id {id} not found"),
+    ("scheduled_change_not_pending", "Scheduled change {id} is {status} and can no longer be cancelled"),
+    ("fetch_scheduled_changes_failed", "Failed to fetch scheduled changes: {error}"),
+    ("create_scheduled_change_failed", "Failed to schedule change: {error}"),
+    ("delete_scheduled_change_failed", "Failed to cancel scheduled change: {error}"),
+    ("api_key_missing", "Send an API key as Authorization: Bearer <key>"),
+    ("api_key_invalid", "The API key is not valid or has been revoked"),
+    ("api_key_not_admin", "Only admin API keys may use /admin endpoints"),
+    ("api_key_read_only", "This API key may only read"),
+    ("api_key_not_found", "API key with id {id} not found"),
+    ("blank_api_key_name", "API keys need a name"),
+    ("authenticate_failed", "Failed to check the API key: {error}"),
+    ("create_api_key_failed", "Failed to issue API key: {error}"),
+    ("fetch_api_keys_failed", "Failed to fetch API keys: {error}"),
+    ("revoke_api_key_failed", "Failed to revoke API key: {error}"),
+    ("environment_not_found", "Environment '{name}' not found"),
+    ("blank_environment_name", "Environments need a name"),
+    ("environment_position_taken", "Another environment is already at position {position}"),
+    ("environment_taken_by_promotion", "DAG {id} cannot move to {environment}: another DAG promoted from the same one is there"),
+    ("dag_not_in_environment", "DAG {id} is not in an environment; put it in one before promoting it"),
+    ("no_next_environment", "DAG {id} is in {environment}, the last environment; there is nowhere to promote it"),
+    ("promotion_not_found", "Promotion with id {id} not found"),
+    ("promotion_not_pending", "Promotion {id} is {status} and can no longer be decided"),
+    ("promotion_needs_admin", "Only admin API keys may approve or reject promotions"),
+    ("promotion_self_approval", "Promotion {id} must be decided by someone other than who requested it"),
+    ("version_saved_before_promotion", "Saved before promoting version {version} from {environment}"),
+    ("fetch_environments_failed", "Failed to fetch environments: {error}"),
+    ("update_environment_failed", "Failed to update environment: {error}"),
+    ("fetch_promotions_failed", "Failed to fetch promotions: {error}"),
+    ("create_promotion_failed", "Failed to promote DAG: {error}"),
+    ("approve_promotion_failed", "Failed to approve promotion: {error}"),
+    ("reject_promotion_failed", "Failed to reject promotion: {error}"),
+    ("invalid_connection_name", "Connection names may only use letters, digits, _ and -, got '{name}'"),
+    ("connection_unknown_field", "{kind} connections have no field '{field}'"),
+    ("connection_missing_field", "{kind} connections need '{field}'"),
+    ("connection_exists", "A connection named {name} already exists for that environment"),
+    ("connection_not_found", "Connection with id {id} not found"),
+    ("connections_key_missing", "Set CONNECTIONS_KEY to store connection secrets and secret variables"),
+    ("seal_secrets_failed", "Failed to seal secrets: {error}"),
+    ("fetch_connections_failed", "Failed to fetch connections: {error}"),
+    ("create_connection_failed", "Failed to create connection: {error}"),
+    ("update_connection_failed", "Failed to update connection: {error}"),
+    ("delete_connection_failed", "Failed to delete connection: {error}"),
+    ("invalid_variable_name", "Variable names may only use letters, digits, _ and -, got '{name}'"),
+    ("variable_exists", "A variable named {name} already exists for that environment"),
+    ("variable_not_found", "Variable with id {id} not found"),
+    ("fetch_variables_failed", "Failed to fetch variables: {error}"),
+    ("create_variable_failed", "Failed to create variable: {error}"),
+    ("update_variable_failed", "Failed to update variable: {error}"),
+    ("delete_variable_failed", "Failed to delete variable: {error}"),
+    ("export_limit_reached", "This key already has {count} exports in progress; try again once one finishes"),
+    ("export_range_invalid", "The requested range starts past the end of the {length} byte export"),
+    ("edge_self_loop", "An edge can't lead from node {id} to itself"),
+    ("edge_node_missing", "Node with id {id} not found"),
+    ("edge_node_in_other_dag", "Node {id} is not in DAG {dag_id}; edges must stay within their DAG"),
+    ("copy_dag_failed", "Failed to copy DAG: {error}"),
+    ("subgraph_empty", "Name at least one node to copy"),
+    ("import_too_large", "Import payloads are limited to {limit} bytes"),
+    ("import_too_many_nodes", "Imports are limited to {limit} nodes; this one has {count}"),
+    ("import_too_many_edges", "Imports are limited to {limit} edges; this one has {count}"),
+    ("import_digest_invalid", "Content-Digest must give a sha-256 digest as sha-256=:<base64>:, got '{value}'"),
+    ("import_digest_mismatch", "The payload doesn't match its Content-Digest; it may have been cut short or changed on the way"),
+    ("import_body_invalid", "The import payload couldn't be read: {error}"),
+    ("invalid_node_weight", "Node {node} has a {field} that isn't a non-negative number"),
+    ("negative_default_weight", "default_weight must not be negative"),
+    ("fetch_history_failed", "Failed to fetch DAG history: {error}"),
+    ("dag_history_not_found", "No history of DAG {id} was recorded at or before {at}"),
+    ("import_aborted", "Validation was aborted before it finished"),
+    ("import_not_validating", "Import {id} is {status}; only a running validation can be aborted"),
+    ("abort_import_failed", "Failed to abort import: {error}"),
+    ("import_label_exists", "Node {ids} has a label the target DAG already has"),
+    ("import_policy_needs_dag", "on_duplicate only applies to imports into an existing DAG; give dag_id"),
+    ("import_labels_exist", "{count} imported nodes have labels the DAG already has; nothing was promoted"),
+    ("import_merge_cycle", "Adding the import to the DAG would create a cycle; nothing was promoted"),
+    ("if_match_required", "Send If-Match with the ETag the resource was read with, or * to change it whatever its version"),
+    ("version_mismatch", "The {entity} {id} was changed since it was read; fetch it again and retry"),
+    ("fetch_task_log_failed", "Failed to fetch the task's log"),
+    ("task_log_not_found", "Node {node} in run {run} has no log"),
+    ("log_store_not_configured", "The {store} log store is not configured"),
+    ("task_log_unavailable", "Failed to read the log from the {store} log store"),
+    ("log_migration_same_store", "Logs are already in the {store} log store"),
+    ("migrate_task_logs_failed", "Failed to start moving task logs"),
+    ("task_token_missing", "Send the task's token in X-Task-Token"),
+    ("task_token_invalid", "The task token does not belong to this node's current attempt"),
+    ("task_not_running", "Node {node} is not running; only a running task can write its outputs"),
+    ("invalid_outputs", "Outputs must be a JSON object of keys and values"),
+    ("output_key_required", "Give the key to store the blob under in ?key="),
+    ("output_key_with_values", "A JSON body sets its own keys; ?key= is for blobs"),
+    ("invalid_output_key", "Output key '{key}' may only hold letters, digits, _ and -"),
+    ("write_outputs_failed", "Failed to write the outputs"),
+    ("fetch_outputs_failed", "Failed to fetch the outputs"),
+    ("output_not_found", "Node {node} has no output '{key}' in this run"),
+    ("label_prefixes_need_admin", "Only admin API keys may change the label vocabulary"),
+    ("catalog_entry_needs_admin", "Only admin API keys may edit catalog entries"),
+];
+
+// Message catalogs keyed by lowercase language tag ("en", "de", "pt-br").
+// English is built in; more catalogs are read from LOCALES_DIR, one flat JSON
+// object of message key to template per file, named after the language
+// (e.g. locales/de.json).
+pub struct Catalogs {
+    languages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalogs {
+    fn builtin() -> Self {
+        let mut languages = HashMap::new();
+        languages.insert(
+            DEFAULT_LANGUAGE.to_string(),
+            ENGLISH.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        );
+        Catalogs { languages }
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let Catalogs { mut languages } = Catalogs::builtin();
+        if let Ok(dir) = env::var("LOCALES_DIR") {
+            let entries = fs::read_dir(&dir).map_err(|e| format!("LOCALES_DIR {} must be a readable directory: {}", dir, e))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                let Some(language) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+                let contents = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read message catalog {}: {}", path.display(), e))?;
+                let catalog: HashMap<String, String> = serde_json::from_str(&contents)
+                    .map_err(|e| format!("Message catalog {} must be a flat JSON object: {}", path.display(), e))?;
+                languages.insert(language.to_lowercase(), catalog);
+            }
+        }
+
+        Ok(Catalogs { languages })
+    }
+
+    // Picks the best available language for an Accept-Language header value
+    fn negotiate(&self, accept_language: &str) -> String {
+        let mut ranges: Vec<(String, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.trim().split(';');
+                let tag = parts.next()?.trim().to_lowercase();
+                let quality = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        for (tag, _) in ranges {
+            if self.languages.contains_key(&tag) {
+                return tag;
+            }
+            let primary = tag.split('-').next().unwrap_or_default();
+            if self.languages.contains_key(primary) {
+                return primary.to_string();
+            }
+        }
+        DEFAULT_LANGUAGE.to_string()
+    }
+}
+
+// The caller's language, negotiated from Accept-Language
+pub struct Locale {
+    catalogs: Arc<Catalogs>,
+    language: String,
+}
+
+impl Locale {
+    // The locale of a request answered later, e.g. by a background task
+    pub fn new(catalogs: Arc<Catalogs>, language: &str) -> Self {
+        Locale { catalogs, language: language.to_string() }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    // Looks up a message, falling back to English and then to the key itself
+    pub fn t(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let template = [self.language.as_str(), DEFAULT_LANGUAGE]
+            .iter()
+            .find_map(|language| self.catalogs.languages.get(*language)?.get(key))
+            .map_or(key, |t| t.as_str());
+
+        let mut message = template.to_string();
+        for (name, value) in args {
+            message = message.replace(&format!("{{{}}}", name), &value.to_string());
+        }
+        message
+    }
+
+    // An error answer with the message for `key`, which is also its code
+    pub fn error(&self, status: StatusCode, key: &str, args: &[(&str, &dyn Display)]) -> AppError {
+        AppError::new(status, key, self.t(key, args))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Locale
+where
+    S: Send + Sync,
+    Arc<Catalogs>: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let catalogs = Arc::<Catalogs>::from_ref(state);
+        let language = parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map_or_else(|| DEFAULT_LANGUAGE.to_string(), |v| catalogs.negotiate(v));
+        Ok(Locale { catalogs, language })
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::config::IdStrategy;
+
+// Makes ids for new rows. The time-ordered strategies keep inserts at the
+// right edge of the primary key indexes instead of scattering them.
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> Uuid;
+}
+
+struct RandomUuid;
+
+impl IdGenerator for RandomUuid {
+    fn generate(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+struct TimeOrderedUuid;
+
+impl IdGenerator for TimeOrderedUuid {
+    fn generate(&self) -> Uuid {
+        Uuid::now_v7()
+    }
+}
+
+// Stored in the uuid columns like any other id: 48 bits of Unix milliseconds
+// followed by random bits, without UUID version markers
+struct Ulid;
+
+impl IdGenerator for Ulid {
+    fn generate(&self) -> Uuid {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let random = Uuid::new_v4().as_u128() & ((1 << 80) - 1);
+        Uuid::from_u128((millis << 80) | random)
+    }
+}
+
+static GENERATOR: OnceLock<Box<dyn IdGenerator>> = OnceLock::new();
+
+// Takes ID_STRATEGY from the config at startup
+pub fn configure(strategy: IdStrategy) {
+    GENERATOR.get_or_init(|| match strategy {
+        IdStrategy::Uuid4 => Box::new(RandomUuid),
+        IdStrategy::Uuid7 => Box::new(TimeOrderedUuid),
+        IdStrategy::Ulid => Box::new(Ulid),
+    });
+}
+
+pub fn new_id() -> Uuid {
+    GENERATOR.get_or_init(|| Box::new(RandomUuid)).generate()
+}
@@ -0,0 +1,1440 @@
+use axum::{
+    async_trait,
+    body::{Body, HttpBody},
+    extract::{FromRef, FromRequest, FromRequestParts, Json, Path, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json as JsonColumn, FromRow, PgPool};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::events;
+use crate::graph::Graph;
+use crate::i18n::{Catalogs, Locale};
+use crate::ids;
+use crate::jobs::{self, JobKind};
+use crate::maintenance::Writable;
+use crate::slugs;
+use crate::usage::{self, Access};
+use crate::vocabulary;
+use crate::{check_writable, external_ids_valid, lock_dag, with_warning, writable};
+
+// Validation stops collecting findings past this many
+const MAX_REPORTED_ISSUES: usize = 100;
+// Nodes or edges validated between saving progress and findings
+const VALIDATION_BATCH: usize = 10_000;
+// How often a findings stream looks for news
+const STREAM_INTERVAL: Duration = Duration::from_millis(500);
+
+struct Limits {
+    bytes: usize,
+    nodes: usize,
+    edges: usize,
+}
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+// Takes IMPORT_MAX_BYTES, IMPORT_MAX_NODES and IMPORT_MAX_EDGES from the
+// config at startup
+pub fn configure(bytes: usize, nodes: usize, edges: usize) {
+    LIMITS.get_or_init(|| Limits { bytes, nodes, edges });
+}
+
+fn limits() -> &'static Limits {
+    LIMITS.get_or_init(|| Limits { bytes: usize::MAX, nodes: usize::MAX, edges: usize::MAX })
+}
+
+// Refuses more nodes or edges than an import may hold, before any of them
+// are checked or written
+fn check_counts(locale: &Locale, nodes: usize, edges: usize) -> Result<(), AppError> {
+    let limits = limits();
+    for (key, count, limit) in [("import_too_many_nodes", nodes, limits.nodes), ("import_too_many_edges", edges, limits.edges)] {
+        if count > limit {
+            return Err(locale
+                .error(StatusCode::PAYLOAD_TOO_LARGE, key, &[("count", &count), ("limit", &limit)])
+                .with_details(serde_json::json!({ "count": count, "limit": limit })));
+        }
+    }
+    Ok(())
+}
+
+// The sha-256 a client declared for the body in Content-Digest (RFC 9530),
+// if it declared one
+fn declared_digest(locale: &Locale, headers: &HeaderMap) -> Result<Option<Vec<u8>>, AppError> {
+    let Some(value) = headers.get("content-digest") else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or_default();
+    let invalid = || locale.error(StatusCode::BAD_REQUEST, "import_digest_invalid", &[("value", &value)]);
+    let sha256 = value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .find(|(algorithm, _)| algorithm.trim().eq_ignore_ascii_case("sha-256"))
+        .ok_or_else(invalid)?
+        .1;
+    let encoded = sha256.trim().strip_prefix(':').and_then(|s| s.strip_suffix(':')).ok_or_else(invalid)?;
+    let digest = base64::engine::general_purpose::STANDARD.decode(encoded).map_err(|_| invalid())?;
+    if digest.len() != 32 {
+        return Err(invalid());
+    }
+    Ok(Some(digest))
+}
+
+// A JSON import payload, read up to IMPORT_MAX_BYTES and checked against
+// its Content-Digest when it has one. Oversized bodies are turned away by
+// their Content-Length before anything is read.
+pub struct ImportBody<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S, Body> for ImportBody<T>
+where
+    S: Send + Sync,
+    Arc<Catalogs>: FromRef<S>,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+        let (mut parts, mut body) = request.into_parts();
+        let Ok(locale) = Locale::from_request_parts(&mut parts, state).await;
+        let limit = limits().bytes;
+        let too_large = || {
+            locale
+                .error(StatusCode::PAYLOAD_TOO_LARGE, "import_too_large", &[("limit", &limit)])
+                .with_details(serde_json::json!({ "limit": limit }))
+        };
+        let declared = declared_digest(&locale, &parts.headers)?;
+        let length = parts
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<usize>().ok());
+        if length.is_some_and(|length| length > limit) {
+            return Err(too_large());
+        }
+
+        let mut bytes = Vec::with_capacity(length.unwrap_or_default());
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk
+                .map_err(|e| locale.error(StatusCode::BAD_REQUEST, "import_body_invalid", &[("error", &e)]))?;
+            if bytes.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        if let Some(declared) = declared {
+            let actual = Sha256::digest(&bytes);
+            if actual.as_slice() != declared {
+                let encode = |digest: &[u8]| base64::engine::general_purpose::STANDARD.encode(digest);
+                return Err(locale
+                    .error(StatusCode::BAD_REQUEST, "import_digest_mismatch", &[])
+                    .with_details(serde_json::json!({ "declared": encode(&declared), "actual": encode(&actual) })));
+            }
+        }
+        serde_json::from_slice(&bytes)
+            .map(ImportBody)
+            .map_err(|e| locale.error(StatusCode::UNPROCESSABLE_ENTITY, "import_body_invalid", &[("error", &e)]))
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+enum ImportStatus {
+    Uploading,
+    Validating,
+    Valid,
+    Invalid,
+    Promoted,
+    RolledBack,
+}
+
+impl ImportStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ImportStatus::Uploading => "uploading",
+            ImportStatus::Validating => "validating",
+            ImportStatus::Valid => "valid",
+            ImportStatus::Invalid => "invalid",
+            ImportStatus::Promoted => "promoted",
+            ImportStatus::RolledBack => "rolled_back",
+        }
+    }
+}
+
+// A validation finding. Stored as a check name plus the offending client ids
+// so the message can be rendered in the language of whoever polls the import.
+#[derive(Serialize, Deserialize)]
+struct Issue {
+    check: String,
+    client_ids: Vec<String>,
+}
+
+impl Issue {
+    fn to_json(&self, locale: &Locale) -> serde_json::Value {
+        serde_json::json!({
+            "check": self.check,
+            "client_ids": self.client_ids,
+            "message": locale.t(&format!("import_{}", self.check), &[("ids", &self.client_ids.join(", "))]),
+        })
+    }
+}
+
+// What validating a staged graph found, up to MAX_REPORTED_ISSUES
+#[derive(Default)]
+struct Findings(Vec<Issue>);
+
+impl Findings {
+    fn report(&mut self, check: &str, client_ids: Vec<String>) {
+        if self.0.len() < MAX_REPORTED_ISSUES {
+            self.0.push(Issue { check: check.to_string(), client_ids });
+        }
+    }
+
+    // Numbers the nodes by client id in the order given and returns, for each
+    // number, the node's position in `nodes`. Repeated client ids keep their
+    // first node.
+    fn check_nodes<'a>(&mut self, nodes: &[(&'a str, &str)]) -> (HashMap<&'a str, usize>, Vec<usize>) {
+        let mut index = HashMap::new();
+        let mut kept = Vec::with_capacity(nodes.len());
+        self.check_more_nodes(nodes, 0, &mut index, &mut kept);
+        (index, kept)
+    }
+
+    // Goes on numbering with the next batch of nodes, which start at
+    // position `start`
+    fn check_more_nodes<'a>(
+        &mut self,
+        nodes: &[(&'a str, &str)],
+        start: usize,
+        index: &mut HashMap<&'a str, usize>,
+        kept: &mut Vec<usize>,
+    ) {
+        for (position, &(client_id, label)) in (start..).zip(nodes) {
+            if label.trim().is_empty() {
+                self.report("blank_label", vec![client_id.to_string()]);
+            }
+            if index.contains_key(client_id) {
+                self.report("duplicate_client_id", vec![client_id.to_string()]);
+            } else {
+                index.insert(client_id, kept.len());
+                kept.push(position);
+            }
+        }
+    }
+
+    // Labels as they will be stored, in the order of `nodes`
+    fn check_label_prefixes(&mut self, nodes: &[(&str, &str)], labels: &[String], prefixes: &[String]) {
+        for (&(client_id, _), label) in nodes.iter().zip(labels) {
+            if !vocabulary::allowed(prefixes, label) {
+                self.report("label_prefix", vec![client_id.to_string()]);
+            }
+        }
+    }
+
+    // Nodes the policy "error" refuses because the target DAG has their label
+    fn check_existing_labels(&mut self, nodes: &[(&str, &str)], labels: &[String], existing: &HashSet<String>) {
+        for (&(client_id, _), label) in nodes.iter().zip(labels) {
+            if existing.contains(label) {
+                self.report("label_exists", vec![client_id.to_string()]);
+            }
+        }
+    }
+
+    // Edges as pairs of node numbers
+    fn check_edges(&mut self, index: &HashMap<&str, usize>, edges: &[(&str, &str)]) -> Vec<(usize, usize)> {
+        let mut arcs = Vec::with_capacity(edges.len());
+        for &(source, target) in edges {
+            match (index.get(source), index.get(target)) {
+                (Some(_), Some(_)) if source == target => self.report("self_loop", vec![source.to_string()]),
+                (Some(&s), Some(&t)) => arcs.push((s, t)),
+                _ => self.report("unknown_endpoint", vec![source.to_string(), target.to_string()]),
+            }
+        }
+        arcs
+    }
+
+    fn check_cycles(&mut self, index: &HashMap<&str, usize>, node_ids: &[Uuid], arcs: Vec<(usize, usize)>) {
+        let graph = Graph::from_arcs(node_ids.to_vec(), arcs);
+        let (_, blocked) = graph.topological_order();
+        if !blocked.is_empty() {
+            let client_of: HashMap<usize, &str> = index.iter().map(|(c, &i)| (i, *c)).collect();
+            self.report("cycle", blocked.iter().map(|n| client_of[n].to_string()).collect());
+        }
+    }
+}
+
+// Slugs for the nodes of a new or emptied DAG, which only need to be unique
+// among themselves
+fn fresh_node_slugs(labels: &[String], node_ids: &[Uuid]) -> Vec<String> {
+    node_slugs_besides(labels, node_ids, HashSet::new())
+}
+
+// Slugs for nodes joining others whose slugs are `taken`
+fn node_slugs_besides(labels: &[String], node_ids: &[Uuid], mut taken: HashSet<String>) -> Vec<String> {
+    labels
+        .iter()
+        .zip(node_ids)
+        .map(|(label, &id)| {
+            let slug = slugs::slugify(label, "node");
+            let slug = if taken.contains(&slug) { slugs::disambiguate(&slug, id) } else { slug };
+            taken.insert(slug.clone());
+            slug
+        })
+        .collect()
+}
+
+#[derive(FromRow)]
+struct Import {
+    id: Uuid,
+    name: String,
+    target_dag_id: Option<Uuid>,
+    status: ImportStatus,
+    node_count: i32,
+    edge_count: i32,
+    validated_items: i32,
+    errors: JsonColumn<Vec<Issue>>,
+    dag_id: Option<Uuid>,
+    namespace: Option<String>,
+    source_system: Option<String>,
+    on_duplicate: Option<DuplicatePolicy>,
+    created_at: DateTime<Utc>,
+}
+
+// What promoting into a DAG does with an imported node whose label (with the
+// namespace) a node already in the DAG has
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+enum DuplicatePolicy {
+    // Refuse the import; validation reports such nodes too
+    Error,
+    // Keep the node in the DAG and attach the imported edges to it
+    Skip,
+    // The same, and merge the imported external ids and metadata into it
+    MergeMetadata,
+    // Create the node anyway as "<label> (2)", "<label> (3)", ...
+    Suffix,
+}
+
+// What happened to one imported node on a promotion governed by a policy
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+    Created,
+    Skipped,
+    Merged,
+    Suffixed,
+}
+
+#[derive(Serialize)]
+struct NodeDecision {
+    client_id: String,
+    decision: Decision,
+    // The node the imported one became or was folded into
+    node_id: Uuid,
+    label: String,
+}
+
+// Recorded on every node an import creates
+#[derive(Serialize, Deserialize)]
+pub struct Provenance {
+    import_id: Uuid,
+    source_system: Option<String>,
+    namespace: Option<String>,
+    imported_at: DateTime<Utc>,
+}
+
+// The label a staged node gets on promotion. The promote query builds the
+// same string in SQL.
+fn namespaced(namespace: Option<&str>, label: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}/{}", namespace, label),
+        None => label.to_string(),
+    }
+}
+
+impl Import {
+    fn to_json(&self, locale: &Locale) -> serde_json::Value {
+        let total = self.node_count + self.edge_count;
+        serde_json::json!({
+            "id": self.id,
+            "name": self.name,
+            "target_dag_id": self.target_dag_id,
+            "status": self.status,
+            "node_count": self.node_count,
+            "edge_count": self.edge_count,
+            "progress": if total == 0 { 0.0 } else { f64::from(self.validated_items) / f64::from(total) },
+            "errors": self.errors.0.iter().map(|issue| issue.to_json(locale)).collect::<Vec<_>>(),
+            "dag_id": self.dag_id,
+            "namespace": self.namespace,
+            "source_system": self.source_system,
+            "on_duplicate": self.on_duplicate,
+            "created_at": self.created_at,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateImportPayload {
+    name: String,
+    dag_id: Option<Uuid>,
+    // Prefixed to every node label as "<namespace>/<label>"
+    namespace: Option<String>,
+    source_system: Option<String>,
+    // Makes the import add to dag_id rather than replace its contents
+    on_duplicate: Option<DuplicatePolicy>,
+}
+
+#[derive(Deserialize)]
+struct StagedNode {
+    client_id: String,
+    label: String,
+    #[serde(default)]
+    external_ids: HashMap<String, String>,
+    #[serde(default = "crate::empty_metadata")]
+    metadata: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct StagedEdge {
+    source_client_id: String,
+    target_client_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChunkPayload {
+    #[serde(default)]
+    nodes: Vec<StagedNode>,
+    #[serde(default)]
+    edges: Vec<StagedEdge>,
+}
+
+fn import_not_found(locale: &Locale, import_id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "import_not_found", &[("id", &import_id)])
+}
+
+async fn fetch_import<'e, E: sqlx::PgExecutor<'e>>(executor: E, import_id: Uuid) -> Result<Option<Import>, sqlx::Error> {
+    sqlx::query_as::<_, Import>(
+        "SELECT id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at
+         FROM imports WHERE id = $1 AND ($2::uuid IS NULL OR owner_key_id = $2)",
+    )
+        .bind(import_id)
+        .bind(auth::owner())
+        .fetch_optional(executor)
+        .await
+}
+
+// Explains why a status transition did not apply: the import is gone or it is
+// in a state that doesn't allow the operation
+async fn transition_refused(pool: &PgPool, locale: &Locale, import_id: Uuid, key: &str) -> AppError {
+    match fetch_import(pool, import_id).await {
+        Ok(Some(import)) => locale.error(StatusCode::CONFLICT, key, &[("id", &import_id), ("status", &import.status.as_str())]),
+        Ok(None) => import_not_found(locale, import_id),
+        Err(e) => AppError::database(e, locale, "fetch_import_failed"),
+    }
+}
+
+pub async fn create_import(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Json(payload): Json<CreateImportPayload>,
+) -> Result<Response, AppError> {
+    if payload.namespace.as_ref().is_some_and(|n| n.trim().is_empty()) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_import_namespace", &[]));
+    }
+    if let Some(dag_id) = payload.dag_id {
+        check_writable(&pool, &locale, dag_id).await?;
+    } else if payload.on_duplicate.is_some() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "import_policy_needs_dag", &[]));
+    }
+
+    let import = sqlx::query_as::<_, Import>(
+        "INSERT INTO imports (id, name, target_dag_id, namespace, source_system, on_duplicate, owner_key_id) VALUES ($1, $2, $3, $4, $5, $6, $7)
+         RETURNING id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at",
+    )
+        .bind(ids::new_id())
+        .bind(payload.name)
+        .bind(payload.dag_id)
+        .bind(payload.namespace)
+        .bind(payload.source_system)
+        .bind(payload.on_duplicate)
+        .bind(auth::creator())
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "create_import_failed"))?;
+    Ok(Json(import.to_json(&locale)).into_response())
+}
+
+pub async fn get_import(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let import = fetch_import(&pool, import_id)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_import_failed"))?
+        .ok_or_else(|| import_not_found(&locale, import_id))?;
+    Ok(Json(import.to_json(&locale)).into_response())
+}
+
+pub async fn upload_chunk(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+    ImportBody(chunk): ImportBody<ChunkPayload>,
+) -> Result<Response, AppError> {
+    check_counts(&locale, chunk.nodes.len(), chunk.edges.len())?;
+    if !chunk.nodes.iter().all(|n| external_ids_valid(&n.external_ids)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    let mut client_ids = Vec::with_capacity(chunk.nodes.len());
+    let mut labels = Vec::with_capacity(chunk.nodes.len());
+    let mut external_ids = Vec::with_capacity(chunk.nodes.len());
+    let mut metadata = Vec::with_capacity(chunk.nodes.len());
+    for node in chunk.nodes {
+        client_ids.push(node.client_id);
+        labels.push(node.label);
+        external_ids.push(serde_json::json!(node.external_ids).to_string());
+        metadata.push(node.metadata.to_string());
+    }
+    let (sources, targets): (Vec<String>, Vec<String>) = chunk
+        .edges
+        .into_iter()
+        .map(|e| (e.source_client_id, e.target_client_id))
+        .unzip();
+
+    let (client_ids, labels, external_ids, metadata, sources, targets, locale) =
+        (&client_ids, &labels, &external_ids, &metadata, &sources, &targets, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // Bumping the counters doubles as the status check and locks the import
+        let Some(import) = sqlx::query_as::<_, Import>(
+            "UPDATE imports SET node_count = node_count + $2, edge_count = edge_count + $3
+             WHERE id = $1 AND status = 'uploading' AND ($4::uuid IS NULL OR owner_key_id = $4)
+             RETURNING id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at",
+        )
+            .bind(import_id)
+            .bind(client_ids.len() as i32)
+            .bind(sources.len() as i32)
+            .bind(auth::owner())
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+        // The limits hold for the import as a whole, not just each chunk
+        check_counts(locale, import.node_count as usize, import.edge_count as usize).map_err(TxError::Rejected)?;
+
+        sqlx::query(
+            "INSERT INTO import_nodes (import_id, client_id, label, external_ids, metadata)
+             SELECT $1, u.client_id, u.label, u.external_ids::jsonb, u.metadata::jsonb
+             FROM UNNEST($2::text[], $3::text[], $4::text[], $5::text[]) AS u(client_id, label, external_ids, metadata)",
+        )
+            .bind(import_id)
+            .bind(client_ids)
+            .bind(labels)
+            .bind(external_ids)
+            .bind(metadata)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO import_edges (import_id, source_client_id, target_client_id)
+             SELECT $1, * FROM UNNEST($2::text[], $3::text[])",
+        )
+            .bind(import_id)
+            .bind(sources)
+            .bind(targets)
+            .execute(&mut *tx)
+            .await?;
+
+        Ok(Some(import))
+    }))
+    .await;
+
+    match result {
+        Ok(Some(import)) => Ok(Json(import.to_json(locale)).into_response()),
+        Ok(None) => Err(transition_refused(&pool, locale, import_id, "import_not_uploading").await),
+        Err(e) => Err(e.respond(locale, "upload_chunk_failed")),
+    }
+}
+
+pub async fn validate_import(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let Some(import) = sqlx::query_as::<_, Import>(
+            "UPDATE imports SET status = 'validating'
+             WHERE id = $1 AND status = 'uploading' AND ($2::uuid IS NULL OR owner_key_id = $2)
+             RETURNING id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at",
+        )
+            .bind(import_id)
+            .bind(auth::owner())
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let job_id = jobs::enqueue(
+            &mut *tx,
+            JobKind::ImportValidation,
+            serde_json::json!({ "import_id": import_id }),
+        )
+            .await?;
+        Ok(Some((import, job_id)))
+    }))
+    .await;
+
+    match result {
+        Ok(Some((import, job_id))) => {
+            jobs::wake();
+            let mut body = import.to_json(&locale);
+            body["job_id"] = serde_json::json!(job_id);
+            Ok((
+                StatusCode::ACCEPTED,
+                [(header::LOCATION, format!("/jobs/{}", job_id))],
+                Json(body),
+            )
+                .into_response())
+        }
+        Ok(None) => Err(transition_refused(&pool, &locale, import_id, "import_not_uploading").await),
+        Err(e) => Err(e.respond(&locale, "validate_import_failed")),
+    }
+}
+
+// Stops a running validation after the batch it is on. Answers 202; the
+// import turns invalid once the validation notices.
+pub async fn abort_import(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let import = sqlx::query_as::<_, Import>(
+        "UPDATE imports SET abort_requested = true
+         WHERE id = $1 AND status = 'validating' AND ($2::uuid IS NULL OR owner_key_id = $2)
+         RETURNING id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at",
+    )
+        .bind(import_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "abort_import_failed"))?;
+    match import {
+        Some(import) => Ok((StatusCode::ACCEPTED, Json(import.to_json(&locale))).into_response()),
+        None => Err(transition_refused(&pool, &locale, import_id, "import_not_validating").await),
+    }
+}
+
+// Streams an import's validation as newline-delimited JSON: a line for each
+// finding as validation comes across it and for each step of progress, then
+// one with the outcome once the import is past validating. Findings so far
+// come first, so watching can start at any time.
+pub async fn stream_findings(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    fetch_import(&pool, import_id)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_import_failed"))?
+        .ok_or_else(|| import_not_found(&locale, import_id))?;
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let (mut sent, mut validated) = (0, None);
+        loop {
+            let import = match fetch_import(&pool, import_id).await {
+                Ok(Some(import)) => import,
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::warn!("Failed to follow validation of import {}: {}", import_id, e);
+                    break;
+                }
+            };
+            let mut lines = String::new();
+            for issue in import.errors.0.iter().skip(sent) {
+                let mut line = issue.to_json(&locale);
+                line["type"] = serde_json::json!("finding");
+                lines.push_str(&format!("{}\n", line));
+            }
+            sent = sent.max(import.errors.0.len());
+            if validated != Some(import.validated_items) {
+                validated = Some(import.validated_items);
+                let line = serde_json::json!({
+                    "type": "progress",
+                    "validated_items": import.validated_items,
+                    "total": import.node_count + import.edge_count,
+                });
+                lines.push_str(&format!("{}\n", line));
+            }
+            let finished = !matches!(import.status, ImportStatus::Uploading | ImportStatus::Validating);
+            if finished {
+                let line = serde_json::json!({ "type": "finished", "status": import.status, "error_count": import.errors.0.len() });
+                lines.push_str(&format!("{}\n", line));
+            }
+            if sender.send_data(lines.into()).await.is_err() || finished {
+                break;
+            }
+            tokio::time::sleep(STREAM_INTERVAL).await;
+        }
+    });
+    let mut response = Response::new(axum::body::boxed(body));
+    response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+    Ok(response)
+}
+
+// Saves how far validation got and what it has found so far, so both can be
+// watched while it runs. Says whether the import was asked to abort.
+async fn report_progress(pool: &PgPool, import_id: Uuid, validated: usize, findings: &Findings) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("UPDATE imports SET validated_items = $2, errors = $3 WHERE id = $1 RETURNING abort_requested")
+        .bind(import_id)
+        .bind(validated as i32)
+        .bind(JsonColumn(&findings.0))
+        .fetch_one(pool)
+        .await
+}
+
+// Ends a validation that was asked to abort, keeping what it found
+async fn abort_validation(pool: &PgPool, import_id: Uuid, findings: Findings) -> Result<(ImportStatus, usize), sqlx::Error> {
+    let Findings(mut issues) = findings;
+    issues.push(Issue { check: "aborted".to_string(), client_ids: Vec::new() });
+    sqlx::query("UPDATE imports SET status = 'invalid', errors = $2 WHERE id = $1")
+        .bind(import_id)
+        .bind(JsonColumn(&issues))
+        .execute(pool)
+        .await?;
+    Ok((ImportStatus::Invalid, issues.len()))
+}
+
+// Checks the staged graph and, when it is sound, fixes the ids its nodes and
+// edges will get on promotion
+async fn run_validation(pool: &PgPool, import_id: Uuid) -> Result<(ImportStatus, usize), sqlx::Error> {
+    let (namespace, target_dag_id, on_duplicate): (Option<String>, Option<Uuid>, Option<DuplicatePolicy>) =
+        sqlx::query_as("SELECT namespace, target_dag_id, on_duplicate FROM imports WHERE id = $1")
+            .bind(import_id)
+            .fetch_one(pool)
+            .await?;
+    let nodes: Vec<(i64, String, String)> =
+        sqlx::query_as("SELECT seq, client_id, label FROM import_nodes WHERE import_id = $1 ORDER BY seq")
+            .bind(import_id)
+            .fetch_all(pool)
+            .await?;
+    let edges: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT seq, source_client_id, target_client_id FROM import_edges WHERE import_id = $1 ORDER BY seq",
+    )
+        .bind(import_id)
+        .fetch_all(pool)
+        .await?;
+
+    let prefixes = vocabulary::label_prefixes(pool).await?;
+    // Checked again on promotion, as the DAG may change until then
+    let existing: Option<HashSet<String>> = match (target_dag_id, on_duplicate) {
+        (Some(dag_id), Some(DuplicatePolicy::Error)) => Some(
+            sqlx::query_scalar("SELECT label FROM nodes WHERE dag_id = $1")
+                .bind(dag_id)
+                .fetch_all(pool)
+                .await?
+                .into_iter()
+                .collect(),
+        ),
+        _ => None,
+    };
+
+    // Checked a batch at a time, saving what was found after each
+    let mut findings = Findings::default();
+    let mut validated = 0;
+    let staged: Vec<(&str, &str)> = nodes.iter().map(|(_, c, l)| (c.as_str(), l.as_str())).collect();
+    let labels: Vec<String> = nodes.iter().map(|(_, _, l)| namespaced(namespace.as_deref(), l)).collect();
+    let mut index = HashMap::new();
+    let mut kept = Vec::with_capacity(nodes.len());
+    for (batch, labels) in staged.chunks(VALIDATION_BATCH).zip(labels.chunks(VALIDATION_BATCH)) {
+        findings.check_more_nodes(batch, validated, &mut index, &mut kept);
+        findings.check_label_prefixes(batch, labels, &prefixes);
+        if let Some(existing) = &existing {
+            findings.check_existing_labels(batch, labels, existing);
+        }
+        validated += batch.len();
+        if report_progress(pool, import_id, validated, &findings).await? {
+            return abort_validation(pool, import_id, findings).await;
+        }
+    }
+    let node_seqs: Vec<i64> = kept.iter().map(|&k| nodes[k].0).collect();
+
+    let staged: Vec<(&str, &str)> = edges.iter().map(|(_, s, t)| (s.as_str(), t.as_str())).collect();
+    let mut arcs = Vec::with_capacity(edges.len());
+    for batch in staged.chunks(VALIDATION_BATCH) {
+        arcs.extend(findings.check_edges(&index, batch));
+        validated += batch.len();
+        if report_progress(pool, import_id, validated, &findings).await? {
+            return abort_validation(pool, import_id, findings).await;
+        }
+    }
+    let node_ids: Vec<Uuid> = node_seqs.iter().map(|_| ids::new_id()).collect();
+    findings.check_cycles(&index, &node_ids, arcs);
+
+    let Findings(issues) = findings;
+    let mut tx = pool.begin().await?;
+    if issues.is_empty() {
+        // The import replaces all nodes of its DAG. Imports that add to it
+        // get their slugs on promotion instead.
+        let labels: Vec<String> = kept.iter().map(|&k| namespaced(namespace.as_deref(), &nodes[k].2)).collect();
+        let node_slugs = fresh_node_slugs(&labels, &node_ids);
+        sqlx::query(
+            "UPDATE import_nodes SET node_id = u.id, slug = u.slug
+             FROM UNNEST($1::bigint[], $2::uuid[], $3::text[]) AS u(seq, id, slug)
+             WHERE import_nodes.seq = u.seq",
+        )
+            .bind(&node_seqs)
+            .bind(&node_ids)
+            .bind(&node_slugs)
+            .execute(&mut tx)
+            .await?;
+        let edge_seqs: Vec<i64> = edges.iter().map(|(seq, _, _)| *seq).collect();
+        let edge_ids: Vec<Uuid> = edge_seqs.iter().map(|_| ids::new_id()).collect();
+        sqlx::query(
+            "UPDATE import_edges SET edge_id = u.id FROM UNNEST($1::bigint[], $2::uuid[]) AS u(seq, id)
+             WHERE import_edges.seq = u.seq",
+        )
+            .bind(&edge_seqs)
+            .bind(&edge_ids)
+            .execute(&mut tx)
+            .await?;
+    }
+    let status = if issues.is_empty() { ImportStatus::Valid } else { ImportStatus::Invalid };
+    let issue_count = issues.len();
+    sqlx::query("UPDATE imports SET status = $2, errors = $3 WHERE id = $1")
+        .bind(import_id)
+        .bind(status)
+        .bind(JsonColumn(issues))
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok((status, issue_count))
+}
+
+#[derive(Deserialize)]
+struct ValidationJob {
+    import_id: Uuid,
+}
+
+pub async fn validation_job(pool: &PgPool, payload: serde_json::Value) -> Result<serde_json::Value, String> {
+    let job: ValidationJob = serde_json::from_value(payload).map_err(|e| e.to_string())?;
+    match run_validation(pool, job.import_id).await {
+        Ok((status, issue_count)) => Ok(serde_json::json!({
+            "import_id": job.import_id,
+            "status": status,
+            "error_count": issue_count,
+        })),
+        Err(e) => {
+            let issues = vec![Issue { check: "internal_error".to_string(), client_ids: Vec::new() }];
+            // After what was found before it, which watchers may have seen
+            let _ = sqlx::query("UPDATE imports SET status = 'invalid', errors = errors || $2 WHERE id = $1")
+                .bind(job.import_id)
+                .bind(JsonColumn(issues))
+                .execute(pool)
+                .await;
+            Err(e.to_string())
+        }
+    }
+}
+
+// Puts the staged nodes and edges into a new or emptied DAG, with the ids and
+// slugs validation gave them
+async fn replace_staged(tx: &mut sqlx::PgConnection, import: &Import, dag_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO nodes (id, dag_id, label, slug, external_ids, metadata, provenance)
+         SELECT node_id, $2, COALESCE($3 || '/', '') || label, slug, external_ids, metadata,
+                jsonb_build_object('import_id', $1::uuid, 'source_system', $4::text,
+                                   'namespace', $3::text, 'imported_at', now())
+         FROM import_nodes WHERE import_id = $1",
+    )
+        .bind(import.id)
+        .bind(dag_id)
+        .bind(&import.namespace)
+        .bind(&import.source_system)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO edges (id, source, target, dag_id)
+         SELECT e.edge_id, s.node_id, t.node_id, $2 FROM import_edges e
+         JOIN import_nodes s ON s.import_id = e.import_id AND s.client_id = e.source_client_id
+         JOIN import_nodes t ON t.import_id = e.import_id AND t.client_id = e.target_client_id
+         WHERE e.import_id = $1",
+    )
+        .bind(import.id)
+        .bind(dag_id)
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// The first of "<label> (2)", "<label> (3)", ... nobody has, which is then
+// taken
+fn suffixed(label: &str, taken: &mut HashSet<String>) -> String {
+    let label = (2..)
+        .map(|n| format!("{} ({})", label, n))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_default();
+    taken.insert(label.clone());
+    label
+}
+
+// Adds the staged nodes and edges to a DAG beside what it already has.
+// Imported nodes whose label is already in the DAG are handled by `policy`;
+// nodes skipped or merged are stood in for by the DAG's node of that label
+// (the first by id if several have it), so their edges attach to it. Edges
+// the DAG already has are not added again, and the result must stay acyclic.
+async fn merge_staged(
+    tx: &mut sqlx::PgConnection,
+    locale: &Locale,
+    import: &Import,
+    dag_id: Uuid,
+    policy: DuplicatePolicy,
+) -> Result<Vec<NodeDecision>, TxError> {
+    let existing: Vec<(Uuid, String, Option<String>)> =
+        sqlx::query_as("SELECT id, label, slug FROM nodes WHERE dag_id = $1 ORDER BY id")
+            .bind(dag_id)
+            .fetch_all(&mut *tx)
+            .await?;
+    let staged: Vec<(String, String, Uuid)> = sqlx::query_as(
+        "SELECT client_id, label, node_id FROM import_nodes WHERE import_id = $1 AND node_id IS NOT NULL ORDER BY seq",
+    )
+        .bind(import.id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let mut by_label: HashMap<&str, Uuid> = HashMap::new();
+    for (id, label, _) in &existing {
+        by_label.entry(label.as_str()).or_insert(*id);
+    }
+    let staged: Vec<(String, String, Uuid)> = staged
+        .into_iter()
+        .map(|(client_id, label, node_id)| (client_id, namespaced(import.namespace.as_deref(), &label), node_id))
+        .collect();
+    let mut taken_labels: HashSet<String> = existing.iter().map(|(_, label, _)| label.clone()).collect();
+    taken_labels.extend(staged.iter().map(|(_, label, _)| label.clone()));
+
+    let mut decisions = Vec::with_capacity(staged.len());
+    let mut clashes = Vec::new();
+    // Where each staged node ended up, by the id validation gave it
+    let mut resolved: HashMap<Uuid, Uuid> = HashMap::with_capacity(staged.len());
+    for (client_id, label, staged_id) in staged {
+        let (decision, node_id, label) = match (by_label.get(label.as_str()), policy) {
+            (None, _) => (Decision::Created, staged_id, label),
+            (Some(_), DuplicatePolicy::Error) => {
+                clashes.push(client_id);
+                continue;
+            }
+            (Some(&id), DuplicatePolicy::Skip) => (Decision::Skipped, id, label),
+            (Some(&id), DuplicatePolicy::MergeMetadata) => (Decision::Merged, id, label),
+            (Some(_), DuplicatePolicy::Suffix) => (Decision::Suffixed, staged_id, suffixed(&label, &mut taken_labels)),
+        };
+        resolved.insert(staged_id, node_id);
+        decisions.push(NodeDecision { client_id, decision, node_id, label });
+    }
+    if !clashes.is_empty() {
+        return Err(TxError::Rejected(
+            locale
+                .error(StatusCode::CONFLICT, "import_labels_exist", &[("count", &clashes.len())])
+                .with_detail("client_ids", serde_json::json!(clashes)),
+        ));
+    }
+
+    let created: Vec<&NodeDecision> = decisions
+        .iter()
+        .filter(|d| matches!(d.decision, Decision::Created | Decision::Suffixed))
+        .collect();
+    let created_ids: Vec<Uuid> = created.iter().map(|d| d.node_id).collect();
+    let created_labels: Vec<String> = created.iter().map(|d| d.label.clone()).collect();
+    let taken_slugs: HashSet<String> = existing.iter().filter_map(|(_, _, slug)| slug.clone()).collect();
+    let created_slugs = node_slugs_besides(&created_labels, &created_ids, taken_slugs);
+    sqlx::query(
+        "INSERT INTO nodes (id, dag_id, label, slug, external_ids, metadata, provenance)
+         SELECT u.id, $2, u.label, u.slug, s.external_ids, s.metadata,
+                jsonb_build_object('import_id', $1::uuid, 'source_system', $4::text,
+                                   'namespace', $3::text, 'imported_at', now())
+         FROM UNNEST($5::uuid[], $6::text[], $7::text[]) AS u(id, label, slug)
+         JOIN import_nodes s ON s.import_id = $1 AND s.node_id = u.id",
+    )
+        .bind(import.id)
+        .bind(dag_id)
+        .bind(&import.namespace)
+        .bind(&import.source_system)
+        .bind(&created_ids)
+        .bind(&created_labels)
+        .bind(&created_slugs)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE import_nodes SET promoted_label = u.label
+         FROM UNNEST($2::uuid[], $3::text[]) AS u(id, label)
+         WHERE import_nodes.import_id = $1 AND import_nodes.node_id = u.id",
+    )
+        .bind(import.id)
+        .bind(&created_ids)
+        .bind(&created_labels)
+        .execute(&mut *tx)
+        .await?;
+
+    let (merged_into, merged_from): (Vec<Uuid>, Vec<Uuid>) = resolved
+        .iter()
+        .filter(|(staged_id, node_id)| staged_id != node_id && policy == DuplicatePolicy::MergeMetadata)
+        .map(|(&staged_id, &node_id)| (node_id, staged_id))
+        .unzip();
+    sqlx::query(
+        "UPDATE nodes SET external_ids = nodes.external_ids || s.external_ids, metadata = nodes.metadata || s.metadata
+         FROM UNNEST($2::uuid[], $3::uuid[]) AS u(id, staged_id)
+         JOIN import_nodes s ON s.import_id = $1 AND s.node_id = u.staged_id
+         WHERE nodes.id = u.id",
+    )
+        .bind(import.id)
+        .bind(&merged_into)
+        .bind(&merged_from)
+        .execute(&mut *tx)
+        .await?;
+
+    let staged_edges: Vec<(Uuid, Uuid, Uuid)> = sqlx::query_as(
+        "SELECT e.edge_id, s.node_id, t.node_id FROM import_edges e
+         JOIN import_nodes s ON s.import_id = e.import_id AND s.client_id = e.source_client_id
+         JOIN import_nodes t ON t.import_id = e.import_id AND t.client_id = e.target_client_id
+         WHERE e.import_id = $1",
+    )
+        .bind(import.id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let existing_edges: Vec<(Uuid, Uuid)> = sqlx::query_as("SELECT source, target FROM edges WHERE dag_id = $1")
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let mut present: HashSet<(Uuid, Uuid)> = existing_edges.iter().copied().collect();
+    let mut edges = Vec::with_capacity(staged_edges.len());
+    for (edge_id, source, target) in staged_edges {
+        let ends = (resolved[&source], resolved[&target]);
+        if present.insert(ends) {
+            edges.push((edge_id, ends.0, ends.1));
+        }
+    }
+
+    let ids: Vec<Uuid> = existing.iter().map(|(id, _, _)| *id).chain(created_ids.iter().copied()).collect();
+    let position: HashMap<Uuid, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+    let arcs: Vec<(usize, usize)> = existing_edges
+        .iter()
+        .copied()
+        .chain(edges.iter().map(|&(_, source, target)| (source, target)))
+        .filter_map(|(source, target)| Some((*position.get(&source)?, *position.get(&target)?)))
+        .collect();
+    let (_, blocked) = Graph::from_arcs(ids.clone(), arcs).topological_order();
+    if !blocked.is_empty() {
+        return Err(TxError::Rejected(
+            locale
+                .error(StatusCode::UNPROCESSABLE_ENTITY, "import_merge_cycle", &[])
+                .with_detail("node_ids", serde_json::json!(blocked.iter().map(|&i| ids[i]).collect::<Vec<_>>())),
+        ));
+    }
+
+    let (edge_ids, (sources, targets)): (Vec<Uuid>, (Vec<Uuid>, Vec<Uuid>)) =
+        edges.iter().map(|&(id, source, target)| (id, (source, target))).unzip();
+    sqlx::query(
+        "INSERT INTO edges (id, source, target, dag_id)
+         SELECT u.id, u.source, u.target, $1 FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[]) AS u(id, source, target)",
+    )
+        .bind(dag_id)
+        .bind(&edge_ids)
+        .bind(&sources)
+        .bind(&targets)
+        .execute(&mut *tx)
+        .await?;
+    Ok(decisions)
+}
+
+// Swaps a validated import in within a single transaction: it either becomes
+// a new DAG or replaces the nodes and edges of its target DAG, or with a
+// duplicate policy adds to them and answers with what became of each node
+pub async fn promote_import(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let locale = &locale;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let Some(import) = sqlx::query_as::<_, Import>(
+            "SELECT id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at
+             FROM imports WHERE id = $1 AND ($2::uuid IS NULL OR owner_key_id = $2) FOR UPDATE",
+        )
+            .bind(import_id)
+            .bind(auth::owner())
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Err(TxError::Rejected(import_not_found(locale, import_id)));
+        };
+        if import.status != ImportStatus::Valid {
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "import_not_valid", &[("id", &import_id), ("status", &import.status.as_str())])));
+        }
+
+        let (dag_id, warning) = match import.target_dag_id {
+            Some(dag_id) => {
+                let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+                    .map_err(TxError::Rejected)?;
+                if import.on_duplicate.is_none() {
+                    sqlx::query(
+                        "DELETE FROM edges WHERE dag_id = $1
+                            OR source IN (SELECT id FROM nodes WHERE dag_id = $1)
+                            OR target IN (SELECT id FROM nodes WHERE dag_id = $1)",
+                    )
+                        .bind(dag_id)
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query("DELETE FROM nodes WHERE dag_id = $1")
+                        .bind(dag_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                (dag_id, warning)
+            }
+            None => {
+                let dag_id = ids::new_id();
+                let slug = slugs::dag_slug(&mut *tx, &import.name, dag_id).await?;
+                // The DAG belongs to whoever started the import
+                sqlx::query(
+                    "INSERT INTO dags (id, name, slug, owner_key_id)
+                     SELECT $1, $2, $3, owner_key_id FROM imports WHERE id = $4",
+                )
+                    .bind(dag_id)
+                    .bind(&import.name)
+                    .bind(slug)
+                    .bind(import_id)
+                    .execute(&mut *tx)
+                    .await?;
+                (dag_id, None)
+            }
+        };
+
+        let decisions = match import.on_duplicate {
+            Some(policy) => Some(merge_staged(&mut *tx, locale, &import, dag_id, policy).await?),
+            None => {
+                replace_staged(&mut *tx, &import, dag_id).await?;
+                None
+            }
+        };
+
+        let promoted = sqlx::query_as::<_, Import>(
+            "UPDATE imports SET status = 'promoted', dag_id = $2 WHERE id = $1
+             RETURNING id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at",
+        )
+            .bind(import_id)
+            .bind(dag_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok((promoted, dag_id, warning, decisions))
+    }))
+    .await;
+
+    let (import, dag_id, warning, decisions) = result.map_err(|e| e.respond(locale, "promote_import_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "graph_changed", serde_json::json!({ "cause": "import", "import_id": import_id }));
+    let mut body = import.to_json(locale);
+    if let Some(decisions) = decisions {
+        body["decisions"] = serde_json::json!(decisions);
+    }
+    Ok(with_warning(Json(body).into_response(), warning))
+}
+
+// Something changed what a promoted import created since, so rolling it
+// back would lose or orphan data
+#[derive(Serialize)]
+struct RollbackConflict {
+    check: &'static str,
+    ids: Vec<Uuid>,
+}
+
+async fn rollback_conflicts(
+    tx: &mut sqlx::PgConnection,
+    import: &Import,
+    dag_id: Uuid,
+) -> Result<Vec<RollbackConflict>, sqlx::Error> {
+    let mut conflicts = Vec::new();
+    let mut report = |check, ids: Vec<Uuid>| {
+        if !ids.is_empty() {
+            conflicts.push(RollbackConflict { check, ids });
+        }
+    };
+
+    report("node_modified", sqlx::query_scalar(
+        "SELECT n.id FROM import_nodes s JOIN nodes n ON n.id = s.node_id
+         WHERE s.import_id = $1
+           AND (n.dag_id <> $2 OR n.label <> COALESCE(s.promoted_label, COALESCE($3 || '/', '') || s.label) OR n.external_ids <> s.external_ids)",
+    )
+        .bind(import.id)
+        .bind(dag_id)
+        .bind(&import.namespace)
+        .fetch_all(&mut *tx)
+        .await?);
+    report("foreign_edge", sqlx::query_scalar(
+        "SELECT e.id FROM edges e
+         WHERE (e.source IN (SELECT node_id FROM import_nodes WHERE import_id = $1)
+                OR e.target IN (SELECT node_id FROM import_nodes WHERE import_id = $1))
+           AND e.id NOT IN (SELECT edge_id FROM import_edges WHERE import_id = $1)",
+    )
+        .bind(import.id)
+        .fetch_all(&mut *tx)
+        .await?);
+
+    // A DAG the import created only goes away with it if nothing else has
+    // come to live in it or point at it
+    if import.target_dag_id.is_none() {
+        report("foreign_node", sqlx::query_scalar(
+            "SELECT id FROM nodes WHERE dag_id = $2
+               AND id NOT IN (SELECT node_id FROM import_nodes WHERE import_id = $1)",
+        )
+            .bind(import.id)
+            .bind(dag_id)
+            .fetch_all(&mut *tx)
+            .await?);
+        report("dag_referenced", sqlx::query_scalar(
+            "SELECT id FROM dags WHERE replaced_by = $2
+             UNION SELECT id FROM imports WHERE id <> $1 AND (target_dag_id = $2 OR dag_id = $2)
+             UNION SELECT id FROM catalog_entries WHERE source_dag_id = $2
+             UNION SELECT id FROM runs WHERE dag_id = $2",
+        )
+            .bind(import.id)
+            .bind(dag_id)
+            .fetch_all(&mut *tx)
+            .await?);
+    }
+    Ok(conflicts)
+}
+
+// Removes the nodes and edges a promoted import created, and the DAG too if
+// the import created it. Contents an import replaced are not restored, nor
+// what it merged into nodes that were already there.
+pub async fn rollback_import(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let locale = &locale;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let Some(import) = sqlx::query_as::<_, Import>(
+            "SELECT id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at
+             FROM imports WHERE id = $1 AND ($2::uuid IS NULL OR owner_key_id = $2) FOR UPDATE",
+        )
+            .bind(import_id)
+            .bind(auth::owner())
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Err(TxError::Rejected(import_not_found(locale, import_id)));
+        };
+        let (ImportStatus::Promoted, Some(dag_id)) = (import.status, import.dag_id) else {
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "import_not_promoted", &[("id", &import_id), ("status", &import.status.as_str())])));
+        };
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+
+        let conflicts = rollback_conflicts(&mut *tx, &import, dag_id).await?;
+        if !conflicts.is_empty() {
+            let conflicts: Vec<_> = conflicts.iter().map(|c| {
+                let ids: Vec<String> = c.ids.iter().map(Uuid::to_string).collect();
+                serde_json::json!({
+                    "check": c.check,
+                    "ids": c.ids,
+                    "message": locale.t(&format!("import_rollback_{}", c.check), &[("ids", &ids.join(", "))]),
+                })
+            }).collect();
+            return Err(TxError::Rejected(
+                locale
+                    .error(StatusCode::CONFLICT, "import_rollback_conflicts", &[("id", &import_id)])
+                    .with_details(serde_json::json!({ "conflicts": conflicts })),
+            ));
+        }
+
+        sqlx::query("DELETE FROM edges WHERE id IN (SELECT edge_id FROM import_edges WHERE import_id = $1)")
+            .bind(import_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE id IN (SELECT node_id FROM import_nodes WHERE import_id = $1)")
+            .bind(import_id)
+            .execute(&mut *tx)
+            .await?;
+        let rolled_back = sqlx::query_as::<_, Import>(
+            "UPDATE imports SET status = 'rolled_back', dag_id = NULL WHERE id = $1
+             RETURNING id, name, target_dag_id, status, node_count, edge_count, validated_items, errors, dag_id, namespace, source_system, on_duplicate, created_at",
+        )
+            .bind(import_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if import.target_dag_id.is_none() {
+            sqlx::query("DELETE FROM dag_usage WHERE dag_id = $1")
+                .bind(dag_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM dags WHERE id = $1")
+                .bind(dag_id)
+                .execute(&mut *tx)
+                .await?;
+            return Ok((rolled_back, None, None));
+        }
+        Ok((rolled_back, Some(dag_id), warning))
+    }))
+    .await;
+
+    let (import, dag_id, warning) = result.map_err(|e| e.respond(locale, "rollback_import_failed"))?;
+    if let Some(dag_id) = dag_id {
+        usage::record(&pool, dag_id, Access::Edit);
+        events::publish(dag_id, "graph_changed", serde_json::json!({ "cause": "import_rollback", "import_id": import_id }));
+    }
+    Ok(with_warning(Json(import.to_json(locale)).into_response(), warning))
+}
+
+pub async fn delete_import(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    // A running validation still owns the staged rows
+    let deleted = sqlx::query("DELETE FROM imports WHERE id = $1 AND status <> 'validating' AND ($2::uuid IS NULL OR owner_key_id = $2)")
+        .bind(import_id)
+        .bind(auth::owner())
+        .execute(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "delete_import_failed"))?;
+    if deleted.rows_affected() == 0 {
+        return Err(transition_refused(&pool, &locale, import_id, "import_validating").await);
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct DagImportPayload {
+    name: String,
+    #[serde(default)]
+    nodes: Vec<StagedNode>,
+    #[serde(default)]
+    edges: Vec<StagedEdge>,
+}
+
+// Creates a DAG with all its nodes and edges in one request and one
+// transaction. Runs the same checks as staged imports, answering 422 with
+// what they found, and maps client ids to the ids the nodes got.
+pub async fn import_dag(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    ImportBody(payload): ImportBody<DagImportPayload>,
+) -> Result<Response, AppError> {
+    check_counts(&locale, payload.nodes.len(), payload.edges.len())?;
+    if !payload.nodes.iter().all(|n| external_ids_valid(&n.external_ids)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    let prefixes = vocabulary::label_prefixes(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "import_dag_failed"))?;
+
+    let mut findings = Findings::default();
+    let staged: Vec<(&str, &str)> = payload.nodes.iter().map(|n| (n.client_id.as_str(), n.label.as_str())).collect();
+    let (index, kept) = findings.check_nodes(&staged);
+    let labels: Vec<String> = payload.nodes.iter().map(|n| n.label.clone()).collect();
+    findings.check_label_prefixes(&staged, &labels, &prefixes);
+    let staged: Vec<(&str, &str)> = payload
+        .edges
+        .iter()
+        .map(|e| (e.source_client_id.as_str(), e.target_client_id.as_str()))
+        .collect();
+    let arcs = findings.check_edges(&index, &staged);
+    let node_ids: Vec<Uuid> = kept.iter().map(|_| ids::new_id()).collect();
+    findings.check_cycles(&index, &node_ids, arcs.clone());
+    let Findings(issues) = findings;
+    if !issues.is_empty() {
+        let errors: Vec<_> = issues.iter().map(|issue| issue.to_json(&locale)).collect();
+        return Err(locale
+            .error(StatusCode::UNPROCESSABLE_ENTITY, "import_invalid", &[("count", &issues.len())])
+            .with_details(serde_json::json!({ "errors": errors })));
+    }
+
+    let labels: Vec<String> = kept.iter().map(|&k| payload.nodes[k].label.clone()).collect();
+    let node_slugs = fresh_node_slugs(&labels, &node_ids);
+    let external_ids: Vec<String> = kept
+        .iter()
+        .map(|&k| serde_json::json!(payload.nodes[k].external_ids).to_string())
+        .collect();
+    let metadata: Vec<String> = kept.iter().map(|&k| payload.nodes[k].metadata.to_string()).collect();
+    let edge_ids: Vec<Uuid> = arcs.iter().map(|_| ids::new_id()).collect();
+    let (sources, targets): (Vec<Uuid>, Vec<Uuid>) = arcs.iter().map(|&(s, t)| (node_ids[s], node_ids[t])).unzip();
+
+    let dag_id = ids::new_id();
+    let (name, node_ids_ref, labels, node_slugs, external_ids, metadata, edge_ids, sources, targets) =
+        (&payload.name, &node_ids, &labels, &node_slugs, &external_ids, &metadata, &edge_ids, &sources, &targets);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let slug = slugs::dag_slug(&mut *tx, name, dag_id).await?;
+        sqlx::query("INSERT INTO dags (id, name, slug, owner_key_id) VALUES ($1, $2, $3, $4)")
+            .bind(dag_id)
+            .bind(name)
+            .bind(&slug)
+            .bind(auth::creator())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO nodes (id, dag_id, label, slug, external_ids, metadata)
+             SELECT u.id, $1, u.label, u.slug, u.external_ids::jsonb, u.metadata::jsonb
+             FROM UNNEST($2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[]) AS u(id, label, slug, external_ids, metadata)",
+        )
+            .bind(dag_id)
+            .bind(node_ids_ref)
+            .bind(labels)
+            .bind(node_slugs)
+            .bind(external_ids)
+            .bind(metadata)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO edges (id, source, target, dag_id)
+             SELECT u.id, u.source, u.target, $1 FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[]) AS u(id, source, target)",
+        )
+            .bind(dag_id)
+            .bind(edge_ids)
+            .bind(sources)
+            .bind(targets)
+            .execute(&mut *tx)
+            .await?;
+        Ok(slug)
+    }))
+    .await;
+
+    let slug = result.map_err(|e| e.respond(&locale, "import_dag_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    let node_ids: HashMap<&str, Uuid> = index.iter().map(|(&client_id, &i)| (client_id, node_ids[i])).collect();
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/dags/{}", dag_id))],
+        Json(serde_json::json!({
+            "dag": { "id": dag_id, "name": payload.name, "slug": slug },
+            "node_ids": node_ids,
+            "node_count": labels.len(),
+            "edge_count": edge_ids.len(),
+        })),
+    )
+        .into_response())
+}
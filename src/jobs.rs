@@ -0,0 +1,235 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as JsonColumn, FromRow, PgPool};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::executor;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::imports;
+use crate::logs;
+use crate::maintenance::Maintenance;
+
+const DEFAULT_WORKERS: usize = 2;
+// Idle workers also look for queued jobs this often, which picks up work
+// enqueued by other processes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// How long a claimed job is held without being renewed, and how often its
+// worker renews it
+const LEASE: Duration = Duration::from_secs(60);
+const RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
+static QUEUED: Notify = Notify::const_new();
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum JobKind {
+    ImportValidation,
+    DagRun,
+    LogMigration,
+}
+
+#[derive(Serialize, sqlx::Type, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, FromRow)]
+struct Job {
+    id: Uuid,
+    kind: JobKind,
+    status: JobStatus,
+    payload: JsonColumn<serde_json::Value>,
+    result: Option<JsonColumn<serde_json::Value>>,
+    error: Option<String>,
+    attempts: i32,
+    // The fencing token of the latest claim, and until when it holds
+    fence: Option<i64>,
+    lease_expires_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+// Queues a job on the caller's connection or transaction, so it only becomes
+// visible together with the state change that asked for it. Call `wake` once
+// that is committed.
+pub async fn enqueue<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    kind: JobKind,
+    payload: serde_json::Value,
+) -> Result<Uuid, sqlx::Error> {
+    let job_id = ids::new_id();
+    sqlx::query("INSERT INTO jobs (id, kind, payload) VALUES ($1, $2, $3)")
+        .bind(job_id)
+        .bind(kind)
+        .bind(JsonColumn(payload))
+        .execute(executor)
+        .await?;
+    Ok(job_id)
+}
+
+pub fn wake() {
+    QUEUED.notify_one();
+}
+
+// A condition that holds while the claim whose fencing token is parameter
+// `param` still owns its job. It locks the job row for the rest of the
+// transaction, so the lease can't go to another worker until the write using
+// it is done.
+pub fn holds_lease(param: usize) -> String {
+    format!("EXISTS (SELECT 1 FROM jobs WHERE fence = ${} AND status = 'running' FOR SHARE)", param)
+}
+
+// Starts JOB_WORKERS workers. Jobs a previous run of the service left behind
+// are claimed again once their leases run out, so other instances keep the
+// ones they hold. Workers leave the queue alone while the service is read-only.
+pub fn start(pool: &PgPool, maintenance: &Arc<Maintenance>) {
+    let workers = env::var("JOB_WORKERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_WORKERS);
+    for _ in 0..workers {
+        tokio::spawn(work(pool.clone(), maintenance.clone()));
+    }
+}
+
+async fn work(pool: PgPool, maintenance: Arc<Maintenance>) {
+    loop {
+        if maintenance.is_read_only() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+        match claim(&pool).await {
+            Ok(Some((job_id, kind, payload, fence))) => {
+                // A worker that loses its lease stops; the one that has it
+                // now picks the job up from what was recorded
+                let outcome = tokio::select! {
+                    outcome = run(&pool, kind, payload.0, fence) => outcome,
+                    _ = keep_lease(&pool, job_id, fence) => Err("Lost the job's lease".to_string()),
+                };
+                match finish(&pool, fence, outcome).await {
+                    Ok(true) => {}
+                    Ok(false) => tracing::warn!("Job {} was claimed again before it finished; dropped its outcome", job_id),
+                    Err(e) => tracing::error!("Failed to record outcome of job {}: {}", job_id, e),
+                }
+            }
+            Ok(None) => {
+                let _ = tokio::time::timeout(POLL_INTERVAL, QUEUED.notified()).await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to claim job: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+// Claims the oldest queued job, or a running one whose lease ran out, under a
+// new fencing token
+async fn claim(pool: &PgPool) -> Result<Option<(Uuid, JobKind, JsonColumn<serde_json::Value>, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        "UPDATE jobs SET status = 'running', started_at = now(), attempts = attempts + 1,
+             fence = nextval('lease_fences'), lease_expires_at = now() + make_interval(secs => $1)
+         WHERE id = (
+             SELECT id FROM jobs WHERE status = 'queued' OR (status = 'running' AND lease_expires_at < now())
+             ORDER BY created_at LIMIT 1 FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id, kind, payload, fence",
+    )
+        .bind(LEASE.as_secs_f64())
+        .fetch_optional(pool)
+        .await
+}
+
+// Renews the lease until it is lost, to a claim made after it ran out
+async fn keep_lease(pool: &PgPool, job_id: Uuid, fence: i64) {
+    loop {
+        tokio::time::sleep(RENEW_INTERVAL).await;
+        let renewed = sqlx::query(
+            "UPDATE jobs SET lease_expires_at = now() + make_interval(secs => $2) WHERE fence = $1 AND status = 'running'",
+        )
+            .bind(fence)
+            .bind(LEASE.as_secs_f64())
+            .execute(pool)
+            .await;
+        match renewed {
+            Ok(done) if done.rows_affected() == 0 => {
+                tracing::warn!("Job {} was claimed again; stopping its worker", job_id);
+                return;
+            }
+            Ok(_) => {}
+            // Tried again at the next interval; the lease lasts for a few
+            Err(e) => tracing::error!("Failed to renew the lease of job {}: {}", job_id, e),
+        }
+    }
+}
+
+async fn run(pool: &PgPool, kind: JobKind, payload: serde_json::Value, fence: i64) -> Result<serde_json::Value, String> {
+    match kind {
+        JobKind::ImportValidation => imports::validation_job(pool, payload).await,
+        JobKind::DagRun => executor::run_job(pool, fence, payload).await,
+        JobKind::LogMigration => logs::migration_job(pool, payload).await,
+    }
+}
+
+// Records the outcome unless the job has been claimed again since. Returns
+// whether it was recorded.
+async fn finish(pool: &PgPool, fence: i64, outcome: Result<serde_json::Value, String>) -> Result<bool, sqlx::Error> {
+    let (status, result, error) = match outcome {
+        Ok(result) => (JobStatus::Succeeded, Some(JsonColumn(result)), None),
+        Err(error) => (JobStatus::Failed, None, Some(error)),
+    };
+    sqlx::query(
+        "UPDATE jobs SET status = $2, result = $3, error = $4, finished_at = now(), lease_expires_at = NULL
+         WHERE fence = $1 AND status = 'running'",
+    )
+        .bind(fence)
+        .bind(status)
+        .bind(result)
+        .bind(error)
+        .execute(pool)
+        .await
+        .map(|done| done.rows_affected() == 1)
+}
+
+pub async fn get_job(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(job_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    // A key sees the jobs of its own imports and runs; log migrations are
+    // for admins only
+    let job = sqlx::query_as::<_, Job>(&format!(
+        "SELECT id, kind, status, payload, result, error, attempts, fence, lease_expires_at, created_at, started_at, finished_at
+         FROM jobs j WHERE id = $1 AND ($2::uuid IS NULL
+             OR (kind = 'import_validation' AND EXISTS (
+                 SELECT 1 FROM imports i WHERE i.id::text = j.payload->>'import_id' AND i.owner_key_id = $2))
+             OR (kind = 'dag_run' AND EXISTS (SELECT 1 FROM runs r WHERE r.job_id = j.id AND {})))",
+        auth::visible("r.dag_id", 2)
+    ))
+        .bind(job_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_job_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "job_not_found", &[("id", &job_id)]))?;
+    Ok(Json(job).into_response())
+}
@@ -0,0 +1,624 @@
+// Where task logs are kept. Each store implements LogStore; TASK_LOG_STORE
+// picks the one new logs go to, and every store whose settings are there can
+// be read from and moved between, so logs written before a switch stay
+// readable. A task's row says which store holds its log. In postgres the log
+// is the row's output; the other stores key it by run, node and the fence
+// of the job lease it was written under, so a worker that lost its lease
+// can't overwrite the log of the one that took over.
+use axum::{
+    async_trait,
+    extract::{Json, Path, State},
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::config::{LokiSettings, S3Settings, TaskLogSettings};
+use crate::error::AppError;
+use crate::executor;
+use crate::i18n::Locale;
+use crate::jobs::{self, JobKind};
+use crate::maintenance::Writable;
+
+// Task logs moved per query by a migration
+const MIGRATION_BATCH: i64 = 100;
+// Most lines read back from Loki for one log, its default query limit
+const LOKI_MAX_LINES: usize = 5000;
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    Filesystem,
+    S3,
+    Loki,
+}
+
+impl Backend {
+    const ALL: [Backend; 4] = [Backend::Postgres, Backend::Filesystem, Backend::S3, Backend::Loki];
+
+    fn parse(name: &str) -> Option<Backend> {
+        Backend::ALL.into_iter().find(|b| b.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Postgres => "postgres",
+            Backend::Filesystem => "filesystem",
+            Backend::S3 => "s3",
+            Backend::Loki => "loki",
+        }
+    }
+}
+
+// Which task's log, as a store finds it
+#[derive(FromRow, Clone)]
+pub struct TaskLog {
+    pub dag_id: Uuid,
+    pub run_id: Uuid,
+    pub node_id: Uuid,
+    pub fence: i64,
+    // When the task finished, which Loki needs to find the log again
+    pub at: DateTime<Utc>,
+}
+
+impl TaskLog {
+    fn key(&self) -> String {
+        format!("{}/{}/{}", self.run_id, self.node_id, self.fence)
+    }
+}
+
+#[async_trait]
+pub trait LogStore: Send + Sync {
+    async fn put(&self, log: &TaskLog, text: &str) -> Result<(), String>;
+    // None if the store has no such log
+    async fn get(&self, log: &TaskLog) -> Result<Option<String>, String>;
+    async fn delete(&self, log: &TaskLog) -> Result<(), String>;
+}
+
+struct Stores {
+    active: Backend,
+    configured: HashMap<Backend, Box<dyn LogStore>>,
+}
+
+static STORES: OnceLock<Stores> = OnceLock::new();
+
+// Sets up the stores from the config at startup; the config has already
+// checked that the active one is configured
+pub fn configure(settings: &TaskLogSettings, pool: &PgPool) {
+    let mut configured: HashMap<Backend, Box<dyn LogStore>> = HashMap::new();
+    configured.insert(Backend::Postgres, Box::new(Postgres { pool: pool.clone() }));
+    if let Some(dir) = &settings.dir {
+        configured.insert(Backend::Filesystem, Box::new(Filesystem { dir: dir.clone() }));
+    }
+    if let Some(s3) = &settings.s3 {
+        configured.insert(Backend::S3, Box::new(S3 { settings: s3.clone() }));
+    }
+    if let Some(loki) = &settings.loki {
+        configured.insert(Backend::Loki, Box::new(Loki { settings: loki.clone() }));
+    }
+    let active = Backend::parse(&settings.store).unwrap_or(Backend::Postgres);
+    STORES.get_or_init(|| Stores { active, configured });
+}
+
+fn store(backend: Backend) -> Option<&'static dyn LogStore> {
+    STORES.get()?.configured.get(&backend).map(Box::as_ref)
+}
+
+fn active() -> Backend {
+    STORES.get().map_or(Backend::Postgres, |stores| stores.active)
+}
+
+// Puts a finished task's log in the active store. Returns the store that
+// holds it and what goes in the row's output: the log itself when that is
+// postgres, which it falls back to if the active store fails.
+pub async fn keep(log: &TaskLog, text: Option<String>) -> (Option<Backend>, Option<String>) {
+    let Some(text) = text else {
+        return (None, None);
+    };
+    let backend = active();
+    if backend == Backend::Postgres || text.is_empty() {
+        return (Some(Backend::Postgres), Some(text));
+    }
+    let Some(store) = store(backend) else {
+        return (Some(Backend::Postgres), Some(text));
+    };
+    match store.put(log, &text).await {
+        Ok(()) => (Some(backend), None),
+        Err(e) => {
+            tracing::warn!("Failed to store the log of node {} in run {} in {}, kept it in postgres: {}", log.node_id, log.run_id, backend.name(), e);
+            (Some(Backend::Postgres), Some(text))
+        }
+    }
+}
+
+// Logs of the DAG's runs kept outside the database, to discard once the
+// runs are deleted
+pub async fn of_dag(tx: &mut sqlx::PgConnection, dag_id: Uuid) -> Result<Vec<(Backend, TaskLog)>, sqlx::Error> {
+    let rows: Vec<(Backend, Uuid, Uuid, Uuid, i64, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT t.log_store, r.dag_id, t.run_id, t.node_id, COALESCE(t.log_fence, 0), COALESCE(t.finished_at, r.created_at)
+         FROM task_runs t JOIN runs r ON r.id = t.run_id
+         WHERE r.dag_id = $1 AND t.log_store IS NOT NULL AND t.log_store <> 'postgres'",
+    )
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(backend, dag_id, run_id, node_id, fence, at)| (backend, TaskLog { dag_id, run_id, node_id, fence, at }))
+        .collect())
+}
+
+pub fn discard(logs: Vec<(Backend, TaskLog)>) {
+    if logs.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        for (backend, log) in logs {
+            let Some(store) = store(backend) else { continue };
+            if let Err(e) = store.delete(&log).await {
+                tracing::warn!("Failed to delete the log of node {} in run {} from {}: {}", log.node_id, log.run_id, backend.name(), e);
+            }
+        }
+    });
+}
+
+#[derive(FromRow)]
+struct Held {
+    log_store: Option<Backend>,
+    dag_id: Uuid,
+    fence: i64,
+    at: DateTime<Utc>,
+}
+
+// A task's whole log as text, from whichever store holds it
+pub async fn get_task_log(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path((run_id, node_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, AppError> {
+    let held = sqlx::query_as::<_, Held>(&format!(
+        "SELECT t.log_store, r.dag_id, COALESCE(t.log_fence, 0) AS fence, COALESCE(t.finished_at, r.created_at) AS at
+         FROM task_runs t JOIN runs r ON r.id = t.run_id
+         WHERE t.run_id = $1 AND t.node_id = $2 AND {}",
+        auth::visible("r.dag_id", 3)
+    ))
+        .bind(run_id)
+        .bind(node_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_task_log_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "task_run_not_found", &[("run", &run_id), ("node", &node_id)]))?;
+    let not_found = || locale.error(StatusCode::NOT_FOUND, "task_log_not_found", &[("run", &run_id), ("node", &node_id)]);
+    let backend = held.log_store.ok_or_else(not_found)?;
+    let store = store(backend)
+        .ok_or_else(|| locale.error(StatusCode::CONFLICT, "log_store_not_configured", &[("store", &backend.name())]))?;
+
+    let log = TaskLog { dag_id: held.dag_id, run_id, node_id, fence: held.fence, at: held.at };
+    let text = store.get(&log).await.map_err(|e| {
+        tracing::warn!("Failed to read the log of node {} in run {} from {}: {}", node_id, run_id, backend.name(), e);
+        locale.error(StatusCode::BAD_GATEWAY, "task_log_unavailable", &[("store", &backend.name())])
+    })?;
+    let text = text.ok_or_else(not_found)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8"), (header::HeaderName::from_static("x-log-store"), backend.name())],
+        text,
+    )
+        .into_response())
+}
+
+// The store new logs go to, the ones configured, and how many logs each
+// store holds
+pub async fn log_stores(State(pool): State<PgPool>, locale: Locale) -> Result<Response, AppError> {
+    let counts: Vec<(Backend, i64)> =
+        sqlx::query_as("SELECT log_store, COUNT(*) FROM task_runs WHERE log_store IS NOT NULL GROUP BY log_store ORDER BY log_store")
+            .fetch_all(&pool)
+            .await
+            .map_err(|e| AppError::database(e, &locale, "fetch_task_log_failed"))?;
+    let configured: Vec<Backend> = Backend::ALL.into_iter().filter(|&b| store(b).is_some()).collect();
+    Ok(Json(serde_json::json!({
+        "active": active(),
+        "configured": configured,
+        "logs": counts.into_iter().map(|(b, n)| (b.name(), n)).collect::<HashMap<_, _>>(),
+    }))
+    .into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MigrationPayload {
+    from: Backend,
+    // The active store unless given
+    to: Option<Backend>,
+}
+
+// Moves every log in one store to another on a background job. Answers 202
+// with the job; its result counts the logs moved, missing from the source
+// and failed. Logs are repointed one at a time, so tasks stay readable while
+// it runs, and a failed one stays where it was.
+pub async fn migrate_logs(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Json(payload): Json<MigrationPayload>,
+) -> Result<Response, AppError> {
+    let to = payload.to.unwrap_or_else(active);
+    for backend in [payload.from, to] {
+        if store(backend).is_none() {
+            return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "log_store_not_configured", &[("store", &backend.name())]));
+        }
+    }
+    if payload.from == to {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "log_migration_same_store", &[("store", &to.name())]));
+    }
+
+    let job_id = jobs::enqueue(
+        &pool,
+        JobKind::LogMigration,
+        serde_json::json!(MigrationPayload { from: payload.from, to: Some(to) }),
+    )
+    .await
+    .map_err(|e| AppError::database(e, &locale, "migrate_task_logs_failed"))?;
+    jobs::wake();
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/jobs/{}", job_id))],
+        Json(serde_json::json!({ "job_id": job_id, "from": payload.from, "to": to })),
+    )
+        .into_response())
+}
+
+pub async fn migration_job(pool: &PgPool, payload: serde_json::Value) -> Result<serde_json::Value, String> {
+    let migration: MigrationPayload = serde_json::from_value(payload).map_err(|e| e.to_string())?;
+    let from_backend = migration.from;
+    let to_backend = migration.to.unwrap_or_else(active);
+    let [from, to] = [from_backend, to_backend]
+        .map(|backend| store(backend).ok_or_else(|| format!("The {} log store is not configured", backend.name())));
+    let (from, to) = (from?, to?);
+
+    let (mut moved, mut missing, mut failed) = (0, 0, 0);
+    let mut after = (Uuid::nil(), Uuid::nil());
+    loop {
+        let batch = sqlx::query_as::<_, TaskLog>(
+            "SELECT r.dag_id, t.run_id, t.node_id, COALESCE(t.log_fence, 0) AS fence, COALESCE(t.finished_at, r.created_at) AS at
+             FROM task_runs t JOIN runs r ON r.id = t.run_id
+             WHERE t.log_store = $1 AND (t.run_id, t.node_id) > ($2, $3)
+             ORDER BY t.run_id, t.node_id LIMIT $4",
+        )
+            .bind(from_backend)
+            .bind(after.0)
+            .bind(after.1)
+            .bind(MIGRATION_BATCH)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(last) = batch.last() else { break };
+        after = (last.run_id, last.node_id);
+
+        for log in batch {
+            let text = match from.get(&log).await {
+                Ok(Some(text)) => text,
+                Ok(None) => {
+                    missing += 1;
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to read the log of node {} in run {} from {}: {}", log.node_id, log.run_id, from_backend.name(), e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            if let Err(e) = to.put(&log, &text).await {
+                tracing::warn!("Failed to store the log of node {} in run {} in {}: {}", log.node_id, log.run_id, to_backend.name(), e);
+                failed += 1;
+                continue;
+            }
+            // The output column only holds logs kept in postgres
+            let repointed = sqlx::query(
+                "UPDATE task_runs SET log_store = $3, output = CASE WHEN $3 = 'postgres' THEN output END
+                 WHERE run_id = $1 AND node_id = $2 AND log_store = $4",
+            )
+                .bind(log.run_id)
+                .bind(log.node_id)
+                .bind(to_backend)
+                .bind(from_backend)
+                .execute(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            if repointed.rows_affected() == 0 {
+                continue;
+            }
+            if from_backend != Backend::Postgres {
+                if let Err(e) = from.delete(&log).await {
+                    tracing::warn!("Failed to delete the log of node {} in run {} from {}: {}", log.node_id, log.run_id, from_backend.name(), e);
+                }
+            }
+            moved += 1;
+        }
+    }
+    Ok(serde_json::json!({ "from": from_backend, "to": to_backend, "moved": moved, "missing": missing, "failed": failed }))
+}
+
+// Logs as the output column of their task's row
+struct Postgres {
+    pool: PgPool,
+}
+
+#[async_trait]
+impl LogStore for Postgres {
+    async fn put(&self, log: &TaskLog, text: &str) -> Result<(), String> {
+        sqlx::query("UPDATE task_runs SET output = $3 WHERE run_id = $1 AND node_id = $2")
+            .bind(log.run_id)
+            .bind(log.node_id)
+            .bind(text)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, log: &TaskLog) -> Result<Option<String>, String> {
+        sqlx::query_scalar::<_, Option<String>>("SELECT output FROM task_runs WHERE run_id = $1 AND node_id = $2")
+            .bind(log.run_id)
+            .bind(log.node_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map(Option::flatten)
+            .map_err(|e| e.to_string())
+    }
+
+    async fn delete(&self, log: &TaskLog) -> Result<(), String> {
+        sqlx::query("UPDATE task_runs SET output = NULL WHERE run_id = $1 AND node_id = $2")
+            .bind(log.run_id)
+            .bind(log.node_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Logs as files under TASK_LOG_DIR, at <run>/<node>/<fence>.log
+struct Filesystem {
+    dir: PathBuf,
+}
+
+impl Filesystem {
+    fn path(&self, log: &TaskLog) -> PathBuf {
+        self.dir.join(format!("{}.log", log.key()))
+    }
+}
+
+#[async_trait]
+impl LogStore for Filesystem {
+    async fn put(&self, log: &TaskLog, text: &str) -> Result<(), String> {
+        let path = self.path(log);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+        }
+        tokio::fs::write(&path, text).await.map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, log: &TaskLog) -> Result<Option<String>, String> {
+        match tokio::fs::read(self.path(log)).await {
+            Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn delete(&self, log: &TaskLog) -> Result<(), String> {
+        match tokio::fs::remove_file(self.path(log)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Logs as objects <prefix><run>/<node>/<fence>.log in a bucket, with
+// requests signed with AWS Signature Version 4
+struct S3 {
+    settings: S3Settings,
+}
+
+impl S3 {
+    async fn request(&self, method: &str, log: &TaskLog, body: Vec<u8>) -> Result<(StatusCode, Vec<u8>), String> {
+        let settings = &self.settings;
+        let path = format!("/{}/{}{}.log", settings.bucket, settings.prefix, log.key());
+        let path = path.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let url = format!("{}{}", settings.endpoint, path);
+        let host = url
+            .parse::<Uri>()
+            .map_err(|e| e.to_string())?
+            .authority()
+            .ok_or("TASK_LOG_S3_ENDPOINT has no host")?
+            .to_string();
+
+        let now = Utc::now();
+        let (date, timestamp) = (now.format("%Y%m%d").to_string(), now.format("%Y%m%dT%H%M%SZ").to_string());
+        let payload_hash = hex(&Sha256::digest(&body));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, host, payload_hash, timestamp, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, settings.region);
+        let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", timestamp, scope, hex(&Sha256::digest(canonical.as_bytes())));
+        let mut key = hmac(format!("AWS4{}", settings.secret_access_key).as_bytes(), &date)?;
+        for part in [settings.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part)?;
+        }
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            settings.access_key_id,
+            scope,
+            signed_headers,
+            hex(&hmac(&key, &to_sign)?)
+        );
+
+        let headers = [
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", timestamp.as_str()),
+            (header::AUTHORIZATION.as_str(), authorization.as_str()),
+        ];
+        executor::send(&url, method, &headers, body).await
+    }
+}
+
+#[async_trait]
+impl LogStore for S3 {
+    async fn put(&self, log: &TaskLog, text: &str) -> Result<(), String> {
+        match self.request("PUT", log, text.as_bytes().to_vec()).await? {
+            (status, _) if status.is_success() => Ok(()),
+            (status, body) => Err(s3_error(status, &body)),
+        }
+    }
+
+    async fn get(&self, log: &TaskLog) -> Result<Option<String>, String> {
+        match self.request("GET", log, Vec::new()).await? {
+            (status, body) if status.is_success() => Ok(Some(String::from_utf8_lossy(&body).into_owned())),
+            (StatusCode::NOT_FOUND, _) => Ok(None),
+            (status, body) => Err(s3_error(status, &body)),
+        }
+    }
+
+    async fn delete(&self, log: &TaskLog) -> Result<(), String> {
+        match self.request("DELETE", log, Vec::new()).await? {
+            (status, _) if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            (status, body) => Err(s3_error(status, &body)),
+        }
+    }
+}
+
+fn s3_error(status: StatusCode, body: &[u8]) -> String {
+    format!("S3 answered {}: {}", status, String::from_utf8_lossy(&body[..body.len().min(500)]))
+}
+
+fn hmac(key: &[u8], data: &str) -> Result<Vec<u8>, String> {
+    let key = PKey::hmac(key).map_err(|e| e.to_string())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+    signer.update(data.as_bytes()).map_err(|e| e.to_string())?;
+    signer.sign_to_vec().map_err(|e| e.to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Percent-encodes all but the characters RFC 3986 leaves unreserved
+fn uri_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Logs as Loki entries, one per line, in the stream {service="dag-service",
+// dag_id} with the run, node and fence as structured metadata (Loki 2.9 or
+// later). Entries are stamped from when the task finished, a nanosecond
+// apart to keep their order. Loki has no per-log delete, so its retention
+// removes them.
+struct Loki {
+    settings: LokiSettings,
+}
+
+impl Loki {
+    fn headers<'a>(&'a self, content_type: Option<&'a str>) -> Vec<(&'a str, &'a str)> {
+        let mut headers: Vec<(&str, &str)> = content_type.map(|t| (header::CONTENT_TYPE.as_str(), t)).into_iter().collect();
+        headers.extend(self.settings.tenant.as_deref().map(|tenant| ("x-scope-orgid", tenant)));
+        headers
+    }
+}
+
+#[derive(Deserialize)]
+struct LokiAnswer {
+    data: LokiData,
+}
+
+#[derive(Deserialize)]
+struct LokiData {
+    result: Vec<LokiStream>,
+}
+
+#[derive(Deserialize)]
+struct LokiStream {
+    // [nanoseconds, line, ...]
+    values: Vec<Vec<serde_json::Value>>,
+}
+
+#[async_trait]
+impl LogStore for Loki {
+    async fn put(&self, log: &TaskLog, text: &str) -> Result<(), String> {
+        let start = log.at.timestamp_nanos_opt().ok_or("The task finished at a time Loki can't take")?;
+        let metadata = serde_json::json!({
+            "run_id": log.run_id.to_string(),
+            "node_id": log.node_id.to_string(),
+            "fence": log.fence.to_string(),
+        });
+        let values: Vec<_> = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| serde_json::json!([(start + i as i64).to_string(), line, metadata]))
+            .collect();
+        let body = serde_json::json!({
+            "streams": [{ "stream": { "service": "dag-service", "dag_id": log.dag_id.to_string() }, "values": values }],
+        });
+        let url = format!("{}/loki/api/v1/push", self.settings.url);
+        match executor::send(&url, "POST", &self.headers(Some("application/json")), body.to_string().into_bytes()).await? {
+            (status, _) if status.is_success() => Ok(()),
+            (status, body) => Err(format!("Loki answered {}: {}", status, String::from_utf8_lossy(&body))),
+        }
+    }
+
+    async fn get(&self, log: &TaskLog) -> Result<Option<String>, String> {
+        let start = log.at.timestamp_nanos_opt().ok_or("The task finished at a time Loki can't take")?;
+        // Lines are stamped from `at` on, one nanosecond each
+        let end = (log.at + ChronoDuration::seconds(1)).timestamp_nanos_opt().unwrap_or(i64::MAX);
+        let query = format!(
+            "{{service=\"dag-service\", dag_id=\"{}\"}} | run_id=\"{}\" | node_id=\"{}\" | fence=\"{}\"",
+            log.dag_id, log.run_id, log.node_id, log.fence
+        );
+        let url = format!(
+            "{}/loki/api/v1/query_range?query={}&start={}&end={}&direction=forward&limit={}",
+            self.settings.url,
+            uri_encode(&query),
+            start,
+            end,
+            LOKI_MAX_LINES
+        );
+        let (status, body) = executor::send(&url, "GET", &self.headers(None), Vec::new()).await?;
+        if !status.is_success() {
+            return Err(format!("Loki answered {}: {}", status, String::from_utf8_lossy(&body)));
+        }
+        let answer: LokiAnswer = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+        let mut lines: Vec<(i64, String)> = answer
+            .data
+            .result
+            .into_iter()
+            .flat_map(|stream| stream.values)
+            .filter_map(|value| Some((value.first()?.as_str()?.parse().ok()?, value.get(1)?.as_str()?.to_string())))
+            .collect();
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        lines.sort_by_key(|(at, _)| *at);
+        Ok(Some(lines.into_iter().map(|(_, line)| line).collect::<Vec<_>>().join("\n")))
+    }
+
+    async fn delete(&self, _log: &TaskLog) -> Result<(), String> {
+        Ok(())
+    }
+}
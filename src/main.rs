@@ -1,25 +1,115 @@
-use sqlx::{PgPool, FromRow};
+use sqlx::{postgres::PgPoolOptions, PgPool, FromRow};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use std::env;
-use dotenvy::dotenv;
+use std::collections::HashMap;
+use std::sync::Arc;
 use axum::{
-    extract::{Extension, Json},
-    response::IntoResponse,
-    routing::{post},
-    Router,
-    http::StatusCode,
+    extract::{Json, Query, State},
+    response::{IntoResponse, Response},
+    http::{header, HeaderValue, StatusCode},
 };
 
+mod analysis;
+mod artifacts;
+mod auth;
+mod catalog;
+mod changes;
+mod checks;
+mod clones;
+mod config;
+mod connections;
+mod cron;
+mod db;
+mod environments;
+mod error;
+mod events;
+mod executor;
+mod export;
+mod filter;
+mod graph;
+mod history;
+mod i18n;
+mod ids;
+mod imports;
+mod jobs;
+mod logs;
+mod maintenance;
+mod openapi;
+mod preconditions;
+mod render;
+mod scheduled_changes;
+mod schedules;
+mod schema;
+mod slugs;
+mod snapshots;
+mod state;
+mod telemetry;
+mod usage;
+mod variables;
+mod versions;
+mod vocabulary;
+mod watchdog;
+
+use changes::Change;
+use config::Config;
+use db::TxError;
+use error::AppError;
+use i18n::Locale;
+use maintenance::{Maintenance, Writable};
+use preconditions::{with_etag, Entity, IfMatch};
+use slugs::DagId;
+use state::AppState;
+use usage::Access;
+
 // Models
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum Lifecycle {
+    Active,
+    Deprecated,
+    Archived,
+}
+
+// Draft nodes are staged edits: runs leave them out until they are made
+// active. New edges into deprecated nodes are answered with a warning.
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum NodeState {
+    Draft,
+    #[default]
+    Active,
+    Deprecated,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, FromRow)]
 struct DAG {
     id: Uuid,
     name: String,
+    lifecycle: Lifecycle,
+    deprecation_reason: Option<String>,
+    replaced_by: Option<Uuid>,
+    slug: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct CreateDAGPayload {
+    // Callers may bring their own id; it must not be in use yet
+    id: Option<Uuid>,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateLifecyclePayload {
+    lifecycle: Lifecycle,
+    reason: Option<String>,
+    replaced_by: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct UpdateDAGPayload {
     name: String,
 }
 
@@ -28,13 +118,64 @@ struct Node {
     id: Uuid,
     dag_id: Uuid,
     label: String,
+    slug: Option<String>,
+    // Ids of the same entity in other systems, keyed by system name
+    external_ids: sqlx::types::Json<HashMap<String, String>>,
+    // Set for nodes that came in through an import
+    provenance: Option<sqlx::types::Json<imports::Provenance>>,
+    // Who to contact about the node and where its implementation lives
+    owner: Option<String>,
+    source_url: Option<String>,
+    task: Option<sqlx::types::Json<executor::Task>>,
+    // Whatever clients want to keep on the node; the service only reads it
+    // for analytics weights
+    metadata: sqlx::types::Json<serde_json::Value>,
+    node_type: Option<String>,
+    // Where a UI last placed the node
+    position: Option<sqlx::types::Json<Position>>,
+    // Who created the node and why, as they said in X-Actor and X-Change-Reason
+    created_by: Option<String>,
+    reason: Option<String>,
+    #[serde(default)]
+    state: NodeState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Position {
+    x: f64,
+    y: f64,
+}
+
+fn empty_metadata() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+// Tells a field sent as null, which clears it, from one left out
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 #[derive(Deserialize)]
 struct CreateNodePayload {
     // name: String,
+    id: Option<Uuid>,
     dag_id: Uuid,
     label: String,
+    #[serde(default)]
+    external_ids: HashMap<String, String>,
+    owner: Option<String>,
+    source_url: Option<String>,
+    task: Option<executor::Task>,
+    #[serde(default = "empty_metadata")]
+    metadata: serde_json::Value,
+    node_type: Option<String>,
+    position: Option<Position>,
+    #[serde(default)]
+    state: NodeState,
 }
 
 
@@ -44,208 +185,2330 @@ struct Edge {
     source: Uuid,
     target: Uuid,
     dag_id: Uuid,
+    // Who created the edge and why, as they said in X-Actor and X-Change-Reason
+    #[serde(default)]
+    created_by: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// Fields left out keep their current value; a blank owner, source_url or
+// node_type clears it, as does a null position. metadata is replaced whole.
+#[derive(Deserialize)]
+struct UpdateNodePayload {
+    label: Option<String>,
+    external_ids: Option<HashMap<String, String>>,
+    owner: Option<String>,
+    source_url: Option<String>,
+    metadata: Option<serde_json::Value>,
+    node_type: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    position: Option<Option<Position>>,
+    state: Option<NodeState>,
+}
+
+#[derive(Deserialize)]
+struct UpdateEdgePayload {
+    source: Option<Uuid>,
+    target: Option<Uuid>,
 }
 
 #[derive(Serialize, Deserialize, FromRow)]
 struct CreateEdgePayload {
+    id: Option<Uuid>,
     source: Uuid,
     target: Uuid,
     dag_id: Uuid,
 }
 
 // Database Setup
-async fn setup_database() -> PgPool {
-    dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgPool::connect(&database_url)
-        .await
-        .expect("Failed to connect to database")
+fn id_taken(locale: &Locale, id: Uuid) -> AppError {
+    locale.error(StatusCode::CONFLICT, "id_taken", &[("id", &id)])
+}
+
+// Turns an insert colliding with a caller-supplied id into a 409
+fn taken_or(e: sqlx::Error, locale: &Locale, id: Uuid) -> TxError {
+    if db::is_duplicate_id(&e) {
+        TxError::Rejected(id_taken(locale, id))
+    } else {
+        TxError::Database(e)
+    }
 }
 
 //CRUD Handlers for DAG
 async fn create_dag(
-    Extension(pool): Extension<PgPool>,
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
     Json(payload): Json<CreateDAGPayload>,
-) -> impl IntoResponse {
-    let id = Uuid::new_v4();
-    let dag = DAG {
+) -> Result<Response, AppError> {
+    let id = payload.id.unwrap_or_else(ids::new_id);
+    let (name, locale) = (&payload.name, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let slug = slugs::dag_slug(&mut *tx, name, id).await?;
+        sqlx::query!(
+            "INSERT INTO dags (id, name, slug, owner_key_id) VALUES ($1, $2, $3, $4)",
+            id,
+            name,
+            slug,
+            auth::creator()
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| taken_or(e, locale, id))?;
+        Ok(slug)
+    }))
+    .await;
+
+    let slug = result.map_err(|e| e.respond(locale, "create_dag_failed"))?;
+    Ok(Json(DAG {
         id,
         name: payload.name,
+        lifecycle: Lifecycle::Active,
+        deprecation_reason: None,
+        replaced_by: None,
+        slug: Some(slug),
+    })
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct ListDAGsParams {
+    name: Option<String>,
+    #[serde(default)]
+    fuzzy: bool,
+    threshold: Option<f32>,
+}
+
+// Escapes LIKE wildcards so user input only ever matches literally
+fn like_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+async fn list_dags(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(params): Query<ListDAGsParams>,
+) -> Result<Response, AppError> {
+    // Names match case-insensitively as substrings; fuzzy matching ranks by
+    // trigram similarity instead (pg_trgm)
+    let query = match (params.name, params.fuzzy) {
+        (None, _) => sqlx::query_as::<_, DAG>(
+            "SELECT id, name, lifecycle, deprecation_reason, replaced_by, slug FROM dags WHERE ($1::uuid IS NULL OR owner_key_id = $1)",
+        )
+            .bind(auth::owner()),
+        (Some(name), false) => sqlx::query_as::<_, DAG>(
+            "SELECT id, name, lifecycle, deprecation_reason, replaced_by, slug FROM dags
+             WHERE ($1::uuid IS NULL OR owner_key_id = $1) AND name ILIKE $2 ORDER BY name",
+        )
+            .bind(auth::owner())
+            .bind(like_pattern(&name)),
+        (Some(name), true) => sqlx::query_as::<_, DAG>(
+            "SELECT id, name, lifecycle, deprecation_reason, replaced_by, slug FROM dags
+             WHERE ($1::uuid IS NULL OR owner_key_id = $1) AND similarity(name, $2) >= $3 ORDER BY similarity(name, $2) DESC, name",
+        )
+            .bind(auth::owner())
+            .bind(name)
+            .bind(params.threshold.unwrap_or(0.3)),
     };
 
-    match sqlx::query!(
-        "INSERT INTO dags (id, name) VALUES ($1, $2)",
-        dag.id,
-        dag.name
+    let dags = query
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_dags_failed"))?;
+    Ok(Json(dags).into_response())
+}
+
+// DAGs of other API keys are treated as missing, here and in lock_dag
+async fn fetch_dag<'e, E: sqlx::PgExecutor<'e>>(executor: E, dag_id: Uuid) -> Result<Option<DAG>, sqlx::Error> {
+    sqlx::query_as::<_, DAG>(
+        "SELECT id, name, lifecycle, deprecation_reason, replaced_by, slug FROM dags
+         WHERE id = $1 AND ($2::uuid IS NULL OR owner_key_id = $2)",
     )
-        .execute(&pool)
+        .bind(dag_id)
+        .bind(auth::owner())
+        .fetch_optional(executor)
         .await
-    {
-        Ok(_) => Json(dag).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create DAG: {}", e),
-        ).into_response(),
-    }
 }
 
-async fn list_dags(Extension(pool): Extension<PgPool>) -> impl IntoResponse {
-    match sqlx::query_as::<_, DAG>("SELECT id, name FROM dags")
-        .fetch_all(&pool)
+// fetch_dag for handlers, where a missing DAG answers 404
+async fn find_dag(pool: &PgPool, locale: &Locale, dag_id: Uuid) -> Result<DAG, AppError> {
+    fetch_dag(pool, dag_id)
         .await
-    {
-        Ok(dags) => Json(dags).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch DAGs: {}", e),
-        ).into_response(),
-    }
+        .map_err(|e| AppError::database(e, locale, "fetch_dag_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", &dag_id)]))
 }
 
-async fn get_dag_with_details(
-    Extension(pool): Extension<PgPool>,
-    axum::extract::Path(dag_id): axum::extract::Path<Uuid>,
-) -> impl IntoResponse {
-    let dag_query = sqlx::query_as::<_, DAG>("SELECT id, name FROM dags WHERE id = $1")
+// load_dag for handlers, where a missing DAG answers 404. `key` names the
+// message for database failures.
+async fn loaded_dag(pool: &PgPool, locale: &Locale, dag_id: Uuid, key: &str) -> Result<(DAG, Vec<Node>, Vec<Edge>), AppError> {
+    Ok(tagged_dag(pool, locale, dag_id, key).await?.0)
+}
+
+// The DAG with its version as of the same snapshot, for the ETag
+async fn tagged_dag(
+    pool: &PgPool,
+    locale: &Locale,
+    dag_id: Uuid,
+    key: &str,
+) -> Result<(LoadedDag, Option<i64>), AppError> {
+    load_dag(pool, dag_id)
+        .await
+        .map_err(|e| AppError::database(e, locale, key))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", &dag_id)]))
+}
+
+// Like fetch_dag, but holds the DAG's row lock until the transaction ends.
+// Structural changes take it so they apply to one DAG one at a time.
+async fn lock_dag<'e, E: sqlx::PgExecutor<'e>>(executor: E, dag_id: Uuid) -> Result<Option<DAG>, sqlx::Error> {
+    sqlx::query_as::<_, DAG>(
+        "SELECT id, name, lifecycle, deprecation_reason, replaced_by, slug FROM dags
+         WHERE id = $1 AND ($2::uuid IS NULL OR owner_key_id = $2) FOR UPDATE",
+    )
         .bind(dag_id)
-        .fetch_optional(&pool)
-        .await;
+        .bind(auth::owner())
+        .fetch_optional(executor)
+        .await
+}
+
+// A DAG with its nodes and edges
+type LoadedDag = (DAG, Vec<Node>, Vec<Edge>);
+
+// Fetches a DAG together with its nodes and edges, or None if it doesn't exist.
+// The reads share one REPEATABLE READ snapshot so concurrent writers can't
+// leave edges pointing at nodes the export doesn't contain.
+async fn load_dag(pool: &PgPool, dag_id: Uuid) -> Result<Option<(LoadedDag, Option<i64>)>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut tx)
+        .await?;
 
-    let nodes_query = sqlx::query_as::<_, Node>("SELECT id, dag_id, label FROM nodes WHERE dag_id = $1")
+    let dag = match fetch_dag(&mut tx, dag_id).await? {
+        Some(dag) => dag,
+        None => return Ok(None),
+    };
+
+    let nodes = sqlx::query_as::<_, Node>("SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes WHERE dag_id = $1")
         .bind(dag_id)
-        .fetch_all(&pool)
-        .await;
+        .fetch_all(&mut tx)
+        .await?;
 
-    let edges_query = sqlx::query_as::<_, Edge>("SELECT id, source, target, dag_id FROM edges WHERE dag_id = $1")
+    let edges = sqlx::query_as::<_, Edge>("SELECT id, source, target, dag_id, created_by, reason FROM edges WHERE dag_id = $1")
         .bind(dag_id)
-        .fetch_all(&pool)
-        .await;
+        .fetch_all(&mut tx)
+        .await?;
+    let version = preconditions::current(&mut tx, Entity::Dag, dag_id).await?;
 
-    match (dag_query, nodes_query, edges_query) {
-        (Ok(Some(dag)), Ok(nodes), Ok(edges)) => {
-            let result = serde_json::json!({
-                "dag": dag,
-                "nodes": nodes,
-                "edges": edges,
-            });
-            Json(result).into_response()
+    tx.commit().await?;
+    Ok(Some(((dag, nodes, edges), version)))
+}
+
+// Deprecated DAGs keep working but say so in a Warning header
+fn lifecycle_warning(dag: &DAG, locale: &Locale) -> Option<HeaderValue> {
+    if dag.lifecycle != Lifecycle::Deprecated {
+        return None;
+    }
+    let text = match &dag.deprecation_reason {
+        Some(reason) => locale.t("dag_deprecated_with_reason", &[("id", &dag.id), ("reason", reason)]),
+        None => locale.t("dag_deprecated", &[("id", &dag.id)]),
+    };
+    warning_header(&text)
+}
+
+fn warning_header(text: &str) -> Option<HeaderValue> {
+    let quoted = text.replace('\\', "\\\\").replace('"', "\\\"");
+    HeaderValue::from_bytes(format!("299 - \"{}\"", quoted).as_bytes()).ok()
+}
+
+// Warnings add up; a response may carry several
+fn with_warning(mut response: Response, warning: Option<HeaderValue>) -> Response {
+    if let Some(warning) = warning {
+        response.headers_mut().append(header::WARNING, warning);
+    }
+    response
+}
+
+// Structural changes are refused on archived DAGs. On success returns the
+// warning to attach for deprecated ones.
+fn writable(dag: Option<DAG>, locale: &Locale, dag_id: Uuid) -> Result<Option<HeaderValue>, AppError> {
+    match dag {
+        Some(dag) if dag.lifecycle == Lifecycle::Archived => Err(locale.error(StatusCode::CONFLICT, "dag_archived", &[("id", &dag_id)])),
+        Some(dag) => Ok(lifecycle_warning(&dag, locale)),
+        None => Err(locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", &dag_id)])),
+    }
+}
+
+async fn check_writable(pool: &PgPool, locale: &Locale, dag_id: Uuid) -> Result<Option<HeaderValue>, AppError> {
+    match fetch_dag(pool, dag_id).await {
+        Ok(dag) => writable(dag, locale, dag_id),
+        Err(e) => Err(AppError::database(e, locale, "fetch_dag_failed")),
+    }
+}
+
+fn node_not_found(locale: &Locale, node_id: Uuid) -> TxError {
+    TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "node_not_found", &[("id", &node_id)]))
+}
+
+fn edge_not_found(locale: &Locale, edge_id: Uuid) -> TxError {
+    TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "edge_not_found", &[("id", &edge_id)]))
+}
+
+// Locks the DAG a node or edge belongs to for a change to it. `lookup`
+// selects the DAG's id; it runs again once the lock is held in case the row
+// went away in the meantime.
+async fn lock_owner(
+    tx: &mut sqlx::PgConnection,
+    lookup: &str,
+    id: Uuid,
+    locale: &Locale,
+    not_found: fn(&Locale, Uuid) -> TxError,
+) -> Result<(Uuid, Option<HeaderValue>), TxError> {
+    let dag_id = sqlx::query_scalar::<_, Uuid>(lookup)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| not_found(locale, id))?;
+    // A DAG the caller may not see hides what is in it, too
+    let dag = lock_dag(&mut *tx, dag_id).await?.ok_or_else(|| not_found(locale, id))?;
+    let warning = writable(Some(dag), locale, dag_id).map_err(TxError::Rejected)?;
+    sqlx::query_scalar::<_, Uuid>(lookup)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| not_found(locale, id))?;
+    Ok((dag_id, warning))
+}
+
+async fn lock_node_dag(tx: &mut sqlx::PgConnection, node_id: Uuid, locale: &Locale) -> Result<(Uuid, Option<HeaderValue>), TxError> {
+    lock_owner(tx, "SELECT dag_id FROM nodes WHERE id = $1", node_id, locale, node_not_found).await
+}
+
+async fn lock_edge_dag(tx: &mut sqlx::PgConnection, edge_id: Uuid, locale: &Locale) -> Result<(Uuid, Option<HeaderValue>), TxError> {
+    lock_owner(tx, "SELECT dag_id FROM edges WHERE id = $1", edge_id, locale, edge_not_found).await
+}
+
+// True if an edge from source to target would close a cycle, i.e. source is
+// already reachable from target. Follows edges across DAG boundaries.
+async fn creates_cycle<'e, E: sqlx::PgExecutor<'e>>(executor: E, source: Uuid, target: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "WITH RECURSIVE reachable(id) AS (
+             SELECT $2::uuid
+             UNION
+             SELECT e.target FROM edges e JOIN reachable r ON e.source = r.id
+         )
+         SELECT EXISTS (SELECT 1 FROM reachable WHERE id = $1)",
+    )
+        .bind(source)
+        .bind(target)
+        .fetch_one(executor)
+        .await
+}
+
+async fn update_dag_lifecycle(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    if_match: IfMatch,
+    Json(payload): Json<UpdateLifecyclePayload>,
+) -> Result<Response, AppError> {
+    // Reactivating a DAG drops its deprecation details
+    let (reason, replaced_by) = match payload.lifecycle {
+        Lifecycle::Active => (None, None),
+        _ => (payload.reason, payload.replaced_by),
+    };
+
+    if let Some(replacement) = replaced_by {
+        if replacement == dag_id {
+            return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "replacement_is_self", &[]));
         }
-        (Ok(None), _, _) => (
-            StatusCode::NOT_FOUND,
-            format!("DAG with id {} not found", dag_id),
+        fetch_dag(&pool, replacement)
+            .await
+            .map_err(|e| AppError::database(e, &locale, "fetch_dag_failed"))?
+            .ok_or_else(|| locale.error(StatusCode::UNPROCESSABLE_ENTITY, "replacement_not_found", &[("id", &replacement)]))?;
+    }
+
+    let (lifecycle, reason, if_match, locale) = (payload.lifecycle, &reason, &if_match, &locale);
+    let (dag, version) = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        preconditions::check(&mut *tx, locale, if_match, Entity::Dag, dag_id).await?;
+        let dag = sqlx::query_as::<_, DAG>(
+            "UPDATE dags SET lifecycle = $2, deprecation_reason = $3, replaced_by = $4 WHERE id = $1
+             RETURNING id, name, lifecycle, deprecation_reason, replaced_by, slug",
         )
-            .into_response(),
-        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch DAG details: {}", e),
+            .bind(dag_id)
+            .bind(lifecycle)
+            .bind(reason)
+            .bind(replaced_by)
+            .fetch_optional(&mut *tx)
+            .await?;
+        Ok((dag, preconditions::current(&mut *tx, Entity::Dag, dag_id).await?))
+    }))
+    .await
+    .map_err(|e| e.respond(locale, "update_lifecycle_failed"))?;
+    let dag = dag.ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", &dag_id)]))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "dag_updated", serde_json::json!({ "dag": &dag }));
+    let warning = lifecycle_warning(&dag, locale);
+    Ok(with_etag(with_warning(Json(dag).into_response(), warning), version))
+}
+
+async fn get_dag_with_details(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(at): Query<versions::VersionParam>,
+) -> Result<Response, AppError> {
+    // Saved versions can't be changed, so only the DAG as it is has an ETag
+    let ((dag, nodes, edges), version) = match at.is_current() {
+        true => tagged_dag(&pool, &locale, dag_id, "fetch_dag_failed").await?,
+        false => (versions::loaded_at(&pool, &locale, dag_id, &at, "fetch_dag_failed").await?, None),
+    };
+    usage::record(&pool, dag_id, Access::Read);
+    let warning = lifecycle_warning(&dag, &locale);
+    let result = serde_json::json!({
+        "dag": dag,
+        "nodes": nodes,
+        "edges": edges,
+    });
+    Ok(with_etag(with_warning(Json(result).into_response(), warning), version))
+}
+
+// Renames a DAG. Its slug stays, so existing links keep working.
+async fn update_dag(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    if_match: IfMatch,
+    Json(payload): Json<UpdateDAGPayload>,
+) -> Result<Response, AppError> {
+    let (name, if_match, locale) = (&payload.name, &if_match, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Dag, dag_id).await?;
+        let dag = sqlx::query_as::<_, DAG>(
+            "UPDATE dags SET name = $2 WHERE id = $1
+             RETURNING id, name, lifecycle, deprecation_reason, replaced_by, slug",
         )
-            .into_response(),
-    }
+            .bind(dag_id)
+            .bind(name)
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok((dag, warning, preconditions::current(&mut *tx, Entity::Dag, dag_id).await?))
+    }))
+    .await;
+
+    let (dag, warning, version) = result.map_err(|e| e.respond(locale, "update_dag_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "dag_updated", serde_json::json!({ "dag": &dag }));
+    Ok(with_etag(with_warning(Json(dag).into_response(), warning), version))
+}
+
+// Deletes a DAG with its nodes, edges and finished runs. Imports, catalog
+// entries and other DAGs that referred to it forget it; imports still
+// waiting to be promoted into it and runs under way keep it from being
+// deleted.
+async fn delete_dag(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    if_match: IfMatch,
+) -> Result<Response, AppError> {
+    let (if_match, locale) = (&if_match, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id).map_err(TxError::Rejected)?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Dag, dag_id).await?;
+        let pending: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM imports WHERE target_dag_id = $1 AND status IN ('uploading', 'validating', 'valid')
+             ORDER BY created_at",
+        )
+            .bind(dag_id)
+            .fetch_all(&mut *tx)
+            .await?;
+        if !pending.is_empty() {
+            let ids = pending.iter().map(Uuid::to_string).collect::<Vec<_>>().join(", ");
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "dag_has_pending_imports", &[("id", &dag_id), ("ids", &ids)])));
+        }
+        let active: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM runs WHERE dag_id = $1 AND status IN ('queued', 'running', 'stalled') ORDER BY created_at",
+        )
+            .bind(dag_id)
+            .fetch_all(&mut *tx)
+            .await?;
+        if !active.is_empty() {
+            let ids = active.iter().map(Uuid::to_string).collect::<Vec<_>>().join(", ");
+            return Err(TxError::Rejected(locale.error(StatusCode::CONFLICT, "dag_has_active_runs", &[("id", &dag_id), ("ids", &ids)])));
+        }
+        let task_logs = logs::of_dag(&mut *tx, dag_id).await?;
+
+        for statement in [
+            "UPDATE imports SET target_dag_id = NULL WHERE target_dag_id = $1",
+            "UPDATE imports SET dag_id = NULL WHERE dag_id = $1",
+            "UPDATE dags SET replaced_by = NULL WHERE replaced_by = $1",
+            "UPDATE catalog_entries SET source_dag_id = NULL WHERE source_dag_id = $1",
+            "UPDATE dags SET promoted_from = NULL WHERE promoted_from = $1",
+            "UPDATE promotions SET target_dag_id = NULL WHERE target_dag_id = $1",
+            "DELETE FROM promotions WHERE dag_id = $1",
+            "DELETE FROM catalog_copies WHERE dag_id = $1",
+            "DELETE FROM dag_usage WHERE dag_id = $1",
+            "DELETE FROM dag_versions WHERE dag_id = $1",
+            "DELETE FROM runs WHERE dag_id = $1",
+            "DELETE FROM schedules WHERE dag_id = $1",
+            "DELETE FROM scheduled_changes WHERE dag_id = $1",
+            "DELETE FROM edges WHERE dag_id = $1
+                OR source IN (SELECT id FROM nodes WHERE dag_id = $1)
+                OR target IN (SELECT id FROM nodes WHERE dag_id = $1)",
+            "DELETE FROM nodes WHERE dag_id = $1",
+            "DELETE FROM dags WHERE id = $1",
+        ] {
+            sqlx::query(statement).bind(dag_id).execute(&mut *tx).await?;
+        }
+        Ok(task_logs)
+    }))
+    .await;
+
+    let task_logs = result.map_err(|e| e.respond(locale, "delete_dag_failed"))?;
+    logs::discard(task_logs);
+    events::publish(dag_id, "dag_deleted", serde_json::json!({}));
+    Ok(StatusCode::NO_CONTENT.into_response())
 }
 
 
 async fn create_node(
-    Extension(pool): Extension<PgPool>,
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
     Json(payload): Json<CreateNodePayload>,
-) -> impl IntoResponse {
-    let id = Uuid::new_v4();
+) -> Result<Response, AppError> {
+    if !external_ids_valid(&payload.external_ids) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    if payload.source_url.as_deref().is_some_and(|url| !source_url_valid(url)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_source_url", &[]));
+    }
+    if payload.task.as_ref().is_some_and(|task| !task.is_valid()) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_task", &[]));
+    }
+
+    let id = payload.id.unwrap_or_else(ids::new_id);
+    let external_ids = sqlx::types::Json(payload.external_ids);
+    let (dag_id, label, external_ids_ref, locale) = (payload.dag_id, &payload.label, &external_ids, &locale);
+    let task = payload.task.map(sqlx::types::Json);
+    let (owner, source_url, task_ref) = (&payload.owner, &payload.source_url, &task);
+    let metadata = sqlx::types::Json(payload.metadata);
+    let node_type = payload.node_type.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    let position = payload.position.map(sqlx::types::Json);
+    let (metadata_ref, node_type_ref, position_ref, change_ref) = (&metadata, &node_type, &position, &change);
+    let state = payload.state;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        vocabulary::check_labels(&mut *tx, locale, [label.as_str()]).await?;
+        let slug = slugs::node_slug(&mut *tx, dag_id, label, id).await?;
+        sqlx::query(
+            "INSERT INTO nodes (id, dag_id, label, slug, external_ids, owner, source_url, task, metadata, node_type, position, created_by, reason, state)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+        )
+            .bind(id)
+            .bind(dag_id)
+            .bind(label)
+            .bind(&slug)
+            .bind(external_ids_ref)
+            .bind(owner)
+            .bind(source_url)
+            .bind(task_ref)
+            .bind(metadata_ref)
+            .bind(node_type_ref)
+            .bind(position_ref)
+            .bind(&change_ref.created_by)
+            .bind(&change_ref.reason)
+            .bind(state)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| taken_or(e, locale, id))?;
+        Ok((warning, slug))
+    }))
+    .await;
+
+    let (warning, slug) = result.map_err(|e| e.respond(locale, "create_node_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
     let node = Node {
         id,
-        dag_id: payload.dag_id,
+        dag_id,
         label: payload.label,
+        slug: Some(slug),
+        external_ids,
+        provenance: None,
+        owner: payload.owner,
+        source_url: payload.source_url,
+        task,
+        metadata,
+        node_type,
+        position,
+        created_by: change.created_by,
+        reason: change.reason,
+        state,
     };
+    events::publish(dag_id, "node_created", serde_json::json!({ "node": &node }));
+    Ok(with_warning(Json(node).into_response(), warning))
+}
 
-    match sqlx::query!(
-        "INSERT INTO nodes (id, dag_id, label) VALUES ($1, $2, $3)",
-        node.id,
-        node.dag_id,
-        node.label
-    )
-        .execute(&pool)
+// A node of a DAG by uuid or by its slug within the DAG
+async fn get_dag_node(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    axum::extract::Path((_, node)): axum::extract::Path<(String, String)>,
+) -> Result<Response, AppError> {
+    let query = match node.parse::<Uuid>() {
+        Ok(node_id) => sqlx::query_as::<_, Node>("SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes WHERE dag_id = $1 AND id = $2")
+            .bind(dag_id)
+            .bind(node_id),
+        Err(_) => sqlx::query_as::<_, Node>("SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes WHERE dag_id = $1 AND slug = $2")
+            .bind(dag_id)
+            .bind(&node),
+    };
+
+    let found = query
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "node_not_found_in_dag", &[("node", &node), ("dag", &dag_id)]))?;
+    let version = preconditions::current(&pool, Entity::Node, found.id)
         .await
-    {
-        Ok(_) => Json(node).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create Node: {}", e),
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(with_etag(Json(found).into_response(), version))
+}
+
+// Source links have to be web URLs so notifications can link to them
+fn source_url_valid(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+// System names and ids must not be blank
+fn external_ids_valid(external_ids: &HashMap<String, String>) -> bool {
+    external_ids.iter().all(|(system, id)| !system.trim().is_empty() && !id.trim().is_empty())
+}
+
+async fn set_external_ids(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(node_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+    Json(external_ids): Json<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    if !external_ids_valid(&external_ids) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    let external_ids = sqlx::types::Json(external_ids);
+    let (external_ids, if_match, locale) = (&external_ids, &if_match, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let (_, warning) = lock_node_dag(&mut *tx, node_id, locale).await?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Node, node_id).await?;
+        let node = sqlx::query_as::<_, Node>(
+            "UPDATE nodes SET external_ids = $2 WHERE id = $1
+             RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
         )
-            .into_response(),
+            .bind(node_id)
+            .bind(external_ids)
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok((node, warning, preconditions::current(&mut *tx, Entity::Node, node_id).await?))
+    }))
+    .await;
+
+    let (node, warning, version) = result.map_err(|e| e.respond(locale, "update_node_failed"))?;
+    usage::record(&pool, node.dag_id, Access::Edit);
+    events::publish(node.dag_id, "node_updated", serde_json::json!({ "node": &node }));
+    Ok(with_etag(with_warning(Json(node).into_response(), warning), version))
+}
+
+// Sets what running the node does
+async fn set_task(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(node_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+    Json(task): Json<executor::Task>,
+) -> Result<Response, AppError> {
+    if !task.is_valid() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_task", &[]));
     }
+    replace_task(&pool, &locale, &if_match, node_id, Some(sqlx::types::Json(task))).await
 }
 
-async fn list_nodes(Extension(pool): Extension<PgPool>) -> impl IntoResponse {
-    match sqlx::query_as::<_, Node>("SELECT id, dag_id, label FROM nodes")
-        .fetch_all(&pool)
+// Without a task the node succeeds as soon as its dependencies have
+async fn clear_task(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(node_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+) -> Result<Response, AppError> {
+    replace_task(&pool, &locale, &if_match, node_id, None).await
+}
+
+async fn replace_task(
+    pool: &PgPool,
+    locale: &Locale,
+    if_match: &IfMatch,
+    node_id: Uuid,
+    task: Option<sqlx::types::Json<executor::Task>>,
+) -> Result<Response, AppError> {
+    let task = &task;
+    let result = db::unit_of_work(pool, |tx| Box::pin(async move {
+        let (_, warning) = lock_node_dag(&mut *tx, node_id, locale).await?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Node, node_id).await?;
+        let node = sqlx::query_as::<_, Node>(
+            "UPDATE nodes SET task = $2 WHERE id = $1
+             RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
+        )
+            .bind(node_id)
+            .bind(task)
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok((node, warning, preconditions::current(&mut *tx, Entity::Node, node_id).await?))
+    }))
+    .await;
+
+    let (node, warning, version) = result.map_err(|e| e.respond(locale, "update_node_failed"))?;
+    usage::record(pool, node.dag_id, Access::Edit);
+    events::publish(node.dag_id, "node_updated", serde_json::json!({ "node": &node }));
+    Ok(with_etag(with_warning(Json(node).into_response(), warning), version))
+}
+
+async fn get_node(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(node_id): axum::extract::Path<Uuid>,
+) -> Result<Response, AppError> {
+    let node = sqlx::query_as::<_, Node>(&format!(
+        "SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes WHERE id = $1 AND {}",
+        auth::visible("dag_id", 2)
+    ))
+        .bind(node_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
         .await
-    {
-        Ok(nodes) => Json(nodes).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch Nodes: {}", e),
-        ).into_response(),
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "node_not_found", &[("id", &node_id)]))?;
+    let version = preconditions::current(&pool, Entity::Node, node_id)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?;
+    usage::record(&pool, node.dag_id, Access::Read);
+    Ok(with_etag(Json(node).into_response(), version))
+}
+
+// Relabeling keeps the node's slug, as renaming a DAG keeps the DAG's
+async fn update_node(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(node_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+    Json(payload): Json<UpdateNodePayload>,
+) -> Result<Response, AppError> {
+    if payload.external_ids.as_ref().is_some_and(|ids| !external_ids_valid(ids)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    if payload.source_url.as_deref().is_some_and(|url| !url.is_empty() && !source_url_valid(url)) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_source_url", &[]));
+    }
+
+    let external_ids = payload.external_ids.map(sqlx::types::Json);
+    let (label, external_ids, if_match, locale) = (&payload.label, &external_ids, &if_match, &locale);
+    let (owner, source_url) = (&payload.owner, &payload.source_url);
+    let metadata = payload.metadata.map(sqlx::types::Json);
+    let (metadata, node_type) = (&metadata, &payload.node_type);
+    let position = payload.position.map(|p| p.map(sqlx::types::Json));
+    let (clear_position, position) = (position.as_ref().is_some_and(|p| p.is_none()), &position.flatten());
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let (_, warning) = lock_node_dag(&mut *tx, node_id, locale).await?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Node, node_id).await?;
+        vocabulary::check_labels(&mut *tx, locale, label.as_deref()).await?;
+        let node = sqlx::query_as::<_, Node>(
+            "UPDATE nodes SET label = COALESCE($2, label), external_ids = COALESCE($3, external_ids),
+                owner = CASE WHEN $4::text IS NULL THEN owner ELSE NULLIF(trim($4), '') END,
+                source_url = CASE WHEN $5::text IS NULL THEN source_url ELSE NULLIF($5, '') END,
+                metadata = COALESCE($6, metadata),
+                node_type = CASE WHEN $7::text IS NULL THEN node_type ELSE NULLIF(trim($7), '') END,
+                position = CASE WHEN $9 THEN NULL ELSE COALESCE($8, position) END,
+                state = COALESCE($10, state)
+             WHERE id = $1
+             RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
+        )
+            .bind(node_id)
+            .bind(label)
+            .bind(external_ids)
+            .bind(owner)
+            .bind(source_url)
+            .bind(metadata)
+            .bind(node_type)
+            .bind(position)
+            .bind(clear_position)
+            .bind(payload.state)
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok((node, warning, preconditions::current(&mut *tx, Entity::Node, node_id).await?))
+    }))
+    .await;
+
+    let (node, warning, version) = result.map_err(|e| e.respond(locale, "update_node_failed"))?;
+    usage::record(&pool, node.dag_id, Access::Edit);
+    events::publish(node.dag_id, "node_updated", serde_json::json!({ "node": &node }));
+    Ok(with_etag(with_warning(Json(node).into_response(), warning), version))
+}
+
+// Deletes a node along with the edges attached to it
+async fn delete_node(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(node_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+) -> Result<Response, AppError> {
+    let (if_match, locale) = (&if_match, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let (dag_id, warning) = lock_node_dag(&mut *tx, node_id, locale).await?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Node, node_id).await?;
+        sqlx::query("DELETE FROM edges WHERE source = $1 OR target = $1")
+            .bind(node_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE id = $1")
+            .bind(node_id)
+            .execute(&mut *tx)
+            .await?;
+        Ok((dag_id, warning))
+    }))
+    .await;
+
+    let (dag_id, warning) = result.map_err(|e| e.respond(locale, "delete_node_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "node_deleted", serde_json::json!({ "node_id": node_id }));
+    Ok(with_warning(StatusCode::NO_CONTENT.into_response(), warning))
+}
+
+// How new attributes combine with those a node already has
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum MergeStrategy {
+    // Incoming keys win, other existing keys stay
+    #[default]
+    Merge,
+    // Existing keys win, incoming keys only fill gaps
+    Keep,
+    // The incoming attributes are all that is left
+    Replace,
+}
+
+#[derive(Deserialize)]
+struct UpsertNodeParams {
+    #[serde(default)]
+    merge: MergeStrategy,
+}
+
+#[derive(Deserialize)]
+struct UpsertNodePayload {
+    // Only used when the node is created
+    id: Option<Uuid>,
+    #[serde(default)]
+    external_ids: HashMap<String, String>,
+}
+
+// Creates the node with this label or updates it, so sync scripts can
+// declare the nodes they want without looking up ids first
+#[allow(clippy::too_many_arguments)]
+async fn upsert_node_by_label(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    change: Change,
+    axum::extract::Path((_, label)): axum::extract::Path<(String, String)>,
+    Query(params): Query<UpsertNodeParams>,
+    Json(payload): Json<UpsertNodePayload>,
+) -> Result<Response, AppError> {
+    if !external_ids_valid(&payload.external_ids) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    let new_id = payload.id.unwrap_or_else(ids::new_id);
+    let external_ids = sqlx::types::Json(payload.external_ids);
+    let merged = match params.merge {
+        MergeStrategy::Merge => "external_ids || $2",
+        MergeStrategy::Keep => "$2 || external_ids",
+        MergeStrategy::Replace => "$2",
+    };
+    let update = format!(
+        "UPDATE nodes SET external_ids = {} WHERE id = $1
+         RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
+        merged
+    );
+    let (label, external_ids, update, locale, change) = (&label, &external_ids, &update, &locale, &change);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let (node, created) = match nodes_labelled(&mut *tx, dag_id, label).await?.as_slice() {
+            [] => {
+                vocabulary::check_labels(&mut *tx, locale, [label.as_str()]).await?;
+                let slug = slugs::node_slug(&mut *tx, dag_id, label, new_id).await?;
+                let node = sqlx::query_as::<_, Node>(
+                    "INSERT INTO nodes (id, dag_id, label, slug, external_ids, created_by, reason) VALUES ($1, $2, $3, $4, $5, $6, $7)
+                     RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
+                )
+                    .bind(new_id)
+                    .bind(dag_id)
+                    .bind(label)
+                    .bind(slug)
+                    .bind(external_ids)
+                    .bind(&change.created_by)
+                    .bind(&change.reason)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(|e| taken_or(e, locale, new_id))?;
+                (node, true)
+            }
+            [id] => {
+                let node = sqlx::query_as::<_, Node>(update)
+                    .bind(id)
+                    .bind(external_ids)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                (node, false)
+            }
+            ids => return Err(ambiguous_label(locale, label, ids)),
+        };
+        Ok((node, created, warning))
+    }))
+    .await;
+
+    let (node, created, warning) = result.map_err(|e| e.respond(locale, "upsert_node_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    let kind = if created { "node_created" } else { "node_updated" };
+    events::publish(dag_id, kind, serde_json::json!({ "node": &node }));
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok(with_warning((status, Json(node)).into_response(), warning))
+}
+
+// What a filter over the nodes of a DAG may refer to
+const NODE_FILTER_FIELDS: &[(&str, &str)] = &[
+    ("label", "label"),
+    ("slug", "slug"),
+    ("namespace", "provenance->>'namespace'"),
+    ("source_system", "provenance->>'source_system'"),
+    ("import_id", "provenance->>'import_id'"),
+    ("owner", "owner"),
+    ("node_type", "node_type"),
+    ("state", "state"),
+];
+
+#[derive(Deserialize)]
+struct DeleteNodesParams {
+    filter: String,
+    // Without confirm=true nothing is deleted; the response only tells what would be
+    #[serde(default)]
+    confirm: bool,
+}
+
+// Deletes the nodes of a DAG matching a filter, together with every edge
+// touching them
+async fn delete_nodes(
+    writable_service: Result<Writable, Response>,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<DeleteNodesParams>,
+) -> Result<Response, AppError> {
+    if params.confirm {
+        if let Err(response) = writable_service {
+            return Ok(response);
+        }
+    }
+    let filter = filter::parse(&params.filter, NODE_FILTER_FIELDS, 2).map_err(|e| e.error(&locale))?;
+
+    let matching = format!("SELECT id FROM nodes WHERE dag_id = $1 AND ({})", filter.sql);
+    let (filter, matching, confirm, locale) = (&filter, &matching, params.confirm, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // A dry run may look into an archived DAG
+        let warning = match lock_dag(&mut *tx, dag_id).await? {
+            Some(_) if !confirm => None,
+            dag => writable(dag, locale, dag_id).map_err(TxError::Rejected)?,
+        };
+        let mut query = sqlx::query_scalar::<_, Uuid>(matching).bind(dag_id);
+        for value in &filter.values {
+            query = query.bind(value);
+        }
+        let node_ids = query.fetch_all(&mut *tx).await?;
+
+        let edge_ids: Vec<Uuid> = if confirm {
+            let edge_ids = sqlx::query_scalar(
+                "DELETE FROM edges WHERE source = ANY($1) OR target = ANY($1) RETURNING id",
+            )
+                .bind(&node_ids)
+                .fetch_all(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM nodes WHERE id = ANY($1)")
+                .bind(&node_ids)
+                .execute(&mut *tx)
+                .await?;
+            edge_ids
+        } else {
+            sqlx::query_scalar("SELECT id FROM edges WHERE source = ANY($1) OR target = ANY($1)")
+                .bind(&node_ids)
+                .fetch_all(&mut *tx)
+                .await?
+        };
+        Ok((node_ids, edge_ids, warning))
+    }))
+    .await;
+
+    let (node_ids, edge_ids, warning) = result.map_err(|e| e.respond(locale, "delete_nodes_failed"))?;
+    if confirm {
+        usage::record(&pool, dag_id, Access::Edit);
+        events::publish(dag_id, "nodes_deleted", serde_json::json!({ "node_ids": &node_ids, "edge_ids": &edge_ids }));
+    }
+    let body = Json(serde_json::json!({
+        "dry_run": !confirm,
+        "node_count": node_ids.len(),
+        "edge_count": edge_ids.len(),
+        "node_ids": node_ids,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
+
+fn keep_existing() -> MergeStrategy {
+    MergeStrategy::Keep
+}
+
+#[derive(Deserialize)]
+struct MergeNodesPayload {
+    keep: Uuid,
+    remove: Uuid,
+    // How the removed node's external ids join the kept node's; by default
+    // the kept node's win
+    #[serde(default = "keep_existing")]
+    merge: MergeStrategy,
+}
+
+// Folds `remove` into `keep`: its edges move over, edges between the two and
+// edges that would duplicate one `keep` already has are dropped, and the
+// merge is refused if it would close a cycle
+async fn merge_nodes(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<MergeNodesPayload>,
+) -> Result<Response, AppError> {
+    let MergeNodesPayload { keep, remove, merge } = payload;
+    if keep == remove {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "merge_same_node", &[]));
+    }
+
+    let merged = match merge {
+        MergeStrategy::Merge => "n.external_ids || r.external_ids",
+        MergeStrategy::Keep => "r.external_ids || n.external_ids",
+        MergeStrategy::Replace => "r.external_ids",
+    };
+    let update = format!(
+        "UPDATE nodes n SET external_ids = {}, owner = COALESCE(n.owner, r.owner), source_url = COALESCE(n.source_url, r.source_url),
+             task = COALESCE(n.task, r.task), node_type = COALESCE(n.node_type, r.node_type),
+             metadata = CASE WHEN n.metadata = '{{}}' THEN r.metadata ELSE n.metadata END, position = COALESCE(n.position, r.position)
+         FROM nodes r WHERE n.id = $1 AND r.id = $2
+         RETURNING n.id, n.dag_id, n.label, n.slug, n.external_ids, n.provenance, n.owner, n.source_url, n.task, n.metadata, n.node_type, n.position, n.created_by, n.reason, n.state",
+        merged
+    );
+    let (update, locale) = (&update, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let found: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM nodes WHERE dag_id = $1 AND id = ANY($2)")
+            .bind(dag_id)
+            .bind([keep, remove])
+            .fetch_all(&mut *tx)
+            .await?;
+        if let Some(missing) = [keep, remove].into_iter().find(|id| !found.contains(id)) {
+            return Err(TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "node_not_found_in_dag", &[("node", &missing), ("dag", &dag_id)])));
+        }
+
+        let dropped = sqlx::query(
+            "DELETE FROM edges e
+             WHERE (e.source = $1 AND e.target = $2) OR (e.source = $2 AND e.target = $1)
+                OR (e.source = $2 AND EXISTS (SELECT 1 FROM edges f WHERE f.source = $1 AND f.target = e.target))
+                OR (e.target = $2 AND EXISTS (SELECT 1 FROM edges f WHERE f.target = $1 AND f.source = e.source))",
+        )
+            .bind(keep)
+            .bind(remove)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let moved = sqlx::query("UPDATE edges SET source = $1 WHERE source = $2")
+            .bind(keep)
+            .bind(remove)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            + sqlx::query("UPDATE edges SET target = $1 WHERE target = $2")
+                .bind(keep)
+                .bind(remove)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+
+        // A path between the two through other nodes becomes a cycle
+        let cyclic: bool = sqlx::query_scalar(
+            "WITH RECURSIVE reachable(id) AS (
+                 SELECT target FROM edges WHERE source = $1
+                 UNION
+                 SELECT e.target FROM edges e JOIN reachable r ON e.source = r.id
+             )
+             SELECT EXISTS (SELECT 1 FROM reachable WHERE id = $1)",
+        )
+            .bind(keep)
+            .fetch_one(&mut *tx)
+            .await?;
+        if cyclic {
+            return Err(TxError::Rejected(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "merge_creates_cycle", &[("keep", &keep), ("remove", &remove)])));
+        }
+
+        let node = sqlx::query_as::<_, Node>(update)
+            .bind(keep)
+            .bind(remove)
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE id = $1")
+            .bind(remove)
+            .execute(&mut *tx)
+            .await?;
+        Ok((node, moved, dropped, warning))
+    }))
+    .await;
+
+    let (node, moved, dropped, warning) = result.map_err(|e| e.respond(locale, "merge_nodes_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "nodes_merged", serde_json::json!({ "node": &node, "removed": remove }));
+    let body = Json(serde_json::json!({
+        "node": node,
+        "removed": remove,
+        "edges_moved": moved,
+        "edges_dropped": dropped,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
+
+// Which of the two nodes of a split takes over something of the original
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SplitSide {
+    First,
+    Second,
+    Both,
+}
+
+impl SplitSide {
+    fn sides(self) -> &'static [usize] {
+        match self {
+            SplitSide::First => &[0],
+            SplitSide::Second => &[1],
+            SplitSide::Both => &[0, 1],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SplitPart {
+    id: Option<Uuid>,
+    label: String,
+}
+
+fn split_first() -> SplitSide {
+    SplitSide::First
+}
+
+fn split_second() -> SplitSide {
+    SplitSide::Second
+}
+
+fn yes() -> bool {
+    true
+}
+
+// By default the first part takes the incoming edges and the external ids,
+// the second the outgoing edges, and the first leads into the second
+#[derive(Deserialize)]
+struct SplitNodePayload {
+    first: SplitPart,
+    second: SplitPart,
+    #[serde(default = "split_first")]
+    incoming: SplitSide,
+    #[serde(default = "split_second")]
+    outgoing: SplitSide,
+    #[serde(default = "split_first")]
+    external_ids: SplitSide,
+    #[serde(default = "yes")]
+    chain: bool,
+}
+
+// Replaces a node with two. Contracting the two again gives back the original
+// graph, so a split can't introduce a cycle.
+async fn split_node(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    axum::extract::Path((_, node)): axum::extract::Path<(String, String)>,
+    change: Change,
+    Json(payload): Json<SplitNodePayload>,
+) -> Result<Response, AppError> {
+    let part_ids = [
+        payload.first.id.unwrap_or_else(ids::new_id),
+        payload.second.id.unwrap_or_else(ids::new_id),
+    ];
+    if part_ids[0] == part_ids[1] {
+        return Err(id_taken(&locale, part_ids[0]));
     }
+
+    let (node, payload, locale, change) = (&node, &payload, &locale, &change);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let Some(original) = sqlx::query_as::<_, Node>(
+            "SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes
+             WHERE dag_id = $1 AND (id::text = $2 OR slug = $2)",
+        )
+            .bind(dag_id)
+            .bind(node)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Err(TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "node_not_found_in_dag", &[("node", node), ("dag", &dag_id)])));
+        };
+        vocabulary::check_labels(&mut *tx, locale, [payload.first.label.as_str(), payload.second.label.as_str()]).await?;
+
+        let old_edges = sqlx::query_as::<_, Edge>(
+            "DELETE FROM edges WHERE source = $1 OR target = $1 RETURNING id, source, target, dag_id, created_by, reason",
+        )
+            .bind(original.id)
+            .fetch_all(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE id = $1")
+            .bind(original.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut parts = Vec::with_capacity(2);
+        for (i, part) in [&payload.first, &payload.second].into_iter().enumerate() {
+            let external_ids = if payload.external_ids.sides().contains(&i) {
+                original.external_ids.clone()
+            } else {
+                sqlx::types::Json(HashMap::new())
+            };
+            let slug = slugs::node_slug(&mut *tx, dag_id, &part.label, part_ids[i]).await?;
+            let node = sqlx::query_as::<_, Node>(
+                "INSERT INTO nodes (id, dag_id, label, slug, external_ids, owner, source_url, metadata, node_type, created_by, reason, state)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                 RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
+            )
+                .bind(part_ids[i])
+                .bind(dag_id)
+                .bind(&part.label)
+                .bind(slug)
+                .bind(external_ids)
+                .bind(&original.owner)
+                .bind(&original.source_url)
+                .bind(&original.metadata)
+                .bind(&original.node_type)
+                .bind(&change.created_by)
+                .bind(&change.reason)
+                .bind(original.state)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| taken_or(e, locale, part_ids[i]))?;
+            parts.push(node);
+        }
+
+        // An edge handed to both parts keeps its id on the first
+        let mut edges = Vec::new();
+        for edge in old_edges {
+            let (side, incoming) = if edge.target == original.id {
+                (payload.incoming, true)
+            } else {
+                (payload.outgoing, false)
+            };
+            for (n, &i) in side.sides().iter().enumerate() {
+                let (source, target) = if incoming { (edge.source, part_ids[i]) } else { (part_ids[i], edge.target) };
+                let moved = if n == 0 {
+                    Edge { id: edge.id, source, target, dag_id: edge.dag_id, created_by: edge.created_by.clone(), reason: edge.reason.clone() }
+                } else {
+                    Edge { id: ids::new_id(), source, target, dag_id: edge.dag_id, created_by: change.created_by.clone(), reason: change.reason.clone() }
+                };
+                edges.push(moved);
+            }
+        }
+        if payload.chain {
+            edges.push(Edge {
+                id: ids::new_id(),
+                source: part_ids[0],
+                target: part_ids[1],
+                dag_id,
+                created_by: change.created_by.clone(),
+                reason: change.reason.clone(),
+            });
+        }
+        sqlx::query(
+            "INSERT INTO edges (id, source, target, dag_id, created_by, reason)
+             SELECT * FROM UNNEST($1::uuid[], $2::uuid[], $3::uuid[], $4::uuid[], $5::text[], $6::text[])",
+        )
+            .bind(edges.iter().map(|e| e.id).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.source).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.target).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.dag_id).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.created_by.as_deref()).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.reason.as_deref()).collect::<Vec<_>>())
+            .execute(&mut *tx)
+            .await?;
+        Ok((original.id, parts, edges, warning))
+    }))
+    .await;
+
+    let (replaced, parts, edges, warning) = result.map_err(|e| e.respond(locale, "split_node_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "node_split", serde_json::json!({ "replaced": replaced, "nodes": &parts, "edges": &edges }));
+    let body = Json(serde_json::json!({
+        "replaced": replaced,
+        "nodes": parts,
+        "edges": edges,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
+
+#[derive(Deserialize)]
+struct RelabelPayload {
+    find: String,
+    replace: String,
+    // A POSIX regular expression as Postgres understands it; the replacement
+    // may refer to groups as \1, \2, ...
+    #[serde(default)]
+    regex: bool,
+    // Limits the relabeling to nodes matching this filter
+    filter: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize, FromRow)]
+struct Relabel {
+    id: Uuid,
+    old_label: String,
+    new_label: String,
+}
+
+// Find/replace over the labels of a DAG. Slugs stay as they are so existing
+// links keep working.
+async fn relabel_nodes(
+    writable_service: Result<Writable, Response>,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<RelabelPayload>,
+) -> Result<Response, AppError> {
+    if !payload.dry_run {
+        if let Err(response) = writable_service {
+            return Ok(response);
+        }
+    }
+    if payload.find.is_empty() {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "relabel_empty_find", &[]));
+    }
+    let filter = payload
+        .filter
+        .as_deref()
+        .map(|f| filter::parse(f, NODE_FILTER_FIELDS, 4))
+        .transpose()
+        .map_err(|e| e.error(&locale))?;
+
+    let (replaced, matches) = if payload.regex {
+        ("regexp_replace(label, $2, $3, 'g')", "label ~ $2")
+    } else {
+        ("replace(label, $2, $3)", "strpos(label, $2) > 0")
+    };
+    let query = format!(
+        "SELECT id, old_label, new_label FROM (
+             SELECT id, label AS old_label, {} AS new_label FROM nodes
+             WHERE dag_id = $1 AND {} AND ({})
+         ) changed
+         WHERE old_label <> new_label
+         ORDER BY old_label, id",
+        replaced,
+        matches,
+        filter.as_ref().map_or("TRUE", |f| f.sql.as_str())
+    );
+    let (payload, filter, query, locale) = (&payload, &filter, &query, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // A dry run may look into an archived DAG
+        let warning = match lock_dag(&mut *tx, dag_id).await? {
+            Some(_) if payload.dry_run => None,
+            dag => writable(dag, locale, dag_id).map_err(TxError::Rejected)?,
+        };
+        let mut select = sqlx::query_as::<_, Relabel>(query)
+            .bind(dag_id)
+            .bind(&payload.find)
+            .bind(&payload.replace);
+        for value in filter.iter().flat_map(|f| &f.values) {
+            select = select.bind(value);
+        }
+        let changes = select.fetch_all(&mut *tx).await.map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some("2201B") => TxError::Rejected(
+                locale.error(StatusCode::UNPROCESSABLE_ENTITY, "relabel_invalid_regex", &[("error", &db.message())]),
+            ),
+            _ => TxError::Database(e),
+        })?;
+
+        if let Some(blank) = changes.iter().find(|c| c.new_label.trim().is_empty()) {
+            return Err(TxError::Rejected(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "relabel_blank_label", &[("label", &blank.old_label)])));
+        }
+        vocabulary::check_labels(&mut *tx, locale, changes.iter().map(|c| c.new_label.as_str())).await?;
+        if !payload.dry_run {
+            sqlx::query(
+                "UPDATE nodes SET label = u.label FROM UNNEST($1::uuid[], $2::text[]) AS u(id, label)
+                 WHERE nodes.id = u.id",
+            )
+                .bind(changes.iter().map(|c| c.id).collect::<Vec<_>>())
+                .bind(changes.iter().map(|c| c.new_label.as_str()).collect::<Vec<_>>())
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok((changes, warning))
+    }))
+    .await;
+
+    let (changes, warning) = result.map_err(|e| e.respond(locale, "relabel_nodes_failed"))?;
+    if !payload.dry_run {
+        usage::record(&pool, dag_id, Access::Edit);
+        events::publish(dag_id, "nodes_relabeled", serde_json::json!({ "changes": &changes }));
+    }
+    let body = Json(serde_json::json!({
+        "dry_run": payload.dry_run,
+        "changes": changes,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
+
+// Every cleanup is on unless switched off
+#[derive(Deserialize)]
+struct NormalizePayload {
+    #[serde(default = "yes")]
+    trim_labels: bool,
+    #[serde(default = "yes")]
+    collapse_duplicate_edges: bool,
+    #[serde(default = "yes")]
+    drop_self_loops: bool,
+    // Removes nodes without edges whose label is blank or matches
+    // placeholder_pattern (LIKE syntax)
+    #[serde(default = "yes")]
+    remove_isolated_placeholders: bool,
+    placeholder_pattern: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+const LEADING_OR_TRAILING_SPACE: &str = r"^\s+|\s+$";
+
+// Post-import hygiene for a DAG's own nodes and edges. Reports what it
+// changed, or with dry_run what it would change.
+async fn normalize_dag(
+    writable_service: Result<Writable, Response>,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<NormalizePayload>,
+) -> Result<Response, AppError> {
+    if !payload.dry_run {
+        if let Err(response) = writable_service {
+            return Ok(response);
+        }
+    }
+
+    // Runs `selection` (a list of ids) as is for a dry run, or deletes what
+    // it selects from `table`
+    let dry_run = payload.dry_run;
+    let removal = move |table: &str, selection: &str| {
+        if dry_run {
+            selection.to_string()
+        } else {
+            format!("DELETE FROM {} WHERE id IN ({}) RETURNING id", table, selection)
+        }
+    };
+    let duplicates = removal(
+        "edges",
+        "SELECT id FROM (
+             SELECT id, row_number() OVER (PARTITION BY source, target ORDER BY id) AS copy
+             FROM edges WHERE dag_id = $1
+         ) copies WHERE copy > 1",
+    );
+    let self_loops = removal("edges", "SELECT id FROM edges WHERE dag_id = $1 AND source = target");
+    // Self loops about to be dropped don't count as edges
+    let placeholders = removal(
+        "nodes",
+        &format!(
+            "SELECT id FROM nodes n WHERE dag_id = $1
+               AND (regexp_replace(label, $2, '', 'g') = '' OR label LIKE $3)
+               AND NOT EXISTS (SELECT 1 FROM edges e WHERE (e.source = n.id OR e.target = n.id){})",
+            if payload.drop_self_loops { " AND e.source <> e.target" } else { "" }
+        ),
+    );
+
+    let (payload, duplicates, self_loops, placeholders, locale) =
+        (&payload, &duplicates, &self_loops, &placeholders, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // A dry run may look into an archived DAG
+        let warning = match lock_dag(&mut *tx, dag_id).await? {
+            Some(_) if payload.dry_run => None,
+            dag => writable(dag, locale, dag_id).map_err(TxError::Rejected)?,
+        };
+
+        let mut trimmed = Vec::new();
+        if payload.trim_labels {
+            trimmed = sqlx::query_as::<_, Relabel>(
+                "SELECT id, label AS old_label, regexp_replace(label, $2, '', 'g') AS new_label
+                 FROM nodes WHERE dag_id = $1 AND label ~ $2 ORDER BY label, id",
+            )
+                .bind(dag_id)
+                .bind(LEADING_OR_TRAILING_SPACE)
+                .fetch_all(&mut *tx)
+                .await?;
+            if !payload.dry_run {
+                sqlx::query("UPDATE nodes SET label = regexp_replace(label, $2, '', 'g') WHERE dag_id = $1 AND label ~ $2")
+                    .bind(dag_id)
+                    .bind(LEADING_OR_TRAILING_SPACE)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        let mut removed: [Vec<Uuid>; 3] = Default::default();
+        let steps = [
+            (payload.collapse_duplicate_edges, duplicates),
+            (payload.drop_self_loops, self_loops),
+            (payload.remove_isolated_placeholders, placeholders),
+        ];
+        for (i, (enabled, query)) in steps.into_iter().enumerate() {
+            if enabled {
+                removed[i] = sqlx::query_scalar(query)
+                    .bind(dag_id)
+                    .bind(LEADING_OR_TRAILING_SPACE)
+                    .bind(payload.placeholder_pattern.as_deref())
+                    .fetch_all(&mut *tx)
+                    .await?;
+            }
+        }
+        Ok((trimmed, removed, warning))
+    }))
+    .await;
+
+    let (trimmed, [duplicates, self_loops, placeholders], warning) = result.map_err(|e| e.respond(locale, "normalize_failed"))?;
+    if !payload.dry_run {
+        usage::record(&pool, dag_id, Access::Edit);
+        events::publish(dag_id, "graph_changed", serde_json::json!({ "cause": "normalize" }));
+    }
+    let body = Json(serde_json::json!({
+        "dry_run": payload.dry_run,
+        "trimmed_labels": trimmed,
+        "duplicate_edges": duplicates,
+        "self_loops": self_loops,
+        "placeholder_nodes": placeholders,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
+
+// Every node, across DAGs, that carries the given id for a system
+async fn nodes_by_external_id(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path((system, external_id)): axum::extract::Path<(String, String)>,
+) -> Result<Response, AppError> {
+    let nodes = sqlx::query_as::<_, Node>(&format!(
+        "SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes WHERE external_ids @> $1 AND {} ORDER BY dag_id, label",
+        auth::visible("dag_id", 2)
+    ))
+        .bind(sqlx::types::Json(HashMap::from([(system, external_id)])))
+        .bind(auth::owner())
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?;
+    Ok(Json(nodes).into_response())
+}
+
+// Narrows GET /nodes to what a given import brought in, e.g. to clean up
+// after a bad one
+#[derive(Serialize, Deserialize)]
+struct NodeFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    import_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+}
+
+async fn list_nodes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(filter): Query<NodeFilter>,
+) -> Result<Response, AppError> {
+    let nodes = sqlx::query_as::<_, Node>(&format!(
+        "SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes
+         WHERE ($1::jsonb = '{{}}' OR provenance @> $1) AND {}",
+        auth::visible("dag_id", 2)
+    ))
+        .bind(sqlx::types::Json(filter))
+        .bind(auth::owner())
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?;
+    Ok(Json(nodes).into_response())
 }
 
 // CRUD Handlers for Edge
 async fn create_edge(
-    Extension(pool): Extension<PgPool>,
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
     Json(payload): Json<CreateEdgePayload>,
-) -> impl IntoResponse {
-    let id = Uuid::new_v4();
+) -> Result<Response, AppError> {
     let edge = Edge {
-        id,
+        id: payload.id.unwrap_or_else(ids::new_id),
         source: payload.source,
         target: payload.target,
         dag_id: payload.dag_id,
+        created_by: change.created_by,
+        reason: change.reason,
     };
 
-    match sqlx::query!(
-        "INSERT INTO edges (id, source, target, dag_id) VALUES ($1, $2, $3, $4)",
+    let (edge, locale) = (&edge, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, edge.dag_id).await?, locale, edge.dag_id)
+            .map_err(TxError::Rejected)?;
+        add_edge(&mut *tx, edge, locale).await?;
+        Ok((warning, deprecated_target(&mut *tx, edge, locale).await?))
+    }))
+    .await;
+
+    let (warning, deprecated) = result.map_err(|e| e.respond(locale, "create_edge_failed"))?;
+    usage::record(&pool, edge.dag_id, Access::Edit);
+    events::publish(edge.dag_id, "edge_created", serde_json::json!({ "edge": edge }));
+    Ok(with_warning(with_warning(Json(edge).into_response(), warning), deprecated))
+}
+
+// Inserts an edge unless it would close a cycle. Callers must hold the DAG
+// lock until the commit, which keeps two concurrent inserts from each passing
+// the check and closing a cycle together.
+async fn add_edge(tx: &mut sqlx::PgConnection, edge: &Edge, locale: &Locale) -> Result<(), TxError> {
+    check_endpoints(&mut *tx, edge, locale).await?;
+    if creates_cycle(&mut *tx, edge.source, edge.target).await? {
+        return Err(TxError::Rejected(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "edge_creates_cycle", &[("source", &edge.source), ("target", &edge.target)])));
+    }
+    sqlx::query!(
+        "INSERT INTO edges (id, source, target, dag_id, created_by, reason) VALUES ($1, $2, $3, $4, $5, $6)",
         edge.id,
         edge.source,
         edge.target,
-        edge.dag_id
+        edge.dag_id,
+        edge.created_by,
+        edge.reason
     )
-        .execute(&pool)
+        .execute(&mut *tx)
         .await
-    {
-        Ok(_) => Json(edge).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create Edge: {}", e),
-        ).into_response(),
+        .map_err(|e| taken_or(e, locale, edge.id))?;
+    Ok(())
+}
+
+// Both ends of an edge must be nodes of the edge's DAG, and not the same one.
+// The edges table enforces this too; checking first answers with which end
+// is wrong. Nodes in DAGs the caller may not see count as missing.
+async fn check_endpoints(tx: &mut sqlx::PgConnection, edge: &Edge, locale: &Locale) -> Result<(), TxError> {
+    let found: Vec<(Uuid, Option<Uuid>)> = sqlx::query_as(&format!(
+        "SELECT id, dag_id FROM nodes WHERE id = ANY($1) AND {}",
+        auth::visible("dag_id", 2)
+    ))
+        .bind([edge.source, edge.target])
+        .bind(auth::owner())
+        .fetch_all(&mut *tx)
+        .await?;
+    let dag_of = |id: Uuid| found.iter().find(|(node, _)| *node == id).map(|(_, dag)| *dag);
+    let details = serde_json::json!({
+        "dag_id": edge.dag_id,
+        "source": { "id": edge.source, "dag_id": dag_of(edge.source).flatten() },
+        "target": { "id": edge.target, "dag_id": dag_of(edge.target).flatten() },
+    });
+    if edge.source == edge.target {
+        let error = locale.error(StatusCode::UNPROCESSABLE_ENTITY, "edge_self_loop", &[("id", &edge.source)]);
+        return Err(TxError::Rejected(error.with_details(details)));
+    }
+    for id in [edge.source, edge.target] {
+        let error = match dag_of(id) {
+            None => locale.error(StatusCode::UNPROCESSABLE_ENTITY, "edge_node_missing", &[("id", &id)]),
+            Some(dag) if dag != Some(edge.dag_id) => {
+                locale.error(StatusCode::UNPROCESSABLE_ENTITY, "edge_node_in_other_dag", &[("id", &id), ("dag_id", &edge.dag_id)])
+            }
+            Some(_) => continue,
+        };
+        return Err(TxError::Rejected(error.with_details(details)));
+    }
+    Ok(())
+}
+
+// The warning for an edge into a deprecated node. Edges out of drafts are
+// work in progress and get none.
+async fn deprecated_target(tx: &mut sqlx::PgConnection, edge: &Edge, locale: &Locale) -> Result<Option<HeaderValue>, sqlx::Error> {
+    let target: Option<String> = sqlx::query_scalar(
+        "SELECT t.label FROM nodes t, nodes s
+         WHERE t.id = $2 AND s.id = $1 AND t.state = 'deprecated' AND s.state <> 'draft'",
+    )
+        .bind(edge.source)
+        .bind(edge.target)
+        .fetch_optional(&mut *tx)
+        .await?;
+    Ok(target.and_then(|label| warning_header(&locale.t("node_deprecated_target", &[("id", &edge.target), ("label", &label)]))))
+}
+
+#[derive(Deserialize)]
+struct CreateEdgeByLabelPayload {
+    id: Option<Uuid>,
+    source_label: String,
+    target_label: String,
+}
+
+async fn nodes_labelled(tx: &mut sqlx::PgConnection, dag_id: Uuid, label: &str) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM nodes WHERE dag_id = $1 AND label = $2 ORDER BY id")
+        .bind(dag_id)
+        .bind(label)
+        .fetch_all(tx)
+        .await
+}
+
+fn ambiguous_label(locale: &Locale, label: &str, ids: &[Uuid]) -> TxError {
+    let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+    TxError::Rejected(locale.error(StatusCode::CONFLICT, "node_label_ambiguous", &[("label", &label), ("ids", &ids.join(", "))]))
+}
+
+// The one node of a DAG with the given label
+async fn node_by_label(tx: &mut sqlx::PgConnection, dag_id: Uuid, label: &str, locale: &Locale) -> Result<Uuid, TxError> {
+    match nodes_labelled(tx, dag_id, label).await?.as_slice() {
+        [id] => Ok(*id),
+        [] => Err(TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "node_label_not_found", &[("label", &label)]))),
+        ids => Err(ambiguous_label(locale, label, ids)),
+    }
+}
+
+// For scripts that know the labels of the nodes but not their ids
+async fn create_edge_by_label(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    change: Change,
+    Json(payload): Json<CreateEdgeByLabelPayload>,
+) -> Result<Response, AppError> {
+    let id = payload.id.unwrap_or_else(ids::new_id);
+    let (payload, locale, change) = (&payload, &locale, &change);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let edge = Edge {
+            id,
+            source: node_by_label(&mut *tx, dag_id, &payload.source_label, locale).await?,
+            target: node_by_label(&mut *tx, dag_id, &payload.target_label, locale).await?,
+            dag_id,
+            created_by: change.created_by.clone(),
+            reason: change.reason.clone(),
+        };
+        add_edge(&mut *tx, &edge, locale).await?;
+        let deprecated = deprecated_target(&mut *tx, &edge, locale).await?;
+        Ok((edge, warning, deprecated))
+    }))
+    .await;
+
+    let (edge, warning, deprecated) = result.map_err(|e| e.respond(locale, "create_edge_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "edge_created", serde_json::json!({ "edge": &edge }));
+    Ok(with_warning(with_warning(Json(edge).into_response(), warning), deprecated))
+}
+
+#[derive(Deserialize)]
+struct BatchEdgePayload {
+    id: Option<Uuid>,
+    source: Uuid,
+    target: Uuid,
+}
+
+// Points a rejection at the edge of a batch that caused it
+fn at_index(e: TxError, index: usize) -> TxError {
+    match e {
+        TxError::Rejected(error) => TxError::Rejected(error.with_detail("index", serde_json::json!(index))),
+        e => e,
     }
 }
 
-async fn list_edges(Extension(pool): Extension<PgPool>) -> impl IntoResponse {
-    match sqlx::query_as::<_, Edge>("SELECT id, source, target, dag_id FROM edges")
+// Creates all the edges or none. Each is checked like a single one, against
+// the ones before it, so a cycle closed only by the batch as a whole is
+// refused too; an error names the edge by its index in the batch.
+async fn create_edges_batch(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    change: Change,
+    Json(payload): Json<Vec<BatchEdgePayload>>,
+) -> Result<Response, AppError> {
+    let edges: Vec<Edge> = payload
+        .into_iter()
+        .map(|e| Edge {
+            id: e.id.unwrap_or_else(ids::new_id),
+            source: e.source,
+            target: e.target,
+            dag_id,
+            created_by: change.created_by.clone(),
+            reason: change.reason.clone(),
+        })
+        .collect();
+
+    let (edges, locale) = (&edges, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let mut warnings = vec![warning];
+        for (index, edge) in edges.iter().enumerate() {
+            add_edge(&mut *tx, edge, locale).await.map_err(|e| at_index(e, index))?;
+            warnings.push(deprecated_target(&mut *tx, edge, locale).await?);
+        }
+        Ok(warnings)
+    }))
+    .await;
+
+    let warnings = result.map_err(|e| e.respond(locale, "create_edge_failed"))?;
+    if !edges.is_empty() {
+        usage::record(&pool, dag_id, Access::Edit);
+        events::publish(dag_id, "edges_created", serde_json::json!({ "edges": edges }));
+    }
+    Ok(warnings.into_iter().fold(Json(edges).into_response(), with_warning))
+}
+
+#[derive(Deserialize)]
+struct InsertNodePayload {
+    id: Option<Uuid>,
+    label: String,
+    #[serde(default)]
+    external_ids: HashMap<String, String>,
+}
+
+// Replaces A -> B with A -> N -> B for a new node N in the edge's DAG.
+// Subdividing an edge can't introduce a cycle.
+async fn insert_node_on_edge(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(edge_id): axum::extract::Path<Uuid>,
+    change: Change,
+    Json(payload): Json<InsertNodePayload>,
+) -> Result<Response, AppError> {
+    if !external_ids_valid(&payload.external_ids) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_external_id", &[]));
+    }
+
+    let node_id = payload.id.unwrap_or_else(ids::new_id);
+    let external_ids = sqlx::types::Json(payload.external_ids);
+    let (label, external_ids, locale, change) = (&payload.label, &external_ids, &locale, &change);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let (dag_id, warning) = lock_edge_dag(&mut *tx, edge_id, locale).await?;
+        let edge = sqlx::query_as::<_, Edge>("DELETE FROM edges WHERE id = $1 RETURNING id, source, target, dag_id, created_by, reason")
+            .bind(edge_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        vocabulary::check_labels(&mut *tx, locale, [label.as_str()]).await?;
+
+        let slug = slugs::node_slug(&mut *tx, dag_id, label, node_id).await?;
+        let node = sqlx::query_as::<_, Node>(
+            "INSERT INTO nodes (id, dag_id, label, slug, external_ids, created_by, reason) VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state",
+        )
+            .bind(node_id)
+            .bind(dag_id)
+            .bind(label)
+            .bind(slug)
+            .bind(external_ids)
+            .bind(&change.created_by)
+            .bind(&change.reason)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| taken_or(e, locale, node_id))?;
+        let (created_by, reason) = (&change.created_by, &change.reason);
+        let edges = [
+            Edge { id: ids::new_id(), source: edge.source, target: node_id, dag_id, created_by: created_by.clone(), reason: reason.clone() },
+            Edge { id: ids::new_id(), source: node_id, target: edge.target, dag_id, created_by: created_by.clone(), reason: reason.clone() },
+        ];
+        for edge in &edges {
+            sqlx::query!(
+                "INSERT INTO edges (id, source, target, dag_id, created_by, reason) VALUES ($1, $2, $3, $4, $5, $6)",
+                edge.id,
+                edge.source,
+                edge.target,
+                edge.dag_id,
+                edge.created_by,
+                edge.reason
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok((node, edges, warning))
+    }))
+    .await;
+
+    let (node, edges, warning) = result.map_err(|e| e.respond(locale, "insert_node_failed"))?;
+    usage::record(&pool, node.dag_id, Access::Edit);
+    events::publish(node.dag_id, "edge_split", serde_json::json!({ "replaced": edge_id, "node": &node, "edges": &edges }));
+    let body = Json(serde_json::json!({
+        "replaced": edge_id,
+        "node": node,
+        "edges": edges,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
+
+async fn list_edges(State(pool): State<PgPool>, locale: Locale) -> Result<Response, AppError> {
+    let edges = sqlx::query_as::<_, Edge>(&format!(
+        "SELECT id, source, target, dag_id, created_by, reason FROM edges WHERE {}",
+        auth::visible("dag_id", 1)
+    ))
+        .bind(auth::owner())
         .fetch_all(&pool)
         .await
-    {
-        Ok(edges) => Json(edges).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch Edges: {}", e),
-        ).into_response(),
+        .map_err(|e| AppError::database(e, &locale, "fetch_edges_failed"))?;
+    Ok(Json(edges).into_response())
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize)]
+struct PageParams {
+    // Case-insensitive substring of the node's label, or of either endpoint's
+    // label for edges
+    label: Option<String>,
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+impl PageParams {
+    fn pattern(&self) -> String {
+        like_pattern(self.label.as_deref().unwrap_or_default())
     }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn offset(&self) -> i64 {
+        self.offset.max(0)
+    }
+}
+
+// A page of rows with the number of rows across all pages in X-Total-Count
+fn page_response<T: Serialize>(rows: Vec<T>, total: i64, warning: Option<HeaderValue>) -> Response {
+    let mut response = Json(rows).into_response();
+    response.headers_mut().insert("x-total-count", HeaderValue::from(total));
+    with_warning(response, warning)
+}
+
+// Pages of a DAG's nodes, ordered by label
+async fn list_dag_nodes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<PageParams>,
+) -> Result<Response, AppError> {
+    let dag = find_dag(&pool, &locale, dag_id).await?;
+
+    let pattern = params.pattern();
+    let page = async {
+        let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM nodes WHERE dag_id = $1 AND label ILIKE $2")
+            .bind(dag_id)
+            .bind(&pattern)
+            .fetch_one(&pool)
+            .await?;
+        let nodes = sqlx::query_as::<_, Node>(
+            "SELECT id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state FROM nodes
+             WHERE dag_id = $1 AND label ILIKE $2 ORDER BY label, id LIMIT $3 OFFSET $4",
+        )
+            .bind(dag_id)
+            .bind(&pattern)
+            .bind(params.limit())
+            .bind(params.offset())
+            .fetch_all(&pool)
+            .await?;
+        Ok::<_, sqlx::Error>((nodes, total))
+    };
+
+    let (nodes, total) = page.await.map_err(|e| AppError::database(e, &locale, "fetch_nodes_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(page_response(nodes, total, lifecycle_warning(&dag, &locale)))
+}
+
+// Pages of a DAG's edges, ordered by id
+async fn list_dag_edges(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<PageParams>,
+) -> Result<Response, AppError> {
+    let dag = find_dag(&pool, &locale, dag_id).await?;
+
+    let matching = "FROM edges e
+         WHERE e.dag_id = $1
+           AND EXISTS (SELECT 1 FROM nodes n WHERE n.id IN (e.source, e.target) AND n.label ILIKE $2)";
+    let pattern = params.pattern();
+    let page = async {
+        let total = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) {}", matching))
+            .bind(dag_id)
+            .bind(&pattern)
+            .fetch_one(&pool)
+            .await?;
+        let edges = sqlx::query_as::<_, Edge>(&format!(
+            "SELECT e.id, e.source, e.target, e.dag_id, e.created_by, e.reason {} ORDER BY e.id LIMIT $3 OFFSET $4",
+            matching
+        ))
+            .bind(dag_id)
+            .bind(&pattern)
+            .bind(params.limit())
+            .bind(params.offset())
+            .fetch_all(&pool)
+            .await?;
+        Ok::<_, sqlx::Error>((edges, total))
+    };
+
+    let (edges, total) = page.await.map_err(|e| AppError::database(e, &locale, "fetch_edges_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(page_response(edges, total, lifecycle_warning(&dag, &locale)))
+}
+
+async fn get_edge(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(edge_id): axum::extract::Path<Uuid>,
+) -> Result<Response, AppError> {
+    let edge = sqlx::query_as::<_, Edge>(&format!(
+        "SELECT id, source, target, dag_id, created_by, reason FROM edges WHERE id = $1 AND {}",
+        auth::visible("dag_id", 2)
+    ))
+        .bind(edge_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_edges_failed"))?
+        .ok_or_else(|| locale.error(StatusCode::NOT_FOUND, "edge_not_found", &[("id", &edge_id)]))?;
+    let version = preconditions::current(&pool, Entity::Edge, edge_id)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_edges_failed"))?;
+    usage::record(&pool, edge.dag_id, Access::Read);
+    Ok(with_etag(Json(edge).into_response(), version))
+}
+
+// Moves an edge's endpoints. The edge keeps its id and goes through the same
+// cycle check as a new one.
+async fn update_edge(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(edge_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+    Json(payload): Json<UpdateEdgePayload>,
+) -> Result<Response, AppError> {
+    let (payload, if_match, locale) = (&payload, &if_match, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let (_, warning) = lock_edge_dag(&mut *tx, edge_id, locale).await?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Edge, edge_id).await?;
+        let old = sqlx::query_as::<_, Edge>("DELETE FROM edges WHERE id = $1 RETURNING id, source, target, dag_id, created_by, reason")
+            .bind(edge_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let retargeted = payload.target.is_some_and(|target| target != old.target);
+        let edge = Edge {
+            source: payload.source.unwrap_or(old.source),
+            target: payload.target.unwrap_or(old.target),
+            ..old
+        };
+        add_edge(&mut *tx, &edge, locale).await?;
+        // Only an edge moved onto a deprecated node is new to it
+        let deprecated = if retargeted { deprecated_target(&mut *tx, &edge, locale).await? } else { None };
+        Ok((edge, warning, deprecated, preconditions::current(&mut *tx, Entity::Edge, edge_id).await?))
+    }))
+    .await;
+
+    let (edge, warning, deprecated, version) = result.map_err(|e| e.respond(locale, "update_edge_failed"))?;
+    usage::record(&pool, edge.dag_id, Access::Edit);
+    events::publish(edge.dag_id, "edge_updated", serde_json::json!({ "edge": &edge }));
+    Ok(with_etag(with_warning(with_warning(Json(edge).into_response(), warning), deprecated), version))
+}
+
+async fn delete_edge(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    axum::extract::Path(edge_id): axum::extract::Path<Uuid>,
+    if_match: IfMatch,
+) -> Result<Response, AppError> {
+    let (if_match, locale) = (&if_match, &locale);
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let (dag_id, warning) = lock_edge_dag(&mut *tx, edge_id, locale).await?;
+        preconditions::check(&mut *tx, locale, if_match, Entity::Edge, edge_id).await?;
+        sqlx::query("DELETE FROM edges WHERE id = $1")
+            .bind(edge_id)
+            .execute(&mut *tx)
+            .await?;
+        Ok((dag_id, warning))
+    }))
+    .await;
+
+    let (dag_id, warning) = result.map_err(|e| e.respond(locale, "delete_edge_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "edge_deleted", serde_json::json!({ "edge_id": edge_id }));
+    Ok(with_warning(StatusCode::NO_CONTENT.into_response(), warning))
 }
 
 // Main Application
 #[tokio::main]
 async fn main() {
-    let pool = setup_database().await;
-
-    let app = Router::new()
-        .route("/dags", post(create_dag).get(list_dags))
-        .route("/dags/:id", axum::routing::get(get_dag_with_details))
-        .route("/nodes", post(create_node).get(list_nodes))
-        .route("/edges", post(create_edge).get(list_edges))
-        .layer(Extension(pool));
-
-    let addr = "127.0.0.1:3000".parse().unwrap();
-    println!("Server running at http://{}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    if let Err(problem) = serve().await {
+        eprintln!("Refusing to start: {}", problem);
+        std::process::exit(1);
+    }
+}
+
+async fn serve() -> Result<(), String> {
+    let config = Config::load()?;
+    telemetry::init(config.log_level);
+    ids::configure(config.id_strategy);
+    connections::configure(config.connections_key.as_deref());
+    export::configure(config.export_concurrency, config.export_bytes_per_sec);
+    imports::configure(config.import_max_bytes, config.import_max_nodes, config.import_max_edges);
+    let pool = PgPoolOptions::new()
+        .max_connections(config.pool_size)
+        .connect(&config.database_url)
         .await
-        .unwrap();
+        .map_err(|e| format!("Failed to connect to database: {}", e))?;
+    schema::check(&pool).await?;
+    logs::configure(&config.task_logs, &pool);
+    artifacts::configure(&config.public_url);
+    let maintenance = Arc::new(Maintenance::from_env());
+    let catalogs = Arc::new(i18n::Catalogs::load()?);
+    jobs::start(&pool, &maintenance);
+    schedules::start(&pool, &maintenance);
+    watchdog::start(&pool, &maintenance);
+    scheduled_changes::start(&pool, &maintenance, &catalogs);
+
+    let api = openapi::Api::new()
+        .post("/dags", "Create a DAG", create_dag)
+        .get("/dags", "List or search DAGs", list_dags)
+        .post("/dags/import", "Create a DAG from nodes and edges in one request", imports::import_dag)
+        .get("/dags/:id", "Get a DAG with its nodes and edges", get_dag_with_details)
+        .put("/dags/:id", "Rename a DAG", update_dag)
+        .delete("/dags/:id", "Delete a DAG with its nodes and edges", delete_dag)
+        .put("/dags/:id/lifecycle", "Set whether a DAG is active, deprecated or archived", update_dag_lifecycle)
+        .get("/dags/:id/nodes", "Page through a DAG's nodes", list_dag_nodes)
+        .delete("/dags/:id/nodes", "Delete the nodes matching a filter", delete_nodes)
+        .post("/dags/:id/clone", "Copy the DAG, or a saved version of it, as a new DAG", clones::clone_dag)
+        .post("/dags/:id/subgraph", "Copy some nodes, optionally with their ancestors or descendants, as a new DAG", clones::extract_subgraph)
+        .post("/dags/:id/normalize", "Tidy labels and drop redundant edges", normalize_dag)
+        .post("/dags/:id/nodes/merge", "Merge nodes into one", merge_nodes)
+        .post("/dags/:id/nodes/relabel", "Find and replace in node labels", relabel_nodes)
+        .get("/dags/:id/nodes/:node", "Get a node of a DAG by id or slug", get_dag_node)
+        .post("/dags/:id/nodes/:node/split", "Split a node in two", split_node)
+        .put("/dags/:id/nodes/by-label/:label", "Create or update a node by label", upsert_node_by_label)
+        .get("/dags/:id/edges", "Page through a DAG's edges", list_dag_edges)
+        .post("/dags/:id/edges/batch", "Create several edges at once, all or none", create_edges_batch)
+        .post("/dags/:id/edges/by-label", "Create an edge between nodes given by label", create_edge_by_label)
+        .get("/dags/:id/ws", "Stream the DAG's change events over a WebSocket", events::dag_socket)
+        .post("/dags/:id/versions", "Save a version of the DAG", versions::create_version)
+        .get("/dags/:id/versions", "List saved versions", versions::list_versions)
+        .get("/dags/:id/versions/:version", "Get a saved version", versions::get_version)
+        .post("/dags/:id/versions/:version/restore", "Restore a saved version", versions::restore_version)
+        .get("/dags/:id/environment", "Which environment the DAG is in and where it was promoted", environments::get_dag_environment)
+        .put("/dags/:id/environment", "Put the DAG in an environment", environments::set_dag_environment)
+        .post("/dags/:id/promotions", "Promote a saved version to the next environment", environments::create_promotion)
+        .get("/dags/:id/promotions", "List promotions of and into the DAG", environments::list_promotions)
+        .get("/promotions/:id", "Get a promotion", environments::get_promotion)
+        .post("/promotions/:id/approve", "Approve and apply a pending promotion", environments::approve_promotion)
+        .post("/promotions/:id/reject", "Reject a pending promotion", environments::reject_promotion)
+        .get("/metrics", "Request, pool and change metrics in the Prometheus text format", telemetry::get_metrics)
+        .get("/environments", "List environments in promotion order", environments::list_environments)
+        .get("/dags/:id/usage", "Daily reads, runs and edits of a DAG", usage::dag_usage)
+        .post("/dags/:id/runs", "Start a run of the DAG", executor::start_run)
+        .get("/dags/:id/runs", "List recent runs", executor::list_runs)
+        .get("/dags/:id/runs/heatmap", "Runs per day for a calendar view", executor::run_heatmap)
+        .post("/dags/:id/schedules", "Schedule runs of the DAG with a cron expression", schedules::create_schedule)
+        .get("/dags/:id/schedules", "List a DAG's schedules", schedules::list_schedules)
+        .get("/schedules/:id", "Get a schedule", schedules::get_schedule)
+        .delete("/schedules/:id", "Delete a schedule", schedules::delete_schedule)
+        .post("/schedules/:id/pause", "Stop a schedule from starting runs", schedules::pause_schedule)
+        .post("/schedules/:id/resume", "Let a paused schedule start runs again", schedules::resume_schedule)
+        .post("/dags/:id/scheduled-changes", "Schedule nodes and edges to be added or removed at a given time", scheduled_changes::create_scheduled_change)
+        .get("/dags/:id/scheduled-changes", "List a DAG's scheduled changes", scheduled_changes::list_dag_scheduled_changes)
+        .get("/scheduled-changes", "List scheduled changes across DAGs", scheduled_changes::list_scheduled_changes)
+        .get("/scheduled-changes/:id", "Get a scheduled change", scheduled_changes::get_scheduled_change)
+        .delete("/scheduled-changes/:id", "Cancel a pending scheduled change", scheduled_changes::cancel_scheduled_change)
+        .get("/runs", "List runs of every DAG, e.g. the stalled ones", executor::list_all_runs)
+        .get("/runs/:id", "Get a run with the state of each node", executor::get_run)
+        .get("/runs/:id/wait", "Wait for a run to change state", executor::wait_for_run)
+        .get("/runs/:id/tasks/:node_id/log", "Get a task's log from the store that holds it", logs::get_task_log)
+        .get("/runs/:id/outputs", "List what the nodes of a run produced", artifacts::run_outputs)
+        .post("/runs/:id/nodes/:node_id/outputs", "Write outputs of a running node, with its task token", artifacts::write_outputs)
+        .get("/runs/:id/nodes/:node_id/outputs", "List a node's outputs in a run", artifacts::node_outputs)
+        .get("/runs/:id/nodes/:node_id/outputs/:key", "Get one output, a value or a blob", artifacts::get_output)
+        .post("/runs/:id/tickets", "Link a ticket to a run", executor::add_ticket)
+        .delete("/runs/:id/tickets/:ticket_id", "Unlink a ticket from a run", executor::delete_ticket)
+        .get("/usage/leaderboard", "Most and least used DAGs", usage::leaderboard)
+        .get("/dags/:id/render.svg", "Render the DAG as SVG", render::render_dag_svg)
+        .get("/dags/:id/export", "Export the DAG as JSON, DOT or Mermaid", export::export_dag)
+        .get("/dags/:id/shortest-path", "Shortest path between two nodes", analysis::shortest_path)
+        .get("/dags/:id/longest-path", "Longest path between two nodes", analysis::longest_path)
+        .get("/dags/:id/path", "Whether one node is up- or downstream of another", analysis::reachability)
+        .post("/dags/:id/flow", "Maximum flow and minimum cut between two nodes", analysis::max_flow)
+        .get("/dags/:id/levels", "Group nodes into parallel execution waves", analysis::levels)
+        .post("/dags/:id/simulate", "Simulate a schedule on a number of workers", analysis::simulate)
+        .post("/dags/:id/impact", "Nodes and DAGs affected by removing nodes", analysis::impact)
+        .post("/dags/:id/partition", "Split the nodes into balanced groups", analysis::partition)
+        .get("/dags/:id/history", "Changes to a DAG, its nodes and edges, newest first", history::dag_history)
+        .get("/dags/:id/history/state", "A DAG as it was at a point in time, replayed from its history", history::dag_state_at)
+        .get("/dags/:id/analytics", "Counts, roots and leaves, depth, critical path and components", analysis::analytics)
+        .post("/dags/:id/validate", "Check the DAG's structure and report what each check finds", checks::validate_dag)
+        .post("/imports", "Start a staged import", imports::create_import)
+        .get("/imports/:id", "Get a staged import", imports::get_import)
+        .delete("/imports/:id", "Discard a staged import", imports::delete_import)
+        .post("/imports/:id/chunks", "Upload nodes and edges to a staged import", imports::upload_chunk)
+        .post("/imports/:id/validate", "Validate a staged import", imports::validate_import)
+        .post("/imports/:id/abort", "Stop a running validation", imports::abort_import)
+        .get("/imports/:id/findings", "Follow a validation's findings as NDJSON", imports::stream_findings)
+        .post("/imports/:id/promote", "Promote a validated import into its DAG", imports::promote_import)
+        .post("/imports/:id/rollback", "Roll back a promoted import", imports::rollback_import)
+        .post("/dags/:id/publish", "Publish the DAG to the catalog", catalog::publish_dag)
+        .post("/dags/:id/catalog-sync", "Update a catalog copy to the latest version", catalog::sync_catalog_copy)
+        .get("/catalog", "List catalog entries", catalog::list_catalog)
+        .get("/catalog/:id", "Get a catalog entry", catalog::get_catalog_entry)
+        .put("/catalog/:id", "Update a catalog entry", catalog::update_catalog_entry)
+        .post("/catalog/:id/reviews", "Review a catalog entry", catalog::add_review)
+        .get("/catalog/:id/reviews", "List a catalog entry's reviews", catalog::list_reviews)
+        .get("/catalog/:id/versions/:version", "Get a published version of a catalog entry", catalog::get_catalog_version)
+        .post("/catalog/:id/copy", "Copy a catalog entry into a new DAG", catalog::copy_catalog_entry)
+        .post("/admin/api-keys", "Issue an API key", auth::create_api_key)
+        .get("/admin/api-keys", "List API keys", auth::list_api_keys)
+        .delete("/admin/api-keys/:id", "Revoke an API key", auth::revoke_api_key)
+        .put("/admin/environments/:name", "Create or update an environment", environments::put_environment)
+        .post("/admin/connections", "Create a connection", connections::create_connection)
+        .get("/admin/connections", "List connections, secrets masked", connections::list_connections)
+        .get("/admin/connections/:id", "Get a connection, secrets masked", connections::get_connection)
+        .put("/admin/connections/:id", "Update a connection's fields", connections::update_connection)
+        .delete("/admin/connections/:id", "Delete a connection", connections::delete_connection)
+        .post("/admin/variables", "Create a variable", variables::create_variable)
+        .get("/admin/variables", "List variables, secrets masked", variables::list_variables)
+        .get("/admin/variables/:id", "Get a variable, secret values masked", variables::get_variable)
+        .put("/admin/variables/:id", "Change a variable's value", variables::update_variable)
+        .delete("/admin/variables/:id", "Delete a variable", variables::delete_variable)
+        .get("/admin/variables/:id/changes", "Who changed a variable, when and how", variables::list_variable_changes)
+        .get("/admin/task-logs", "Where task logs go and how many each store holds", logs::log_stores)
+        .post("/admin/task-logs/migrations", "Move task logs from one store to another", logs::migrate_logs)
+        .get("/admin/read-only", "Whether the service is read-only", maintenance::get_read_only)
+        .put("/admin/read-only", "Turn read-only mode on or off", maintenance::set_read_only)
+        .get("/jobs/:id", "Get a background job", jobs::get_job)
+        .get("/label-prefixes", "List the allowed label prefixes", vocabulary::list_label_prefixes)
+        .put("/label-prefixes", "Replace the allowed label prefixes", vocabulary::set_label_prefixes)
+        .get("/label-prefixes/suggest", "Suggest label prefixes for a label", vocabulary::suggest_label_prefixes)
+        .post("/nodes", "Create a node", create_node)
+        .get("/nodes", "List nodes", list_nodes)
+        .get("/nodes/by-external-id/:system/:id", "Find nodes by external id", nodes_by_external_id)
+        .get("/nodes/:id", "Get a node", get_node)
+        .put("/nodes/:id", "Update a node", update_node)
+        .delete("/nodes/:id", "Delete a node with its edges", delete_node)
+        .put("/nodes/:id/external-ids", "Replace a node's external ids", set_external_ids)
+        .put("/nodes/:id/task", "Set a node's task", set_task)
+        .delete("/nodes/:id/task", "Remove a node's task", clear_task)
+        .get("/nodes/:id/ancestors", "Nodes upstream of a node", analysis::ancestors)
+        .get("/nodes/:id/descendants", "Nodes downstream of a node", analysis::descendants)
+        .post("/edges", "Create an edge", create_edge)
+        .get("/edges", "List edges", list_edges)
+        .get("/edges/:id", "Get an edge", get_edge)
+        .put("/edges/:id", "Move an edge's endpoints", update_edge)
+        .delete("/edges/:id", "Delete an edge", delete_edge)
+        .post("/edges/:id/insert-node", "Insert a new node in the middle of an edge", insert_node_on_edge);
+    #[cfg(feature = "png")]
+    let api = api.get("/dags/:id/render.png", "Render the DAG as PNG", render::render_dag_png);
+    let addr = config.bind_addr;
+    let server = axum::Server::try_bind(&addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+    let config = Arc::new(config);
+    let state = AppState {
+        pool: pool.clone(),
+        config: config.clone(),
+        maintenance,
+        catalogs,
+        events: events::sender(),
+    };
+    let app = api
+        .into_router()
+        .layer(axum::middleware::from_fn(changes::scope))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), auth::authenticate))
+        .layer(axum::middleware::from_fn(telemetry::track))
+        .with_state(state);
+    if config.admin_api_key.is_none() {
+        tracing::warn!("ADMIN_API_KEY is not set; anyone who can reach the service may use it");
+    }
+    tracing::info!("Server running at http://{}", addr);
+    // In-flight requests finish before serve returns; open WebSockets are
+    // dropped with the process
+    let served = server
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
+        .await;
+    tracing::info!("Shutting down");
+    pool.close().await;
+    if let Err(e) = served {
+        tracing::error!("Server failed: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Resolves on SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
 }
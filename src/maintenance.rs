@@ -0,0 +1,94 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Json, State},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::auth;
+use crate::i18n::{Catalogs, Locale};
+
+// Seconds clients are asked to wait before retrying a refused write
+const RETRY_AFTER: &str = "60";
+
+// Global read-only switch for migrations and failovers. Starts from
+// READ_ONLY and can be flipped at runtime through /admin/read-only.
+pub struct Maintenance {
+    read_only: AtomicBool,
+    message: RwLock<Option<String>>,
+}
+
+impl Maintenance {
+    pub fn from_env() -> Self {
+        let read_only = env::var("READ_ONLY").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+        Maintenance {
+            read_only: AtomicBool::new(read_only),
+            message: RwLock::new(None),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    fn status(&self) -> ReadOnlyStatus {
+        ReadOnlyStatus {
+            enabled: self.is_read_only(),
+            message: self.message.read().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReadOnlyStatus {
+    enabled: bool,
+    message: Option<String>,
+}
+
+// Extracted by every handler that changes data; refuses with 503 while the
+// service is read-only, and with 403 for read-only API keys
+pub struct Writable;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Writable
+where
+    S: Send + Sync,
+    Arc<Maintenance>: FromRef<S>,
+    Arc<Catalogs>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let maintenance = Arc::<Maintenance>::from_ref(state);
+        if !maintenance.is_read_only() && !auth::read_only() {
+            return Ok(Writable);
+        }
+
+        let Ok(locale) = Locale::from_request_parts(parts, state).await;
+        if auth::read_only() {
+            return Err(locale.error(StatusCode::FORBIDDEN, "api_key_read_only", &[]).into_response());
+        }
+        let error = match maintenance.message.read().unwrap().clone() {
+            Some(message) => locale.error(StatusCode::SERVICE_UNAVAILABLE, "read_only_with_message", &[("message", &message)]),
+            None => locale.error(StatusCode::SERVICE_UNAVAILABLE, "read_only", &[]),
+        };
+        Err(([(header::RETRY_AFTER, RETRY_AFTER)], error).into_response())
+    }
+}
+
+pub async fn get_read_only(State(maintenance): State<Arc<Maintenance>>) -> impl IntoResponse {
+    Json(maintenance.status())
+}
+
+pub async fn set_read_only(
+    State(maintenance): State<Arc<Maintenance>>,
+    Json(payload): Json<ReadOnlyStatus>,
+) -> impl IntoResponse {
+    *maintenance.message.write().unwrap() = payload.message.filter(|_| payload.enabled);
+    maintenance.read_only.store(payload.enabled, Ordering::Relaxed);
+    Json(maintenance.status())
+}
@@ -0,0 +1,141 @@
+// The OpenAPI description of the service. Routes are registered through Api,
+// which records each one as it adds it to the router, so the spec served at
+// /api-docs/openapi.json always lists exactly the endpoints that exist.
+use axum::{
+    handler::Handler,
+    response::{Html, IntoResponse},
+    routing::{self, MethodRouter},
+    Json, Router,
+};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+// Swagger UI comes from the CDN; the service only serves the page around it
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Axum DAG Manager API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/api-docs/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+struct Endpoint {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    operation_id: &'static str,
+}
+
+pub struct Api<S> {
+    router: Router<S>,
+    endpoints: Vec<Endpoint>,
+}
+
+impl<S: Clone + Send + Sync + 'static> Api<S> {
+    pub fn new() -> Self {
+        Api { router: Router::new(), endpoints: Vec::new() }
+    }
+
+    pub fn get<H: Handler<T, S>, T: 'static>(self, path: &'static str, summary: &'static str, handler: H) -> Self {
+        self.add("get", path, summary, routing::get(handler), operation_id::<H>())
+    }
+
+    pub fn post<H: Handler<T, S>, T: 'static>(self, path: &'static str, summary: &'static str, handler: H) -> Self {
+        self.add("post", path, summary, routing::post(handler), operation_id::<H>())
+    }
+
+    pub fn put<H: Handler<T, S>, T: 'static>(self, path: &'static str, summary: &'static str, handler: H) -> Self {
+        self.add("put", path, summary, routing::put(handler), operation_id::<H>())
+    }
+
+    pub fn delete<H: Handler<T, S>, T: 'static>(self, path: &'static str, summary: &'static str, handler: H) -> Self {
+        self.add("delete", path, summary, routing::delete(handler), operation_id::<H>())
+    }
+
+    fn add(
+        mut self,
+        method: &'static str,
+        path: &'static str,
+        summary: &'static str,
+        route: MethodRouter<S>,
+        operation_id: &'static str,
+    ) -> Self {
+        self.router = self.router.route(path, route);
+        self.endpoints.push(Endpoint { method, path, summary, operation_id });
+        self
+    }
+
+    // The router with the spec and Swagger UI added
+    pub fn into_router(self) -> Router<S> {
+        let spec = Arc::new(spec(&self.endpoints));
+        self.router
+            .route("/api-docs", routing::get(|| async { Html(SWAGGER_UI) }))
+            .route("/api-docs/openapi.json", routing::get(move || async move { Json(spec.as_ref().clone()).into_response() }))
+    }
+}
+
+// The handler's function name, e.g. create_dag for dag_service::create_dag
+fn operation_id<H>() -> &'static str {
+    let name = std::any::type_name::<H>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+fn spec(endpoints: &[Endpoint]) -> Value {
+    let mut paths = Map::new();
+    for endpoint in endpoints {
+        let segments: Vec<&str> = endpoint.path.split('/').filter(|s| !s.is_empty()).collect();
+        let path = segments
+            .iter()
+            .map(|s| match s.strip_prefix(':') {
+                Some(name) => format!("/{{{}}}", name),
+                None => format!("/{}", s),
+            })
+            .collect::<String>();
+        let parameters: Vec<Value> = segments
+            .iter()
+            .filter_map(|s| s.strip_prefix(':'))
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+            .collect();
+        let operation = json!({
+            "operationId": endpoint.operation_id,
+            "summary": endpoint.summary,
+            "tags": [segments.first().copied().unwrap_or_default()],
+            "parameters": parameters,
+            "responses": {
+                "2XX": { "description": "Success" },
+                "default": {
+                    "description": "Error",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+                },
+            },
+        });
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[endpoint.method] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "Axum DAG Manager", "version": env!("CARGO_PKG_VERSION") },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": { "type": "string", "description": "The message key, e.g. dag_not_found" },
+                        "message": { "type": "string", "description": "In the language negotiated from Accept-Language" },
+                        "details": { "type": "object" },
+                    },
+                },
+            },
+        },
+    })
+}
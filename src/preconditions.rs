@@ -0,0 +1,143 @@
+// Optimistic concurrency for DAGs, nodes and edges. Every row has a version
+// the database moves on whenever the row changes (see migration 0033); reads
+// send it as the ETag, and changes must send it back in If-Match so an edit
+// made from a stale copy fails with 412 instead of overwriting someone else's.
+// A DAG is read together with its nodes and edges, so its version covers
+// theirs as well.
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::db::TxError;
+use crate::i18n::{Catalogs, Locale};
+
+#[derive(Clone, Copy)]
+pub enum Entity {
+    Dag,
+    Node,
+    Edge,
+}
+
+// A hash of the DAG row's version and those of its nodes and edges, which
+// changes when any of them changes, is added or goes away. It keeps to 60 bits
+// so it stays a positive BIGINT like the row versions.
+const DAG_VERSION: &str = "SELECT ('x' || left(md5(d.version || ':'
+        || COALESCE((SELECT string_agg(n.id || '.' || n.version, ',' ORDER BY n.id) FROM nodes n WHERE n.dag_id = d.id), '') || ':'
+        || COALESCE((SELECT string_agg(e.id || '.' || e.version, ',' ORDER BY e.id) FROM edges e WHERE e.dag_id = d.id), '')
+    ), 15))::bit(60)::bigint
+    FROM dags d WHERE d.id = $1";
+
+impl Entity {
+    fn version_query(self) -> &'static str {
+        match self {
+            Entity::Dag => DAG_VERSION,
+            Entity::Node => "SELECT version FROM nodes WHERE id = $1",
+            Entity::Edge => "SELECT version FROM edges WHERE id = $1",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Entity::Dag => "DAG",
+            Entity::Node => "node",
+            Entity::Edge => "edge",
+        }
+    }
+}
+
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+// The versions a change may apply to, from If-Match. "*" takes any; weak tags
+// never match, as If-Match compares strongly.
+pub enum IfMatch {
+    Any,
+    Versions(Vec<i64>),
+}
+
+impl IfMatch {
+    fn allows(&self, version: i64) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Versions(versions) => versions.contains(&version),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+    Arc<Catalogs>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let values: Vec<&str> = parts
+            .headers
+            .get_all(header::IF_MATCH)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .collect();
+        if values.is_empty() {
+            let Ok(locale) = Locale::from_request_parts(parts, state).await;
+            return Err(locale.error(StatusCode::PRECONDITION_REQUIRED, "if_match_required", &[]).into_response());
+        }
+        if values.contains(&"*") {
+            return Ok(IfMatch::Any);
+        }
+        Ok(IfMatch::Versions(
+            values
+                .iter()
+                .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+                .collect(),
+        ))
+    }
+}
+
+// The row's version, or None if there is no such row
+pub async fn current<'e, E: sqlx::PgExecutor<'e>>(executor: E, entity: Entity, id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(entity.version_query())
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+}
+
+// Locks the row and refuses the change with 412 unless If-Match names its
+// version. A missing row passes, for the caller to answer 404 as it would.
+pub async fn check(
+    tx: &mut sqlx::PgConnection,
+    locale: &Locale,
+    if_match: &IfMatch,
+    entity: Entity,
+    id: Uuid,
+) -> Result<(), TxError> {
+    let version: Option<i64> = sqlx::query_scalar(&format!("{} FOR UPDATE", entity.version_query()))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    match version {
+        Some(version) if !if_match.allows(version) => Err(TxError::Rejected(
+            locale
+                .error(StatusCode::PRECONDITION_FAILED, "version_mismatch", &[("entity", &entity.name()), ("id", &id)])
+                .with_detail("etag", serde_json::json!(etag(version))),
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn with_etag(mut response: Response, version: Option<i64>) -> Response {
+    if let Some(value) = version.and_then(|v| HeaderValue::from_str(&etag(v)).ok()) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
@@ -0,0 +1,263 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::error::AppError;
+use crate::graph::Graph;
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::usage::{self, Access};
+use crate::versions::{self, VersionParam};
+use crate::{Edge, Node};
+
+const NODE_WIDTH: f64 = 160.0;
+const NODE_HEIGHT: f64 = 40.0;
+const H_GAP: f64 = 40.0;
+const V_GAP: f64 = 60.0;
+const MARGIN: f64 = 20.0;
+const MAX_LABEL_CHARS: usize = 20;
+const FONT_FAMILY: &str = "Helvetica, Arial, &apos;DejaVu Sans&apos;, sans-serif";
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+struct Palette {
+    background: &'static str,
+    node_fill: &'static str,
+    node_stroke: &'static str,
+    text: &'static str,
+    edge: &'static str,
+}
+
+impl Theme {
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Light => Palette {
+                background: "#ffffff",
+                node_fill: "#f4f6fa",
+                node_stroke: "#4a5568",
+                text: "#1a202c",
+                edge: "#718096",
+            },
+            Theme::Dark => Palette {
+                background: "#1a202c",
+                node_fill: "#2d3748",
+                node_stroke: "#a0aec0",
+                text: "#f7fafc",
+                edge: "#a0aec0",
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenderParams {
+    width: Option<u32>,
+    height: Option<u32>,
+    #[serde(default)]
+    theme: Theme,
+}
+
+struct Layout {
+    width: f64,
+    height: f64,
+    // Top-left corner of every node, indexed like `Graph::ids`
+    positions: Vec<(f64, f64)>,
+}
+
+// Layered (Sugiyama-style) layout: rows come from the longest-path layering,
+// and each row is ordered by the barycenter of its already placed parents to
+// keep edge crossings down.
+fn layout(graph: &Graph) -> Layout {
+    let mut layers = graph.layers();
+    let widest = layers.iter().map(|l| l.len()).max().unwrap_or(0) as f64;
+    let width = widest * (NODE_WIDTH + H_GAP) - H_GAP + 2.0 * MARGIN;
+    let height = layers.len() as f64 * (NODE_HEIGHT + V_GAP) - V_GAP + 2.0 * MARGIN;
+    let mut positions = vec![(0.0, 0.0); graph.node_count()];
+    let mut placed = vec![false; graph.node_count()];
+
+    for (row, layer) in layers.iter_mut().enumerate() {
+        let barycenter = |n: usize| {
+            let parents: Vec<f64> = graph.incoming[n]
+                .iter()
+                .filter(|&&p| placed[p])
+                .map(|&p| positions[p].0)
+                .collect();
+            if parents.is_empty() {
+                f64::MAX
+            } else {
+                parents.iter().sum::<f64>() / parents.len() as f64
+            }
+        };
+        let mut keyed: Vec<(f64, usize)> = layer.iter().map(|&n| (barycenter(n), n)).collect();
+        keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let row_width = keyed.len() as f64 * (NODE_WIDTH + H_GAP) - H_GAP;
+        let left = MARGIN + (width - 2.0 * MARGIN - row_width) / 2.0;
+        let top = MARGIN + row as f64 * (NODE_HEIGHT + V_GAP);
+        for (i, &(_, n)) in keyed.iter().enumerate() {
+            positions[n] = (left + i as f64 * (NODE_WIDTH + H_GAP), top);
+            placed[n] = true;
+        }
+    }
+
+    Layout {
+        width: width.max(2.0 * MARGIN),
+        height: height.max(2.0 * MARGIN),
+        positions,
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn display_label(label: &str) -> String {
+    if label.chars().count() > MAX_LABEL_CHARS {
+        let truncated: String = label.chars().take(MAX_LABEL_CHARS - 1).collect();
+        format!("{}…", truncated)
+    } else {
+        label.to_string()
+    }
+}
+
+fn render_svg(nodes: &[Node], edges: &[Edge], params: &RenderParams) -> String {
+    let graph = Graph::new(nodes, edges);
+    let layout = layout(&graph);
+    let palette = params.theme.palette();
+    // A single requested dimension scales the other one to keep the aspect ratio
+    let (width, height) = match (params.width.map(f64::from), params.height.map(f64::from)) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, w * layout.height / layout.width),
+        (None, Some(h)) => (h * layout.width / layout.height, h),
+        (None, None) => (layout.width, layout.height),
+    };
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+        width, height, layout.width, layout.height
+    );
+    let _ = write!(
+        svg,
+        r#"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="8" markerHeight="8" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="{}"/></marker></defs>"#,
+        palette.edge
+    );
+    let _ = write!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        palette.background
+    );
+
+    for (source, targets) in graph.outgoing.iter().enumerate() {
+        let (sx, sy) = layout.positions[source];
+        let (x1, y1) = (sx + NODE_WIDTH / 2.0, sy + NODE_HEIGHT);
+        for &target in targets {
+            let (tx, ty) = layout.positions[target];
+            let (x2, y2) = (tx + NODE_WIDTH / 2.0, ty);
+            let bend = V_GAP / 2.0;
+            // Edges that skip layers bow out sideways so they don't hide behind
+            // the nodes in between
+            let (c1, c2) = if ty - sy > NODE_HEIGHT + V_GAP {
+                let side = x1.max(x2) + NODE_WIDTH * 0.7;
+                (side, side)
+            } else {
+                (x1, x2)
+            };
+            let _ = write!(
+                svg,
+                r#"<path d="M {} {} C {} {} {} {} {} {}" fill="none" stroke="{}" stroke-width="1.5" marker-end="url(#arrow)"/>"#,
+                x1, y1, c1, y1 + bend, c2, y2 - bend, x2, y2, palette.edge
+            );
+        }
+    }
+
+    for (node, &(x, y)) in nodes.iter().zip(layout.positions.iter()) {
+        let _ = write!(
+            svg,
+            r#"<g><title>{}</title><rect x="{}" y="{}" width="{}" height="{}" rx="6" fill="{}" stroke="{}"/><text x="{}" y="{}" font-family="{}" font-size="13" text-anchor="middle" dominant-baseline="central" fill="{}">{}</text></g>"#,
+            escape_xml(&node.label),
+            x,
+            y,
+            NODE_WIDTH,
+            NODE_HEIGHT,
+            palette.node_fill,
+            palette.node_stroke,
+            x + NODE_WIDTH / 2.0,
+            y + NODE_HEIGHT / 2.0,
+            FONT_FAMILY,
+            palette.text,
+            escape_xml(&display_label(&node.label))
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+pub async fn render_dag_svg(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<RenderParams>,
+    Query(at): Query<VersionParam>,
+) -> Result<Response, AppError> {
+    let (_, nodes, edges) = versions::loaded_at(&pool, &locale, dag_id, &at, "render_failed").await?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok((
+        [(header::CONTENT_TYPE, "image/svg+xml")],
+        render_svg(&nodes, &edges, &params),
+    )
+        .into_response())
+}
+
+#[cfg(feature = "png")]
+fn rasterize(svg: &str) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| "Image size must be non-zero".to_string())?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "png")]
+pub async fn render_dag_png(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<RenderParams>,
+    Query(at): Query<VersionParam>,
+) -> Result<Response, AppError> {
+    let (_, nodes, edges) = versions::loaded_at(&pool, &locale, dag_id, &at, "render_failed").await?;
+    usage::record(&pool, dag_id, Access::Read);
+    let png = rasterize(&render_svg(&nodes, &edges, &params))
+        .map_err(|e| locale.error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, "rasterize_failed", &[("error", &e)]))?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
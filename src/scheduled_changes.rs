@@ -0,0 +1,467 @@
+// Structural changes that take effect later, e.g. a cutover at 00:00 UTC.
+// A change lists nodes and edges to add and remove; it is checked for shape
+// when submitted and applied in one transaction once effective_at passes,
+// when the nodes it touches are looked up. A change that no longer fits the
+// DAG is marked failed with the error its submitter would have got.
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as JsonColumn, Acquire, FromRow, PgPool};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::changes::Change;
+use crate::db::TxError;
+use crate::error::AppError;
+use crate::events;
+use crate::i18n::{Catalogs, Locale};
+use crate::ids;
+use crate::maintenance::{Maintenance, Writable};
+use crate::slugs::{self, DagId};
+use crate::usage::{self, Access};
+use crate::vocabulary;
+use crate::{add_edge, find_dag, lock_dag, node_not_found, writable, Edge, NodeState};
+
+// The applier also looks for due changes this often, which picks up changes
+// submitted to other processes
+const TICK: Duration = Duration::from_secs(15);
+
+const CHANGE_COLUMNS: &str =
+    "id, dag_id, effective_at, status, operations, error, language, created_by, reason, created_at, applied_at";
+
+static SUBMITTED: Notify = Notify::const_new();
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum ChangeStatus {
+    #[default]
+    Pending,
+    Applied,
+    Failed,
+}
+
+impl ChangeStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeStatus::Pending => "pending",
+            ChangeStatus::Applied => "applied",
+            ChangeStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NewNode {
+    id: Uuid,
+    label: String,
+    #[serde(default)]
+    state: NodeState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NewEdge {
+    id: Uuid,
+    source: Uuid,
+    target: Uuid,
+}
+
+// What a change does, with the ids the new nodes and edges will get
+#[derive(Serialize, Deserialize)]
+struct Operations {
+    add_nodes: Vec<NewNode>,
+    remove_nodes: Vec<Uuid>,
+    add_edges: Vec<NewEdge>,
+    remove_edges: Vec<Uuid>,
+}
+
+#[derive(Serialize, FromRow)]
+struct ScheduledChange {
+    id: Uuid,
+    dag_id: Uuid,
+    effective_at: DateTime<Utc>,
+    status: ChangeStatus,
+    operations: JsonColumn<Operations>,
+    error: Option<JsonColumn<serde_json::Value>>,
+    // The submitter's language, for the error if applying fails
+    #[serde(skip)]
+    language: String,
+    created_by: Option<String>,
+    reason: Option<String>,
+    created_at: DateTime<Utc>,
+    applied_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeNode {
+    client_id: String,
+    label: String,
+    #[serde(default)]
+    state: NodeState,
+}
+
+// Edge endpoints are a new node's client_id or an existing node's id
+#[derive(Deserialize)]
+pub struct ChangeEdge {
+    source: String,
+    target: String,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduledChangePayload {
+    effective_at: DateTime<Utc>,
+    #[serde(default)]
+    add_nodes: Vec<ChangeNode>,
+    #[serde(default)]
+    remove_nodes: Vec<Uuid>,
+    #[serde(default)]
+    add_edges: Vec<ChangeEdge>,
+    #[serde(default)]
+    remove_edges: Vec<Uuid>,
+}
+
+fn unprocessable(locale: &Locale, key: &str, args: &[(&str, &dyn std::fmt::Display)]) -> AppError {
+    locale.error(StatusCode::UNPROCESSABLE_ENTITY, key, args)
+}
+
+// Checks what can be checked before effective_at and assigns the new ids
+fn operations(locale: &Locale, payload: ScheduledChangePayload) -> Result<Operations, AppError> {
+    if payload.add_nodes.is_empty() && payload.remove_nodes.is_empty() && payload.add_edges.is_empty() && payload.remove_edges.is_empty() {
+        return Err(unprocessable(locale, "scheduled_change_empty", &[]));
+    }
+    let mut new_ids = HashMap::new();
+    let mut add_nodes = Vec::with_capacity(payload.add_nodes.len());
+    for node in payload.add_nodes {
+        let label = node.label.trim();
+        if label.is_empty() {
+            return Err(unprocessable(locale, "scheduled_change_blank_label", &[("client_id", &node.client_id)]));
+        }
+        let id = ids::new_id();
+        if new_ids.insert(node.client_id.clone(), id).is_some() {
+            return Err(unprocessable(locale, "import_duplicate_client_id", &[("ids", &node.client_id)]));
+        }
+        add_nodes.push(NewNode { id, label: label.to_string(), state: node.state });
+    }
+    let endpoint = |endpoint: &str| {
+        new_ids
+            .get(endpoint)
+            .copied()
+            .or_else(|| endpoint.parse().ok())
+            .ok_or_else(|| unprocessable(locale, "scheduled_change_unknown_endpoint", &[("endpoint", &endpoint)]))
+    };
+    let add_edges = payload
+        .add_edges
+        .iter()
+        .map(|edge| Ok(NewEdge { id: ids::new_id(), source: endpoint(&edge.source)?, target: endpoint(&edge.target)? }))
+        .collect::<Result<Vec<_>, AppError>>()?;
+    Ok(Operations { add_nodes, remove_nodes: payload.remove_nodes, add_edges, remove_edges: payload.remove_edges })
+}
+
+pub async fn create_scheduled_change(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
+    DagId(dag_id): DagId,
+    Json(payload): Json<ScheduledChangePayload>,
+) -> Result<Response, AppError> {
+    if payload.effective_at <= Utc::now() {
+        return Err(unprocessable(&locale, "effective_at_not_future", &[("effective_at", &payload.effective_at)]));
+    }
+    let effective_at = payload.effective_at;
+    let operations = operations(&locale, payload)?;
+    find_dag(&pool, &locale, dag_id).await?;
+    let prefixes = vocabulary::label_prefixes(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "create_scheduled_change_failed"))?;
+    if let Some(node) = operations.add_nodes.iter().find(|n| !vocabulary::allowed(&prefixes, &n.label)) {
+        return Err(unprocessable(&locale, "label_prefix_not_allowed", &[("label", &node.label)])
+            .with_details(serde_json::json!({ "allowed_prefixes": prefixes })));
+    }
+
+    let scheduled = sqlx::query_as::<_, ScheduledChange>(&format!(
+        "INSERT INTO scheduled_changes (id, dag_id, effective_at, operations, language, created_by, reason)
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+        CHANGE_COLUMNS
+    ))
+        .bind(ids::new_id())
+        .bind(dag_id)
+        .bind(effective_at)
+        .bind(JsonColumn(&operations))
+        .bind(locale.language())
+        .bind(&change.created_by)
+        .bind(&change.reason)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "create_scheduled_change_failed"))?;
+    SUBMITTED.notify_one();
+    usage::record(&pool, dag_id, Access::Edit);
+    let location = format!("/scheduled-changes/{}", scheduled.id);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(scheduled)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    status: ChangeStatus,
+}
+
+// A DAG's changes with the given status (pending by default), soonest first
+pub async fn list_dag_scheduled_changes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<ListParams>,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+    let changes = sqlx::query_as::<_, ScheduledChange>(&format!(
+        "SELECT {} FROM scheduled_changes WHERE dag_id = $1 AND status = $2 ORDER BY effective_at, created_at",
+        CHANGE_COLUMNS
+    ))
+        .bind(dag_id)
+        .bind(params.status)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_scheduled_changes_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(Json(changes).into_response())
+}
+
+// The same across all DAGs, e.g. to see what cuts over tonight
+pub async fn list_scheduled_changes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(params): Query<ListParams>,
+) -> Result<Response, AppError> {
+    let changes = sqlx::query_as::<_, ScheduledChange>(&format!(
+        "SELECT {} FROM scheduled_changes WHERE status = $1 AND {} ORDER BY effective_at, created_at",
+        CHANGE_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(params.status)
+        .bind(auth::owner())
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_scheduled_changes_failed"))?;
+    Ok(Json(changes).into_response())
+}
+
+fn scheduled_change_not_found(locale: &Locale, id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "scheduled_change_not_found", &[("id", &id)])
+}
+
+pub async fn get_scheduled_change(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(change_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let change = sqlx::query_as::<_, ScheduledChange>(&format!(
+        "SELECT {} FROM scheduled_changes WHERE id = $1 AND {}",
+        CHANGE_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(change_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_scheduled_changes_failed"))?
+        .ok_or_else(|| scheduled_change_not_found(&locale, change_id))?;
+    usage::record(&pool, change.dag_id, Access::Read);
+    Ok(Json(change).into_response())
+}
+
+// Withdraws a pending change; applied and failed ones stay as a record
+pub async fn cancel_scheduled_change(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(change_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    // Applying holds the row lock, so a change being applied right now is
+    // no longer pending by the time the delete gets to it
+    let dag_id: Option<Uuid> = sqlx::query_scalar(&format!(
+        "DELETE FROM scheduled_changes WHERE id = $1 AND status = 'pending' AND {} RETURNING dag_id",
+        auth::visible("dag_id", 2)
+    ))
+        .bind(change_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "delete_scheduled_change_failed"))?;
+    let Some(dag_id) = dag_id else {
+        let status: ChangeStatus = sqlx::query_scalar(&format!("SELECT status FROM scheduled_changes WHERE id = $1 AND {}", auth::visible("dag_id", 2)))
+            .bind(change_id)
+            .bind(auth::owner())
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| AppError::database(e, &locale, "delete_scheduled_change_failed"))?
+            .ok_or_else(|| scheduled_change_not_found(&locale, change_id))?;
+        return Err(locale.error(
+            StatusCode::CONFLICT,
+            "scheduled_change_not_pending",
+            &[("id", &change_id), ("status", &status.as_str())],
+        ));
+    };
+    usage::record(&pool, dag_id, Access::Edit);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// Starts the applier. Like the job workers it leaves changes alone while the
+// service is read-only; they are applied, late, once it is writable again.
+pub fn start(pool: &PgPool, maintenance: &Arc<Maintenance>, catalogs: &Arc<Catalogs>) {
+    let (pool, maintenance, catalogs) = (pool.clone(), maintenance.clone(), catalogs.clone());
+    tokio::spawn(async move {
+        loop {
+            if !maintenance.is_read_only() {
+                loop {
+                    match apply_next(&pool, &catalogs).await {
+                        Ok(true) => continue,
+                        Ok(false) => break,
+                        Err(e) => {
+                            tracing::error!("Failed to apply scheduled changes: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            // Sleep until the next change is due, so it applies on time
+            let next: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT min(effective_at) FROM scheduled_changes WHERE status = 'pending'")
+                .fetch_one(&pool)
+                .await
+                .unwrap_or(None);
+            let wait = next.and_then(|at| (at - Utc::now()).to_std().ok()).map_or(TICK, |until| until.min(TICK));
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = SUBMITTED.notified() => {}
+            }
+        }
+    });
+}
+
+// Applies the earliest due change and records how it went. Returns whether
+// there was one.
+async fn apply_next(pool: &PgPool, catalogs: &Arc<Catalogs>) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let Some(change) = sqlx::query_as::<_, ScheduledChange>(&format!(
+        "SELECT {} FROM scheduled_changes WHERE status = 'pending' AND effective_at <= now()
+         ORDER BY effective_at, created_at LIMIT 1 FOR UPDATE SKIP LOCKED",
+        CHANGE_COLUMNS
+    ))
+        .fetch_optional(&mut tx)
+        .await?
+    else {
+        return Ok(false);
+    };
+
+    // The change's own edits run in a savepoint, so a change that fails half
+    // way leaves the DAG as it was while its failure is still recorded
+    let locale = Locale::new(catalogs.clone(), &change.language);
+    let mut edits = Acquire::begin(&mut tx).await?;
+    let error = match apply(&mut edits, &change, &locale).await {
+        Ok(()) => {
+            edits.commit().await?;
+            None
+        }
+        Err(TxError::Rejected(error)) => {
+            edits.rollback().await?;
+            Some(error.to_json())
+        }
+        Err(TxError::Database(e) | TxError::Contended(e)) => return Err(e),
+    };
+    let status = if error.is_some() { ChangeStatus::Failed } else { ChangeStatus::Applied };
+    sqlx::query("UPDATE scheduled_changes SET status = $2, error = $3, applied_at = now() WHERE id = $1")
+        .bind(change.id)
+        .bind(status)
+        .bind(error.map(JsonColumn))
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    if status == ChangeStatus::Applied {
+        usage::record(pool, change.dag_id, Access::Edit);
+        events::publish(change.dag_id, "graph_changed", serde_json::json!({ "cause": "scheduled_change", "change_id": change.id }));
+    }
+    Ok(true)
+}
+
+// Removes edges, then nodes (with their edges), then adds nodes and edges,
+// each edge checked for cycles as POST /edges does
+async fn apply(tx: &mut sqlx::PgConnection, change: &ScheduledChange, locale: &Locale) -> Result<(), TxError> {
+    let (dag_id, operations) = (change.dag_id, &change.operations.0);
+    writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id).map_err(TxError::Rejected)?;
+
+    let removed: HashSet<Uuid> = sqlx::query_scalar("DELETE FROM edges WHERE id = ANY($1) AND dag_id = $2 RETURNING id")
+        .bind(&operations.remove_edges)
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+    if let Some(&edge_id) = operations.remove_edges.iter().find(|id| !removed.contains(id)) {
+        return Err(TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "edge_not_found", &[("id", &edge_id)])));
+    }
+
+    sqlx::query("DELETE FROM edges WHERE source = ANY($1) OR target = ANY($1)")
+        .bind(&operations.remove_nodes)
+        .execute(&mut *tx)
+        .await?;
+    let removed: HashSet<Uuid> = sqlx::query_scalar("DELETE FROM nodes WHERE id = ANY($1) AND dag_id = $2 RETURNING id")
+        .bind(&operations.remove_nodes)
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+    if let Some(&node_id) = operations.remove_nodes.iter().find(|id| !removed.contains(id)) {
+        return Err(node_not_found(locale, node_id));
+    }
+
+    vocabulary::check_labels(&mut *tx, locale, operations.add_nodes.iter().map(|n| n.label.as_str())).await?;
+    for node in &operations.add_nodes {
+        let slug = slugs::node_slug(&mut *tx, dag_id, &node.label, node.id).await?;
+        sqlx::query(
+            "INSERT INTO nodes (id, dag_id, label, slug, created_by, reason, state) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+            .bind(node.id)
+            .bind(dag_id)
+            .bind(&node.label)
+            .bind(&slug)
+            .bind(&change.created_by)
+            .bind(&change.reason)
+            .bind(node.state)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let endpoints: Vec<Uuid> = operations.add_edges.iter().flat_map(|e| [e.source, e.target]).collect();
+    let present: HashSet<Uuid> = sqlx::query_scalar("SELECT id FROM nodes WHERE id = ANY($1) AND dag_id = $2")
+        .bind(&endpoints)
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+    if let Some(&node_id) = endpoints.iter().find(|id| !present.contains(id)) {
+        return Err(node_not_found(locale, node_id));
+    }
+    for new in &operations.add_edges {
+        let edge = Edge {
+            id: new.id,
+            source: new.source,
+            target: new.target,
+            dag_id,
+            created_by: change.created_by.clone(),
+            reason: change.reason.clone(),
+        };
+        add_edge(&mut *tx, &edge, locale).await?;
+    }
+    Ok(())
+}
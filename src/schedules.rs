@@ -0,0 +1,355 @@
+// Cron schedules for DAG runs. A background task wakes up every TICK,
+// claims schedules that are due and queues their runs like POST
+// /dags/:id/runs does. Runs know which schedule started them and the time
+// they were due, so a backfilled run can be told from a late one. A DAG
+// whose runs could never finish gets none: the schedule records why and
+// publishes a schedule_refused event instead.
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as JsonColumn, FromRow, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::cron::Cron;
+use crate::error::AppError;
+use crate::events;
+use crate::executor;
+use crate::graph::Graph;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::{Maintenance, Writable};
+use crate::slugs::DagId;
+use crate::usage::{self, Access};
+use crate::{find_dag, lock_dag};
+
+const TICK: Duration = Duration::from_secs(15);
+// How late a run may start under catch_up = skip before it counts as missed
+const GRACE: chrono::Duration = chrono::Duration::minutes(5);
+// Runs one schedule backfills per tick; the rest follow on the next ones
+const MAX_BACKFILL: usize = 100;
+
+const SCHEDULE_COLUMNS: &str = "id, dag_id, cron, catch_up, paused, next_run_at, last_run_id, last_run_at, last_refusal, created_at";
+
+// What to do about due times that passed while the service was down or the
+// schedule paused: skip them, run once for the latest, or run each in turn
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+enum CatchUp {
+    #[default]
+    Skip,
+    Latest,
+    All,
+}
+
+#[derive(Serialize, FromRow)]
+struct Schedule {
+    id: Uuid,
+    dag_id: Uuid,
+    cron: String,
+    catch_up: CatchUp,
+    paused: bool,
+    next_run_at: DateTime<Utc>,
+    last_run_id: Option<Uuid>,
+    last_run_at: Option<DateTime<Utc>>,
+    last_refusal: Option<JsonColumn<serde_json::Value>>,
+    created_at: DateTime<Utc>,
+}
+
+// Something about a DAG's structure that keeps its runs from ever finishing
+#[derive(Serialize)]
+struct Problem {
+    check: &'static str,
+    node_ids: Vec<Uuid>,
+}
+
+// Checks what a run of the DAG would contain: that there is a node to run,
+// that some node can start without waiting, and that every leaf can be
+// reached from one that can (it can't behind a cycle)
+fn diagnose(nodes: Vec<Uuid>, dependencies: &[(Uuid, Uuid)]) -> Vec<Problem> {
+    if nodes.is_empty() {
+        return vec![Problem { check: "no_executable_nodes", node_ids: Vec::new() }];
+    }
+    let index: HashMap<Uuid, usize> = nodes.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+    let arcs = dependencies
+        .iter()
+        .filter_map(|(node, source)| Some((*index.get(source)?, *index.get(node)?)));
+    let graph = Graph::from_arcs(nodes, arcs);
+
+    let mut problems = Vec::new();
+    if graph.incoming.iter().all(|sources| !sources.is_empty()) {
+        problems.push(Problem { check: "no_roots", node_ids: Vec::new() });
+    }
+    let (_, blocked) = graph.topological_order();
+    let unreachable: Vec<Uuid> = blocked
+        .into_iter()
+        .filter(|&i| graph.outgoing[i].is_empty())
+        .map(|i| graph.ids[i])
+        .collect();
+    if !unreachable.is_empty() {
+        problems.push(Problem { check: "unreachable_leaves", node_ids: unreachable });
+    }
+    problems
+}
+
+// Checks the expression and works out when it first fires
+fn first_run(locale: &Locale, cron: &str) -> Result<DateTime<Utc>, AppError> {
+    let parsed = Cron::parse(cron).map_err(|error| {
+        locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_cron", &[("cron", &cron), ("error", &error)])
+    })?;
+    parsed
+        .next_after(Utc::now())
+        .ok_or_else(|| locale.error(StatusCode::UNPROCESSABLE_ENTITY, "cron_never_fires", &[("cron", &cron)]))
+}
+
+#[derive(Deserialize)]
+pub struct CreateSchedulePayload {
+    cron: String,
+    #[serde(default)]
+    catch_up: CatchUp,
+    #[serde(default)]
+    paused: bool,
+}
+
+pub async fn create_schedule(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<CreateSchedulePayload>,
+) -> Result<Response, AppError> {
+    let cron = payload.cron.trim();
+    let next_run_at = first_run(&locale, cron)?;
+    find_dag(&pool, &locale, dag_id).await?;
+    let schedule = sqlx::query_as::<_, Schedule>(&format!(
+        "INSERT INTO schedules (id, dag_id, cron, catch_up, paused, next_run_at) VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING {}",
+        SCHEDULE_COLUMNS
+    ))
+        .bind(ids::new_id())
+        .bind(dag_id)
+        .bind(cron)
+        .bind(payload.catch_up)
+        .bind(payload.paused)
+        .bind(next_run_at)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "create_schedule_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    let location = format!("/schedules/{}", schedule.id);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(schedule)).into_response())
+}
+
+pub async fn list_schedules(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+    let schedules = sqlx::query_as::<_, Schedule>(&format!(
+        "SELECT {} FROM schedules WHERE dag_id = $1 ORDER BY created_at",
+        SCHEDULE_COLUMNS
+    ))
+        .bind(dag_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_schedules_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(Json(schedules).into_response())
+}
+
+fn schedule_not_found(locale: &Locale, id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "schedule_not_found", &[("id", &id)])
+}
+
+pub async fn get_schedule(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let schedule = sqlx::query_as::<_, Schedule>(&format!(
+        "SELECT {} FROM schedules WHERE id = $1 AND {}",
+        SCHEDULE_COLUMNS,
+        auth::visible("dag_id", 2)
+    ))
+        .bind(schedule_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_schedules_failed"))?
+        .ok_or_else(|| schedule_not_found(&locale, schedule_id))?;
+    usage::record(&pool, schedule.dag_id, Access::Read);
+    Ok(Json(schedule).into_response())
+}
+
+async fn set_paused(pool: &PgPool, locale: &Locale, schedule_id: Uuid, paused: bool) -> Result<Response, AppError> {
+    let schedule = sqlx::query_as::<_, Schedule>(&format!(
+        "UPDATE schedules SET paused = $2 WHERE id = $1 AND {} RETURNING {}",
+        auth::visible("dag_id", 3),
+        SCHEDULE_COLUMNS
+    ))
+        .bind(schedule_id)
+        .bind(paused)
+        .bind(auth::owner())
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::database(e, locale, "update_schedule_failed"))?
+        .ok_or_else(|| schedule_not_found(locale, schedule_id))?;
+    usage::record(pool, schedule.dag_id, Access::Edit);
+    Ok(Json(schedule).into_response())
+}
+
+pub async fn pause_schedule(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    set_paused(&pool, &locale, schedule_id, true).await
+}
+
+// Due times that passed while paused are handled by the catch_up setting
+pub async fn resume_schedule(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    set_paused(&pool, &locale, schedule_id, false).await
+}
+
+pub async fn delete_schedule(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let dag_id: Uuid = sqlx::query_scalar(&format!("DELETE FROM schedules WHERE id = $1 AND {} RETURNING dag_id", auth::visible("dag_id", 2)))
+        .bind(schedule_id)
+        .bind(auth::owner())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "delete_schedule_failed"))?
+        .ok_or_else(|| schedule_not_found(&locale, schedule_id))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// Starts the scheduler. Like the job workers it leaves schedules alone while
+// the service is read-only.
+pub fn start(pool: &PgPool, maintenance: &Arc<Maintenance>) {
+    let (pool, maintenance) = (pool.clone(), maintenance.clone());
+    tokio::spawn(async move {
+        loop {
+            if !maintenance.is_read_only() {
+                loop {
+                    match fire_next(&pool).await {
+                        Ok(true) => continue,
+                        Ok(false) => break,
+                        Err(e) => {
+                            tracing::error!("Failed to start scheduled runs: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            tokio::time::sleep(TICK).await;
+        }
+    });
+}
+
+// Queues the runs of one due schedule and moves it on to its next due time.
+// Returns whether there was one.
+async fn fire_next(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let Some(schedule) = sqlx::query_as::<_, Schedule>(&format!(
+        "SELECT {} FROM schedules WHERE NOT paused AND next_run_at <= now()
+         ORDER BY next_run_at LIMIT 1 FOR UPDATE SKIP LOCKED",
+        SCHEDULE_COLUMNS
+    ))
+        .fetch_optional(&mut tx)
+        .await?
+    else {
+        return Ok(false);
+    };
+
+    let now = Utc::now();
+    let (due, next) = match Cron::parse(&schedule.cron) {
+        Ok(cron) => due_times(&cron, schedule.catch_up, schedule.next_run_at, now),
+        // Only valid expressions are stored, but a schedule that cannot be
+        // evaluated is paused rather than retried every tick
+        Err(_) => (Vec::new(), None),
+    };
+
+    let mut runs = Vec::with_capacity(due.len());
+    let mut refusal = None;
+    if !due.is_empty() && lock_dag(&mut tx, schedule.dag_id).await?.is_some() {
+        let (nodes, dependencies) = executor::run_plan(&mut tx, schedule.dag_id).await?;
+        let problems = diagnose(nodes, &dependencies);
+        if problems.is_empty() {
+            for &at in &due {
+                runs.push(executor::queue_run(&mut tx, schedule.dag_id, (schedule.id, at)).await?);
+            }
+        } else {
+            refusal = Some(serde_json::json!({ "at": now, "due": due, "problems": problems }));
+        }
+    }
+    sqlx::query(
+        "UPDATE schedules SET next_run_at = COALESCE($2, next_run_at), paused = paused OR $2 IS NULL,
+             last_run_id = COALESCE($3, last_run_id), last_run_at = COALESCE($4, last_run_at),
+             last_refusal = CASE WHEN $3 IS NOT NULL THEN NULL ELSE COALESCE($5, last_refusal) END
+         WHERE id = $1",
+    )
+        .bind(schedule.id)
+        .bind(next)
+        .bind(runs.last())
+        .bind(due.last().filter(|_| !runs.is_empty()))
+        .bind(refusal.as_ref().map(JsonColumn))
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    if let Some(mut refusal) = refusal {
+        tracing::warn!("Schedule {} started no runs: DAG {} has runs that could never finish", schedule.id, schedule.dag_id);
+        refusal["schedule_id"] = serde_json::json!(schedule.id);
+        events::publish(schedule.dag_id, "schedule_refused", refusal);
+    }
+
+    for run_id in runs {
+        executor::announce_run(schedule.dag_id, run_id);
+        usage::record(pool, schedule.dag_id, Access::Run);
+    }
+    Ok(true)
+}
+
+// The due times to run now, from `from` (the schedule's next_run_at) up to
+// `now`, and the next due time after them
+fn due_times(cron: &Cron, catch_up: CatchUp, from: DateTime<Utc>, now: DateTime<Utc>) -> (Vec<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let mut passed = vec![from];
+    let mut next = cron.next_after(from);
+    while let Some(at) = next.filter(|at| *at <= now) {
+        if catch_up == CatchUp::All && passed.len() == MAX_BACKFILL {
+            break;
+        }
+        if catch_up != CatchUp::All {
+            passed.clear();
+        }
+        passed.push(at);
+        next = cron.next_after(at);
+    }
+    let latest = passed.last().copied();
+    let due = match catch_up {
+        CatchUp::All => passed,
+        CatchUp::Latest => latest.into_iter().collect(),
+        CatchUp::Skip => latest.into_iter().filter(|at| now - *at <= GRACE).collect(),
+    };
+    (due, next)
+}
@@ -0,0 +1,75 @@
+use sqlx::PgPool;
+
+// Number of the newest file in migrations/ this binary is written against
+const SCHEMA_VERSION: i32 = 43;
+const REQUIRED_EXTENSIONS: &[&str] = &["pg_trgm"];
+
+// Checks that the database has the migrations this binary expects and the
+// extensions it relies on, so a mismatch is reported at boot rather than by
+// the first query that trips over it. Newer expand migrations are fine: they
+// are applied while the previous build still serves (blue/green), and only a
+// newer contract migration makes the schema incompatible.
+pub async fn check(pool: &PgPool) -> Result<(), String> {
+    let recorded: Option<String> = sqlx::query_scalar("SELECT to_regclass('schema_migrations')::text")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to inspect the schema: {}", e))?;
+    if recorded.is_none() {
+        return Err(format!(
+            "The database has no schema_migrations table; apply migrations/ up to {:04}",
+            SCHEMA_VERSION
+        ));
+    }
+
+    let version: Option<i32> = sqlx::query_scalar("SELECT max(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to read the schema version: {}", e))?;
+    let version = version.unwrap_or(0);
+    if version < SCHEMA_VERSION {
+        return Err(format!(
+            "The database schema is at version {}, this build needs {}; apply migrations/{:04}_* through {:04}_*",
+            version,
+            SCHEMA_VERSION,
+            version + 1,
+            SCHEMA_VERSION
+        ));
+    }
+    if version > SCHEMA_VERSION {
+        let contract: Option<i32> = sqlx::query_scalar(
+            "SELECT min(version) FROM schema_migrations WHERE version > $1 AND phase = 'contract'",
+        )
+            .bind(SCHEMA_VERSION)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| format!("Failed to read the schema version: {}", e))?;
+        if let Some(contract) = contract {
+            return Err(format!(
+                "The database schema is at version {} and contract migration {:04} dropped what this build (version {}) uses; deploy a newer build",
+                version, contract, SCHEMA_VERSION
+            ));
+        }
+        tracing::warn!(
+            "Database schema is at version {}, ahead of this build ({}) by expand migrations only",
+            version, SCHEMA_VERSION
+        );
+    }
+
+    let installed: Vec<String> = sqlx::query_scalar("SELECT extname::text FROM pg_extension")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to list extensions: {}", e))?;
+    let missing: Vec<&str> = REQUIRED_EXTENSIONS
+        .iter()
+        .copied()
+        .filter(|name| !installed.iter().any(|i| i == name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Required Postgres extensions are missing: {} (CREATE EXTENSION as a superuser)",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(())
+}
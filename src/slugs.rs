@@ -0,0 +1,121 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::i18n::{Catalogs, Locale};
+
+const MAX_SLUG_CHARS: usize = 60;
+// Serializes DAG slug allocation, which is global rather than per DAG
+const DAG_SLUG_LOCK: i64 = 0x736c_7567;
+// Fixed routes under /dags/ that a DAG slug would be shadowed by
+const RESERVED_DAG_SLUGS: &[&str] = &["import"];
+
+// Lowercase ASCII letters and digits, with every other run of characters
+// turned into a single '-'. Must match the backfill in 0008_slugs.sql.
+pub fn slugify(text: &str, fallback: &str) -> String {
+    let mut slug = String::new();
+    for c in text.to_lowercase().chars() {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(MAX_SLUG_CHARS).collect();
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        fallback.to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// The fallback when the plain slug is taken
+pub fn disambiguate(slug: &str, id: Uuid) -> String {
+    format!("{}-{}", slug, &id.simple().to_string()[..8])
+}
+
+// Picks the slug for a new DAG. Callers hold the transaction open until the
+// DAG is inserted.
+pub async fn dag_slug(tx: &mut sqlx::PgConnection, name: &str, id: Uuid) -> Result<String, sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(DAG_SLUG_LOCK)
+        .execute(&mut *tx)
+        .await?;
+    let slug = slugify(name, "dag");
+    let taken: bool = RESERVED_DAG_SLUGS.contains(&slug.as_str())
+        || sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM dags WHERE slug = $1)")
+            .bind(&slug)
+            .fetch_one(&mut *tx)
+            .await?;
+    Ok(if taken { disambiguate(&slug, id) } else { slug })
+}
+
+// Picks the slug for a new node; expects the DAG to be locked
+pub async fn node_slug(tx: &mut sqlx::PgConnection, dag_id: Uuid, label: &str, id: Uuid) -> Result<String, sqlx::Error> {
+    let slug = slugify(label, "node");
+    let taken: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM nodes WHERE dag_id = $1 AND slug = $2)")
+        .bind(dag_id)
+        .bind(&slug)
+        .fetch_one(&mut *tx)
+        .await?;
+    Ok(if taken { disambiguate(&slug, id) } else { slug })
+}
+
+// The :id of a /dags/:id/... route, given either as the DAG's uuid or its slug
+pub struct DagId(pub Uuid);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DagId
+where
+    S: Send + Sync,
+    PgPool: FromRef<S>,
+    Arc<Catalogs>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let Some(raw) = params.get("id") else {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        };
+        let pool = PgPool::from_ref(state);
+        let Ok(locale) = Locale::from_request_parts(parts, state).await;
+        let not_found = || locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", raw)]).into_response();
+        let failed = |e| AppError::database(e, &locale, "fetch_dag_failed").into_response();
+        let id = match raw.parse() {
+            Ok(id) => id,
+            Err(_) => sqlx::query_scalar("SELECT id FROM dags WHERE slug = $1")
+                .bind(raw)
+                .fetch_optional(&pool)
+                .await
+                .map_err(failed)?
+                .ok_or_else(not_found)?,
+        };
+
+        // Other keys' DAGs answer as if they didn't exist
+        if let Some(owner) = auth::owner() {
+            let visible: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM dags WHERE id = $1 AND owner_key_id = $2)")
+                .bind(id)
+                .bind(owner)
+                .fetch_one(&pool)
+                .await
+                .map_err(failed)?;
+            if !visible {
+                return Err(not_found());
+            }
+        }
+        Ok(DagId(id))
+    }
+}
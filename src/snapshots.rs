@@ -0,0 +1,306 @@
+// A compact binary form of a DAG's nodes and edges, used to store versions
+// and as an export format. A snapshot starts with a header: the magic bytes
+// "DAGS", the format version and the codec the rest is compressed with.
+// The body is laid out by column (all node ids, then all labels, and so on)
+// so like values sit together and compress well; ids are 16 raw bytes, and
+// edges name their ends by position in the node list rather than by id.
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use sqlx::types::Json;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+use crate::{Edge, Node, NodeState, Position};
+
+pub const CONTENT_TYPE: &str = "application/vnd.dag-snapshot";
+
+const MAGIC: &[u8; 4] = b"DAGS";
+const FORMAT_VERSION: u8 = 1;
+// Room for other codecs later; snapshots say which one they were written with
+const DEFLATE: u8 = 1;
+
+// Encodes the nodes and edges of `dag_id`. Both are sorted by id first, so
+// the same contents always give the same bytes.
+pub fn encode(dag_id: Uuid, nodes: &[Node], edges: &[Edge]) -> Vec<u8> {
+    let mut nodes: Vec<&Node> = nodes.iter().collect();
+    nodes.sort_by_key(|n| n.id);
+    let mut edges: Vec<&Edge> = edges.iter().collect();
+    edges.sort_by_key(|e| e.id);
+    let position: HashMap<Uuid, usize> = nodes.iter().enumerate().map(|(i, n)| (n.id, i)).collect();
+
+    let mut out = Writer::default();
+    out.uuid(dag_id);
+    out.count(nodes.len());
+    for n in &nodes { out.uuid(n.id); }
+    for n in &nodes { out.text(&n.label); }
+    for n in &nodes { out.optional_text(n.slug.as_deref()); }
+    for n in &nodes { out.json(&n.external_ids); }
+    for n in &nodes { out.optional_json(n.provenance.as_ref()); }
+    for n in &nodes { out.optional_text(n.owner.as_deref()); }
+    for n in &nodes { out.optional_text(n.source_url.as_deref()); }
+    for n in &nodes { out.optional_json(n.task.as_ref()); }
+    for n in &nodes { out.json(&n.metadata); }
+    for n in &nodes { out.optional_text(n.node_type.as_deref()); }
+    for node in &nodes {
+        match &node.position {
+            Some(Json(at)) => {
+                out.byte(1);
+                out.0.extend_from_slice(&at.x.to_le_bytes());
+                out.0.extend_from_slice(&at.y.to_le_bytes());
+            }
+            None => out.byte(0),
+        }
+    }
+    for n in &nodes { out.optional_text(n.created_by.as_deref()); }
+    for n in &nodes { out.optional_text(n.reason.as_deref()); }
+    for n in &nodes { out.byte(state_code(n.state)); }
+
+    out.count(edges.len());
+    for e in &edges { out.uuid(e.id); }
+    // Versions saved before edges had to stay within their DAG may hold
+    // edges to nodes elsewhere; those ends are written out as ids
+    for end in edges.iter().map(|e| e.source).chain(edges.iter().map(|e| e.target)) {
+        match position.get(&end) {
+            Some(&i) => out.count(i + 1),
+            None => {
+                out.count(0);
+                out.uuid(end);
+            }
+        }
+    }
+    for e in &edges { out.optional_text(e.created_by.as_deref()); }
+    for e in &edges { out.optional_text(e.reason.as_deref()); }
+
+    let mut snapshot = Vec::with_capacity(out.0.len() / 4);
+    snapshot.extend_from_slice(MAGIC);
+    snapshot.push(FORMAT_VERSION);
+    snapshot.push(DEFLATE);
+    let mut encoder = DeflateEncoder::new(snapshot, Compression::default());
+    // Writing into a Vec can't fail
+    let _ = encoder.write_all(&out.0);
+    encoder.finish().unwrap_or_default()
+}
+
+// Decodes a snapshot written by `encode` into the DAG id, nodes and edges it
+// holds, or says why it can't be read
+pub fn decode(snapshot: &[u8]) -> Result<(Uuid, Vec<Node>, Vec<Edge>), String> {
+    let (header, compressed) = snapshot.split_at_checked(6).ok_or("the snapshot is too short")?;
+    if &header[..4] != MAGIC {
+        return Err("not a DAG snapshot".to_string());
+    }
+    if header[4] != FORMAT_VERSION {
+        return Err(format!("unsupported snapshot format version {}", header[4]));
+    }
+    if header[5] != DEFLATE {
+        return Err(format!("unsupported snapshot codec {}", header[5]));
+    }
+    let mut body = Vec::new();
+    DeflateDecoder::new(compressed)
+        .read_to_end(&mut body)
+        .map_err(|e| format!("the snapshot doesn't decompress: {}", e))?;
+
+    let mut input = Reader { bytes: &body, at: 0 };
+    let dag_id = input.uuid()?;
+    let count = input.count()?;
+    let ids = input.column(count, Reader::uuid)?;
+    let mut nodes: Vec<Node> = ids
+        .iter()
+        .map(|&id| Node {
+            id,
+            dag_id,
+            label: String::new(),
+            slug: None,
+            external_ids: Json(HashMap::new()),
+            provenance: None,
+            owner: None,
+            source_url: None,
+            task: None,
+            metadata: Json(serde_json::json!({})),
+            node_type: None,
+            position: None,
+            created_by: None,
+            reason: None,
+            state: NodeState::Active,
+        })
+        .collect();
+    for n in &mut nodes { n.label = input.text()?; }
+    for n in &mut nodes { n.slug = input.optional_text()?; }
+    for n in &mut nodes { n.external_ids = input.json()?; }
+    for n in &mut nodes { n.provenance = input.optional_json()?; }
+    for n in &mut nodes { n.owner = input.optional_text()?; }
+    for n in &mut nodes { n.source_url = input.optional_text()?; }
+    for n in &mut nodes { n.task = input.optional_json()?; }
+    for n in &mut nodes { n.metadata = input.json()?; }
+    for n in &mut nodes { n.node_type = input.optional_text()?; }
+    for node in &mut nodes {
+        if input.byte()? != 0 {
+            node.position = Some(Json(Position { x: input.float()?, y: input.float()? }));
+        }
+    }
+    for n in &mut nodes { n.created_by = input.optional_text()?; }
+    for n in &mut nodes { n.reason = input.optional_text()?; }
+    for n in &mut nodes { n.state = state_from(input.byte()?)?; }
+
+    let count = input.count()?;
+    let edge_ids = input.column(count, Reader::uuid)?;
+    let ends = input.column(count.saturating_mul(2), |input| match input.count()? {
+        0 => input.uuid(),
+        i => ids.get(i - 1).copied().ok_or_else(|| format!("edge end {} is past the last node", i)),
+    })?;
+    let created_by = input.column(count, Reader::optional_text)?;
+    let reasons = input.column(count, Reader::optional_text)?;
+    if input.at != body.len() {
+        return Err("the snapshot has trailing bytes".to_string());
+    }
+    let edges = edge_ids
+        .into_iter()
+        .zip(ends[..count].iter().zip(&ends[count..]))
+        .zip(created_by.into_iter().zip(reasons))
+        .map(|((id, (&source, &target)), (created_by, reason))| Edge { id, source, target, dag_id, created_by, reason })
+        .collect();
+    Ok((dag_id, nodes, edges))
+}
+
+fn state_code(state: NodeState) -> u8 {
+    match state {
+        NodeState::Draft => 0,
+        NodeState::Active => 1,
+        NodeState::Deprecated => 2,
+    }
+}
+
+fn state_from(code: u8) -> Result<NodeState, String> {
+    match code {
+        0 => Ok(NodeState::Draft),
+        1 => Ok(NodeState::Active),
+        2 => Ok(NodeState::Deprecated),
+        other => Err(format!("unknown node state {}", other)),
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+
+    // LEB128, so small counts and lengths take a byte
+    fn count(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.0.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        self.0.push(n as u8);
+    }
+
+    fn uuid(&mut self, id: Uuid) {
+        self.0.extend_from_slice(id.as_bytes());
+    }
+
+    fn text(&mut self, text: &str) {
+        self.count(text.len());
+        self.0.extend_from_slice(text.as_bytes());
+    }
+
+    fn optional_text(&mut self, text: Option<&str>) {
+        match text {
+            Some(text) => {
+                self.byte(1);
+                self.text(text);
+            }
+            None => self.byte(0),
+        }
+    }
+
+    fn json<T: serde::Serialize>(&mut self, value: &T) {
+        self.text(&serde_json::to_string(value).unwrap_or_default());
+    }
+
+    fn optional_json<T: serde::Serialize>(&mut self, value: Option<&T>) {
+        match value {
+            Some(value) => {
+                self.byte(1);
+                self.json(value);
+            }
+            None => self.byte(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let taken = self
+            .bytes
+            .get(self.at..self.at.saturating_add(len))
+            .ok_or("the snapshot ends early")?;
+        self.at += len;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err("a count in the snapshot is too long".to_string())
+    }
+
+    fn float(&mut self) -> Result<f64, String> {
+        let bytes = self.take(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap_or_default()))
+    }
+
+    fn uuid(&mut self) -> Result<Uuid, String> {
+        Uuid::from_slice(self.take(16)?).map_err(|e| e.to_string())
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        let len = self.count()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    fn optional_text(&mut self) -> Result<Option<String>, String> {
+        match self.byte()? {
+            0 => Ok(None),
+            _ => self.text().map(Some),
+        }
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&mut self) -> Result<Json<T>, String> {
+        serde_json::from_str(&self.text()?).map(Json).map_err(|e| e.to_string())
+    }
+
+    fn optional_json<T: serde::de::DeserializeOwned>(&mut self) -> Result<Option<Json<T>>, String> {
+        match self.byte()? {
+            0 => Ok(None),
+            _ => self.json().map(Some),
+        }
+    }
+
+    // Reads `count` values one after the other. The count comes from the
+    // snapshot, so capacity is capped by what's left to read.
+    fn column<T>(&mut self, count: usize, mut read: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let mut values = Vec::with_capacity(count.min(self.bytes.len() - self.at));
+        for _ in 0..count {
+            values.push(read(self)?);
+        }
+        Ok(values)
+    }
+}
@@ -0,0 +1,50 @@
+use axum::extract::FromRef;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::config::Config;
+use crate::events::Event;
+use crate::i18n::Catalogs;
+use crate::maintenance::Maintenance;
+
+// Everything handlers share. Handlers and extractors ask for the part they
+// need, e.g. State<PgPool>, rather than for the whole state.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub config: Arc<Config>,
+    pub maintenance: Arc<Maintenance>,
+    pub catalogs: Arc<Catalogs>,
+    pub events: broadcast::Sender<Event>,
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Maintenance> {
+    fn from_ref(state: &AppState) -> Self {
+        state.maintenance.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Catalogs> {
+    fn from_ref(state: &AppState) -> Self {
+        state.catalogs.clone()
+    }
+}
+
+impl FromRef<AppState> for broadcast::Sender<Event> {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
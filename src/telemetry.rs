@@ -0,0 +1,334 @@
+// Metrics and logging. Every request runs inside a `request` span carrying
+// its id (X-Request-Id, taken from the client or made up), method and route,
+// so whatever a handler logs says which request it belongs to; the id goes
+// back in the answer. GET /metrics reports request counts and latencies per
+// route, the database pool and how many of each kind of change was
+// published, in the Prometheus text format.
+//
+// Log lines go through `tracing`. Logger prints this crate's events at
+// LOG_LEVEL and above, with the spans they happened in: errors and warnings
+// to stderr, the rest to stdout.
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Instrument, Level, Metadata, Subscriber};
+
+use crate::config::{Config, LogLevel};
+use crate::ids;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+// Longest request id taken from a client; longer ones are replaced
+const MAX_REQUEST_ID: usize = 128;
+// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+struct Histogram {
+    // Requests at or under each bound in BUCKETS
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS.len()];
+        }
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    // By method, route and status
+    requests: BTreeMap<(String, String, u16), u64>,
+    // By method and route
+    latencies: BTreeMap<(String, String), Histogram>,
+    // By event kind
+    changes: BTreeMap<&'static str, u64>,
+}
+
+static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+
+fn metrics() -> std::sync::MutexGuard<'static, Metrics> {
+    let metrics = METRICS.get_or_init(Default::default);
+    metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Counts a published change, e.g. node_created; called by events::publish
+pub fn count_change(kind: &'static str) {
+    *metrics().changes.entry(kind).or_default() += 1;
+}
+
+fn request_id<B>(request: &Request<B>) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID)
+        .map_or_else(|| ids::new_id().to_string(), str::to_string)
+}
+
+// The outermost layer, so it times and counts what the others answer too
+pub async fn track<B>(request: Request<B>, next: Next<B>) -> Response {
+    let id = request_id(&request);
+    let method = request.method().to_string();
+    // Counting by path would make a series per DAG; unmatched requests share one
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string());
+    let span = tracing::info_span!("request", id = %id, method = %method, route = %route);
+
+    let started = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+    let elapsed = started.elapsed();
+    let status = response.status().as_u16();
+    span.in_scope(|| tracing::debug!(status, ms = elapsed.as_millis() as u64, "answered"));
+
+    let mut metrics = metrics();
+    *metrics.requests.entry((method.clone(), route.clone(), status)).or_default() += 1;
+    metrics.latencies.entry((method, route)).or_default().observe(elapsed.as_secs_f64());
+    drop(metrics);
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// Quotes a label value as the text format wants it
+fn label(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+pub async fn get_metrics(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Response {
+    let mut text = String::new();
+    let metrics = metrics();
+
+    text.push_str("# HELP dag_service_requests_total Requests answered, by method, route and status.\n");
+    text.push_str("# TYPE dag_service_requests_total counter\n");
+    for ((method, route, status), count) in &metrics.requests {
+        let _ = writeln!(
+            text,
+            "dag_service_requests_total{{method={},route={},status=\"{}\"}} {}",
+            label(method),
+            label(route),
+            status,
+            count
+        );
+    }
+
+    text.push_str("# HELP dag_service_request_duration_seconds Time taken to answer requests, by method and route.\n");
+    text.push_str("# TYPE dag_service_request_duration_seconds histogram\n");
+    for ((method, route), histogram) in &metrics.latencies {
+        let labels = format!("method={},route={}", label(method), label(route));
+        for (count, bound) in histogram.counts.iter().zip(BUCKETS) {
+            let _ = writeln!(text, "dag_service_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+        }
+        let _ = writeln!(text, "dag_service_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+        let _ = writeln!(text, "dag_service_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+        let _ = writeln!(text, "dag_service_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+    }
+
+    text.push_str("# HELP dag_service_changes_total Changes published to DAG subscribers, by event kind.\n");
+    text.push_str("# TYPE dag_service_changes_total counter\n");
+    for (kind, count) in &metrics.changes {
+        let _ = writeln!(text, "dag_service_changes_total{{kind={}}} {}", label(kind), count);
+    }
+    drop(metrics);
+
+    let (size, idle) = (pool.size(), pool.num_idle() as u32);
+    text.push_str("# HELP dag_service_db_connections Open database connections, by state.\n");
+    text.push_str("# TYPE dag_service_db_connections gauge\n");
+    let _ = writeln!(text, "dag_service_db_connections{{state=\"idle\"}} {}", idle);
+    let _ = writeln!(text, "dag_service_db_connections{{state=\"in_use\"}} {}", size.saturating_sub(idle));
+    text.push_str("# HELP dag_service_db_max_connections DB_POOL_SIZE.\n");
+    text.push_str("# TYPE dag_service_db_max_connections gauge\n");
+    let _ = writeln!(text, "dag_service_db_max_connections {}", config.pool_size);
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response()
+}
+
+// Installs Logger as the global subscriber
+pub fn init(level: LogLevel) {
+    let level = match level {
+        LogLevel::Error => Level::ERROR,
+        LogLevel::Warn => Level::WARN,
+        LogLevel::Info => Level::INFO,
+        LogLevel::Debug => Level::DEBUG,
+    };
+    let logger = Logger { level, next_id: AtomicU64::new(1), spans: Mutex::new(HashMap::new()) };
+    // Only fails if one is installed already
+    let _ = tracing::subscriber::set_global_default(logger);
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    parent: Option<Id>,
+    references: usize,
+}
+
+struct Logger {
+    level: Level,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+thread_local! {
+    // Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+// Collects fields as ` name=value`, with the message first and bare
+struct Fields<'a>(&'a mut String);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.insert_str(0, value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{:?}", value));
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl Logger {
+    fn spans(&self) -> std::sync::MutexGuard<'_, HashMap<u64, SpanData>> {
+        self.spans.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // Libraries' own logging (hyper's, for one) stays quiet
+        // Spans are kept at any level so the events that are printed have them
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME")) && (metadata.is_span() || *metadata.level() <= self.level)
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = String::new();
+        attributes.record(&mut Fields(&mut fields));
+        let parent = attributes.parent().cloned().or_else(|| {
+            if attributes.is_contextual() {
+                ENTERED.with(|entered| entered.borrow().last().cloned())
+            } else {
+                None
+            }
+        });
+        if let Some(parent) = &parent {
+            if let Some(data) = self.spans().get_mut(&parent.into_u64()) {
+                data.references += 1;
+            }
+        }
+        let data = SpanData { name: attributes.metadata().name(), fields: fields.trim_start().to_string(), parent, references: 1 };
+        self.spans().insert(id, data);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans().get_mut(&span.into_u64()) {
+            let mut fields = String::new();
+            values.record(&mut Fields(&mut fields));
+            data.fields.push_str(&fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = String::new();
+        event.record(&mut Fields(&mut message));
+        let mut context = Vec::new();
+        {
+            let spans = self.spans();
+            let mut current = event
+                .parent()
+                .cloned()
+                .or_else(|| if event.is_contextual() { ENTERED.with(|entered| entered.borrow().last().cloned()) } else { None });
+            while let Some(id) = current {
+                let Some(data) = spans.get(&id.into_u64()) else { break };
+                context.push(format!("{}{{{}}}", data.name, data.fields));
+                current = data.parent.clone();
+            }
+        }
+        context.reverse();
+        let context = if context.is_empty() { String::new() } else { format!("{}: ", context.join(":")) };
+        let line = format!("{} {:>5} {}{}", chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"), event.metadata().level(), context, message);
+        if *event.metadata().level() <= Level::WARN {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|id| id == span) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans();
+        let mut closing = Some(span);
+        let mut closed_first = None;
+        // Closing a span lets go of its parent as well
+        while let Some(id) = closing.take() {
+            let Some(data) = spans.get_mut(&id.into_u64()) else { break };
+            data.references -= 1;
+            let closed = data.references == 0;
+            closed_first.get_or_insert(closed);
+            if closed {
+                closing = spans.remove(&id.into_u64()).and_then(|data| data.parent);
+            }
+        }
+        closed_first.unwrap_or(false)
+    }
+}
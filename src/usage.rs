@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Json, Query, State},
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::slugs::DagId;
+use crate::find_dag;
+
+#[derive(Clone, Copy)]
+pub enum Access {
+    Read,
+    Run,
+    Edit,
+}
+
+// Bumps today's counter for a DAG in the background; usage tracking must
+// never slow down or fail the request that triggered it
+pub fn record(pool: &PgPool, dag_id: Uuid, access: Access) {
+    let pool = pool.clone();
+    let (reads, runs, edits) = match access {
+        Access::Read => (1i64, 0i64, 0i64),
+        Access::Run => (0, 1, 0),
+        Access::Edit => (0, 0, 1),
+    };
+    tokio::spawn(async move {
+        let result = sqlx::query!(
+            "INSERT INTO dag_usage (dag_id, day, reads, runs, edits) VALUES ($1, CURRENT_DATE, $2, $3, $4)
+             ON CONFLICT (dag_id, day)
+             DO UPDATE SET reads = dag_usage.reads + $2, runs = dag_usage.runs + $3, edits = dag_usage.edits + $4",
+            dag_id,
+            reads,
+            runs,
+            edits
+        )
+            .execute(&pool)
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to record usage of DAG {}: {}", dag_id, e);
+        }
+    });
+}
+
+#[derive(Serialize, FromRow)]
+struct DailyUsage {
+    day: NaiveDate,
+    reads: i64,
+    runs: i64,
+    edits: i64,
+}
+
+#[derive(Deserialize)]
+pub struct UsageParams {
+    days: Option<i32>,
+}
+
+pub async fn dag_usage(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Query(params): Query<UsageParams>,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+
+    let days = params.days.unwrap_or(30).max(1);
+    let daily = sqlx::query_as::<_, DailyUsage>(
+        "SELECT day, reads, runs, edits FROM dag_usage
+         WHERE dag_id = $1 AND day > CURRENT_DATE - $2
+         ORDER BY day",
+    )
+        .bind(dag_id)
+        .bind(days)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_usage_failed"))?;
+    Ok(Json(serde_json::json!({
+        "dag_id": dag_id,
+        "days": days,
+        "totals": {
+            "reads": daily.iter().map(|d| d.reads).sum::<i64>(),
+            "runs": daily.iter().map(|d| d.runs).sum::<i64>(),
+            "edits": daily.iter().map(|d| d.edits).sum::<i64>(),
+        },
+        "daily": daily,
+    }))
+        .into_response())
+}
+
+#[derive(Serialize, FromRow)]
+struct LeaderboardEntry {
+    dag_id: Uuid,
+    name: String,
+    reads: i64,
+    runs: i64,
+    edits: i64,
+    total: i64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Desc,
+    Asc,
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardParams {
+    days: Option<i32>,
+    limit: Option<i64>,
+    #[serde(default)]
+    order: Order,
+}
+
+// Ranks every DAG by accesses in the window. DAGs nobody touched count as
+// zero, so order=asc lists the best candidates for retirement first.
+pub async fn leaderboard(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(params): Query<LeaderboardParams>,
+) -> Result<Response, AppError> {
+    let direction = match params.order {
+        Order::Desc => "DESC",
+        Order::Asc => "ASC",
+    };
+    let query = format!(
+        "SELECT d.id AS dag_id, d.name,
+                COALESCE(SUM(u.reads), 0)::BIGINT AS reads,
+                COALESCE(SUM(u.runs), 0)::BIGINT AS runs,
+                COALESCE(SUM(u.edits), 0)::BIGINT AS edits,
+                COALESCE(SUM(u.reads + u.runs + u.edits), 0)::BIGINT AS total
+         FROM dags d
+         LEFT JOIN dag_usage u ON u.dag_id = d.id AND u.day > CURRENT_DATE - $1
+         WHERE $3::uuid IS NULL OR d.owner_key_id = $3
+         GROUP BY d.id, d.name
+         ORDER BY total {}, d.name
+         LIMIT $2",
+        direction
+    );
+
+    let entries = sqlx::query_as::<_, LeaderboardEntry>(&query)
+        .bind(params.days.unwrap_or(30).max(1))
+        .bind(params.limit.unwrap_or(20).max(1))
+        .bind(auth::owner())
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_usage_failed"))?;
+    Ok(Json(entries).into_response())
+}
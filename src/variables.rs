@@ -0,0 +1,429 @@
+// Variables: named values node tasks refer to as {{ var.NAME }}, managed
+// under /admin/variables so pipeline settings live in one place instead of
+// in every task. A variable without an environment applies everywhere; one
+// with an environment overrides it for DAGs in that environment. Placeholders
+// are filled in when a run dispatches the task.
+//
+// Secret values are sealed like connection secrets and answered as
+// ********. Like connections, a secret variable only fills in tasks of DAGs
+// without an owner or whose owner key it lists in owners. Every change is written to variable_changes, with the old and
+// new values unless the variable is secret.
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use uuid::Uuid;
+
+use crate::changes::Change;
+use crate::connections::{self, MASK};
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::ids;
+use crate::maintenance::Writable;
+
+const VARIABLE_COLUMNS: &str = "id, name, environment, value, secret, owners, created_by, created_at, updated_at";
+const CHANGE_COLUMNS: &str = "id, variable_id, name, environment, action, old_value, new_value, changed_by, reason, changed_at";
+// The associated data secret values are sealed under, with the row's id
+const SEALED_FIELD: &str = "value";
+
+#[derive(FromRow)]
+struct Row {
+    id: Uuid,
+    name: String,
+    environment: Option<String>,
+    // Sealed if the variable is secret
+    value: String,
+    secret: bool,
+    owners: Vec<Uuid>,
+    created_by: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Row {
+    // The value as variable_changes may record it
+    fn recorded(&self, value: &str) -> Option<String> {
+        (!self.secret).then(|| value.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct Variable {
+    id: Uuid,
+    name: String,
+    environment: Option<String>,
+    value: String,
+    secret: bool,
+    owners: Vec<Uuid>,
+    created_by: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<Row> for Variable {
+    fn from(row: Row) -> Self {
+        Variable {
+            id: row.id,
+            name: row.name,
+            environment: row.environment,
+            value: if row.secret { MASK.to_string() } else { row.value },
+            secret: row.secret,
+            owners: row.owners,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct VariableChange {
+    id: Uuid,
+    variable_id: Uuid,
+    name: String,
+    environment: Option<String>,
+    action: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    changed_by: Option<String>,
+    reason: Option<String>,
+    changed_at: DateTime<Utc>,
+}
+
+fn variable_not_found(locale: &Locale, id: Uuid) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "variable_not_found", &[("id", &id)])
+}
+
+// The value to store: sealed for secrets, which need CONNECTIONS_KEY
+fn stored(locale: &Locale, id: Uuid, secret: bool, value: &str) -> Result<String, AppError> {
+    if !secret {
+        return Ok(value.to_string());
+    }
+    let key = connections::key().ok_or_else(|| locale.error(StatusCode::CONFLICT, "connections_key_missing", &[]))?;
+    connections::seal(key, id, SEALED_FIELD, value)
+        .map_err(|e| locale.error(StatusCode::INTERNAL_SERVER_ERROR, "seal_secrets_failed", &[("error", &e)]))
+}
+
+fn saved_or(e: sqlx::Error, locale: &Locale, name: &str, environment: Option<&str>) -> TxError {
+    match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+            TxError::Rejected(locale.error(StatusCode::CONFLICT, "variable_exists", &[("name", &name)]))
+        }
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23503") => TxError::Rejected(locale.error(
+            StatusCode::NOT_FOUND,
+            "environment_not_found",
+            &[("name", &environment.unwrap_or_default())],
+        )),
+        _ => TxError::Database(e),
+    }
+}
+
+async fn record(
+    tx: &mut sqlx::PgConnection,
+    row: &Row,
+    action: &str,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    change: &Change,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO variable_changes (id, variable_id, name, environment, action, old_value, new_value, changed_by, reason)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+        .bind(ids::new_id())
+        .bind(row.id)
+        .bind(&row.name)
+        .bind(&row.environment)
+        .bind(action)
+        .bind(old_value)
+        .bind(new_value)
+        .bind(&change.created_by)
+        .bind(&change.reason)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct CreateVariablePayload {
+    name: String,
+    value: String,
+    #[serde(default)]
+    secret: bool,
+    // Makes the variable an override for DAGs in that environment
+    environment: Option<String>,
+    // API keys whose DAGs may use it if it is secret, besides DAGs without an
+    // owner
+    #[serde(default)]
+    owners: Vec<Uuid>,
+}
+
+pub async fn create_variable(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    change: Change,
+    Json(payload): Json<CreateVariablePayload>,
+) -> Result<Response, AppError> {
+    let name = payload.name.trim();
+    if !connections::valid_name(name) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "invalid_variable_name", &[("name", &name)]));
+    }
+    let id = ids::new_id();
+    let value = stored(&locale, id, payload.secret, &payload.value)?;
+    let environment = payload.environment.as_deref().map(str::trim);
+    let (payload, locale, change, value) = (&payload, &locale, &change, &value);
+    let row = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let row = sqlx::query_as::<_, Row>(&format!(
+            "INSERT INTO variables (id, name, environment, value, secret, owners, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+            VARIABLE_COLUMNS
+        ))
+            .bind(id)
+            .bind(name)
+            .bind(environment)
+            .bind(value)
+            .bind(payload.secret)
+            .bind(&payload.owners)
+            .bind(&change.created_by)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| saved_or(e, locale, name, environment))?;
+        let recorded = row.recorded(&payload.value);
+        record(&mut *tx, &row, "created", None, recorded, change).await?;
+        Ok(row)
+    }))
+    .await
+    .map_err(|e| e.respond(locale, "create_variable_failed"))?;
+    let location = format!("/admin/variables/{}", row.id);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(Variable::from(row))).into_response())
+}
+
+pub async fn list_variables(
+    State(pool): State<PgPool>,
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let rows = sqlx::query_as::<_, Row>(&format!(
+        "SELECT {} FROM variables ORDER BY name, environment NULLS FIRST",
+        VARIABLE_COLUMNS
+    ))
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_variables_failed"))?;
+    Ok(Json(rows.into_iter().map(Variable::from).collect::<Vec<_>>()).into_response())
+}
+
+pub async fn get_variable(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let row = sqlx::query_as::<_, Row>(&format!("SELECT {} FROM variables WHERE id = $1", VARIABLE_COLUMNS))
+        .bind(id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_variables_failed"))?
+        .ok_or_else(|| variable_not_found(&locale, id))?;
+    Ok(Json(Variable::from(row)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UpdateVariablePayload {
+    value: String,
+    owners: Option<Vec<Uuid>>,
+}
+
+// A secret's value sent back masked, as it was answered, leaves it as it is;
+// owners are replaced if given
+pub async fn update_variable(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+    change: Change,
+    Json(payload): Json<UpdateVariablePayload>,
+) -> Result<Response, AppError> {
+    let (payload, locale, change) = (&payload, &locale, &change);
+    let row = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let old = sqlx::query_as::<_, Row>(&format!("SELECT {} FROM variables WHERE id = $1 FOR UPDATE", VARIABLE_COLUMNS))
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| TxError::Rejected(variable_not_found(locale, id)))?;
+        let same_value = (old.secret && payload.value == MASK) || (!old.secret && payload.value == old.value);
+        let same_owners = payload.owners.as_ref().is_none_or(|owners| *owners == old.owners);
+        if same_value && same_owners {
+            return Ok(old);
+        }
+        let value = match same_value {
+            true => old.value.clone(),
+            false => stored(locale, id, old.secret, &payload.value).map_err(TxError::Rejected)?,
+        };
+        let row = sqlx::query_as::<_, Row>(&format!(
+            "UPDATE variables SET value = $2, owners = COALESCE($3, owners), updated_at = now() WHERE id = $1 RETURNING {}",
+            VARIABLE_COLUMNS
+        ))
+            .bind(id)
+            .bind(&value)
+            .bind(&payload.owners)
+            .fetch_one(&mut *tx)
+            .await?;
+        let (old_value, new_value) = (old.recorded(&old.value), row.recorded(&payload.value));
+        record(&mut *tx, &row, "updated", old_value, new_value, change).await?;
+        Ok(row)
+    }))
+    .await
+    .map_err(|e| e.respond(locale, "update_variable_failed"))?;
+    Ok(Json(Variable::from(row)).into_response())
+}
+
+// Tasks that still refer to the variable fail when next dispatched
+pub async fn delete_variable(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+    change: Change,
+) -> Result<Response, AppError> {
+    let (locale, change) = (&locale, &change);
+    db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let row = sqlx::query_as::<_, Row>(&format!("DELETE FROM variables WHERE id = $1 RETURNING {}", VARIABLE_COLUMNS))
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| TxError::Rejected(variable_not_found(locale, id)))?;
+        record(&mut *tx, &row, "deleted", row.recorded(&row.value), None, change).await?;
+        Ok(())
+    }))
+    .await
+    .map_err(|e| e.respond(locale, "delete_variable_failed"))?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// Newest first. Kept after the variable is deleted.
+pub async fn list_variable_changes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let changes = sqlx::query_as::<_, VariableChange>(&format!(
+        "SELECT {} FROM variable_changes WHERE variable_id = $1 ORDER BY changed_at DESC, id",
+        CHANGE_COLUMNS
+    ))
+        .bind(id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_variables_failed"))?;
+    if changes.is_empty() {
+        return Err(variable_not_found(&locale, id));
+    }
+    Ok(Json(changes).into_response())
+}
+
+// Where the text has {{ var.NAME }} placeholders, and the names. Braces
+// around anything else are left alone.
+fn placeholders(text: &str) -> Vec<(Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(start) = text[from..].find("{{").map(|i| from + i) {
+        let Some(end) = text[start..].find("}}").map(|i| start + i + 2) else { break };
+        match text[start + 2..end - 2].trim().strip_prefix("var.") {
+            Some(name) if connections::valid_name(name) => {
+                found.push((start..end, name));
+                from = end;
+            }
+            _ => from = start + 2,
+        }
+    }
+    found
+}
+
+// Whether the text has any placeholders to fill in
+pub fn is_templated(text: &str) -> bool {
+    !placeholders(text).is_empty()
+}
+
+fn visit_strings(value: &mut Value, f: &mut impl FnMut(&mut String)) {
+    match value {
+        Value::String(text) => f(text),
+        Value::Array(items) => items.iter_mut().for_each(|item| visit_strings(item, f)),
+        Value::Object(fields) => fields.values_mut().for_each(|item| visit_strings(item, f)),
+        _ => {}
+    }
+}
+
+// Fills in the placeholders in the strings of `task` with the variables for
+// the DAG's environment, secret ones only if the DAG's owner may use them.
+// Values aren't searched for placeholders in turn. The inner error says why
+// the task can't be given them.
+pub async fn render<T: Serialize + DeserializeOwned>(
+    pool: &PgPool,
+    dag_id: Uuid,
+    task: &T,
+) -> Result<Result<T, String>, sqlx::Error> {
+    let mut value = match serde_json::to_value(task) {
+        Ok(value) => value,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+    let mut names = BTreeSet::new();
+    visit_strings(&mut value, &mut |text| {
+        names.extend(placeholders(text).into_iter().map(|(_, name)| name.to_string()));
+    });
+    if names.is_empty() {
+        return Ok(serde_json::from_value(value).map_err(|e| e.to_string()));
+    }
+
+    let names: Vec<String> = names.into_iter().collect();
+    let rows = sqlx::query_as::<_, Row>(&format!(
+        "SELECT DISTINCT ON (name) {} FROM variables
+         WHERE name = ANY($2) AND (environment IS NULL OR environment = (SELECT environment FROM dags WHERE id = $1))
+             AND (NOT secret OR {})
+         ORDER BY name, environment NULLS LAST",
+        VARIABLE_COLUMNS,
+        connections::usable_by(1)
+    ))
+        .bind(dag_id)
+        .bind(&names)
+        .fetch_all(pool)
+        .await?;
+    let mut values = HashMap::new();
+    for name in &names {
+        let Some(row) = rows.iter().find(|r| &r.name == name) else {
+            return Ok(Err(format!("Variable '{}' is not set, or is secret and not shared with the DAG's owner", name)));
+        };
+        let value = match row.secret {
+            false => row.value.clone(),
+            true => {
+                let Some(key) = connections::key() else {
+                    return Ok(Err(format!("Variable '{}' is secret but CONNECTIONS_KEY is not set", name)));
+                };
+                match connections::unseal(key, row.id, SEALED_FIELD, &row.value) {
+                    Ok(value) => value,
+                    Err(e) => return Ok(Err(format!("Variable '{}': {}", name, e))),
+                }
+            }
+        };
+        values.insert(name.as_str(), value);
+    }
+
+    visit_strings(&mut value, &mut |text| {
+        let mut filled = String::with_capacity(text.len());
+        let mut last = 0;
+        for (range, name) in placeholders(text) {
+            filled.push_str(&text[last..range.start]);
+            filled.push_str(&values[name]);
+            last = range.end;
+        }
+        filled.push_str(&text[last..]);
+        *text = filled;
+    });
+    Ok(serde_json::from_value(value).map_err(|e| e.to_string()))
+}
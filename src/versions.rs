@@ -0,0 +1,283 @@
+// Saved revisions of a DAG. Saving keeps the DAG's nodes and edges, ids
+// included, as a compressed snapshot; restoring puts them back as they were,
+// so links to nodes by id keep working across a restore. Versions saved
+// before snapshots have their nodes and edges in the version tables.
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json as JsonColumn;
+use sqlx::{FromRow, PgPool};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::events;
+use crate::i18n::Locale;
+use crate::maintenance::Writable;
+use crate::slugs::DagId;
+use crate::snapshots;
+use crate::usage::{self, Access};
+use crate::{find_dag, loaded_dag, lock_dag, with_warning, writable, Edge, Node, DAG};
+
+pub const NODE_COLUMNS: &str =
+    "id, dag_id, label, slug, external_ids, provenance, owner, source_url, task, metadata, node_type, position, created_by, reason, state";
+
+#[derive(Serialize, FromRow)]
+pub struct Version {
+    version: i32,
+    message: Option<String>,
+    node_count: i32,
+    edge_count: i32,
+    created_at: DateTime<Utc>,
+}
+
+// Lets reads look at a saved version instead of the working copy
+#[derive(Deserialize)]
+pub struct VersionParam {
+    version: Option<i32>,
+}
+
+impl VersionParam {
+    // Whether the DAG is wanted as it is now rather than as saved
+    pub fn is_current(&self) -> bool {
+        self.version.is_none()
+    }
+}
+
+pub fn version_not_found(locale: &Locale, dag_id: Uuid, version: i32) -> AppError {
+    locale.error(StatusCode::NOT_FOUND, "version_not_found", &[("dag", &dag_id), ("version", &version)])
+}
+
+// loaded_dag, or the nodes and edges of one of the DAG's versions when the
+// read asks for one
+pub async fn loaded_at(
+    pool: &PgPool,
+    locale: &Locale,
+    dag_id: Uuid,
+    at: &VersionParam,
+    key: &str,
+) -> Result<(DAG, Vec<Node>, Vec<Edge>), AppError> {
+    let Some(version) = at.version else {
+        return loaded_dag(pool, locale, dag_id, key).await;
+    };
+    let dag = find_dag(pool, locale, dag_id).await?;
+    let (nodes, edges) = version_contents(pool, locale, dag_id, version).await?;
+    Ok((dag, nodes, edges))
+}
+
+async fn version_contents(pool: &PgPool, locale: &Locale, dag_id: Uuid, version: i32) -> Result<(Vec<Node>, Vec<Edge>), AppError> {
+    let contents = async {
+        let mut conn = pool.acquire().await?;
+        saved_contents(&mut conn, dag_id, version).await
+    };
+    contents
+        .await
+        .map_err(|e| AppError::database(e, locale, "fetch_versions_failed"))?
+        .ok_or_else(|| version_not_found(locale, dag_id, version))
+}
+
+// The nodes and edges saved as a version, or None if there is no such version
+pub async fn saved_contents(conn: &mut sqlx::PgConnection, dag_id: Uuid, version: i32) -> Result<Option<(Vec<Node>, Vec<Edge>)>, sqlx::Error> {
+    let snapshot: Option<Option<Vec<u8>>> = sqlx::query_scalar("SELECT snapshot FROM dag_versions WHERE dag_id = $1 AND version = $2")
+        .bind(dag_id)
+        .bind(version)
+        .fetch_optional(&mut *conn)
+        .await?;
+    match snapshot {
+        None => return Ok(None),
+        Some(Some(snapshot)) => {
+            let (_, nodes, edges) = snapshots::decode(&snapshot).map_err(|e| sqlx::Error::Decode(e.into()))?;
+            return Ok(Some((nodes, edges)));
+        }
+        Some(None) => {}
+    }
+    let nodes = sqlx::query_as::<_, Node>(&format!(
+        "SELECT {} FROM dag_version_nodes WHERE dag_id = $1 AND version = $2",
+        NODE_COLUMNS
+    ))
+        .bind(dag_id)
+        .bind(version)
+        .fetch_all(&mut *conn)
+        .await?;
+    let edges = sqlx::query_as::<_, Edge>(
+        "SELECT id, source, target, dag_id, created_by, reason FROM dag_version_edges WHERE dag_id = $1 AND version = $2",
+    )
+        .bind(dag_id)
+        .bind(version)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(Some((nodes, edges)))
+}
+
+// Snapshots the DAG as it is now into a new version. Callers hold the DAG lock.
+pub async fn save_version(tx: &mut sqlx::PgConnection, dag_id: Uuid, message: Option<&str>) -> Result<Version, sqlx::Error> {
+    let nodes = sqlx::query_as::<_, Node>(&format!("SELECT {} FROM nodes WHERE dag_id = $1", NODE_COLUMNS))
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let edges = sqlx::query_as::<_, Edge>("SELECT id, source, target, dag_id, created_by, reason FROM edges WHERE dag_id = $1")
+        .bind(dag_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let snapshot = snapshots::encode(dag_id, &nodes, &edges);
+    sqlx::query_as::<_, Version>(
+        "INSERT INTO dag_versions (dag_id, version, message, node_count, edge_count, snapshot)
+         SELECT $1, COALESCE((SELECT MAX(version) FROM dag_versions WHERE dag_id = $1), 0) + 1, $2, $3, $4, $5
+         RETURNING version, message, node_count, edge_count, created_at",
+    )
+        .bind(dag_id)
+        .bind(message)
+        .bind(nodes.len() as i32)
+        .bind(edges.len() as i32)
+        .bind(snapshot)
+        .fetch_one(&mut *tx)
+        .await
+}
+
+#[derive(Deserialize)]
+pub struct CreateVersionPayload {
+    message: Option<String>,
+}
+
+pub async fn create_version(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Json(payload): Json<CreateVersionPayload>,
+) -> Result<Response, AppError> {
+    let message = payload.message.as_deref().map(str::trim).filter(|m| !m.is_empty());
+    let locale = &locale;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        // Reading an archived DAG into a version changes nothing about it
+        if lock_dag(&mut *tx, dag_id).await?.is_none() {
+            return Err(TxError::Rejected(locale.error(StatusCode::NOT_FOUND, "dag_not_found", &[("id", &dag_id)])));
+        }
+        Ok(save_version(&mut *tx, dag_id, message).await?)
+    }))
+    .await;
+
+    let version = result.map_err(|e| e.respond(locale, "create_version_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    let location = format!("/dags/{}/versions/{}", dag_id, version.version);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(version)).into_response())
+}
+
+pub async fn list_versions(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+) -> Result<Response, AppError> {
+    find_dag(&pool, &locale, dag_id).await?;
+    let versions = sqlx::query_as::<_, Version>(
+        "SELECT version, message, node_count, edge_count, created_at FROM dag_versions
+         WHERE dag_id = $1 ORDER BY version DESC",
+    )
+        .bind(dag_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_versions_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    Ok(Json(versions).into_response())
+}
+
+pub async fn get_version(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Path((_, version)): Path<(String, i32)>,
+) -> Result<Response, AppError> {
+    let (nodes, edges) = version_contents(&pool, &locale, dag_id, version).await?;
+    let saved = sqlx::query_as::<_, Version>(
+        "SELECT version, message, node_count, edge_count, created_at FROM dag_versions WHERE dag_id = $1 AND version = $2",
+    )
+        .bind(dag_id)
+        .bind(version)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_versions_failed"))?;
+    usage::record(&pool, dag_id, Access::Read);
+    let mut body = serde_json::json!(saved);
+    body["nodes"] = serde_json::json!(nodes);
+    body["edges"] = serde_json::json!(edges);
+    Ok(Json(body).into_response())
+}
+
+// Replaces the working copy with a saved version. The working copy is saved
+// as a new version first, so a restore can itself be undone. Edges other
+// DAGs had to the replaced nodes go with them.
+pub async fn restore_version(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    DagId(dag_id): DagId,
+    Path((_, version)): Path<(String, i32)>,
+) -> Result<Response, AppError> {
+    let locale = &locale;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        let warning = writable(lock_dag(&mut *tx, dag_id).await?, locale, dag_id)
+            .map_err(TxError::Rejected)?;
+        let Some((nodes, edges)) = saved_contents(&mut *tx, dag_id, version).await? else {
+            return Err(TxError::Rejected(version_not_found(locale, dag_id, version)));
+        };
+
+        let message = locale.t("version_saved_before_restore", &[("version", &version)]);
+        let saved = save_version(&mut *tx, dag_id, Some(&message)).await?;
+        sqlx::query(
+            "DELETE FROM edges WHERE dag_id = $1
+                OR source IN (SELECT id FROM nodes WHERE dag_id = $1)
+                OR target IN (SELECT id FROM nodes WHERE dag_id = $1)",
+        )
+            .bind(dag_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE dag_id = $1")
+            .bind(dag_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!(
+            "INSERT INTO nodes ({columns}) SELECT {columns} FROM jsonb_populate_recordset(NULL::nodes, $1)",
+            columns = NODE_COLUMNS
+        ))
+            .bind(JsonColumn(&nodes))
+            .execute(&mut *tx)
+            .await?;
+        // Versions saved before edges had to stay within their DAG may hold
+        // edges into other DAGs; those don't come back
+        let restored: HashSet<Uuid> = nodes.iter().map(|n| n.id).collect();
+        let edges: Vec<&Edge> = edges
+            .iter()
+            .filter(|e| restored.contains(&e.source) && restored.contains(&e.target))
+            .collect();
+        sqlx::query(
+            "INSERT INTO edges (id, source, target, dag_id, created_by, reason)
+             SELECT u.id, u.source, u.target, $1, u.created_by, u.reason
+             FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[], $5::text[], $6::text[]) AS u(id, source, target, created_by, reason)",
+        )
+            .bind(dag_id)
+            .bind(edges.iter().map(|e| e.id).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.source).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.target).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.created_by.clone()).collect::<Vec<_>>())
+            .bind(edges.iter().map(|e| e.reason.clone()).collect::<Vec<_>>())
+            .execute(&mut *tx)
+            .await?;
+        Ok((saved, warning))
+    }))
+    .await;
+
+    let (saved, warning) = result.map_err(|e| e.respond(locale, "restore_version_failed"))?;
+    usage::record(&pool, dag_id, Access::Edit);
+    events::publish(dag_id, "graph_changed", serde_json::json!({ "cause": "restore", "version": version }));
+    let body = Json(serde_json::json!({
+        "dag_id": dag_id,
+        "restored": version,
+        "saved_as": saved.version,
+    }));
+    Ok(with_warning(body.into_response(), warning))
+}
@@ -0,0 +1,143 @@
+// The controlled vocabulary for node labels. While no prefixes are listed any
+// label goes; once there are some, every label a caller writes has to start
+// with one of them. Labels already stored are left alone until they change.
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+use crate::auth;
+use crate::db::{self, TxError};
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::maintenance::Writable;
+
+const DEFAULT_SUGGESTIONS: i64 = 5;
+const MAX_SUGGESTIONS: i64 = 50;
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct LabelPrefix {
+    prefix: String,
+    description: Option<String>,
+}
+
+pub async fn label_prefixes<'e, E: PgExecutor<'e>>(executor: E) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT prefix FROM label_prefixes ORDER BY prefix")
+        .fetch_all(executor)
+        .await
+}
+
+pub fn allowed(prefixes: &[String], label: &str) -> bool {
+    prefixes.is_empty() || prefixes.iter().any(|prefix| label.starts_with(prefix.as_str()))
+}
+
+// Refuses the first label outside the vocabulary with 422
+pub async fn check_labels<'a>(
+    tx: &mut sqlx::PgConnection,
+    locale: &Locale,
+    labels: impl IntoIterator<Item = &'a str>,
+) -> Result<(), TxError> {
+    let prefixes = label_prefixes(&mut *tx).await?;
+    match labels.into_iter().find(|label| !allowed(&prefixes, label)) {
+        Some(label) => Err(TxError::Rejected(
+            locale
+                .error(StatusCode::UNPROCESSABLE_ENTITY, "label_prefix_not_allowed", &[("label", &label)])
+                .with_details(serde_json::json!({ "allowed_prefixes": prefixes })),
+        )),
+        None => Ok(()),
+    }
+}
+
+async fn all_prefixes<'e, E: PgExecutor<'e>>(executor: E) -> Result<Vec<LabelPrefix>, sqlx::Error> {
+    sqlx::query_as::<_, LabelPrefix>("SELECT prefix, description FROM label_prefixes ORDER BY prefix")
+        .fetch_all(executor)
+        .await
+}
+
+pub async fn list_label_prefixes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let prefixes = all_prefixes(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_label_prefixes_failed"))?;
+    Ok(Json(prefixes).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct LabelPrefixesPayload {
+    prefixes: Vec<LabelPrefix>,
+}
+
+// Replaces the whole vocabulary; an empty list lifts the restriction. It
+// applies to every key's DAGs, so only admins may change it.
+pub async fn set_label_prefixes(
+    _: Writable,
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Json(payload): Json<LabelPrefixesPayload>,
+) -> Result<Response, AppError> {
+    if !auth::admin() {
+        return Err(locale.error(StatusCode::FORBIDDEN, "label_prefixes_need_admin", &[]));
+    }
+    if payload.prefixes.iter().any(|p| p.prefix.trim().is_empty()) {
+        return Err(locale.error(StatusCode::UNPROCESSABLE_ENTITY, "blank_label_prefix", &[]));
+    }
+
+    let payload = &payload;
+    let result = db::unit_of_work(&pool, |tx| Box::pin(async move {
+        sqlx::query("DELETE FROM label_prefixes").execute(&mut *tx).await?;
+        // A prefix listed twice keeps its first description
+        sqlx::query(
+            "INSERT INTO label_prefixes (prefix, description)
+             SELECT * FROM UNNEST($1::text[], $2::text[]) ON CONFLICT (prefix) DO NOTHING",
+        )
+            .bind(payload.prefixes.iter().map(|p| p.prefix.as_str()).collect::<Vec<_>>())
+            .bind(payload.prefixes.iter().map(|p| p.description.as_deref()).collect::<Vec<_>>())
+            .execute(&mut *tx)
+            .await?;
+        Ok(all_prefixes(&mut *tx).await?)
+    }))
+    .await;
+
+    let prefixes = result.map_err(|e| e.respond(&locale, "set_label_prefixes_failed"))?;
+    Ok(Json(prefixes).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    #[serde(default)]
+    label: String,
+    limit: Option<i64>,
+}
+
+// Prefixes for a label being typed: the ones it already starts with, then
+// the ones that start with it, then the closest by trigram similarity
+pub async fn suggest_label_prefixes(
+    State(pool): State<PgPool>,
+    locale: Locale,
+    Query(params): Query<SuggestParams>,
+) -> Result<Response, AppError> {
+    let suggestions = sqlx::query_as::<_, LabelPrefix>(
+        "SELECT prefix, description FROM label_prefixes
+         ORDER BY starts_with($1, prefix) DESC, starts_with(prefix, $1) DESC, similarity(prefix, $1) DESC, prefix
+         LIMIT $2",
+    )
+        .bind(&params.label)
+        .bind(params.limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS))
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| AppError::database(e, &locale, "fetch_label_prefixes_failed"))?;
+
+    // A matching prefix sorts first, so the first suggestion settles it
+    let allowed = suggestions.first().is_none_or(|first| params.label.starts_with(first.prefix.as_str()));
+    Ok(Json(serde_json::json!({
+        "label": params.label,
+        "allowed": allowed,
+        "suggestions": suggestions,
+    }))
+    .into_response())
+}
@@ -0,0 +1,98 @@
+// Finds runs that stopped moving. A run changes state when it starts and
+// whenever one of its nodes starts or finishes; one queued or running with no
+// such change for RUN_STALL_AFTER seconds has most likely lost its worker or
+// been forgotten by the job queue. The watchdog sets it to stalled, logs a
+// warning and publishes a run_stalled event, and puts it back to running if
+// it moves again. It does not stop or restart anything: GET /runs?status=stalled
+// lists what needs looking at.
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::events;
+use crate::executor::{self, RunStatus};
+use crate::maintenance::Maintenance;
+
+// How long a run may go without a state change, unless RUN_STALL_AFTER says
+// otherwise; 0 turns the watchdog off
+const DEFAULT_STALL_AFTER: u64 = 30 * 60;
+const TICK: Duration = Duration::from_secs(30);
+
+// When the run or one of its nodes last changed state
+const LAST_TRANSITION: &str = "GREATEST(r.created_at, r.started_at,
+    (SELECT MAX(GREATEST(t.started_at, t.finished_at)) FROM task_runs t WHERE t.run_id = r.id))";
+
+#[derive(FromRow)]
+struct Stalled {
+    id: Uuid,
+    dag_id: Uuid,
+    last_transition_at: DateTime<Utc>,
+}
+
+// Starts the watchdog. Like the scheduler it leaves runs alone while the
+// service is read-only.
+pub fn start(pool: &PgPool, maintenance: &Arc<Maintenance>) {
+    let stall_after = env::var("RUN_STALL_AFTER")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_STALL_AFTER);
+    if stall_after == 0 {
+        return;
+    }
+    let (pool, maintenance) = (pool.clone(), maintenance.clone());
+    tokio::spawn(async move {
+        loop {
+            if !maintenance.is_read_only() {
+                if let Err(e) = check(&pool, stall_after).await {
+                    tracing::error!("Failed to look for stalled runs: {}", e);
+                }
+            }
+            tokio::time::sleep(TICK).await;
+        }
+    });
+}
+
+async fn check(pool: &PgPool, stall_after: u64) -> Result<(), sqlx::Error> {
+    let moved: Vec<(Uuid, Uuid)> = sqlx::query_as(&format!(
+        "UPDATE runs r SET status = 'running' WHERE status = 'stalled' AND {} > stalled_at RETURNING id, dag_id",
+        LAST_TRANSITION
+    ))
+        .fetch_all(pool)
+        .await?;
+    for (run_id, dag_id) in moved {
+        tracing::info!("Run {} of DAG {} is moving again", run_id, dag_id);
+        executor::run_changed(dag_id, run_id, RunStatus::Running);
+    }
+
+    let stalled = sqlx::query_as::<_, Stalled>(&format!(
+        "UPDATE runs r SET status = 'stalled', stalled_at = now()
+         WHERE status IN ('queued', 'running') AND {lt} < now() - make_interval(secs => $1)
+         RETURNING id, dag_id, {lt} AS last_transition_at",
+        lt = LAST_TRANSITION
+    ))
+        .bind(stall_after as f64)
+        .fetch_all(pool)
+        .await?;
+    for run in stalled {
+        tracing::warn!(
+            "Run {} of DAG {} has not changed state since {}; marked stalled",
+            run.id,
+            run.dag_id,
+            run.last_transition_at
+        );
+        executor::run_changed(run.dag_id, run.id, RunStatus::Stalled);
+        events::publish(
+            run.dag_id,
+            "run_stalled",
+            serde_json::json!({
+                "run_id": run.id,
+                "last_transition_at": run.last_transition_at,
+                "stall_after_secs": stall_after,
+            }),
+        );
+    }
+    Ok(())
+}